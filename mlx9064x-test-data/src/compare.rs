@@ -0,0 +1,31 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright © 2021 Will Ross
+
+/// Assert that two images are the same to within `tolerance`, returning the largest difference.
+///
+/// This is for comparing the images generated by different temperature calculations. Pixels that
+/// are NaN in `expected` (like the pixels that weren't in a subpage) must also be NaN in `actual`.
+pub fn compare_images(expected: &[f32], actual: &[f32], tolerance: f32) -> f32 {
+    assert_eq!(
+        expected.len(),
+        actual.len(),
+        "The images are different sizes"
+    );
+    let mut max_difference = 0f32;
+    for (index, (expected, actual)) in expected.iter().zip(actual.iter()).enumerate() {
+        if expected.is_nan() {
+            assert!(actual.is_nan(), "Pixel {} is {}, not NaN", index, actual);
+        } else {
+            let difference = (expected - actual).abs();
+            assert!(
+                difference <= tolerance,
+                "Pixel {} is {}, which is too far from {}",
+                index,
+                actual,
+                expected
+            );
+            max_difference = max_difference.max(difference);
+        }
+    }
+    max_difference
+}
@@ -0,0 +1,243 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright © 2021 Will Ross
+
+/// The faults currently programmed into a [`MockCameraBus`][crate::MockCameraBus].
+///
+/// All of the faults are off by default, so the mock only fails on illegal accesses.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct Faults {
+    /// How many more transactions succeed before transactions start being NACKed.
+    nack_countdown: Option<usize>,
+
+    /// How many transactions to NACK once the countdown has finished.
+    nack_count: usize,
+
+    /// Random corruption of the bytes read from the camera.
+    corruption: Option<Corruption>,
+
+    /// The value the new data flag is stuck at, if it is stuck.
+    stuck_new_data: Option<bool>,
+
+    /// How many status register reads to hide a new frame from.
+    ready_delay: usize,
+
+    /// How many more status register reads will hide the new data flag for the current frame.
+    ready_delay_remaining: usize,
+}
+
+#[derive(Clone, Debug)]
+struct Corruption {
+    rng: XorShift,
+    probability: f32,
+}
+
+impl Faults {
+    pub(crate) fn nack_after(&mut self, operations: usize, count: usize) {
+        self.nack_countdown = Some(operations);
+        self.nack_count = count;
+    }
+
+    pub(crate) fn corrupt_reads(&mut self, seed: u64, probability: f32) {
+        self.corruption = Some(Corruption {
+            rng: XorShift::new(seed),
+            probability,
+        });
+    }
+
+    pub(crate) fn stick_new_data(&mut self, stuck: Option<bool>) {
+        self.stuck_new_data = stuck;
+    }
+
+    pub(crate) fn delay_ready(&mut self, status_reads: usize) {
+        self.ready_delay = status_reads;
+        self.ready_delay_remaining = 0;
+    }
+
+    /// Restart the readiness delay, as a new frame has been made available.
+    pub(crate) fn new_frame(&mut self) {
+        self.ready_delay_remaining = self.ready_delay;
+    }
+
+    /// Check whether the next transaction should be NACKed, counting it towards the faults.
+    pub(crate) fn should_nack(&mut self) -> bool {
+        match self.nack_countdown {
+            Some(0) if self.nack_count > 0 => {
+                self.nack_count -= 1;
+                true
+            }
+            Some(0) => {
+                self.nack_countdown = None;
+                false
+            }
+            Some(ref mut countdown) => {
+                *countdown -= 1;
+                false
+            }
+            None => false,
+        }
+    }
+
+    /// Apply any faults to the status register value being read.
+    pub(crate) fn status_read(&mut self, status: &mut [u8]) {
+        const NEW_DATA_MASK: u8 = 0x08;
+        if self.ready_delay_remaining > 0 {
+            self.ready_delay_remaining -= 1;
+            status[1] &= !NEW_DATA_MASK;
+        }
+        match self.stuck_new_data {
+            Some(true) => status[1] |= NEW_DATA_MASK,
+            Some(false) => status[1] &= !NEW_DATA_MASK,
+            None => (),
+        }
+    }
+
+    /// Randomly corrupt bytes that have been read.
+    pub(crate) fn corrupt(&mut self, bytes: &mut [u8]) {
+        if let Some(corruption) = self.corruption.as_mut() {
+            for byte in bytes.iter_mut() {
+                if corruption.rng.next_f32() < corruption.probability {
+                    // Flip a single random bit in the byte.
+                    *byte ^= 1 << (corruption.rng.next_u64() % 8);
+                }
+            }
+        }
+    }
+}
+
+/// A tiny xorshift PRNG, so corruption is repeatable for a given seed.
+#[derive(Clone, Debug)]
+struct XorShift(u64);
+
+impl XorShift {
+    fn new(seed: u64) -> Self {
+        // xorshift gets stuck at 0, so nudge that seed.
+        Self(seed.max(1))
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    /// A value in [0, 1).
+    fn next_f32(&mut self) -> f32 {
+        // The top 24 bits fit exactly in an f32's mantissa.
+        (self.next_u64() >> 40) as f32 / (1u32 << 24) as f32
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use embedded_hal::i2c::{Error as _, ErrorKind, I2c, NoAcknowledgeSource};
+    use mlx9064x::{mlx90640, Address, Error, LibraryError, MelexisCamera};
+    use mlx9064x::{Mlx90640Driver, Mlx90641Driver};
+
+    use crate::{example_mlx90640_at_address, mock_mlx90641_at_address, MockError};
+
+    const ADDRESS: u8 = 0x33;
+
+    fn temperatures() -> [f32; mlx90640::Mlx90640::NUM_PIXELS] {
+        [0f32; mlx90640::Mlx90640::NUM_PIXELS]
+    }
+
+    #[test]
+    fn nack_after() {
+        let mut mocked = example_mlx90640_at_address(ADDRESS);
+        let mut cam = Mlx90640Driver::new(mocked.clone(), ADDRESS).unwrap();
+        let mut temperatures = temperatures();
+        mocked.set_data_available(true);
+        // Let the status register read through, then fail the pixel data read.
+        mocked.nack_after(1, 1);
+        let err = cam.generate_image_if_ready(&mut temperatures).unwrap_err();
+        assert!(matches!(err, Error::I2cError(MockError::Nack)));
+        assert_eq!(
+            err.kind(),
+            ErrorKind::NoAcknowledge(NoAcknowledgeSource::Unknown)
+        );
+        // The frame wasn't marked as read, so retrying picks it back up.
+        assert!(cam.generate_image_if_ready(&mut temperatures).unwrap());
+        // Failing forever until cleared
+        mocked.set_data_available(true);
+        mocked.nack_after(0, usize::MAX);
+        assert!(cam.generate_image_if_ready(&mut temperatures).is_err());
+        assert!(cam.generate_image_if_ready(&mut temperatures).is_err());
+        mocked.clear_faults();
+        assert!(cam.generate_image_if_ready(&mut temperatures).unwrap());
+    }
+
+    #[test]
+    fn corrupt_reads() {
+        let mut mocked = example_mlx90640_at_address(ADDRESS);
+        let read_eeprom = |mocked: &mut crate::MockCameraBus<_>| {
+            let mut buffer = [0u8; 64];
+            mocked
+                .write_read(ADDRESS, &[0x24, 0x40], &mut buffer)
+                .unwrap();
+            buffer
+        };
+        let clean = read_eeprom(&mut mocked);
+        mocked.corrupt_reads(42, 1.0);
+        let corrupted = read_eeprom(&mut mocked);
+        for (clean, corrupted) in clean.iter().zip(corrupted.iter()) {
+            assert_eq!((clean ^ corrupted).count_ones(), 1);
+        }
+        // The corruption is repeatable
+        mocked.corrupt_reads(42, 1.0);
+        assert_eq!(read_eeprom(&mut mocked), corrupted);
+        mocked.corrupt_reads(42, 0.0);
+        assert_eq!(read_eeprom(&mut mocked), clean);
+    }
+
+    #[test]
+    fn stuck_new_data() {
+        let mut mocked = example_mlx90640_at_address(ADDRESS);
+        let mut cam = Mlx90640Driver::new(mocked.clone(), ADDRESS).unwrap();
+        let mut temperatures = temperatures();
+        mocked.set_data_available(true);
+        mocked.set_new_data_stuck(Some(false));
+        assert!(!cam.generate_image_if_ready(&mut temperatures).unwrap());
+        // Stuck on means every poll looks like a new frame, even after resetting the flag.
+        mocked.set_new_data_stuck(Some(true));
+        assert!(cam.generate_image_if_ready(&mut temperatures).unwrap());
+        assert!(cam.data_available().unwrap().is_some());
+        mocked.set_new_data_stuck(None);
+        assert!(cam.data_available().unwrap().is_none());
+    }
+
+    #[test]
+    fn delayed_frame() {
+        let mut mocked = example_mlx90640_at_address(ADDRESS);
+        let mut cam = Mlx90640Driver::new(mocked.clone(), ADDRESS).unwrap();
+        let mut temperatures = temperatures();
+        mocked.delay_frame_ready(2);
+        mocked.set_data_available(true);
+        assert!(!cam.generate_image_if_ready(&mut temperatures).unwrap());
+        assert!(!cam.generate_image_if_ready(&mut temperatures).unwrap());
+        assert!(cam.generate_image_if_ready(&mut temperatures).unwrap());
+    }
+
+    #[test]
+    fn eeprom_bit_flips() {
+        let word = Address::new(0x2410);
+        // A single bit error is corrected by the MLX90641's Hamming code.
+        let mut mocked = mock_mlx90641_at_address(ADDRESS);
+        mocked.flip_eeprom_bits(word, 0x0001).unwrap();
+        assert!(Mlx90641Driver::new(mocked.clone(), ADDRESS).is_ok());
+        // But two can't be.
+        mocked.flip_eeprom_bits(word, 0x0100).unwrap();
+        let err = Mlx90641Driver::new(mocked.clone(), ADDRESS).unwrap_err();
+        assert!(matches!(
+            err,
+            Error::LibraryError(LibraryError::Checksum(_))
+        ));
+        // Flipping the same bits restores the EEPROM
+        mocked.flip_eeprom_bits(word, 0x0101).unwrap();
+        assert!(Mlx90641Driver::new(mocked.clone(), ADDRESS).is_ok());
+        assert!(matches!(
+            mocked.flip_eeprom_bits(Address::new(0x0400), 1),
+            Err(MockError::IllegalAccess(_))
+        ));
+    }
+}
@@ -4,15 +4,16 @@ extern crate alloc;
 
 use alloc::collections::VecDeque;
 use alloc::rc::Rc;
-use core::cell::{Ref, RefCell};
+use core::cell::{Cell, Ref, RefCell};
 use core::convert::TryInto;
 use core::ops::RangeInclusive;
 
-use arrayvec::ArrayVec;
-use embedded_hal::blocking::i2c;
+use embedded_hal::i2c;
+use embedded_hal_0_2::blocking::i2c as i2c_0_2;
 use mlx9064x::{mlx90640, mlx90641, Address, MelexisCamera};
 
 use super::eeprom_data::{mlx90640_datasheet_eeprom, mlx90641_datasheet_eeprom, EEPROM_LENGTH};
+use super::faults::Faults;
 use super::mlx90640_example_data;
 
 /// The number of bytes the MLX90640 has of RAM.
@@ -29,17 +30,27 @@ pub const MLX90640_RAM_LENGTH: usize = (0x0740 - 0x0400) * 2;
 /// corresponds to 16 bits of data.
 pub const MLX90641_RAM_LENGTH: usize = (0x05C0 - 0x0400) * 2;
 
-/// The MLX90642 uses the same RAM window as the MLX90641.
-pub const MLX90642_RAM_LENGTH: usize = MLX90641_RAM_LENGTH;
-
 const STATUS_REGISTER_ADDRESS: u16 = 0x8000;
 
 // The lowest 6 bits are documented, but the 6th bit is only documented in earlier versions of the
 // datasheet.
 const STATUS_REGISTER_WRITE_MASK: [u8; 2] = [0x00, 0x3F];
 
+// Setting this bit (in step mode) starts a measurement.
+const START_MEASUREMENT_MASK: u16 = 0x0020;
+
+const NEW_DATA_MASK: u16 = 0x0008;
+
+const LAST_SUBPAGE_MASK: u16 = 0x0007;
+
 const CONTROL_REGISTER_ONE_ADDRESS: u16 = 0x800D;
 
+const STEP_MODE_MASK: u16 = 0x0002;
+
+const SUBPAGE_REPEAT_MASK: u16 = 0x0008;
+
+const SELECTED_SUBPAGE_SHIFT: u16 = 4;
+
 // Only the top three bits of control register 1 are reserved.
 const CONTROL_REGISTER_1_WRITE_MASK: [u8; 2] = [0x1F, 0xFF];
 
@@ -53,7 +64,6 @@ const I2C_CONFIG_REGISTER_WRITE_MASK: [u8; 2] = [0x00, 0x0F];
 
 const RECENT_OPERATIONS_QUEUE_LENGTH: usize = 32;
 
-#[allow(dead_code)]
 #[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
 #[repr(u16)]
 enum EepromAddress {
@@ -61,8 +71,42 @@ enum EepromAddress {
     ControlRegister2 = 0x240D,
     I2cConfigRegister = 0x240E,
     I2cAddress = 0x240F,
+    /// Only present on the MLX90641.
+    Emissivity = 0x2423,
 }
 
+/// The EEPROM words that can be written on both cameras, along with which bits can be changed.
+///
+/// Technically you can write anywhere in the EEPROM, but then the calibration data is lost and
+/// can't be recovered. Control register 2 is documented, but the contents are not, so it's treated
+/// as entirely reserved. The lower half of the I2C address word is the I2C address of the device,
+/// meaning the lower 7 bits.
+const EEPROM_WRITE_MASKS: [(EepromAddress, u16); 4] = [
+    (
+        EepromAddress::ControlRegister1,
+        u16::from_be_bytes(CONTROL_REGISTER_1_WRITE_MASK),
+    ),
+    (
+        EepromAddress::ControlRegister2,
+        u16::from_be_bytes(CONTROL_REGISTER_2_WRITE_MASK),
+    ),
+    (
+        EepromAddress::I2cConfigRegister,
+        u16::from_be_bytes(I2C_CONFIG_REGISTER_WRITE_MASK),
+    ),
+    (EepromAddress::I2cAddress, 0x007F),
+];
+
+/// The MLX90641 also allows changing the emissivity. The entire word can change, as the Hamming
+/// code is in the upper bits.
+const MLX90641_EEPROM_WRITE_MASKS: [(EepromAddress, u16); 5] = [
+    EEPROM_WRITE_MASKS[0],
+    EEPROM_WRITE_MASKS[1],
+    EEPROM_WRITE_MASKS[2],
+    EEPROM_WRITE_MASKS[3],
+    (EepromAddress::Emissivity, 0xFFFF),
+];
+
 #[derive(Copy, Clone, Debug)]
 pub enum MockError {
     /// The given address shouldn't be accessed.
@@ -89,6 +133,11 @@ pub enum MockError {
     ///   operation).
     /// * Read operations that aren't readying a full number of words (each word is two bytes).
     IllegalOperation,
+
+    /// The transaction was not acknowledged because of an injected fault.
+    ///
+    /// See [`MockCameraBus::nack_after`].
+    Nack,
 }
 
 #[derive(Copy, Clone, Debug, PartialEq, Eq)]
@@ -105,11 +154,16 @@ pub struct MockCameraBus<const RAM_LENGTH: usize> {
     eeprom_range: RangeInclusive<u16>,
     register_range: RangeInclusive<u16>,
     eeprom_data: Rc<RefCell<[u8; EEPROM_LENGTH]>>,
+    /// The EEPROM as it was created, used to check reserved bits after a word is erased.
+    factory_eeprom: Rc<[u8; EEPROM_LENGTH]>,
+    eeprom_write_masks: &'static [(EepromAddress, u16)],
     ram_data: Rc<RefCell<[u8; RAM_LENGTH]>>,
     status_register: Rc<RefCell<[u8; 2]>>,
     control_register: Rc<RefCell<[u8; 2]>>,
     i2c_config_register: Rc<RefCell<[u8; 2]>>,
     recent_operations: Rc<RefCell<VecDeque<I2cOperation>>>,
+    faults: Rc<RefCell<Faults>>,
+    step_measurements: Rc<Cell<usize>>,
 }
 
 impl MockCameraBus<MLX90640_RAM_LENGTH> {
@@ -138,11 +192,15 @@ impl MockCameraBus<MLX90640_RAM_LENGTH> {
             eeprom_range: 0x2400..=0x273F,
             register_range: 0x8000..=0x8016,
             eeprom_data: Rc::new(RefCell::new(eeprom_data)),
+            factory_eeprom: Rc::new(eeprom_data),
+            eeprom_write_masks: &EEPROM_WRITE_MASKS,
             ram_data: Rc::new(RefCell::new(ram_data)),
             status_register: Rc::new(RefCell::new(status_register_owned)),
             control_register: Rc::new(RefCell::new(control_register_owned)),
             i2c_config_register: Rc::new(RefCell::new(i2c_register_owned)),
             recent_operations: Rc::new(RefCell::new(VecDeque::new())),
+            faults: Rc::new(RefCell::new(Faults::default())),
+            step_measurements: Rc::new(Cell::new(0)),
         }
     }
 }
@@ -164,7 +222,7 @@ impl<const RAM_LENGTH: usize> MockCameraBus<RAM_LENGTH> {
     }
 
     /// Access a slice containing `count` 16-bit words, starting at the given address.
-    pub fn get(&self, address: Address, byte_count: usize) -> Result<Ref<[u8]>, MockError> {
+    pub fn get(&self, address: Address, byte_count: usize) -> Result<Ref<'_, [u8]>, MockError> {
         let start_address: u16 = address.into();
         let end_address = start_address + (byte_count / 2) as u16;
         // classify the address and check if it's supposed to be read
@@ -233,42 +291,37 @@ impl<const RAM_LENGTH: usize> MockCameraBus<RAM_LENGTH> {
             // It's in the name, Read-Only Memory; and only the camera is allowed to write to it's RAM.
             Err(MockError::IllegalWriteAddress(address))
         } else if self.eeprom_range.contains(&start_address) {
-            // Technically you can write anywhere in the EEPROM, but then the config data is lost
-            // and can't be recovered. The configuration register, I2C config register, and the I2C
-            // address register are the only safe locations that can be written. There is also a
-            // control register 2 documented, but the contents are not documented, so writing
-            // anything other than 0x0000 is treated as an error.
+            // EEPROM words are written one at a time. Writing 0 erases a word, any other value is
+            // combined with the current contents (so it only works as intended on an erased word).
+            if data.len() != 2 {
+                return Err(MockError::IllegalWriteAddress(end_address.into()));
+            }
+            let mask_word = self
+                .eeprom_write_masks
+                .iter()
+                .find(|(eeprom_address, _)| *eeprom_address as u16 == start_address)
+                .map(|(_, mask)| *mask)
+                .ok_or(MockError::IllegalWriteAddress(address))?;
             let slice_start = ((start_address - self.eeprom_range.start()) as usize) * 2;
             let slice_end = slice_start + data.len();
-            let existing_data = &mut self.eeprom_data.borrow_mut()[slice_start..slice_end];
-            // Create a mask of which bytes are writeable
-            // At most 4 words will be present.
-            let mut write_mask: ArrayVec<u8, { 4 * 2 }> = ArrayVec::new();
-            write_mask.extend(CONTROL_REGISTER_1_WRITE_MASK.iter().copied());
-            write_mask.extend(CONTROL_REGISTER_2_WRITE_MASK.iter().copied());
-            write_mask.extend(I2C_CONFIG_REGISTER_WRITE_MASK.iter().copied());
-            // The lower half is the I2C address of the device, meaning the lower 7 bits.
-            write_mask.extend([0x00, 0x7F]);
-            // Control register 1 is the first address in the sequence
-            let write_mask_start =
-                ((start_address - EepromAddress::ControlRegister1 as u16) as usize) * 2;
-            let write_mask = &write_mask[write_mask_start..data.len()];
-            let zipped_chunks = existing_data
-                .chunks_exact(2)
-                .zip(write_mask.chunks_exact(2))
-                .zip(data.chunks_exact(2));
-            for (index, ((existing_bytes, mask_bytes), new_bytes)) in zipped_chunks.enumerate() {
-                let existing_word = u16::from_be_bytes(existing_bytes.try_into().unwrap());
-                let mask_word = u16::from_be_bytes(mask_bytes.try_into().unwrap());
-                let new_word = u16::from_be_bytes(new_bytes.try_into().unwrap());
-                // Check that the new data isn't overwriting any of the reserved bits.
-                if !check_new_against_mask(existing_word, mask_word, new_word) {
-                    let bad_address = (start_address + index as u16).into();
-                    return Err(MockError::IllegalWriteValue(bad_address, new_word));
-                }
+            let factory_word = u16::from_be_bytes(
+                self.factory_eeprom[slice_start..slice_end]
+                    .try_into()
+                    .unwrap(),
+            );
+            let new_word = u16::from_be_bytes(data.try_into().unwrap());
+            // Check that the new data isn't overwriting any of the reserved bits.
+            if new_word != 0 && !check_new_against_mask(factory_word, mask_word, new_word) {
+                return Err(MockError::IllegalWriteValue(address, new_word));
             }
-            // Only change the values after the entire write has been checked
-            existing_data.copy_from_slice(data);
+            let existing_data = &mut self.eeprom_data.borrow_mut()[slice_start..slice_end];
+            let existing_word = u16::from_be_bytes((&*existing_data).try_into().unwrap());
+            let stored_word = if new_word == 0 {
+                0
+            } else {
+                existing_word | new_word
+            };
+            existing_data.copy_from_slice(&stored_word.to_be_bytes());
             Ok(())
         } else if self.register_range.contains(&start_address) {
             // There are only three registers, and they're non-contiguous, so only 1 word can be
@@ -301,7 +354,10 @@ impl<const RAM_LENGTH: usize> MockCameraBus<RAM_LENGTH> {
                 }
                 match start_address {
                     STATUS_REGISTER_ADDRESS => {
-                        self.status_register.borrow_mut().copy_from_slice(data)
+                        self.status_register.borrow_mut().copy_from_slice(data);
+                        if new_word & START_MEASUREMENT_MASK != 0 {
+                            self.step_measurement();
+                        }
                     }
                     CONTROL_REGISTER_ONE_ADDRESS => {
                         self.control_register.borrow_mut().copy_from_slice(data)
@@ -328,12 +384,42 @@ impl<const RAM_LENGTH: usize> MockCameraBus<RAM_LENGTH> {
         self.status_register
             .borrow_mut()
             .copy_from_slice(status_register);
+        self.faults.borrow_mut().new_frame();
+    }
+
+    /// Simulate a step mode measurement, if the camera is in step mode.
+    ///
+    /// Measurements are instantaneous, and update the next subpage (or the selected subpage in
+    /// subpage repeat mode). The RAM is not changed.
+    fn step_measurement(&mut self) {
+        let control = u16::from_be_bytes(*self.control_register.borrow());
+        if control & STEP_MODE_MASK == 0 {
+            return;
+        }
+        let mut status_register = self.status_register.borrow_mut();
+        let status = u16::from_be_bytes(*status_register);
+        let subpage = if control & SUBPAGE_REPEAT_MASK != 0 {
+            (control >> SELECTED_SUBPAGE_SHIFT) & LAST_SUBPAGE_MASK
+        } else {
+            (status & LAST_SUBPAGE_MASK) ^ 1
+        };
+        let status =
+            (status & !(START_MEASUREMENT_MASK | LAST_SUBPAGE_MASK)) | subpage | NEW_DATA_MASK;
+        status_register.copy_from_slice(&status.to_be_bytes());
+        self.step_measurements.set(self.step_measurements.get() + 1);
+        self.faults.borrow_mut().new_frame();
+    }
+
+    /// How many measurements have been started in step mode.
+    pub fn step_measurements(&self) -> usize {
+        self.step_measurements.get()
     }
 
     /// Set the "new data available" flag in the status register to a new value
     pub fn set_data_available(&mut self, available: bool) {
         if available {
             self.status_register.borrow_mut()[1] |= 0x8;
+            self.faults.borrow_mut().new_frame();
         } else {
             self.status_register.borrow_mut()[1] &= 0x7;
         }
@@ -345,7 +431,7 @@ impl<const RAM_LENGTH: usize> MockCameraBus<RAM_LENGTH> {
         recent_ops.truncate(RECENT_OPERATIONS_QUEUE_LENGTH);
     }
 
-    pub fn recent_operations(&self) -> Ref<VecDeque<I2cOperation>> {
+    pub fn recent_operations(&self) -> Ref<'_, VecDeque<I2cOperation>> {
         self.recent_operations.borrow()
     }
 
@@ -354,14 +440,76 @@ impl<const RAM_LENGTH: usize> MockCameraBus<RAM_LENGTH> {
     }
 }
 
-impl<const RAM_LENGTH: usize> i2c::Write for MockCameraBus<RAM_LENGTH> {
-    type Error = MockError;
+/// Fault injection, for testing how errors from the camera are handled.
+///
+/// Faults are shared between clones of a mock, like the rest of the mock's state, so they can be
+/// programmed after a clone has been given to a driver.
+impl<const RAM_LENGTH: usize> MockCameraBus<RAM_LENGTH> {
+    /// Fail `count` transactions with [`MockError::Nack`] after the next `operations` transactions.
+    ///
+    /// Use [`usize::MAX`] for `count` to keep failing until the faults are
+    /// [cleared][Self::clear_faults].
+    pub fn nack_after(&mut self, operations: usize, count: usize) {
+        self.faults.borrow_mut().nack_after(operations, count)
+    }
 
-    fn write(&mut self, i2c_address: u8, bytes: &[u8]) -> Result<(), Self::Error> {
+    /// Randomly flip bits in the data read from the camera.
+    ///
+    /// Each byte read has one bit flipped with the given `probability` (from 0 to 1). The
+    /// corruption is pseudo-random and repeatable for a given `seed`. The stored data is not
+    /// changed, only the bytes returned from reads.
+    pub fn corrupt_reads(&mut self, seed: u64, probability: f32) {
+        self.faults.borrow_mut().corrupt_reads(seed, probability)
+    }
+
+    /// Force the "new data available" flag to always read as the given value.
+    ///
+    /// `None` un-sticks the flag.
+    pub fn set_new_data_stuck(&mut self, stuck: Option<bool>) {
+        self.faults.borrow_mut().stick_new_data(stuck)
+    }
+
+    /// Hide each new frame for `status_reads` reads of the status register.
+    ///
+    /// After a new frame is made available (with [`update_frame`][Self::update_frame] or
+    /// [`set_data_available`][Self::set_data_available]), the "new data available" flag reads as
+    /// unset until the status register has been read `status_reads` times.
+    pub fn delay_frame_ready(&mut self, status_reads: usize) {
+        self.faults.borrow_mut().delay_ready(status_reads)
+    }
+
+    /// Flip the bits set in `mask` of the EEPROM word at `address`.
+    ///
+    /// Unlike the other faults, this changes the stored EEPROM data, and is not undone by
+    /// [`clear_faults`][Self::clear_faults] (flipping the same bits again will restore it).
+    pub fn flip_eeprom_bits(&mut self, address: Address, mask: u16) -> Result<(), MockError> {
+        let raw_address: u16 = address.into();
+        if !self.eeprom_range.contains(&raw_address) {
+            return Err(MockError::IllegalAccess(address));
+        }
+        let offset = (raw_address - self.eeprom_range.start()) as usize * 2;
+        let mut eeprom = self.eeprom_data.borrow_mut();
+        let word = &mut eeprom[offset..(offset + 2)];
+        let flipped = u16::from_be_bytes(word[..].try_into().unwrap()) ^ mask;
+        word.copy_from_slice(&flipped.to_be_bytes());
+        Ok(())
+    }
+
+    /// Remove all injected faults (except for EEPROM bit flips).
+    pub fn clear_faults(&mut self) {
+        *self.faults.borrow_mut() = Faults::default();
+    }
+}
+
+impl<const RAM_LENGTH: usize> MockCameraBus<RAM_LENGTH> {
+    fn write_bytes(&mut self, i2c_address: u8, bytes: &[u8]) -> Result<(), MockError> {
         if i2c_address != self.i2c_address {
             return Err(MockError::UnknownI2cAddress(i2c_address));
         }
         // Just writes can only happen to registers or EEPROM
+        if self.faults.borrow_mut().should_nack() {
+            return Err(MockError::Nack);
+        }
         let raw_address = self.extract_address(bytes)?;
         let address: Address = raw_address.into();
         let payload = &bytes[2..];
@@ -372,17 +520,13 @@ impl<const RAM_LENGTH: usize> i2c::Write for MockCameraBus<RAM_LENGTH> {
         });
         Ok(())
     }
-}
 
-impl<const RAM_LENGTH: usize> i2c::WriteRead for MockCameraBus<RAM_LENGTH> {
-    type Error = MockError;
-
-    fn write_read(
+    fn write_read_bytes(
         &mut self,
         i2c_address: u8,
         write_buffer: &[u8],
         out_buffer: &mut [u8],
-    ) -> Result<(), Self::Error> {
+    ) -> Result<(), MockError> {
         if i2c_address != self.i2c_address {
             return Err(MockError::UnknownI2cAddress(i2c_address));
         }
@@ -390,6 +534,10 @@ impl<const RAM_LENGTH: usize> i2c::WriteRead for MockCameraBus<RAM_LENGTH> {
         if write_buffer.len() != 2 || out_buffer.is_empty() {
             return Err(MockError::IllegalOperation);
         }
+        let mut faults = self.faults.borrow_mut();
+        if faults.should_nack() {
+            return Err(MockError::Nack);
+        }
         let raw_address = self.extract_address(write_buffer)?;
         let address: Address = raw_address.into();
         self.add_operation(I2cOperation::Read {
@@ -398,8 +546,86 @@ impl<const RAM_LENGTH: usize> i2c::WriteRead for MockCameraBus<RAM_LENGTH> {
         });
         let read_data = self.get(address, out_buffer.len())?;
         out_buffer.copy_from_slice(&read_data[..]);
+        if raw_address == STATUS_REGISTER_ADDRESS {
+            faults.status_read(out_buffer);
+        }
+        faults.corrupt(out_buffer);
         Ok(())
     }
+
+    fn run_transaction(
+        &mut self,
+        i2c_address: u8,
+        operations: &mut [i2c::Operation<'_>],
+    ) -> Result<(), MockError> {
+        // The cameras only support writes and write-reads, so only those shapes of transactions
+        // are supported.
+        match operations {
+            [i2c::Operation::Write(bytes)] => self.write_bytes(i2c_address, bytes),
+            [i2c::Operation::Write(write_buffer), i2c::Operation::Read(out_buffer)] => {
+                self.write_read_bytes(i2c_address, write_buffer, out_buffer)
+            }
+            _ => Err(MockError::IllegalOperation),
+        }
+    }
+}
+
+impl i2c::Error for MockError {
+    fn kind(&self) -> i2c::ErrorKind {
+        match self {
+            MockError::UnknownI2cAddress(_) => {
+                i2c::ErrorKind::NoAcknowledge(i2c::NoAcknowledgeSource::Address)
+            }
+            MockError::Nack => i2c::ErrorKind::NoAcknowledge(i2c::NoAcknowledgeSource::Unknown),
+            _ => i2c::ErrorKind::Other,
+        }
+    }
+}
+
+impl<const RAM_LENGTH: usize> i2c::ErrorType for MockCameraBus<RAM_LENGTH> {
+    type Error = MockError;
+}
+
+impl<const RAM_LENGTH: usize> i2c::I2c for MockCameraBus<RAM_LENGTH> {
+    fn transaction(
+        &mut self,
+        i2c_address: u8,
+        operations: &mut [i2c::Operation<'_>],
+    ) -> Result<(), Self::Error> {
+        self.run_transaction(i2c_address, operations)
+    }
+}
+
+impl<const RAM_LENGTH: usize> embedded_hal_async::i2c::I2c for MockCameraBus<RAM_LENGTH> {
+    async fn transaction(
+        &mut self,
+        i2c_address: u8,
+        operations: &mut [i2c::Operation<'_>],
+    ) -> Result<(), Self::Error> {
+        self.run_transaction(i2c_address, operations)
+    }
+}
+
+// The embedded-hal 0.2 traits are kept for testing the compatibility adapter in mlx9064x.
+impl<const RAM_LENGTH: usize> i2c_0_2::Write for MockCameraBus<RAM_LENGTH> {
+    type Error = MockError;
+
+    fn write(&mut self, i2c_address: u8, bytes: &[u8]) -> Result<(), Self::Error> {
+        self.write_bytes(i2c_address, bytes)
+    }
+}
+
+impl<const RAM_LENGTH: usize> i2c_0_2::WriteRead for MockCameraBus<RAM_LENGTH> {
+    type Error = MockError;
+
+    fn write_read(
+        &mut self,
+        i2c_address: u8,
+        write_buffer: &[u8],
+        out_buffer: &mut [u8],
+    ) -> Result<(), Self::Error> {
+        self.write_read_bytes(i2c_address, write_buffer, out_buffer)
+    }
 }
 
 fn check_new_against_mask(existing: u16, mask: u16, new: u16) -> bool {
@@ -478,19 +704,19 @@ pub fn mock_mlx90641_at_address(i2c_address: u8) -> MockCameraBus<MLX90641_RAM_L
         eeprom_range: 0x2400..=0x273F,
         register_range: 0x8000..=0x8016,
         eeprom_data: Rc::new(RefCell::new(eeprom_data)),
+        factory_eeprom: Rc::new(eeprom_data),
+        eeprom_write_masks: &MLX90641_EEPROM_WRITE_MASKS,
         ram_data: Rc::new(RefCell::new(ram_data)),
         // The worked example uses subpage 0, so mark that as the current subpage with new data.
         status_register: Rc::new(RefCell::new([0x00, 0x08])),
         control_register: Rc::new(RefCell::new([0x09, 0x01])),
         i2c_config_register: Rc::new(RefCell::new([0x00, 0x00])),
         recent_operations: Rc::new(RefCell::new(VecDeque::new())),
+        faults: Rc::new(RefCell::new(Faults::default())),
+        step_measurements: Rc::new(Cell::new(0)),
     }
 }
 
-pub fn mock_mlx90642_at_address(i2c_address: u8) -> MockCameraBus<MLX90642_RAM_LENGTH> {
-    mock_mlx90641_at_address(i2c_address)
-}
-
 pub fn example_mlx90640_at_address(i2c_address: u8) -> MockCameraBus<MLX90640_RAM_LENGTH> {
     MockCameraBus::new_mlx90640(
         i2c_address,
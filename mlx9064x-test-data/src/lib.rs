@@ -1,14 +1,18 @@
 // SPDX-License-Identifier: Apache-2.0
 // Copyright © 2021 Will Ross
+mod compare;
 mod eeprom_data;
+mod faults;
 mod i2c_mock;
 pub mod mlx90640_example_data;
+mod mlx90642_mock;
+mod shared_bus;
 
-pub use eeprom_data::{
-    mlx90640_datasheet_eeprom, mlx90641_datasheet_eeprom, mlx90642_datasheet_eeprom, EEPROM_LENGTH,
-};
+pub use compare::compare_images;
+pub use eeprom_data::{mlx90640_datasheet_eeprom, mlx90641_datasheet_eeprom, EEPROM_LENGTH};
 pub use i2c_mock::{
     datasheet_mlx90640_at_address, example_mlx90640_at_address, mock_mlx90641_at_address,
-    mock_mlx90642_at_address, MockCameraBus, MLX90640_RAM_LENGTH, MLX90641_RAM_LENGTH,
-    MLX90642_RAM_LENGTH,
+    I2cOperation, MockCameraBus, MockError, MLX90640_RAM_LENGTH, MLX90641_RAM_LENGTH,
 };
+pub use mlx90642_mock::{mock_mlx90642_at_address, MockMlx90642Bus};
+pub use shared_bus::SharedMockBus;
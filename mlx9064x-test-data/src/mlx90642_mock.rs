@@ -0,0 +1,292 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright © 2021 Will Ross
+extern crate alloc;
+
+use alloc::rc::Rc;
+use core::cell::RefCell;
+
+use arrayvec::ArrayVec;
+use embedded_hal::i2c;
+use mlx9064x::mlx90642::{self, Command, Mlx90642, RamAddress};
+use mlx9064x::Address;
+
+use super::i2c_mock::MockError;
+
+/// The device ID reported by the mock MLX90642.
+const DEVICE_ID: [u16; 3] = [0x1A2B, 0x3C4D, 0x5E6F];
+
+const DATA_READY_MASK: u16 = 0x0001;
+
+#[derive(Clone, Debug)]
+struct State {
+    refresh_rate: u16,
+    emissivity: u16,
+    reflected_temperature: u16,
+    measurement_mode: u16,
+    temperatures: [u16; Mlx90642::NUM_PIXELS],
+    ambient_temperature: u16,
+    status: u16,
+    asleep: bool,
+    measurements: usize,
+}
+
+impl State {
+    fn read_word(&self, address: u16) -> Result<u16, MockError> {
+        let temperature_start: u16 = RamAddress::ObjectTemperatureStart.into();
+        let temperature_end: u16 = RamAddress::ObjectTemperatureEnd.into();
+        let device_id_start: u16 = RamAddress::DeviceId.into();
+        if (temperature_start..=temperature_end).contains(&address) {
+            return Ok(self.temperatures[(address - temperature_start) as usize]);
+        }
+        if (device_id_start..(device_id_start + DEVICE_ID.len() as u16)).contains(&address) {
+            return Ok(DEVICE_ID[(address - device_id_start) as usize]);
+        }
+        match RamAddress::try_from(address) {
+            Ok(RamAddress::RefreshRate) => Ok(self.refresh_rate),
+            Ok(RamAddress::Emissivity) => Ok(self.emissivity),
+            Ok(RamAddress::ReflectedTemperature) => Ok(self.reflected_temperature),
+            Ok(RamAddress::MeasurementMode) => Ok(self.measurement_mode),
+            Ok(RamAddress::AmbientTemperature) => Ok(self.ambient_temperature),
+            Ok(RamAddress::Status) => Ok(self.status),
+            _ => Err(MockError::IllegalAccess(address.into())),
+        }
+    }
+
+    fn configure(&mut self, address: u16, value: u16) -> Result<(), MockError> {
+        let illegal_value = MockError::IllegalWriteValue(address.into(), value);
+        match RamAddress::try_from(address) {
+            Ok(RamAddress::RefreshRate) if value <= 7 => self.refresh_rate = value,
+            Ok(RamAddress::Emissivity) if value > 0 && value <= 0x4000 => self.emissivity = value,
+            Ok(RamAddress::ReflectedTemperature) => self.reflected_temperature = value,
+            Ok(RamAddress::MeasurementMode) if value <= 1 => self.measurement_mode = value,
+            // Only the data ready flag can be cleared by the controller.
+            Ok(RamAddress::Status) if value & DATA_READY_MASK == 0 => {
+                self.status &= !DATA_READY_MASK
+            }
+            Ok(RamAddress::Status) => (),
+            Ok(RamAddress::RefreshRate)
+            | Ok(RamAddress::Emissivity)
+            | Ok(RamAddress::MeasurementMode) => return Err(illegal_value),
+            _ => return Err(MockError::IllegalWriteAddress(address.into())),
+        }
+        Ok(())
+    }
+
+    fn command(&mut self, command: Command) {
+        match command {
+            Command::StartMeasurement => {
+                // Measurements are instantaneous for the mock.
+                self.measurements += 1;
+                self.status |= DATA_READY_MASK;
+            }
+            Command::Sleep => self.asleep = true,
+            Command::WakeUp => self.asleep = false,
+            // Configuration commands always have a payload.
+            Command::Configure => (),
+        }
+    }
+}
+
+/// A mock I²C bus with an MLX90642 on it.
+///
+/// Unlike [`MockCameraBus`][crate::MockCameraBus], this mock implements the MLX90642's command
+/// protocol. Clones share the same camera, so one can be given to a driver while the test keeps
+/// another to change the scene.
+#[derive(Clone, Debug)]
+pub struct MockMlx90642Bus {
+    i2c_address: u8,
+    state: Rc<RefCell<State>>,
+}
+
+impl MockMlx90642Bus {
+    /// Make a new frame available, with the given object and ambient temperatures (in degrees
+    /// Celsius).
+    ///
+    /// # Panics
+    /// If any temperature cannot be represented by the camera.
+    pub fn update_frame(&mut self, temperatures: &[f32], ambient_temperature: f32) {
+        let mut state = self.state.borrow_mut();
+        for (raw, temperature) in state.temperatures.iter_mut().zip(temperatures.iter()) {
+            *raw = mlx90642::temperature_to_raw(*temperature).unwrap() as u16;
+        }
+        state.ambient_temperature =
+            mlx90642::temperature_to_raw(ambient_temperature).unwrap() as u16;
+        state.status |= DATA_READY_MASK;
+    }
+
+    /// Set (or clear) the data ready flag.
+    pub fn set_data_available(&mut self, available: bool) {
+        let mut state = self.state.borrow_mut();
+        if available {
+            state.status |= DATA_READY_MASK;
+        } else {
+            state.status &= !DATA_READY_MASK;
+        }
+    }
+
+    /// The device ID the mock reports.
+    pub fn device_id(&self) -> [u16; 3] {
+        DEVICE_ID
+    }
+
+    /// How many measurements have been started with [`Command::StartMeasurement`].
+    pub fn measurements(&self) -> usize {
+        self.state.borrow().measurements
+    }
+
+    /// Whether the camera has been put to sleep.
+    pub fn is_asleep(&self) -> bool {
+        self.state.borrow().asleep
+    }
+
+    fn write_bytes(&mut self, i2c_address: u8, bytes: &[u8]) -> Result<(), MockError> {
+        if i2c_address != self.i2c_address {
+            return Err(MockError::UnknownI2cAddress(i2c_address));
+        }
+        // Commands are a single word, configuration writes are three.
+        if bytes.len() != 2 && bytes.len() != 6 {
+            return Err(MockError::IllegalOperation);
+        }
+        let words: ArrayVec<u16, 3> = bytes
+            .chunks_exact(2)
+            .map(|word| u16::from_be_bytes([word[0], word[1]]))
+            .collect();
+        let mut state = self.state.borrow_mut();
+        let command = Command::try_from(words[0]).map_err(|_| MockError::IllegalOperation)?;
+        // Nothing but waking up is acknowledged while asleep.
+        if state.asleep && command != Command::WakeUp {
+            return Err(MockError::Nack);
+        }
+        match (command, &words[1..]) {
+            (Command::Configure, [address, value]) => state.configure(*address, *value),
+            (Command::Configure, _) => Err(MockError::IllegalOperation),
+            (command, []) => {
+                state.command(command);
+                Ok(())
+            }
+            _ => Err(MockError::IllegalOperation),
+        }
+    }
+
+    fn write_read_bytes(
+        &mut self,
+        i2c_address: u8,
+        write_buffer: &[u8],
+        out_buffer: &mut [u8],
+    ) -> Result<(), MockError> {
+        if i2c_address != self.i2c_address {
+            return Err(MockError::UnknownI2cAddress(i2c_address));
+        }
+        if write_buffer.len() != 2 || out_buffer.is_empty() || out_buffer.len() % 2 != 0 {
+            return Err(MockError::IllegalOperation);
+        }
+        let state = self.state.borrow();
+        if state.asleep {
+            return Err(MockError::Nack);
+        }
+        let start = u16::from_be_bytes([write_buffer[0], write_buffer[1]]);
+        for (offset, word) in out_buffer.chunks_exact_mut(2).enumerate() {
+            let address = start
+                .checked_add(offset as u16)
+                .ok_or(MockError::IllegalAccess(Address::new(start)))?;
+            word.copy_from_slice(&state.read_word(address)?.to_be_bytes());
+        }
+        Ok(())
+    }
+
+    fn run_transaction(
+        &mut self,
+        i2c_address: u8,
+        operations: &mut [i2c::Operation<'_>],
+    ) -> Result<(), MockError> {
+        match operations {
+            [i2c::Operation::Write(bytes)] => self.write_bytes(i2c_address, bytes),
+            [i2c::Operation::Write(write_buffer), i2c::Operation::Read(out_buffer)] => {
+                self.write_read_bytes(i2c_address, write_buffer, out_buffer)
+            }
+            _ => Err(MockError::IllegalOperation),
+        }
+    }
+}
+
+impl i2c::ErrorType for MockMlx90642Bus {
+    type Error = MockError;
+}
+
+impl i2c::I2c for MockMlx90642Bus {
+    fn transaction(
+        &mut self,
+        i2c_address: u8,
+        operations: &mut [i2c::Operation<'_>],
+    ) -> Result<(), Self::Error> {
+        self.run_transaction(i2c_address, operations)
+    }
+}
+
+impl embedded_hal_async::i2c::I2c for MockMlx90642Bus {
+    async fn transaction(
+        &mut self,
+        i2c_address: u8,
+        operations: &mut [i2c::Operation<'_>],
+    ) -> Result<(), Self::Error> {
+        self.run_transaction(i2c_address, operations)
+    }
+}
+
+/// Create a mock MLX90642 at the given address, with the camera's default configuration.
+pub fn mock_mlx90642_at_address(i2c_address: u8) -> MockMlx90642Bus {
+    MockMlx90642Bus {
+        i2c_address,
+        state: Rc::new(RefCell::new(State {
+            // 2 FPS
+            refresh_rate: 0x02,
+            emissivity: 0x4000,
+            reflected_temperature: 0,
+            measurement_mode: 0,
+            temperatures: [0; Mlx90642::NUM_PIXELS],
+            ambient_temperature: 0,
+            status: 0,
+            asleep: false,
+            measurements: 0,
+        })),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use embedded_hal::i2c::I2c;
+
+    use super::*;
+
+    const ADDRESS: u8 = mlx90642::DEFAULT_ADDRESS;
+
+    #[test]
+    fn illegal_accesses() {
+        let mut mocked = mock_mlx90642_at_address(ADDRESS);
+        let mut buffer = [0u8; 2];
+        assert!(matches!(
+            mocked.write_read(ADDRESS, &[0x04, 0x00], &mut buffer),
+            Err(MockError::IllegalAccess(_))
+        ));
+        // Reading past the end of the temperatures
+        let mut buffer = [0u8; 4];
+        assert!(matches!(
+            mocked.write_read(ADDRESS, &[0x37, 0x2B], &mut buffer),
+            Err(MockError::IllegalAccess(_))
+        ));
+        // Unknown command
+        assert!(matches!(
+            mocked.write(ADDRESS, &[0x12, 0x34]),
+            Err(MockError::IllegalOperation)
+        ));
+        // The ambient temperature isn't configurable
+        assert!(matches!(
+            mocked.write(ADDRESS, &[0x3A, 0x2E, 0x3A, 0x2C, 0x00, 0x00]),
+            Err(MockError::IllegalWriteAddress(_))
+        ));
+        assert!(matches!(
+            mocked.write(ADDRESS, &[0x3A, 0x2E, 0x11, 0xF2, 0x00, 0x08]),
+            Err(MockError::IllegalWriteValue(_, 8))
+        ));
+    }
+}
@@ -0,0 +1,68 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright © 2021 Will Ross
+use embedded_hal::i2c;
+
+use super::i2c_mock::MockError;
+
+/// Two mock devices on the same I²C bus.
+///
+/// Transactions go to the first device, unless it doesn't have the requested address, in which
+/// case they go to the second. More devices can be added by nesting `SharedMockBus`es.
+#[derive(Clone, Debug)]
+pub struct SharedMockBus<A, B> {
+    first: A,
+    second: B,
+}
+
+impl<A, B> SharedMockBus<A, B> {
+    pub fn new(first: A, second: B) -> Self {
+        Self { first, second }
+    }
+}
+
+impl<A, B> i2c::ErrorType for SharedMockBus<A, B> {
+    type Error = MockError;
+}
+
+impl<A, B> i2c::I2c for SharedMockBus<A, B>
+where
+    A: i2c::I2c<Error = MockError>,
+    B: i2c::I2c<Error = MockError>,
+{
+    fn transaction(
+        &mut self,
+        i2c_address: u8,
+        operations: &mut [i2c::Operation<'_>],
+    ) -> Result<(), Self::Error> {
+        match self.first.transaction(i2c_address, operations) {
+            Err(MockError::UnknownI2cAddress(_)) => {
+                self.second.transaction(i2c_address, operations)
+            }
+            result => result,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use embedded_hal::i2c::I2c;
+
+    use crate::{example_mlx90640_at_address, mock_mlx90641_at_address};
+
+    use super::*;
+
+    #[test]
+    fn routing() {
+        let mut bus = SharedMockBus::new(
+            example_mlx90640_at_address(0x33),
+            mock_mlx90641_at_address(0x34),
+        );
+        let mut buffer = [0u8; 2];
+        assert!(bus.write_read(0x33, &[0x80, 0x0D], &mut buffer).is_ok());
+        assert!(bus.write_read(0x34, &[0x80, 0x0D], &mut buffer).is_ok());
+        assert!(matches!(
+            bus.write_read(0x35, &[0x80, 0x0D], &mut buffer),
+            Err(MockError::UnknownI2cAddress(0x35))
+        ));
+    }
+}
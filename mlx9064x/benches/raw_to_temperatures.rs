@@ -1,5 +1,5 @@
+use std::cell::RefCell;
 use std::ops::DerefMut;
-use std::sync::{Arc, Mutex};
 
 use criterion::{criterion_group, criterion_main, BatchSize, Criterion};
 
@@ -7,6 +7,9 @@ use mlx9064x::calculations::{
     raw_ir_to_temperatures, raw_pixels_to_ir_data, raw_pixels_to_temperatures,
 };
 use mlx9064x::common::{read_ram, FromI2C, MelexisCamera};
+use mlx9064x::compiled::CompiledCalibration;
+#[cfg(feature = "fixed-point")]
+use mlx9064x::fixed_point::FixedPointCalibration;
 use mlx9064x::mlx90640::{Mlx90640, Mlx90640Calibration};
 use mlx9064x::{AccessPattern, CalibrationData, ControlRegister, StatusRegister, Subpage};
 use mlx9064x_test_data::{example_mlx90640_at_address, mlx90640_example_data};
@@ -22,11 +25,11 @@ pub fn criterion_benchmark(c: &mut Criterion) {
     let emissivity = calibration.emissivity().unwrap_or(1f32);
     let access_pattern = AccessPattern::Chess;
 
-    let mocked = Arc::new(Mutex::new(mocked));
+    let mocked = RefCell::new(mocked);
     let mut group = c.benchmark_group("Pixels to Temperatures");
 
     let setup = || {
-        let mut bus_guard = mocked.lock().unwrap();
+        let mut bus_guard = mocked.borrow_mut();
         let bus = bus_guard.deref_mut();
         let status = StatusRegister::from_i2c(bus, address).unwrap();
         let subpage = status.last_updated_subpage();
@@ -71,6 +74,7 @@ pub fn criterion_benchmark(c: &mut Criterion) {
                     resolution_correction,
                     &pixel_bytes,
                     ram_data,
+                    None,
                     subpage,
                     access_pattern,
                     &mut valid_pixels.clone(),
@@ -101,6 +105,52 @@ pub fn criterion_benchmark(c: &mut Criterion) {
                     resolution_correction,
                     &pixel_bytes,
                     ram_data,
+                    None,
+                    subpage,
+                    access_pattern,
+                    &mut valid_pixels,
+                    &mut temperatures,
+                );
+            },
+            BatchSize::SmallInput,
+        )
+    });
+
+    let compiled =
+        CompiledCalibration::<{ Mlx90640::NUM_PIXELS }>::new(&calibration, access_pattern);
+    group.bench_function("compiled", |b| {
+        b.iter_batched(
+            setup,
+            |(subpage, pixel_bytes, mut temperatures, _, ram_data)| {
+                let _ambient_temperature = compiled.raw_pixels_to_temperatures(
+                    &calibration,
+                    emissivity,
+                    None,
+                    resolution_correction,
+                    &pixel_bytes,
+                    ram_data,
+                    None,
+                    subpage,
+                    &mut temperatures,
+                );
+            },
+            BatchSize::SmallInput,
+        )
+    });
+
+    #[cfg(feature = "simd")]
+    group.bench_function("simd", |b| {
+        b.iter_batched(
+            setup,
+            |(subpage, pixel_bytes, mut temperatures, mut valid_pixels, ram_data)| {
+                let _ambient_temperature = mlx9064x::simd::raw_pixels_to_temperatures(
+                    &calibration,
+                    emissivity,
+                    None,
+                    resolution_correction,
+                    &pixel_bytes,
+                    ram_data,
+                    None,
                     subpage,
                     access_pattern,
                     &mut valid_pixels,
@@ -110,6 +160,33 @@ pub fn criterion_benchmark(c: &mut Criterion) {
             BatchSize::SmallInput,
         )
     });
+
+    #[cfg(feature = "fixed-point")]
+    {
+        let fixed =
+            FixedPointCalibration::<{ Mlx90640::NUM_PIXELS }>::new(&calibration, access_pattern);
+        group.bench_function("fixed point", |b| {
+            b.iter_batched(
+                setup,
+                |(subpage, pixel_bytes, _, mut valid_pixels, ram_data)| {
+                    let mut temperatures = [0i32; Mlx90640::NUM_PIXELS];
+                    let _ambient_temperature = fixed.raw_pixels_to_temperatures(
+                        &calibration,
+                        emissivity,
+                        None,
+                        resolution_correction,
+                        &pixel_bytes,
+                        ram_data,
+                        None,
+                        subpage,
+                        &mut valid_pixels,
+                        &mut temperatures,
+                    );
+                },
+                BatchSize::SmallInput,
+            )
+        });
+    }
 }
 
 criterion_group!(benches, criterion_benchmark);
@@ -5,107 +5,58 @@ use std::thread::sleep;
 use std::time::Duration;
 
 use linux_embedded_hal::I2cdev;
-use mlx9064x::{Mlx90640Driver, Mlx90641Driver, Mlx90642Driver};
+use mlx9064x::compat::Compat;
+use mlx9064x::palette::{Palette, Renderer};
+use mlx9064x::AnyCameraDriver;
 
 fn main() -> Result<(), AnyError> {
     let args: Vec<String> = env::args().collect();
-    if args.len() != 4 {
+    if args.len() != 3 {
         return Err(AnyError::String(
-            "Three arguments required: [640|641|642] <I2C bus> <camera address>".to_string(),
+            "Two arguments required: <I2C bus> <camera address>".to_string(),
         ));
     }
-    let address: u8 = if args[3].starts_with("0x") {
-        let hex_digits = args[3].split_at(2).1;
-        u8::from_str_radix(&hex_digits, 16)?
+    let address: u8 = if args[2].starts_with("0x") {
+        let hex_digits = args[2].split_at(2).1;
+        u8::from_str_radix(hex_digits, 16)?
     } else {
-        args[3].parse()?
+        args[2].parse()?
     };
-    let bus_path = Path::new(&args[2]);
-    let bus = I2cdev::new(bus_path)?;
-    let (temperatures, width) = match args[1].as_ref() {
-        "640" => {
-            let mut camera = Mlx90640Driver::new(bus, address)?;
-            let mut temperatures = vec![0f32; camera.height() * camera.width()];
-            let delay = Duration::from_millis(500);
-            wait_for_frame(
-                || {
-                    camera
-                        .generate_image_if_ready(&mut temperatures)
-                        .map_err(AnyError::from)
-                },
-                delay,
-            )?;
-            wait_for_frame(
-                || {
-                    camera
-                        .generate_image_if_ready(&mut temperatures)
-                        .map_err(AnyError::from)
-                },
-                delay,
-            )?;
-            (temperatures, camera.width())
-        }
-        "641" => {
-            let mut camera = Mlx90641Driver::new(bus, address)?;
-            let mut temperatures = vec![0f32; camera.height() * camera.width()];
-            let delay = Duration::from_millis(500);
-            wait_for_frame(
-                || {
-                    camera
-                        .generate_image_if_ready(&mut temperatures)
-                        .map_err(AnyError::from)
-                },
-                delay,
-            )?;
-            wait_for_frame(
-                || {
-                    camera
-                        .generate_image_if_ready(&mut temperatures)
-                        .map_err(AnyError::from)
-                },
-                delay,
-            )?;
-            (temperatures, camera.width())
-        }
-        "642" => {
-            let mut camera = Mlx90642Driver::new(bus, address)?;
-            let mut temperatures = vec![0f32; camera.height() * camera.width()];
-            let delay = Duration::from_millis(500);
-            wait_for_frame(
-                || {
-                    camera
-                        .generate_image_if_ready(&mut temperatures)
-                        .map_err(AnyError::from)
-                },
-                delay,
-            )?;
-            wait_for_frame(
-                || {
-                    camera
-                        .generate_image_if_ready(&mut temperatures)
-                        .map_err(AnyError::from)
-                },
-                delay,
-            )?;
-            (temperatures, camera.width())
-        }
-        _ => {
-            return Err(AnyError::String(
-                "The second argument must be 640, 641, or 642".to_string(),
-            ));
-        }
-    };
-    print_temperatures(&temperatures, width);
+    let bus_path = Path::new(&args[1]);
+    let bus = Compat::new(I2cdev::new(bus_path)?);
+    let mut camera = AnyCameraDriver::new(bus, address)?;
+    println!("Found {:?}", camera.model());
+    let mut temperatures = vec![0f32; camera.height() * camera.width()];
+    let delay = Duration::from_millis(500);
+    wait_for_frame(
+        || {
+            camera
+                .generate_full_frame_if_ready(&mut temperatures)
+                .map_err(AnyError::from)
+        },
+        delay,
+    )?;
+    print_temperatures(&temperatures, camera.width());
     println!();
     Ok(())
 }
 
 fn print_temperatures(temperatures: &[f32], width: usize) {
-    for (count, temperature) in temperatures.iter().enumerate() {
+    // Color the background of each temperature, using 24-bit terminal colors.
+    let mut colors = vec![0u8; temperatures.len() * 3];
+    Renderer::new(Palette::Ironbow).render_rgb888(temperatures, &mut colors);
+    for (count, (temperature, color)) in temperatures.iter().zip(colors.chunks_exact(3)).enumerate()
+    {
         if count % width == 0 {
             println!();
         }
-        print!("{:4.2}  ", temperature);
+        // Use dark text on the lighter half of the palette.
+        let brightness = color.iter().map(|channel| u16::from(*channel)).sum::<u16>();
+        let text = if brightness > 384 { 30 } else { 97 };
+        print!(
+            "\x1b[{};48;2;{};{};{}m{:6.2} \x1b[0m",
+            text, color[0], color[1], color[2], temperature
+        );
     }
 }
 
@@ -1,59 +1,40 @@
 use std::convert::TryInto;
 use std::env;
 use std::error::Error as StdError;
-use std::fmt;
 use std::path::Path;
 use std::time::{Duration, Instant};
 
-use embedded_hal::blocking::i2c::{Write, WriteRead};
 use linux_embedded_hal::I2cdev;
-use mlx9064x::{CameraDriver, Mlx90640Driver, Mlx90641Driver, Mlx90642Driver};
+use mlx9064x::compat::Compat;
+use mlx9064x::AnyCameraDriver;
 
 fn main() -> Result<(), AnyError> {
     let args: Vec<String> = env::args().collect();
-    if args.len() < 5 || args.len() > 6 {
+    if args.len() < 4 || args.len() > 5 {
         return Err(AnyError::String(
-            "Four arguments required: [640|641|642] <I2C bus> <camera address> <frame rate> [num_frames]"
+            "Three arguments required: <I2C bus> <camera address> <frame rate> [num_frames]"
                 .to_string(),
         ));
     }
-    let address: u8 = if args[3].starts_with("0x") {
-        let hex_digits = args[3].split_at(2).1;
-        u8::from_str_radix(&hex_digits, 16)?
+    let address: u8 = if args[2].starts_with("0x") {
+        let hex_digits = args[2].split_at(2).1;
+        u8::from_str_radix(hex_digits, 16)?
     } else {
-        args[3].parse()?
+        args[2].parse()?
     };
-    let bus_path = Path::new(&args[2]);
-    let bus = I2cdev::new(bus_path)?;
-    let frame_rate_num: f32 = args[4].parse()?;
-    let num_frames: usize = if args.len() < 6 {
+    let bus_path = Path::new(&args[1]);
+    let bus = Compat::new(I2cdev::new(bus_path)?);
+    let frame_rate_num: f32 = args[3].parse()?;
+    let num_frames: usize = if args.len() < 5 {
         (frame_rate_num * 10f32) as usize
     } else {
-        args[5].parse()?
+        args[4].parse()?
     };
+    let mut camera = AnyCameraDriver::new(bus, address)?;
+    println!("Found {:?}", camera.model());
+    camera.set_frame_rate(frame_rate_num.try_into()?)?;
     println!("Starting measurements.");
-    let instants = match args[1].as_ref() {
-        "640" => {
-            let mut camera = Mlx90640Driver::new(bus, address)?;
-            camera.set_frame_rate(frame_rate_num.try_into()?)?;
-            find_frequency(&mut camera, num_frames)?
-        }
-        "641" => {
-            let mut camera = Mlx90641Driver::new(bus, address)?;
-            camera.set_frame_rate(frame_rate_num.try_into()?)?;
-            find_frequency(&mut camera, num_frames)?
-        }
-        "642" => {
-            let mut camera = Mlx90642Driver::new(bus, address)?;
-            camera.set_frame_rate(frame_rate_num.try_into()?)?;
-            find_frequency(&mut camera, num_frames)?
-        }
-        _ => {
-            return Err(AnyError::String(
-                "The second argument must be 640, 641, or 642".to_string(),
-            ));
-        }
-    };
+    let instants = find_frequency(&mut camera, num_frames)?;
     // Find the duration between each instant, then calculate some statistics on those durations.
     let durations: Vec<Duration> = instants
         .windows(2)
@@ -79,7 +60,7 @@ fn main() -> Result<(), AnyError> {
     println!("{}", as_frequency(&mean_duration));
     let middle = sorted_durations.len() / 2;
     let median = if sorted_durations.len() % 2 == 0 {
-        sorted_durations[middle..middle + 1]
+        sorted_durations[middle - 1..middle + 1]
             .iter()
             .sum::<Duration>()
             / 2
@@ -116,37 +97,16 @@ fn main() -> Result<(), AnyError> {
     Ok(())
 }
 
-fn find_frequency<
-    'a,
-    Cam,
-    Clb,
-    I2C,
-    const HEIGHT: usize,
-    const WIDTH: usize,
-    const NUM_BYTES: usize,
->(
-    driver: &mut CameraDriver<Cam, Clb, I2C, HEIGHT, WIDTH, NUM_BYTES>,
+fn find_frequency(
+    driver: &mut AnyCameraDriver<Compat<I2cdev>>,
     num_frames: usize,
-) -> Result<Vec<Instant>, AnyError>
-where
-    Cam: mlx9064x::common::MelexisCamera,
-    Clb: mlx9064x::common::CalibrationData<'a>,
-    I2C: Write + WriteRead + 'static,
-    <I2C as WriteRead>::Error: 'static + StdError + fmt::Debug + Sync + Send,
-    <I2C as Write>::Error: 'static + StdError + fmt::Debug + Sync + Send,
-{
-    let mut results = Vec::new();
-    results.reserve(num_frames);
-    let mut last_subpage = driver.last_measured_subpage()?;
+) -> Result<Vec<Instant>, AnyError> {
+    let mut results = Vec::with_capacity(num_frames);
+    driver.reset_data_available()?;
     for _ in 0..num_frames {
-        loop {
-            let current_subpage = driver.last_measured_subpage()?;
-            if current_subpage != last_subpage {
-                results.push(Instant::now());
-                last_subpage = current_subpage;
-                break;
-            }
-        }
+        while !driver.data_available()? {}
+        results.push(Instant::now());
+        driver.reset_data_available()?;
     }
     Ok(results)
 }
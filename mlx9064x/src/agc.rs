@@ -0,0 +1,391 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright © 2021 Will Ross
+
+//! Automatic gain control (AGC) for turning images into grayscale.
+//!
+//! Machine vision usually wants a grayscale image with a fixed number of levels instead of
+//! temperatures, and the datasheets suggest stopping at the $V_{IR}$ data (from
+//! [`raw_pixels_to_ir_data`]) if actual temperatures aren't needed. [`Agc`] converts either kind
+//! of image to 8-bit or 16-bit grayscale, choosing the range of values to use for each image:
+//!
+//! * The range starts as the lowest and highest values in the image, but a fraction of the pixels
+//!   at each end can be [clipped][Agc::low_clip] so a few very hot or cold pixels don't squash the
+//!   rest of the image into a handful of levels.
+//! * The values in the range are then either mapped [linearly][Mapping::Linear] to the output
+//!   levels, or spread out with [plateau histogram equalization][Mapping::PlateauEqualization].
+//! * The range can be [smoothed][Agc::smoothing] between images, so the brightness of the image
+//!   doesn't flicker as objects enter and leave the scene.
+//!
+//! Everything is done with a fixed-size histogram of [`BINS`] bins, so no allocation is needed.
+//!
+//! ```
+//! use mlx9064x::agc::{Agc, Mapping};
+//!
+//! let mut agc = Agc::new(Mapping::Linear);
+//! let temperatures = [20.0, 25.0, 30.0];
+//! let mut gray = [0u8; 3];
+//! let range = agc.process_u8(&temperatures, &mut gray);
+//! assert_eq!(range, Some((20.0, 30.0)));
+//! assert_eq!(gray, [0, 128, 255]);
+//! ```
+//!
+//! [`raw_pixels_to_ir_data`]: crate::calculations::raw_pixels_to_ir_data
+#![doc = include_str!("katex.html")]
+
+/// The number of bins in the histograms used for clipping and equalization.
+pub const BINS: usize = 256;
+
+/// How the values in the range are mapped to the output levels.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Mapping {
+    /// Map the range linearly, with the lowest value being black and the highest white.
+    Linear,
+
+    /// [Histogram equalization][equalization], with the histogram bins limited to `plateau`
+    /// pixels.
+    ///
+    /// Equalization spreads the output levels out so that each level has roughly the same number
+    /// of pixels, which brings out detail in the most common temperatures. Without a limit, large
+    /// areas of similar temperature (like a background) take up most of the output levels, so the
+    /// number of pixels counted in each of the [`BINS`] histogram bins is capped at `plateau`.
+    /// Lower values are closer to a linear mapping, while very high values are plain histogram
+    /// equalization.
+    ///
+    /// [equalization]: https://en.wikipedia.org/wiki/Histogram_equalization
+    PlateauEqualization { plateau: u32 },
+}
+
+/// A histogram of the values in an image between `low` and `high`.
+struct Histogram {
+    bins: [u32; BINS],
+    low: f32,
+    bin_width: f32,
+}
+
+impl Histogram {
+    /// Build a histogram, with values outside of the range counted in the first or last bins.
+    fn new(image: &[f32], low: f32, high: f32) -> Self {
+        let mut histogram = Self {
+            bins: [0; BINS],
+            low,
+            bin_width: (high - low) / BINS as f32,
+        };
+        for value in image.iter().filter(|value| !value.is_nan()) {
+            histogram.bins[histogram.bin(*value)] += 1;
+        }
+        histogram
+    }
+
+    /// The bin a value falls into.
+    fn bin(&self, value: f32) -> usize {
+        if self.bin_width > 0f32 {
+            // Negative values saturate to 0 when casting
+            (((value - self.low) / self.bin_width) as usize).min(BINS - 1)
+        } else {
+            0
+        }
+    }
+
+    /// The value that `fraction` of the pixels are below.
+    ///
+    /// The pixels are assumed to be spread evenly within each bin.
+    fn percentile(&self, fraction: f32) -> f32 {
+        let total: u32 = self.bins.iter().sum();
+        let target = fraction.clamp(0f32, 1f32) * total as f32;
+        let mut below = 0f32;
+        for (index, count) in self.bins.iter().enumerate() {
+            let count = *count as f32;
+            if count > 0f32 && below + count >= target {
+                let within = (target - below) / count;
+                return self.low + (index as f32 + within) * self.bin_width;
+            }
+            below += count;
+        }
+        self.low + BINS as f32 * self.bin_width
+    }
+}
+
+/// Automatic gain control, converting images to grayscale.
+///
+/// The settings are public fields, while the smoothed range is kept between images. If the scene
+/// changes completely (for example when switching between $V_{IR}$ data and temperatures), call
+/// [`reset`][Self::reset].
+#[doc = include_str!("katex.html")]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Agc {
+    /// How values are mapped to output levels.
+    pub mapping: Mapping,
+
+    /// The fraction (between 0 and 1) of the coldest pixels to clip.
+    ///
+    /// Clipped pixels are black. For example 0.01 ignores the lowest 1% of pixels when choosing the
+    /// range.
+    pub low_clip: f32,
+
+    /// The fraction (between 0 and 1) of the hottest pixels to clip.
+    ///
+    /// Clipped pixels are white.
+    pub high_clip: f32,
+
+    /// How much of the previous range is kept for each new image, between 0 and 1.
+    ///
+    /// 0 disables smoothing, so each image uses its own range. Otherwise the range is an
+    /// [exponential moving average][ema], with values closer to 1 changing more slowly.
+    ///
+    /// [ema]: https://en.wikipedia.org/wiki/Moving_average#Exponential_moving_average
+    pub smoothing: f32,
+
+    range: Option<(f32, f32)>,
+}
+
+impl Agc {
+    /// Create a new `Agc` with no clipping or smoothing.
+    pub fn new(mapping: Mapping) -> Self {
+        Self {
+            mapping,
+            low_clip: 0f32,
+            high_clip: 0f32,
+            smoothing: 0f32,
+            range: None,
+        }
+    }
+
+    /// The range used for the most recent image.
+    pub fn range(&self) -> Option<(f32, f32)> {
+        self.range
+    }
+
+    /// Forget the smoothed range, so the next image uses its own range.
+    pub fn reset(&mut self) {
+        self.range = None;
+    }
+
+    /// Update the (smoothed) range for a new image, returning it.
+    fn update_range(&mut self, image: &[f32]) -> Option<(f32, f32)> {
+        let (min, max) = image.iter().filter(|value| !value.is_nan()).fold(
+            None,
+            |limits, value| match limits {
+                None => Some((*value, *value)),
+                Some((min, max)) => Some((value.min(min), value.max(max))),
+            },
+        )?;
+        let (low, high) = if self.low_clip > 0f32 || self.high_clip > 0f32 {
+            let histogram = Histogram::new(image, min, max);
+            let low = if self.low_clip > 0f32 {
+                histogram.percentile(self.low_clip)
+            } else {
+                min
+            };
+            let high = if self.high_clip > 0f32 {
+                histogram.percentile(1f32 - self.high_clip)
+            } else {
+                max
+            };
+            (low, high)
+        } else {
+            (min, max)
+        };
+        let range = match self.range {
+            Some((previous_low, previous_high)) if self.smoothing > 0f32 => {
+                let smooth = |previous: f32, current: f32| {
+                    previous + (current - previous) * (1f32 - self.smoothing)
+                };
+                (smooth(previous_low, low), smooth(previous_high, high))
+            }
+            _ => (low, high),
+        };
+        self.range = Some(range);
+        self.range
+    }
+
+    /// Map each pixel to a level between 0 and `max_level`, calling `write` with the index and
+    /// level of each pixel. The level still needs to be truncated to an integer.
+    fn process<W>(&mut self, image: &[f32], max_level: f32, mut write: W) -> Option<(f32, f32)>
+    where
+        W: FnMut(usize, f32),
+    {
+        let range = self.update_range(image);
+        let (low, high) = match range {
+            Some(range) => range,
+            None => {
+                (0..image.len()).for_each(|index| write(index, 0f32));
+                return None;
+            }
+        };
+        // When equalizing, the level for a value is the fraction of (limited) pixels below it.
+        let cumulative = match self.mapping {
+            Mapping::PlateauEqualization { plateau } if high > low => {
+                let mut histogram = Histogram::new(image, low, high);
+                let mut total = 0u32;
+                for bin in histogram.bins.iter_mut() {
+                    total += (*bin).min(plateau);
+                    *bin = total;
+                }
+                Some((histogram, total))
+            }
+            _ => None,
+        };
+        for (index, value) in image.iter().enumerate() {
+            let fraction = if value.is_nan() {
+                0f32
+            } else if high <= low {
+                // Every pixel is the same, so use the middle level.
+                0.5
+            } else {
+                match &cumulative {
+                    Some((_, 0)) => 0f32,
+                    Some((histogram, total)) => {
+                        let bin = histogram.bin(*value);
+                        let below = if bin > 0 { histogram.bins[bin - 1] } else { 0 };
+                        // Assume the pixels are spread out evenly within the bin.
+                        let within =
+                            ((value - low) / histogram.bin_width - bin as f32).clamp(0f32, 1f32);
+                        let count = histogram.bins[bin] - below;
+                        (below as f32 + within * count as f32) / *total as f32
+                    }
+                    None => (value - low) / (high - low),
+                }
+            };
+            // Rounding to the nearest level, as the casts truncate.
+            write(index, fraction.clamp(0f32, 1f32) * max_level + 0.5);
+        }
+        range
+    }
+
+    /// Convert an image to 8-bit grayscale.
+    ///
+    /// `image` can be either temperatures or $V_{IR}$ data. NaN pixels are black. If `output` is
+    /// too short for the whole image, only the pixels that fit are written. The range used is
+    /// returned, which is `None` if every pixel is NaN.
+    pub fn process_u8(&mut self, image: &[f32], output: &mut [u8]) -> Option<(f32, f32)> {
+        self.process(image, f32::from(u8::MAX), |index, level| {
+            if let Some(pixel) = output.get_mut(index) {
+                *pixel = level as u8;
+            }
+        })
+    }
+
+    /// Convert an image to 16-bit grayscale.
+    ///
+    /// This is the same as [`process_u8`][Self::process_u8], but with 65536 levels.
+    pub fn process_u16(&mut self, image: &[f32], output: &mut [u16]) -> Option<(f32, f32)> {
+        self.process(image, f32::from(u16::MAX), |index, level| {
+            if let Some(pixel) = output.get_mut(index) {
+                *pixel = level as u16;
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Agc, Mapping};
+
+    /// An image with values 0 to N - 1
+    fn ramp<const N: usize>() -> [f32; N] {
+        let mut image = [0f32; N];
+        image
+            .iter_mut()
+            .enumerate()
+            .for_each(|(index, pixel)| *pixel = index as f32);
+        image
+    }
+
+    #[test]
+    fn linear() {
+        let image = ramp::<256>();
+        let mut agc = Agc::new(Mapping::Linear);
+        let mut output = [0u8; 256];
+        assert_eq!(agc.process_u8(&image, &mut output), Some((0.0, 255.0)));
+        for (index, level) in output.iter().enumerate() {
+            assert_eq!(*level as usize, index);
+        }
+        let mut output = [0u16; 256];
+        agc.process_u16(&image, &mut output);
+        assert_eq!(output[0], 0);
+        assert_eq!(output[255], u16::MAX);
+        assert_eq!(output[51], 13107);
+    }
+
+    #[test]
+    fn clip_outliers() {
+        let mut image = ramp::<100>();
+        // One very hot pixel
+        image[50] = 1000.0;
+        let mut agc = Agc::new(Mapping::Linear);
+        agc.high_clip = 0.02;
+        let mut output = [0u8; 100];
+        let (low, high) = agc.process_u8(&image, &mut output).unwrap();
+        assert_eq!(low, 0.0);
+        assert!(high < 100.0, "{} wasn't clipped", high);
+        assert_eq!(output[50], u8::MAX);
+        // The rest of the image still uses most of the levels
+        assert!(output[99] > 240);
+        assert!(output[25] > 60);
+
+        agc.low_clip = 0.1;
+        let (low, _) = agc.process_u8(&image, &mut output).unwrap();
+        assert!((low - 10.0).abs() < 5.0, "{}", low);
+        assert_eq!(output[0], 0);
+        assert_eq!(output[5], 0);
+    }
+
+    #[test]
+    fn equalization() {
+        // Most of the image is a background at 20, with a gradient from 30 to 40
+        let mut image = [20f32; 200];
+        for (index, pixel) in image.iter_mut().take(50).enumerate() {
+            *pixel = 30.0 + index as f32 / 5.0;
+        }
+        let mut linear = [0u8; 200];
+        Agc::new(Mapping::Linear).process_u8(&image, &mut linear);
+        let mut equalized = [0u8; 200];
+        let mut agc = Agc::new(Mapping::PlateauEqualization { plateau: 2 });
+        agc.process_u8(&image, &mut equalized);
+        // The background is black in both
+        assert_eq!(linear[100], 0);
+        assert_eq!(equalized[100], 0);
+        // Without equalization the gradient only uses the top half of the levels, but with it the
+        // gradient is spread out over most of them.
+        assert!(linear[0] > 120);
+        assert!(equalized[0] < 40, "{}", equalized[0]);
+        assert_eq!(equalized[49], u8::MAX);
+        // And it stays in order
+        for pair in equalized[..50].windows(2) {
+            assert!(pair[0] <= pair[1]);
+        }
+    }
+
+    #[test]
+    fn smoothing() {
+        let mut agc = Agc::new(Mapping::Linear);
+        agc.smoothing = 0.75;
+        let mut output = [0u8; 2];
+        assert_eq!(
+            agc.process_u8(&[0.0, 100.0], &mut output),
+            Some((0.0, 100.0))
+        );
+        // The range only moves a quarter of the way to the new range
+        assert_eq!(agc.process_u8(&[0.0, 20.0], &mut output), Some((0.0, 80.0)));
+        assert_eq!(output, [0, 64]);
+        agc.reset();
+        assert_eq!(agc.process_u8(&[0.0, 20.0], &mut output), Some((0.0, 20.0)));
+    }
+
+    #[test]
+    fn nan_pixels() {
+        let mut agc = Agc::new(Mapping::PlateauEqualization { plateau: 10 });
+        let mut output = [7u8; 3];
+        assert_eq!(agc.process_u8(&[f32::NAN; 3], &mut output), None);
+        assert_eq!(output, [0; 3]);
+        assert_eq!(
+            agc.process_u8(&[1.0, f32::NAN, 3.0], &mut output),
+            Some((1.0, 3.0))
+        );
+        assert_eq!(output[1], 0);
+        // A flat image uses the middle level
+        let mut agc = Agc::new(Mapping::Linear);
+        agc.process_u8(&[5.0; 3], &mut output);
+        assert_eq!(output, [128; 3]);
+    }
+}
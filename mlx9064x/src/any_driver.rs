@@ -0,0 +1,428 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright © 2021 Will Ross
+
+use core::time::Duration;
+
+use embedded_hal::delay::DelayNs;
+use embedded_hal::i2c;
+
+use crate::common::Address;
+use crate::error::{Error, LibraryError};
+use crate::metadata::FrameMetadata;
+use crate::mlx90641::hamming::validate_checksum;
+use crate::register::FrameRate;
+use crate::{mlx90640, mlx90641, mlx90642};
+use crate::{Mlx90640Driver, Mlx90641Driver, Mlx90642Driver};
+
+/// The first EEPROM address checked when telling an MLX90640 and MLX90641 apart.
+///
+/// The first 16 words are device information, which don't have checksums on the MLX90641.
+const PROBE_EEPROM_START: u16 = 0x2410;
+
+/// How many EEPROM words are checked when telling an MLX90640 and MLX90641 apart.
+const PROBE_EEPROM_WORDS: usize = 48;
+
+const MLX90642_EEPROM_UNSUPPORTED: LibraryError =
+    LibraryError::Other("Writing the EEPROM is not supported on the MLX90642");
+
+/// The camera models supported by this library.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum CameraModel {
+    Mlx90640,
+    Mlx90641,
+    Mlx90642,
+}
+
+impl CameraModel {
+    /// Identify the camera at the given I²C address.
+    ///
+    /// The MLX90642 is checked for first, by reading its configuration words and checking they
+    /// have valid values. The other two cameras don't have anything at those addresses, so either
+    /// the reads fail or the values are nonsensical. The MLX90640 and MLX90641 are then told apart
+    /// by their calibration data in EEPROM; every calibration word on an MLX90641 has a Hamming
+    /// code in its upper bits, while the MLX90640's calibration data does not.
+    ///
+    /// If no camera responds, the error from reading the EEPROM is returned.
+    pub fn detect<I2C: i2c::I2c>(bus: &mut I2C, address: u8) -> Result<Self, Error<I2C>> {
+        if is_mlx90642(bus, address) {
+            return Ok(Self::Mlx90642);
+        }
+        let mut eeprom = [0u8; PROBE_EEPROM_WORDS * 2];
+        bus.write_read(
+            address,
+            &Address::new(PROBE_EEPROM_START).as_bytes(),
+            &mut eeprom,
+        )
+        .map_err(Error::I2cError)?;
+        let all_checksummed = eeprom
+            .chunks_exact(2)
+            .map(|word| u16::from_be_bytes([word[0], word[1]]))
+            .all(|word| validate_checksum(word).is_ok());
+        Ok(if all_checksummed {
+            Self::Mlx90641
+        } else {
+            Self::Mlx90640
+        })
+    }
+
+    /// The height of this camera's images, in pixels.
+    pub fn height(&self) -> usize {
+        match self {
+            Self::Mlx90640 => <mlx90640::Mlx90640 as crate::MelexisCamera>::HEIGHT,
+            Self::Mlx90641 => <mlx90641::Mlx90641 as crate::MelexisCamera>::HEIGHT,
+            Self::Mlx90642 => mlx90642::Mlx90642::HEIGHT,
+        }
+    }
+
+    /// The width of this camera's images, in pixels.
+    pub fn width(&self) -> usize {
+        match self {
+            Self::Mlx90640 => <mlx90640::Mlx90640 as crate::MelexisCamera>::WIDTH,
+            Self::Mlx90641 => <mlx90641::Mlx90641 as crate::MelexisCamera>::WIDTH,
+            Self::Mlx90642 => mlx90642::Mlx90642::WIDTH,
+        }
+    }
+
+    /// The number of pixels in this camera's images.
+    pub fn num_pixels(&self) -> usize {
+        self.height() * self.width()
+    }
+}
+
+/// Check if the MLX90642 configuration words can be read and have valid values.
+fn is_mlx90642<I2C: i2c::I2c>(bus: &mut I2C, address: u8) -> bool {
+    let mut read_word = |register: mlx90642::RamAddress| -> Option<u16> {
+        let mut word = [0u8; 2];
+        bus.write_read(address, &Address::from(register).as_bytes(), &mut word)
+            .ok()?;
+        Some(u16::from_be_bytes(word))
+    };
+    let refresh_rate = read_word(mlx90642::RamAddress::RefreshRate);
+    let emissivity = read_word(mlx90642::RamAddress::Emissivity);
+    let measurement_mode = read_word(mlx90642::RamAddress::MeasurementMode);
+    matches!(
+        (refresh_rate, emissivity, measurement_mode),
+        (Some(0..=7), Some(1..=0x4000), Some(0..=1))
+    )
+}
+
+/// Dispatch a method call to whichever driver is in use.
+macro_rules! dispatch {
+    ($self:ident, $driver:ident => $body:expr) => {
+        match $self {
+            AnyCameraDriver::Mlx90640($driver) => $body,
+            AnyCameraDriver::Mlx90641($driver) => $body,
+            AnyCameraDriver::Mlx90642($driver) => $body,
+        }
+    };
+}
+
+/// A high-level driver for whichever camera is attached.
+///
+/// The camera model is [detected][CameraModel::detect] when the driver is created, so one program
+/// can support all of the cameras without knowing which is fitted at compile time. Only the
+/// functionality common to all of the cameras is available directly; the model specific driver can
+/// be used by matching on the variants.
+// The drivers are large because of their calibration data, but they can't be boxed without alloc.
+#[allow(clippy::large_enum_variant)]
+#[derive(Clone, Debug)]
+pub enum AnyCameraDriver<I2C> {
+    Mlx90640(Mlx90640Driver<I2C>),
+    Mlx90641(Mlx90641Driver<I2C>),
+    Mlx90642(Mlx90642Driver<I2C>),
+}
+
+impl<I2C> AnyCameraDriver<I2C>
+where
+    I2C: i2c::I2c,
+{
+    /// Detect the camera at the given address, then create a driver for it.
+    pub fn new(bus: I2C, address: u8) -> Result<Self, Error<I2C>> {
+        let mut bus = bus;
+        let model = CameraModel::detect(&mut bus, address)?;
+        Self::new_with_model(bus, address, model)
+    }
+
+    /// Create a driver for a known camera model.
+    pub fn new_with_model(bus: I2C, address: u8, model: CameraModel) -> Result<Self, Error<I2C>> {
+        Ok(match model {
+            CameraModel::Mlx90640 => Self::Mlx90640(Mlx90640Driver::new(bus, address)?),
+            CameraModel::Mlx90641 => Self::Mlx90641(Mlx90641Driver::new(bus, address)?),
+            CameraModel::Mlx90642 => Self::Mlx90642(Mlx90642Driver::new(bus, address)?),
+        })
+    }
+
+    /// Access the bus the camera is on.
+    pub(crate) fn bus_mut(&mut self) -> &mut I2C {
+        dispatch!(self, driver => driver.bus_mut())
+    }
+
+    /// The model of camera this driver is for.
+    pub fn model(&self) -> CameraModel {
+        match self {
+            Self::Mlx90640(_) => CameraModel::Mlx90640,
+            Self::Mlx90641(_) => CameraModel::Mlx90641,
+            Self::Mlx90642(_) => CameraModel::Mlx90642,
+        }
+    }
+
+    /// The height of the thermal image, in pixels.
+    pub fn height(&self) -> usize {
+        self.model().height()
+    }
+
+    /// The width of the thermal image, in pixels.
+    pub fn width(&self) -> usize {
+        self.model().width()
+    }
+
+    /// Read the frame rate from the camera.
+    pub fn frame_rate(&mut self) -> Result<FrameRate, Error<I2C>> {
+        dispatch!(self, driver => driver.frame_rate())
+    }
+
+    /// Set the camera's frame rate.
+    pub fn set_frame_rate(&mut self, frame_rate: FrameRate) -> Result<(), Error<I2C>> {
+        dispatch!(self, driver => driver.set_frame_rate(frame_rate))
+    }
+
+    /// Check if there is new data available.
+    pub fn data_available(&mut self) -> Result<bool, Error<I2C>> {
+        match self {
+            Self::Mlx90640(driver) => Ok(driver.data_available()?.is_some()),
+            Self::Mlx90641(driver) => Ok(driver.data_available()?.is_some()),
+            Self::Mlx90642(driver) => driver.data_available(),
+        }
+    }
+
+    /// Clear the data available flag, signaling to the camera that the controller is ready for
+    /// more data.
+    pub fn reset_data_available(&mut self) -> Result<(), Error<I2C>> {
+        dispatch!(self, driver => driver.reset_data_available())
+    }
+
+    /// The ambient temperature of the camera, from the last data that was processed.
+    pub fn ambient_temperature(&self) -> Option<f32> {
+        dispatch!(self, driver => driver.ambient_temperature())
+    }
+
+    /// Get the metadata for the most recently processed subpage.
+    ///
+    /// The MLX90642 calculates the temperatures itself, so there is no metadata for it.
+    pub fn frame_metadata(&self) -> Option<FrameMetadata> {
+        match self {
+            Self::Mlx90640(driver) => driver.frame_metadata(),
+            Self::Mlx90641(driver) => driver.frame_metadata(),
+            Self::Mlx90642(_) => None,
+        }
+    }
+
+    /// Generate a thermal "image" from the camera's current data.
+    ///
+    /// See [`CameraDriver::generate_image_to`][crate::CameraDriver::generate_image_to].
+    pub fn generate_image_to(&mut self, destination: &mut [f32]) -> Result<(), Error<I2C>> {
+        dispatch!(self, driver => driver.generate_image_to(destination))
+    }
+
+    /// Generate a thermal "image" from the camera's current data, if there's new data.
+    ///
+    /// For the MLX90640 only some of the pixels are updated by each call, see
+    /// [`generate_full_frame_if_ready`][Self::generate_full_frame_if_ready] for a function that
+    /// reports when the whole image has been updated.
+    pub fn generate_image_if_ready(&mut self, destination: &mut [f32]) -> Result<bool, Error<I2C>> {
+        dispatch!(self, driver => driver.generate_image_if_ready(destination))
+    }
+
+    /// Generate a thermal "image" from the camera's current data if there's new data, reporting
+    /// whether a full frame is now available.
+    ///
+    /// See
+    /// [`CameraDriver::generate_full_frame_if_ready`][crate::CameraDriver::generate_full_frame_if_ready].
+    /// Every frame from the MLX90642 is a full frame.
+    pub fn generate_full_frame_if_ready(
+        &mut self,
+        destination: &mut [f32],
+    ) -> Result<bool, Error<I2C>> {
+        match self {
+            Self::Mlx90640(driver) => driver.generate_full_frame_if_ready(destination),
+            Self::Mlx90641(driver) => driver.generate_full_frame_if_ready(destination),
+            Self::Mlx90642(driver) => driver.generate_image_if_ready(destination),
+        }
+    }
+
+    /// Store a new I²C address for the camera in its EEPROM.
+    ///
+    /// The new address is used after the camera is power cycled. The MLX90642 is not supported.
+    pub fn set_i2c_address_persistent<D: DelayNs>(
+        &mut self,
+        delay: &mut D,
+        new_address: u8,
+    ) -> Result<(), Error<I2C>> {
+        match self {
+            Self::Mlx90640(driver) => driver.set_i2c_address_persistent(delay, new_address),
+            Self::Mlx90641(driver) => driver.set_i2c_address_persistent(delay, new_address),
+            Self::Mlx90642(_) => Err(MLX90642_EEPROM_UNSUPPORTED.into()),
+        }
+    }
+
+    /// Store the current control register settings as the camera's power on defaults.
+    ///
+    /// The MLX90642 is not supported.
+    pub fn store_default_control_register<D: DelayNs>(
+        &mut self,
+        delay: &mut D,
+    ) -> Result<(), Error<I2C>> {
+        match self {
+            Self::Mlx90640(driver) => driver.store_default_control_register(delay),
+            Self::Mlx90641(driver) => driver.store_default_control_register(delay),
+            Self::Mlx90642(_) => Err(MLX90642_EEPROM_UNSUPPORTED.into()),
+        }
+    }
+
+    /// Synchronize with the camera's frame update timing.
+    pub fn synchronize(&mut self) -> Result<(), Error<I2C>> {
+        dispatch!(self, driver => driver.synchronize())
+    }
+
+    /// Synchronize with the camera's frame update timing, giving up after `timeout`.
+    ///
+    /// The status is checked every [poll interval][FrameRate::poll_interval], with `delay` used to
+    /// wait in between. [`LibraryError::Timeout`] is returned if there is no new data in time.
+    pub fn synchronize_with_timeout<D: DelayNs>(
+        &mut self,
+        delay: &mut D,
+        timeout: Duration,
+    ) -> Result<(), Error<I2C>> {
+        dispatch!(self, driver => driver.synchronize_with_timeout(delay, timeout))
+    }
+
+    /// Synchronize with the camera's frame update timing, giving up after checking the status
+    /// `max_polls` times.
+    pub fn synchronize_with_poll_limit<D: DelayNs>(
+        &mut self,
+        delay: &mut D,
+        max_polls: u32,
+    ) -> Result<(), Error<I2C>> {
+        dispatch!(self, driver => driver.synchronize_with_poll_limit(delay, max_polls))
+    }
+
+    /// Check if the camera is in step mode.
+    pub fn step_mode(&mut self) -> Result<bool, Error<I2C>> {
+        dispatch!(self, driver => driver.step_mode())
+    }
+
+    /// Enable (or disable) step mode.
+    pub fn set_step_mode(&mut self, step_mode: bool) -> Result<(), Error<I2C>> {
+        dispatch!(self, driver => driver.set_step_mode(step_mode))
+    }
+
+    /// Take a single frame in step mode, writing the temperatures into `destination`.
+    ///
+    /// See [`CameraDriver::measure_frame`][crate::CameraDriver::measure_frame]. The MLX90642 only
+    /// needs one measurement for a full frame.
+    pub fn measure_frame<D: DelayNs>(
+        &mut self,
+        delay: &mut D,
+        destination: &mut [f32],
+    ) -> Result<(), Error<I2C>> {
+        dispatch!(self, driver => driver.measure_frame(delay, destination))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use mlx9064x_test_data::*;
+
+    use crate::{Error, FrameRate};
+
+    use super::{AnyCameraDriver, CameraModel};
+
+    #[test]
+    fn detect() {
+        let mut mocked = example_mlx90640_at_address(0x33);
+        assert_eq!(
+            CameraModel::detect(&mut mocked, 0x33).unwrap(),
+            CameraModel::Mlx90640
+        );
+        let mut mocked = datasheet_mlx90640_at_address(0x33);
+        assert_eq!(
+            CameraModel::detect(&mut mocked, 0x33).unwrap(),
+            CameraModel::Mlx90640
+        );
+        let mut mocked = mock_mlx90641_at_address(0x33);
+        assert_eq!(
+            CameraModel::detect(&mut mocked, 0x33).unwrap(),
+            CameraModel::Mlx90641
+        );
+        let mut mocked = mock_mlx90642_at_address(0x66);
+        assert_eq!(
+            CameraModel::detect(&mut mocked, 0x66).unwrap(),
+            CameraModel::Mlx90642
+        );
+    }
+
+    #[test]
+    fn detect_missing() {
+        let mut mocked = example_mlx90640_at_address(0x33);
+        assert!(matches!(
+            CameraModel::detect(&mut mocked, 0x34),
+            Err(Error::I2cError(MockError::UnknownI2cAddress(0x34)))
+        ));
+    }
+
+    #[test]
+    fn dimensions() {
+        assert_eq!(CameraModel::Mlx90640.num_pixels(), 768);
+        assert_eq!(CameraModel::Mlx90641.num_pixels(), 192);
+        assert_eq!(CameraModel::Mlx90642.num_pixels(), 768);
+        assert_eq!(CameraModel::Mlx90641.width(), 16);
+    }
+
+    #[test]
+    fn mlx90640() {
+        let mut mocked = example_mlx90640_at_address(0x33);
+        let mut cam = AnyCameraDriver::new(mocked.clone(), 0x33).unwrap();
+        assert_eq!(cam.model(), CameraModel::Mlx90640);
+        let mut buffer = [f32::NAN; 768];
+        let temperatures = &mut buffer[..cam.height() * cam.width()];
+        mocked.set_data_available(true);
+        // Only half a frame
+        assert!(!cam.generate_full_frame_if_ready(temperatures).unwrap());
+        mocked.update_frame(
+            mlx90640_example_data::FRAME_1_DATA,
+            mlx90640_example_data::FRAME_1_STATUS_REGISTER,
+        );
+        mocked.set_data_available(true);
+        assert!(cam.data_available().unwrap());
+        assert!(cam.generate_full_frame_if_ready(temperatures).unwrap());
+        assert!(temperatures.iter().all(|t| !t.is_nan()));
+        assert!(cam.ambient_temperature().is_some());
+    }
+
+    #[test]
+    fn mlx90641() {
+        let mocked = mock_mlx90641_at_address(0x33);
+        let mut cam = AnyCameraDriver::new(mocked, 0x33).unwrap();
+        assert_eq!(cam.model(), CameraModel::Mlx90641);
+        let mut buffer = [f32::NAN; 768];
+        let temperatures = &mut buffer[..cam.height() * cam.width()];
+        assert!(cam.generate_full_frame_if_ready(temperatures).unwrap());
+        assert!(!cam.data_available().unwrap());
+        cam.set_frame_rate(FrameRate::Eight).unwrap();
+        assert_eq!(cam.frame_rate().unwrap(), FrameRate::Eight);
+    }
+
+    #[test]
+    fn mlx90642() {
+        let mut mocked = mock_mlx90642_at_address(0x66);
+        let mut cam = AnyCameraDriver::new(mocked.clone(), 0x66).unwrap();
+        assert_eq!(cam.model(), CameraModel::Mlx90642);
+        let mut buffer = [f32::NAN; 768];
+        let temperatures = &mut buffer[..cam.height() * cam.width()];
+        assert!(!cam.generate_full_frame_if_ready(temperatures).unwrap());
+        mocked.update_frame(&[20.0; 768], 25.0);
+        assert!(cam.generate_full_frame_if_ready(temperatures).unwrap());
+        assert!(temperatures.iter().all(|t| *t == 20.0));
+        assert_eq!(cam.ambient_temperature(), Some(25.0));
+    }
+}
@@ -0,0 +1,661 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright © 2021 Will Ross
+
+use core::time::Duration;
+
+use embedded_hal_async::delay::DelayNs;
+use embedded_hal_async::i2c::I2c;
+use paste::paste;
+
+use crate::calculations::RamData;
+use crate::common::*;
+use crate::driver::{processor_accessors, set_register_field, FrameProcessor};
+use crate::eeprom;
+use crate::emissivity::EmissivityMap;
+use crate::error::{Error, LibraryError};
+use crate::filter::CompensationPixelFilter;
+use crate::frame::FrameAssembler;
+use crate::image::PixelReplacement;
+use crate::metadata::{Clock, FrameMetadata};
+use crate::register::*;
+use crate::util::{polls_within, saturating_micros};
+
+/// The async version of [`CameraDriver`][crate::CameraDriver], using `embedded-hal-async`.
+///
+/// The API is the same as `CameraDriver`, except the methods that access the camera are `async`.
+/// This driver also takes a [`DelayNs`] implementation, which is used to wait between checks of
+/// the camera's status instead of spinning (see [`synchronize`][Self::synchronize]).
+#[derive(Clone, Debug)]
+pub struct AsyncCameraDriver<Clb, I2C, D, const HEIGHT: usize, const NUM_BYTES: usize> {
+    /// The I²C bus this camera is accessible on.
+    bus: I2C,
+
+    /// Used to wait without blocking while the camera is busy.
+    delay: D,
+
+    /// The I²C address this camera is accessible at.
+    address: u8,
+
+    /// Buffer for reading pixel data off of the camera.
+    pixel_buffer: [u8; NUM_BYTES],
+
+    /// Calibration data, settings, and state for processing images.
+    processor: FrameProcessor<Clb>,
+}
+
+impl<'a, Clb, I2C, D, const HEIGHT: usize, const BUFFER_SIZE: usize>
+    AsyncCameraDriver<Clb, I2C, D, HEIGHT, BUFFER_SIZE>
+where
+    Clb: CalibrationData<'a>,
+    I2C: I2c,
+    D: DelayNs,
+{
+    /// Create a new `AsyncCameraDriver`, obtaining the calibration data from the camera over I²C.
+    pub async fn new(bus: I2C, delay: D, address: u8) -> Result<Self, Error<I2C>>
+    where
+        Clb: AsyncFromI2C<I2C, Ok = Clb, Error = Error<I2C>>,
+    {
+        let mut bus = bus;
+        let calibration = Clb::from_i2c_async(&mut bus, address).await?;
+        Self::new_with_calibration(bus, delay, address, calibration).await
+    }
+
+    /// Create an `AsyncCameraDriver` for accessing the camera at the given I²C address.
+    ///
+    /// MLX9064\*s can be configured to use any I²C address (except 0x00), but the default address
+    /// is 0x33.
+    pub async fn new_with_calibration(
+        bus: I2C,
+        delay: D,
+        address: u8,
+        calibration: Clb,
+    ) -> Result<Self, Error<I2C>> {
+        let mut bus = bus;
+        let control = ControlRegister::from_i2c_async(&mut bus, address).await?;
+        Ok(Self {
+            bus,
+            delay,
+            address,
+            pixel_buffer: [0u8; BUFFER_SIZE],
+            processor: FrameProcessor::new(calibration, &control),
+        })
+    }
+
+    async fn status_register(&mut self) -> Result<StatusRegister, Error<I2C>> {
+        StatusRegister::from_i2c_async(&mut self.bus, self.address).await
+    }
+
+    async fn set_status_register(&mut self, register: StatusRegister) -> Result<(), Error<I2C>> {
+        register.to_i2c_async(&mut self.bus, self.address).await
+    }
+
+    async fn control_register(&mut self) -> Result<ControlRegister, Error<I2C>> {
+        let register = ControlRegister::from_i2c_async(&mut self.bus, self.address).await?;
+        self.processor.update_control_register(&register);
+        Ok(register)
+    }
+
+    async fn set_control_register(&mut self, register: ControlRegister) -> Result<(), Error<I2C>> {
+        self.processor.update_control_register(&register);
+        register.to_i2c_async(&mut self.bus, self.address).await
+    }
+
+    /// Get the last measured subpage.
+    pub async fn last_measured_subpage(&mut self) -> Result<Subpage, Error<I2C>> {
+        Ok(self.status_register().await?.last_updated_subpage())
+    }
+
+    /// Check if there is new data available, and if so, which subpage.
+    pub async fn data_available(&mut self) -> Result<Option<Subpage>, Error<I2C>> {
+        let register = self.status_register().await?;
+        Ok(if register.new_data() {
+            Some(register.last_updated_subpage())
+        } else {
+            None
+        })
+    }
+
+    /// Clear the data available flag, signaling to the camera that the controller is ready for
+    /// more data.
+    ///
+    /// This flag can only be reset by the controller.
+    pub async fn reset_data_available(&mut self) -> Result<(), Error<I2C>> {
+        let mut current = self.status_register().await?;
+        current.reset_new_data();
+        self.set_status_register(current).await
+    }
+
+    /// Check if the overwrite enabled flag is set.
+    ///
+    /// This flag is only effective when `data_hold_enabled` is active.
+    pub async fn overwrite_enabled(&mut self) -> Result<bool, Error<I2C>> {
+        Ok(self.status_register().await?.overwrite_enabled())
+    }
+
+    set_register_field! {
+        async status_register,
+        overwrite_enabled,
+        "Enabled (or disable) overwriting of data in RAM with new data."
+    }
+
+    /// Check if the camera is using subpages.
+    ///
+    /// When disabled, only one page will be measured. The default is to use subpages.
+    pub async fn subpages_enabled(&mut self) -> Result<bool, Error<I2C>> {
+        Ok(self.control_register().await?.use_subpages())
+    }
+
+    set_register_field! {
+        async control_register,
+        use_subpages,
+        "Enabled (or disable) the use of subpages."
+    }
+
+    /// Check if the "Enable data hold" flag is set.
+    ///
+    /// When this flag (bit 2 on 0x800D) is set, data is not copied to RAM unless the
+    /// `enable_overwrite` flag is set. The default is for this mode to be disabled.
+    pub async fn data_hold_enabled(&mut self) -> Result<bool, Error<I2C>> {
+        Ok(self.control_register().await?.data_hold())
+    }
+
+    set_register_field! {
+        async control_register,
+        data_hold,
+        "Enabled (or disable) data holding."
+    }
+
+    /// Check if the camera is in subpage repeat mode.
+    ///
+    /// This flag only has an effect if [subpages][Self::subpages_enabled] is enabled. In subpage
+    /// repeat mode, only the subpage set in `selected_subpage` will be measured and updated. When
+    /// disabled, the active subpage will alternate between the two. The default is disabled.
+    pub async fn subpage_repeat(&mut self) -> Result<bool, Error<I2C>> {
+        Ok(self.control_register().await?.subpage_repeat())
+    }
+
+    set_register_field! {
+        async control_register,
+        subpage_repeat,
+        "Enabled (or disable) subpage repeat mode."
+    }
+
+    /// Get the currently selected subpage when [subpage repeat] is enabled.
+    ///
+    /// This setting only has an effect when `subpage_repeat` is enabled. The default value is
+    /// `Subpage::Zero`.
+    ///
+    /// [subpage repeat]: Self::subpage_repeat
+    pub async fn selected_subpage(&mut self) -> Result<Subpage, Error<I2C>> {
+        Ok(self.control_register().await?.subpage())
+    }
+
+    set_register_field! {
+        async control_register,
+        subpage,
+        Subpage,
+        "Set the currently selected subpage when [subpage repeat][Self::subpage_repeat] is enabled."
+    }
+
+    /// Read the frame rate from the camera.
+    ///
+    /// The default frame rate is [2 FPS][FrameRate::Two].
+    pub async fn frame_rate(&mut self) -> Result<FrameRate, Error<I2C>> {
+        Ok(self.control_register().await?.frame_rate())
+    }
+
+    set_register_field! {
+        async control_register,
+        frame_rate,
+        FrameRate,
+        "Set camera's frame rate."
+    }
+
+    /// Get the current resolution of the ADC in the camera.
+    ///
+    /// The default resolution is [18 bits][Resolution::Eighteen].
+    pub async fn resolution(&mut self) -> Result<Resolution, Error<I2C>> {
+        Ok(self.control_register().await?.resolution())
+    }
+
+    set_register_field! {
+        async control_register,
+        resolution,
+        Resolution,
+        "Set ADC resolution within the camera."
+    }
+
+    /// Get the current access pattern used by the camera when updating subpages.
+    ///
+    /// The default for the MLX90640 is the chess patterm while the default for the MLX90641 is the
+    /// interleaved pattern.
+    pub async fn access_pattern(&mut self) -> Result<AccessPattern, Error<I2C>> {
+        Ok(self.control_register().await?.access_pattern())
+    }
+
+    set_register_field! {
+        async control_register,
+        access_pattern,
+        AccessPattern,
+        "Set the access pattern used by the camera."
+    }
+
+    /// Check if the camera is in step mode.
+    ///
+    /// See [`CameraDriver::step_mode`][crate::CameraDriver::step_mode] for details.
+    pub async fn step_mode(&mut self) -> Result<bool, Error<I2C>> {
+        Ok(self.control_register().await?.step_mode())
+    }
+
+    set_register_field! {
+        async control_register,
+        step_mode,
+        "Enable (or disable) step mode."
+    }
+
+    processor_accessors!();
+
+    async fn read_ram(&mut self, subpage: Subpage) -> Result<RamData, Error<I2C>> {
+        read_ram_async::<Clb::Camera, I2C, HEIGHT>(
+            &mut self.bus,
+            self.address,
+            self.processor.access_pattern,
+            subpage,
+            &mut self.pixel_buffer,
+        )
+        .await
+    }
+
+    pub async fn generate_raw_image_subpage_to(
+        &'a mut self,
+        subpage: Subpage,
+        destination: &mut [f32],
+    ) -> Result<(), Error<I2C>> {
+        let ram = self.read_ram(subpage).await?;
+        self.processor
+            .process(subpage, ram, &self.pixel_buffer, false, None, destination);
+        Ok(())
+    }
+
+    pub async fn generate_image_subpage_to(
+        &'a mut self,
+        subpage: Subpage,
+        destination: &mut [f32],
+    ) -> Result<(), Error<I2C>> {
+        let ram = self.read_ram(subpage).await?;
+        self.processor
+            .process(subpage, ram, &self.pixel_buffer, true, None, destination);
+        Ok(())
+    }
+
+    /// Generate a thermal "image" from the camera's current data.
+    ///
+    /// This function does *not* check if there is new data, it just copies the current frame of
+    /// data.
+    pub async fn generate_image_to(
+        &'a mut self,
+        destination: &mut [f32],
+    ) -> Result<(), Error<I2C>> {
+        let subpage = self.last_measured_subpage().await?;
+        self.generate_image_subpage_to(subpage, destination).await
+    }
+
+    /// Generate a thermal "image" from the camera's current data, if there's new data.
+    ///
+    /// See [`CameraDriver::generate_image_if_ready`][crate::CameraDriver::generate_image_if_ready]
+    /// for details.
+    pub async fn generate_image_if_ready(
+        &'a mut self,
+        destination: &mut [f32],
+    ) -> Result<bool, Error<I2C>> {
+        Ok(self.process_new_data(None, destination).await?.is_some())
+    }
+
+    /// Generate a thermal "image" from the camera's current data if there's new data, returning
+    /// the metadata for it.
+    ///
+    /// See [`CameraDriver::generate_image_with_metadata`] for details.
+    ///
+    /// [`CameraDriver::generate_image_with_metadata`]: crate::CameraDriver::generate_image_with_metadata
+    pub async fn generate_image_with_metadata(
+        &'a mut self,
+        destination: &mut [f32],
+        clock: Option<&mut dyn Clock>,
+    ) -> Result<Option<FrameMetadata>, Error<I2C>> {
+        self.process_new_data(clock, destination).await
+    }
+
+    /// Process new data from the camera into `destination` if there is any.
+    ///
+    /// If there was no new data, `None` is returned. Otherwise the metadata for the new subpage is
+    /// returned, which includes whether or not a full frame is available. This is checked here as
+    /// `self` can't be used again by the caller after borrowing it for `'a`. If a clock is given,
+    /// the metadata is timestamped with it.
+    async fn process_new_data(
+        &'a mut self,
+        clock: Option<&mut dyn Clock>,
+        destination: &mut [f32],
+    ) -> Result<Option<FrameMetadata>, Error<I2C>> {
+        // Not going through the helper methods on self to avoid infecting them with 'a
+        let address = self.address;
+        let bus = &mut self.bus;
+        let mut status_register = StatusRegister::from_i2c_async(bus, address).await?;
+        if status_register.new_data() {
+            let timestamp = clock.map(|clock| clock.now());
+            let subpage = status_register.last_updated_subpage();
+            let ram = read_ram_async::<Clb::Camera, I2C, HEIGHT>(
+                bus,
+                address,
+                self.processor.access_pattern,
+                subpage,
+                &mut self.pixel_buffer,
+            )
+            .await?;
+            let metadata = self.processor.process(
+                subpage,
+                ram,
+                &self.pixel_buffer,
+                true,
+                timestamp,
+                destination,
+            );
+            status_register.reset_new_data();
+            status_register.to_i2c_async(bus, address).await?;
+            Ok(Some(metadata))
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// Generate a thermal "image" from the camera's current data if there's new data, reporting
+    /// whether a full frame is now available.
+    ///
+    /// See [`CameraDriver::generate_full_frame_if_ready`] for details.
+    ///
+    /// [`CameraDriver::generate_full_frame_if_ready`]: crate::CameraDriver::generate_full_frame_if_ready
+    pub async fn generate_full_frame_if_ready(
+        &'a mut self,
+        destination: &mut [f32],
+    ) -> Result<bool, Error<I2C>> {
+        Ok(self
+            .process_new_data(None, destination)
+            .await?
+            .is_some_and(|metadata| metadata.frame_complete))
+    }
+
+    /// Store a new I²C address for the camera in its EEPROM.
+    ///
+    /// The camera (and this driver) keep using the current address until the camera is power
+    /// cycled. See the [`eeprom`] module for how the EEPROM is written.
+    pub async fn set_i2c_address_persistent(&mut self, new_address: u8) -> Result<(), Error<I2C>> {
+        eeprom::set_i2c_address_persistent_async::<Clb::Camera, _, _>(
+            &mut self.bus,
+            &mut self.delay,
+            self.address,
+            new_address,
+        )
+        .await
+    }
+
+    /// Store the current control register settings (frame rate, resolution and so on) as the
+    /// camera's power on defaults.
+    pub async fn store_default_control_register(&mut self) -> Result<(), Error<I2C>> {
+        eeprom::store_default_control_register_async::<Clb::Camera, _, _>(
+            &mut self.bus,
+            &mut self.delay,
+            self.address,
+        )
+        .await
+    }
+
+    /// Synchronize with the camera's frame update timing
+    ///
+    /// This function ignores any new data, then forces a new measurement by the camera, only
+    /// returning when that measurement is complete. While waiting, the status register is checked
+    /// every [poll interval][FrameRate::poll_interval] for the current frame rate, with the delay
+    /// used in between.
+    pub async fn synchronize(&mut self) -> Result<(), Error<I2C>> {
+        let poll_interval = self.frame_rate().await?.poll_interval();
+        self.synchronize_polling(poll_interval, None).await
+    }
+
+    /// Synchronize with the camera's frame update timing, giving up after `timeout`.
+    ///
+    /// This works like [`synchronize`][Self::synchronize], but if there is still no new data
+    /// after `timeout`, [`LibraryError::Timeout`] is returned.
+    pub async fn synchronize_with_timeout(&mut self, timeout: Duration) -> Result<(), Error<I2C>> {
+        let poll_interval = self.frame_rate().await?.poll_interval();
+        let max_polls = polls_within(timeout, poll_interval);
+        self.synchronize_polling(poll_interval, Some(max_polls))
+            .await
+    }
+
+    /// Synchronize with the camera's frame update timing, giving up after checking the status
+    /// register `max_polls` times.
+    pub async fn synchronize_with_poll_limit(&mut self, max_polls: u32) -> Result<(), Error<I2C>> {
+        let poll_interval = self.frame_rate().await?.poll_interval();
+        self.synchronize_polling(poll_interval, Some(max_polls))
+            .await
+    }
+
+    /// Start a new measurement, then wait for it, checking the status at most `max_polls` times
+    /// (or forever if it is `None`).
+    async fn synchronize_polling(
+        &mut self,
+        poll_interval: Duration,
+        max_polls: Option<u32>,
+    ) -> Result<(), Error<I2C>> {
+        let poll_interval = saturating_micros(poll_interval);
+        let mut status_register = self.status_register().await?;
+        status_register.reset_new_data();
+        status_register.set_overwrite_enabled(true);
+        status_register.set_start_measurement();
+        self.set_status_register(status_register).await?;
+        let mut polls = 0;
+        while !status_register.new_data() {
+            if max_polls.is_some_and(|max_polls| polls >= max_polls) {
+                return Err(LibraryError::Timeout.into());
+            }
+            self.delay.delay_us(poll_interval).await;
+            status_register = self.status_register().await?;
+            polls += 1;
+        }
+        Ok(())
+    }
+}
+
+impl<Clb, I2C, D, const HEIGHT: usize, const BUFFER_SIZE: usize>
+    AsyncCameraDriver<Clb, I2C, D, HEIGHT, BUFFER_SIZE>
+where
+    Clb: for<'a> CalibrationData<'a>,
+    I2C: I2c,
+    D: DelayNs,
+{
+    /// Take a single frame in step mode, writing the temperatures into `destination`.
+    ///
+    /// See [`CameraDriver::measure_frame`][crate::CameraDriver::measure_frame] for details.
+    pub async fn measure_frame(&mut self, destination: &mut [f32]) -> Result<(), Error<I2C>> {
+        let mut control = self.control_register().await?;
+        if !control.step_mode() {
+            control.set_step_mode(true);
+            self.set_control_register(control).await?;
+        }
+        let frame_rate = control.frame_rate();
+        let poll_interval = frame_rate.poll_interval();
+        let max_polls = polls_within(Duration::from(frame_rate) * 2, poll_interval);
+        self.processor.frame_assembler.reset();
+        for _ in 0..2 {
+            self.synchronize_polling(poll_interval, Some(max_polls))
+                .await?;
+            let metadata = self.process_new_data(None, destination).await?;
+            if metadata.is_some_and(|metadata| metadata.frame_complete) {
+                return Ok(());
+            }
+        }
+        Err(LibraryError::Other("Step mode measurements did not cover a full frame").into())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use embassy_futures::block_on;
+    use embedded_hal_async::delay::DelayNs;
+    use float_cmp::approx_eq;
+
+    use mlx9064x_test_data::*;
+
+    use crate::{mlx90640, AccessPattern, AsyncMlx90640Driver, FrameRate, MelexisCamera, Subpage};
+    use crate::{Error, LibraryError};
+
+    use super::Duration;
+
+    /// A delay that doesn't wait, but marks new data as available after a number of delays.
+    struct MockDelay {
+        bus: MockCameraBus<MLX90640_RAM_LENGTH>,
+        delays_until_ready: usize,
+        delay_count: usize,
+        total_us: u64,
+    }
+
+    impl MockDelay {
+        fn new(bus: &MockCameraBus<MLX90640_RAM_LENGTH>, delays_until_ready: usize) -> Self {
+            Self {
+                bus: bus.clone(),
+                delays_until_ready,
+                delay_count: 0,
+                total_us: 0,
+            }
+        }
+    }
+
+    impl DelayNs for MockDelay {
+        async fn delay_ns(&mut self, ns: u32) {
+            self.delay_count += 1;
+            self.total_us += u64::from(ns / 1000);
+            if self.delay_count == self.delays_until_ready {
+                self.bus.set_data_available(true);
+            }
+        }
+
+        async fn delay_us(&mut self, us: u32) {
+            self.delay_ns(us.saturating_mul(1000)).await
+        }
+    }
+
+    fn create_mlx90640(
+        address: u8,
+        delays_until_ready: usize,
+    ) -> (
+        MockCameraBus<MLX90640_RAM_LENGTH>,
+        AsyncMlx90640Driver<MockCameraBus<MLX90640_RAM_LENGTH>, MockDelay>,
+    ) {
+        let mocked = example_mlx90640_at_address(address);
+        let delay = MockDelay::new(&mocked, delays_until_ready);
+        let cam = block_on(AsyncMlx90640Driver::new(mocked.clone(), delay, address))
+            .expect("A MLX90640 camera should be created after loading its data");
+        (mocked, cam)
+    }
+
+    #[test]
+    fn mlx90640_example_integration() {
+        let (mut mocked, mut cam) = create_mlx90640(0x43, 0);
+        mocked.set_data_available(false);
+        let mut temperatures = [f32::NAN; mlx90640::Mlx90640::NUM_PIXELS];
+        assert!(!block_on(cam.generate_image_if_ready(&mut temperatures)).unwrap());
+        assert!(temperatures.iter().all(|t| t.is_nan()));
+        mocked.set_data_available(true);
+        assert!(block_on(cam.generate_image_if_ready(&mut temperatures)).unwrap());
+        mocked.update_frame(
+            mlx90640_example_data::FRAME_1_DATA,
+            mlx90640_example_data::FRAME_1_STATUS_REGISTER,
+        );
+        mocked.set_data_available(true);
+        assert!(block_on(cam.generate_full_frame_if_ready(&mut temperatures)).unwrap());
+        let paired = temperatures
+            .iter()
+            .zip(mlx90640_example_data::TEMPERATURES.iter());
+        for (index, (actual, expected)) in paired.enumerate() {
+            assert!(
+                approx_eq!(f32, *actual, *expected, epsilon = 0.001),
+                "[pixel {}]: expected {}, actual {}",
+                index,
+                expected,
+                actual
+            );
+        }
+    }
+
+    #[test]
+    fn register_setters() {
+        let (mocked, mut cam) = create_mlx90640(0x33, 0);
+        block_on(cam.set_frame_rate(FrameRate::Sixteen)).unwrap();
+        assert_eq!(block_on(cam.frame_rate()).unwrap(), FrameRate::Sixteen);
+        // Setting the same value again doesn't write to the camera
+        mocked.clear_recent_operations();
+        block_on(cam.set_frame_rate(FrameRate::Sixteen)).unwrap();
+        assert_eq!(mocked.recent_operations().len(), 1);
+        block_on(cam.set_access_pattern(AccessPattern::Interleave)).unwrap();
+        assert_eq!(
+            block_on(cam.access_pattern()).unwrap(),
+            AccessPattern::Interleave
+        );
+        block_on(cam.set_subpage_repeat(true)).unwrap();
+        block_on(cam.set_subpage(Subpage::One)).unwrap();
+        assert!(block_on(cam.subpage_repeat()).unwrap());
+        assert_eq!(block_on(cam.selected_subpage()).unwrap(), Subpage::One);
+    }
+
+    #[test]
+    fn synchronize() {
+        let (mut mocked, mut cam) = create_mlx90640(0x33, 3);
+        mocked.set_data_available(true);
+        block_on(cam.set_frame_rate(FrameRate::Four)).unwrap();
+        block_on(cam.synchronize()).unwrap();
+        // The existing data was ignored, and the camera was polled until the new data arrived.
+        assert_eq!(cam.delay.delay_count, 3);
+        // 16 polls per frame at 4 FPS
+        assert_eq!(cam.delay.total_us, 3 * 250_000 / 16);
+        assert!(block_on(cam.data_available()).unwrap().is_some());
+    }
+
+    #[test]
+    fn persistent_settings() {
+        let (mocked, mut cam) = create_mlx90640(0x33, 0);
+        block_on(cam.set_frame_rate(FrameRate::Four)).unwrap();
+        block_on(cam.store_default_control_register()).unwrap();
+        block_on(cam.set_i2c_address_persistent(0x34)).unwrap();
+        // Two 10ms delays for each write
+        assert_eq!(cam.delay.total_us, 40_000);
+        let address = mocked.get(0x240F.into(), 2).unwrap();
+        assert_eq!(address[1], 0x34);
+        let control = mocked.get(0x240C.into(), 2).unwrap();
+        // The frame rate is bits 7 through 9
+        assert_eq!(
+            (u16::from_be_bytes([control[0], control[1]]) >> 7) & 0x7,
+            0x3
+        );
+    }
+
+    #[test]
+    fn synchronize_with_timeout() {
+        let (_mocked, mut cam) = create_mlx90640(0x33, 0);
+        assert!(matches!(
+            block_on(cam.synchronize_with_timeout(Duration::from_millis(250))),
+            Err(Error::LibraryError(LibraryError::Timeout))
+        ));
+        // 2 Hz, so 8 polls 31.25ms apart
+        assert_eq!(cam.delay.delay_count, 8);
+        let (_mocked, mut cam) = create_mlx90640(0x33, 2);
+        block_on(cam.synchronize_with_poll_limit(2)).unwrap();
+    }
+
+    #[test]
+    fn measure_frame() {
+        let (mocked, mut cam) = create_mlx90640(0x33, 0);
+        let mut temperatures = [f32::NAN; mlx90640::Mlx90640::NUM_PIXELS];
+        block_on(cam.measure_frame(&mut temperatures)).unwrap();
+        assert!(block_on(cam.step_mode()).unwrap());
+        assert_eq!(mocked.step_measurements(), 2);
+        assert!(temperatures.iter().all(|t| !t.is_nan()));
+    }
+}
@@ -0,0 +1,1000 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright © 2021 Will Ross
+
+//! Storing processed calibration data as a binary blob.
+//!
+//! Loading the calibration data from a camera requires reading the entire EEPROM over I²C and then
+//! processing it, which is slow and keeps several kilobytes of calibration values in RAM. Instead,
+//! the processed calibration data can be written to a blob once with [`write_blob`], stored
+//! somewhere like a microcontroller's flash memory, and then used directly with
+//! [`CalibrationBlob`]. The per-pixel values are not copied out of the blob, only a few scalar
+//! values are.
+//!
+//! ```
+//! use mlx9064x::blob::{max_blob_length, write_blob, AlignedBlob, CalibrationBlob};
+//! use mlx9064x::mlx90640::{Mlx90640, Mlx90640Calibration};
+//! use mlx9064x::CalibrationData;
+//! use mlx9064x_test_data::mlx90640_datasheet_eeprom;
+//!
+//! let calibration = Mlx90640Calibration::from_data(&mlx90640_datasheet_eeprom())?;
+//! let mut buffer = AlignedBlob([0u8; max_blob_length::<Mlx90640>()]);
+//! let length = write_blob(&calibration, &mut buffer.0)?;
+//! // The blob would normally be saved somewhere at this point, then used on the next boot
+//! let stored = CalibrationBlob::<Mlx90640>::new(&buffer.0[..length])?;
+//! assert_eq!(stored.gain(), calibration.gain());
+//! # Ok::<(), mlx9064x::LibraryError>(())
+//! ```
+//!
+//! # Format
+//! All values are little-endian, and every section of the blob starts on a 4-byte boundary. The
+//! blob starts with a 16 byte header:
+//!
+//! | Offset | Length | Contents                                     |
+//! |--------|--------|----------------------------------------------|
+//! | 0      | 4      | The magic bytes `MLXC`                       |
+//! | 4      | 2      | The format version, [`FORMAT_VERSION`]       |
+//! | 6      | 2      | The camera model, [`BlobCamera::BLOB_ID`]    |
+//! | 8      | 4      | The length of the data following the header  |
+//! | 12     | 4      | The CRC-32 of the data following the header  |
+//!
+//! The header is followed by the scalar calibration values, then the per-pixel values. Per-pixel
+//! values that are the same for both subpages are only stored once, and access pattern
+//! compensation values are only stored for access patterns that use them.
+use core::iter;
+use core::marker::PhantomData;
+use core::mem::size_of;
+use core::slice;
+
+use bitvec::order::Lsb0;
+use bitvec::slice::BitSlice;
+
+use crate::common::{CalibrationData, FlaggedPixels, MelexisCamera};
+use crate::error::LibraryError;
+use crate::expose_member;
+use crate::mlx90640::Mlx90640;
+use crate::mlx90641::Mlx90641;
+use crate::register::{AccessPattern, Resolution, Subpage};
+
+/// The version of the blob format written by [`write_blob`].
+///
+/// [`CalibrationBlob`] will only accept blobs with this version.
+pub const FORMAT_VERSION: u16 = 1;
+
+const MAGIC: [u8; 4] = *b"MLXC";
+
+const HEADER_LENGTH: usize = 16;
+
+/// The length of the scalar values at the start of the payload.
+const SCALARS_LENGTH: usize = 96;
+
+/// The most corner temperatures any of the supported cameras have (the MLX90641 has 8).
+const MAX_CORNER_TEMPERATURES: usize = 8;
+
+const FLAG_EMISSIVITY: u32 = 1 << 0;
+const FLAG_TEMPERATURE_GRADIENT: u32 = 1 << 1;
+const FLAG_OFFSET_PER_SUBPAGE: u32 = 1 << 2;
+const FLAG_ALPHA_PER_SUBPAGE: u32 = 1 << 3;
+const FLAG_K_V_PER_SUBPAGE: u32 = 1 << 4;
+const FLAG_K_TA_PER_SUBPAGE: u32 = 1 << 5;
+/// Shifted by the access pattern index.
+const FLAG_ACCESS_PATTERN_PIXELS: u32 = 1 << 6;
+/// Shifted by `subpage * 2 + access pattern index`.
+const FLAG_ACCESS_PATTERN_CP: u32 = 1 << 8;
+
+const ACCESS_PATTERNS: [AccessPattern; 2] = [AccessPattern::Chess, AccessPattern::Interleave];
+
+const SUBPAGES: [Subpage; 2] = [Subpage::Zero, Subpage::One];
+
+/// Cameras that calibration blobs can be created for.
+pub trait BlobCamera: MelexisCamera {
+    /// The identifier stored in the blob header, to prevent using a blob with the wrong camera.
+    const BLOB_ID: u16;
+}
+
+impl BlobCamera for Mlx90640 {
+    const BLOB_ID: u16 = 0x0640;
+}
+
+impl BlobCamera for Mlx90641 {
+    const BLOB_ID: u16 = 0x0641;
+}
+
+/// Force the wrapped value to be aligned to 4 bytes.
+///
+/// [`CalibrationBlob`] borrows values directly from the blob, so the blob needs to be aligned.
+/// Byte arrays (like those from `include_bytes!`) have no alignment requirement, so they can be
+/// wrapped in this type to fix that:
+///
+/// ```
+/// # use mlx9064x::blob::AlignedBlob;
+/// # const BLOB: [u8; 6] = *b"\x00\x01\x02\x03\x04\x05";
+/// // Normally this would be `AlignedBlob(*include_bytes!("calibration.bin"))`
+/// static CALIBRATION: &AlignedBlob<[u8]> = &AlignedBlob(BLOB);
+/// assert_eq!(CALIBRATION.0.as_ptr() as usize % 4, 0);
+/// ```
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[repr(C, align(4))]
+pub struct AlignedBlob<B: ?Sized>(pub B);
+
+/// The maximum length of a blob for a camera model.
+///
+/// Blobs are usually shorter than this, as values that are the same for both subpages are only
+/// stored once.
+pub const fn max_blob_length<Cam: MelexisCamera>() -> usize {
+    let corners = padded(MAX_CORNER_TEMPERATURES * size_of::<i16>())
+        + 2 * MAX_CORNER_TEMPERATURES * size_of::<f32>();
+    // Offsets for both subpages, then alpha, k_V and k_Ta for both subpages, then the access
+    // pattern compensation for both access patterns.
+    let pixels = 2 * padded(Cam::NUM_PIXELS * size_of::<i16>())
+        + (3 * 2 + 2) * Cam::NUM_PIXELS * size_of::<f32>();
+    let flags = 2 * flags_length(Cam::NUM_PIXELS);
+    HEADER_LENGTH + SCALARS_LENGTH + corners + pixels + flags
+}
+
+const fn padded(length: usize) -> usize {
+    length.next_multiple_of(4)
+}
+
+/// The length of a bitmap of flagged pixels, stored as 32-bit words.
+const fn flags_length(num_pixels: usize) -> usize {
+    num_pixels.div_ceil(u32::BITS as usize) * size_of::<u32>()
+}
+
+/// The CRC-32 (the one used by zlib and Ethernet) of some data.
+fn crc32(data: &[u8]) -> u32 {
+    !data.iter().fold(!0u32, |crc, byte| {
+        (0..u8::BITS).fold(crc ^ u32::from(*byte), |crc, _| {
+            if crc & 1 == 1 {
+                (crc >> 1) ^ 0xEDB8_8320
+            } else {
+                crc >> 1
+            }
+        })
+    })
+}
+
+fn access_pattern_index(access_pattern: AccessPattern) -> usize {
+    match access_pattern {
+        AccessPattern::Chess => 0,
+        AccessPattern::Interleave => 1,
+    }
+}
+
+/// A cursor for writing values into a buffer.
+struct Writer<'w> {
+    buffer: &'w mut [u8],
+    position: usize,
+}
+
+impl Writer<'_> {
+    fn reserve(&mut self, length: usize) -> Result<&mut [u8], LibraryError> {
+        let start = self.position;
+        let end = start + length;
+        if end > self.buffer.len() {
+            return Err(LibraryError::Other(
+                "The buffer is too small for the calibration blob",
+            ));
+        }
+        self.position = end;
+        Ok(&mut self.buffer[start..end])
+    }
+
+    fn put(&mut self, bytes: &[u8]) -> Result<(), LibraryError> {
+        self.reserve(bytes.len())?.copy_from_slice(bytes);
+        Ok(())
+    }
+
+    fn pad(&mut self) -> Result<(), LibraryError> {
+        let padding = padded(self.position) - self.position;
+        self.reserve(padding)?.fill(0);
+        Ok(())
+    }
+
+    /// Write all of the values from an iterator, checking that there are as many as expected.
+    fn put_all<T, const N: usize>(
+        &mut self,
+        values: impl Iterator<Item = T>,
+        expected: usize,
+        to_bytes: fn(T) -> [u8; N],
+    ) -> Result<(), LibraryError> {
+        let mut count = 0;
+        for value in values {
+            self.put(&to_bytes(value))?;
+            count += 1;
+        }
+        if count != expected {
+            return Err(LibraryError::InvalidData(
+                "The calibration data has an unexpected number of values",
+            ));
+        }
+        self.pad()
+    }
+
+    fn put_flags<F: FlaggedPixels>(
+        &mut self,
+        flagged: F,
+        num_pixels: usize,
+    ) -> Result<(), LibraryError> {
+        let bitmap = self.reserve(flags_length(num_pixels))?;
+        bitmap.fill(0);
+        // With little-endian words, the bits are in the same order as the bytes.
+        for index in flagged.iter_flagged().filter(|index| *index < num_pixels) {
+            bitmap[index / 8] |= 1 << (index % 8);
+        }
+        Ok(())
+    }
+}
+
+/// A cursor for reading values out of a blob.
+struct Reader<'b>(&'b [u8]);
+
+impl<'b> Reader<'b> {
+    fn take(&mut self, length: usize) -> Result<&'b [u8], LibraryError> {
+        if length > self.0.len() {
+            return Err(LibraryError::InvalidData(
+                "The calibration blob is too short",
+            ));
+        }
+        let (taken, rest) = self.0.split_at(length);
+        self.0 = rest;
+        Ok(taken)
+    }
+
+    fn get<const N: usize>(&mut self) -> Result<[u8; N], LibraryError> {
+        let mut bytes = [0u8; N];
+        bytes.copy_from_slice(self.take(N)?);
+        Ok(bytes)
+    }
+
+    fn get_f32s<const N: usize>(&mut self) -> Result<[f32; N], LibraryError> {
+        let mut values = [0f32; N];
+        for value in values.iter_mut() {
+            *value = f32::from_le_bytes(self.get()?);
+        }
+        Ok(values)
+    }
+
+    /// Borrow `count` values directly from the blob.
+    fn get_slice<T: bytemuck::Pod>(&mut self, count: usize) -> Result<&'b [T], LibraryError> {
+        let length = count * size_of::<T>();
+        let bytes = self.take(padded(length))?;
+        bytemuck::try_cast_slice(&bytes[..length]).map_err(|_| {
+            LibraryError::InvalidData("The calibration blob is not aligned to 4 bytes")
+        })
+    }
+
+    /// Borrow per-pixel values for each subpage, which may be shared between the subpages.
+    fn get_subpage_slices<T: bytemuck::Pod>(
+        &mut self,
+        per_subpage: bool,
+        count: usize,
+    ) -> Result<[&'b [T]; 2], LibraryError> {
+        let first = self.get_slice(count)?;
+        let second = if per_subpage {
+            self.get_slice(count)?
+        } else {
+            first
+        };
+        Ok([first, second])
+    }
+}
+
+/// Write calibration data into a blob, returning the length of the blob.
+///
+/// Any [`CalibrationData`] implementation can be written, including
+/// [`Mlx90640Calibration`][crate::mlx90640::Mlx90640Calibration] and
+/// [`Mlx90641Calibration`][crate::mlx90641::Mlx90641Calibration]. `buffer` needs to be large
+/// enough for the blob, [`max_blob_length`] is always large enough.
+pub fn write_blob<'a, Clb>(calibration: &'a Clb, buffer: &mut [u8]) -> Result<usize, LibraryError>
+where
+    Clb: CalibrationData<'a>,
+    Clb::Camera: BlobCamera,
+{
+    let num_pixels = Clb::Camera::NUM_PIXELS;
+    let num_corners = calibration.corner_temperatures().len();
+    if num_corners > MAX_CORNER_TEMPERATURES
+        || calibration.k_s_to().len() != num_corners
+        || calibration.alpha_correction().len() != num_corners
+    {
+        return Err(LibraryError::InvalidData(
+            "The calibration data has an unexpected number of corner temperatures",
+        ));
+    }
+    let mut flags = 0;
+    if calibration.emissivity().is_some() {
+        flags |= FLAG_EMISSIVITY;
+    }
+    if calibration.temperature_gradient_coefficient().is_some() {
+        flags |= FLAG_TEMPERATURE_GRADIENT;
+    }
+    if !calibration
+        .offset_reference_pixels(Subpage::Zero)
+        .eq(calibration.offset_reference_pixels(Subpage::One))
+    {
+        flags |= FLAG_OFFSET_PER_SUBPAGE;
+    }
+    if !calibration
+        .alpha_pixels(Subpage::Zero)
+        .eq(calibration.alpha_pixels(Subpage::One))
+    {
+        flags |= FLAG_ALPHA_PER_SUBPAGE;
+    }
+    if !calibration
+        .k_v_pixels(Subpage::Zero)
+        .eq(calibration.k_v_pixels(Subpage::One))
+    {
+        flags |= FLAG_K_V_PER_SUBPAGE;
+    }
+    if !calibration
+        .k_ta_pixels(Subpage::Zero)
+        .eq(calibration.k_ta_pixels(Subpage::One))
+    {
+        flags |= FLAG_K_TA_PER_SUBPAGE;
+    }
+    for (index, access_pattern) in ACCESS_PATTERNS.into_iter().enumerate() {
+        let mut present = calibration
+            .access_pattern_compensation_pixels(access_pattern)
+            .map(|value| value.is_some());
+        let first = present.next().unwrap_or_default();
+        if present.any(|present| present != first) {
+            return Err(LibraryError::InvalidData(
+                "Access pattern compensation must be present for either all pixels or none",
+            ));
+        }
+        if first {
+            flags |= FLAG_ACCESS_PATTERN_PIXELS << index;
+        }
+        for subpage in SUBPAGES {
+            if calibration
+                .access_pattern_compensation_cp(subpage, access_pattern)
+                .is_some()
+            {
+                flags |= FLAG_ACCESS_PATTERN_CP << (subpage as usize * 2 + index);
+            }
+        }
+    }
+    // Only write the first subpage's values if they're shared
+    let subpages = |flag: u32| {
+        if flags & flag != 0 {
+            &SUBPAGES[..]
+        } else {
+            &SUBPAGES[..1]
+        }
+    };
+
+    let mut writer = Writer {
+        buffer: &mut *buffer,
+        position: HEADER_LENGTH,
+    };
+    writer.put(&flags.to_le_bytes())?;
+    writer.put(&calibration.k_v_dd().to_le_bytes())?;
+    writer.put(&calibration.v_dd_25().to_le_bytes())?;
+    writer.put(&[u8::from(calibration.resolution()), num_corners as u8, 0, 0])?;
+    for value in [
+        calibration.v_dd_0(),
+        calibration.k_v_ptat(),
+        calibration.k_t_ptat(),
+        calibration.v_ptat_25(),
+        calibration.alpha_ptat(),
+        calibration.gain(),
+        calibration.k_s_ta(),
+        calibration.emissivity().unwrap_or_default(),
+        calibration
+            .temperature_gradient_coefficient()
+            .unwrap_or_default(),
+    ] {
+        writer.put(&value.to_le_bytes())?;
+    }
+    for subpage in SUBPAGES {
+        writer.put(&calibration.offset_reference_cp(subpage).to_le_bytes())?;
+        writer.pad()?;
+        writer.put(&calibration.alpha_cp(subpage).to_le_bytes())?;
+        writer.put(&calibration.k_v_cp(subpage).to_le_bytes())?;
+        writer.put(&calibration.k_ta_cp(subpage).to_le_bytes())?;
+        for access_pattern in ACCESS_PATTERNS {
+            let compensation = calibration.access_pattern_compensation_cp(subpage, access_pattern);
+            writer.put(&compensation.unwrap_or_default().to_le_bytes())?;
+        }
+    }
+    debug_assert_eq!(writer.position, HEADER_LENGTH + SCALARS_LENGTH);
+
+    let corner_temperatures = calibration.corner_temperatures().iter().copied();
+    writer.put_all(corner_temperatures, num_corners, i16::to_le_bytes)?;
+    let k_s_to = calibration.k_s_to().iter().copied();
+    writer.put_all(k_s_to, num_corners, f32::to_le_bytes)?;
+    let alpha_correction = calibration.alpha_correction().iter().copied();
+    writer.put_all(alpha_correction, num_corners, f32::to_le_bytes)?;
+    for subpage in subpages(FLAG_OFFSET_PER_SUBPAGE) {
+        let offsets = calibration.offset_reference_pixels(*subpage).copied();
+        writer.put_all(offsets, num_pixels, i16::to_le_bytes)?;
+    }
+    for subpage in subpages(FLAG_ALPHA_PER_SUBPAGE) {
+        let alpha = calibration.alpha_pixels(*subpage).copied();
+        writer.put_all(alpha, num_pixels, f32::to_le_bytes)?;
+    }
+    for subpage in subpages(FLAG_K_V_PER_SUBPAGE) {
+        let k_v = calibration.k_v_pixels(*subpage).copied();
+        writer.put_all(k_v, num_pixels, f32::to_le_bytes)?;
+    }
+    for subpage in subpages(FLAG_K_TA_PER_SUBPAGE) {
+        let k_ta = calibration.k_ta_pixels(*subpage).copied();
+        writer.put_all(k_ta, num_pixels, f32::to_le_bytes)?;
+    }
+    for (index, access_pattern) in ACCESS_PATTERNS.into_iter().enumerate() {
+        if flags & (FLAG_ACCESS_PATTERN_PIXELS << index) != 0 {
+            let compensation = calibration
+                .access_pattern_compensation_pixels(access_pattern)
+                .flatten()
+                .copied();
+            writer.put_all(compensation, num_pixels, f32::to_le_bytes)?;
+        }
+    }
+    writer.put_flags(calibration.failed_pixels(), num_pixels)?;
+    writer.put_flags(calibration.outlier_pixels(), num_pixels)?;
+    let length = writer.position;
+
+    let payload_length = (length - HEADER_LENGTH) as u32;
+    let checksum = crc32(&buffer[HEADER_LENGTH..length]);
+    buffer[0..4].copy_from_slice(&MAGIC);
+    buffer[4..6].copy_from_slice(&FORMAT_VERSION.to_le_bytes());
+    buffer[6..8].copy_from_slice(&Clb::Camera::BLOB_ID.to_le_bytes());
+    buffer[8..12].copy_from_slice(&payload_length.to_le_bytes());
+    buffer[12..16].copy_from_slice(&checksum.to_le_bytes());
+    Ok(length)
+}
+
+/// Calibration data borrowed from a blob created by [`write_blob`].
+///
+/// Only the scalar values are copied out of the blob, the per-pixel values are used directly from
+/// the blob. With the blob stored in flash, this saves several kilobytes of RAM compared to the
+/// calibration types that parse the camera's EEPROM.
+#[derive(Clone, Debug, PartialEq)]
+pub struct CalibrationBlob<'b, Cam> {
+    k_v_dd: i16,
+
+    v_dd_25: i16,
+
+    resolution: Resolution,
+
+    v_dd_0: f32,
+
+    k_v_ptat: f32,
+
+    k_t_ptat: f32,
+
+    v_ptat_25: f32,
+
+    alpha_ptat: f32,
+
+    gain: f32,
+
+    k_s_ta: f32,
+
+    emissivity: Option<f32>,
+
+    temperature_gradient_coefficient: Option<f32>,
+
+    offset_reference_cp: [i16; 2],
+
+    alpha_cp: [f32; 2],
+
+    k_v_cp: [f32; 2],
+
+    k_ta_cp: [f32; 2],
+
+    /// Indexed by subpage, then access pattern.
+    access_pattern_compensation_cp: [[Option<f32>; 2]; 2],
+
+    corner_temperatures: &'b [i16],
+
+    k_s_to: &'b [f32],
+
+    alpha_correction: &'b [f32],
+
+    offset_reference_pixels: [&'b [i16]; 2],
+
+    alpha_pixels: [&'b [f32]; 2],
+
+    k_v_pixels: [&'b [f32]; 2],
+
+    k_ta_pixels: [&'b [f32]; 2],
+
+    access_pattern_compensation_pixels: [Option<&'b [f32]>; 2],
+
+    failed_pixels: &'b [u32],
+
+    outlier_pixels: &'b [u32],
+
+    camera: PhantomData<Cam>,
+}
+
+impl<'b, Cam: BlobCamera> CalibrationBlob<'b, Cam> {
+    /// Use a blob created by [`write_blob`] as calibration data.
+    ///
+    /// The blob is checked to make sure it's for the right camera, uses the current
+    /// [`FORMAT_VERSION`], and that the checksum matches. The blob must be aligned to 4 bytes (see
+    /// [`AlignedBlob`]), and any bytes after the end of the blob are ignored. Blobs can only be
+    /// used on little-endian platforms.
+    pub fn new(blob: &'b [u8]) -> Result<Self, LibraryError> {
+        if cfg!(target_endian = "big") {
+            return Err(LibraryError::Other(
+                "Calibration blobs can only be used on little-endian platforms",
+            ));
+        }
+        if blob.as_ptr() as usize % 4 != 0 {
+            return Err(LibraryError::InvalidData(
+                "The calibration blob is not aligned to 4 bytes",
+            ));
+        }
+        let mut header = Reader(blob);
+        if header.get()? != MAGIC {
+            return Err(LibraryError::InvalidData(
+                "The data is not a calibration blob",
+            ));
+        }
+        if u16::from_le_bytes(header.get()?) != FORMAT_VERSION {
+            return Err(LibraryError::InvalidData(
+                "The calibration blob version is not supported",
+            ));
+        }
+        if u16::from_le_bytes(header.get()?) != Cam::BLOB_ID {
+            return Err(LibraryError::InvalidData(
+                "The calibration blob is for a different camera model",
+            ));
+        }
+        let length = u32::from_le_bytes(header.get()?) as usize;
+        let checksum = u32::from_le_bytes(header.get()?);
+        let payload = header.take(length)?;
+        if crc32(payload) != checksum {
+            return Err(LibraryError::InvalidData(
+                "The calibration blob checksum does not match",
+            ));
+        }
+
+        let mut reader = Reader(payload);
+        let flags = u32::from_le_bytes(reader.get()?);
+        let is_set = |flag: u32| flags & flag != 0;
+        let k_v_dd = i16::from_le_bytes(reader.get()?);
+        let v_dd_25 = i16::from_le_bytes(reader.get()?);
+        let [resolution, num_corners, _, _] = reader.get()?;
+        let resolution = Resolution::try_from(resolution)?;
+        let num_corners = usize::from(num_corners);
+        if num_corners > MAX_CORNER_TEMPERATURES {
+            return Err(LibraryError::InvalidData(
+                "The calibration blob has too many corner temperatures",
+            ));
+        }
+        let [v_dd_0, k_v_ptat, k_t_ptat, v_ptat_25, alpha_ptat, gain, k_s_ta, emissivity, tgc] =
+            reader.get_f32s()?;
+        let mut offset_reference_cp = [0i16; 2];
+        let mut alpha_cp = [0f32; 2];
+        let mut k_v_cp = [0f32; 2];
+        let mut k_ta_cp = [0f32; 2];
+        let mut access_pattern_compensation_cp = [[None; 2]; 2];
+        for subpage in 0..2 {
+            let [low, high, _, _] = reader.get()?;
+            offset_reference_cp[subpage] = i16::from_le_bytes([low, high]);
+            let [alpha, k_v, k_ta, chess, interleave] = reader.get_f32s()?;
+            alpha_cp[subpage] = alpha;
+            k_v_cp[subpage] = k_v;
+            k_ta_cp[subpage] = k_ta;
+            for (index, compensation) in [chess, interleave].into_iter().enumerate() {
+                access_pattern_compensation_cp[subpage][index] =
+                    is_set(FLAG_ACCESS_PATTERN_CP << (subpage * 2 + index)).then_some(compensation);
+            }
+        }
+
+        let num_pixels = Cam::NUM_PIXELS;
+        let corner_temperatures = reader.get_slice(num_corners)?;
+        let k_s_to = reader.get_slice(num_corners)?;
+        let alpha_correction = reader.get_slice(num_corners)?;
+        let offset_reference_pixels =
+            reader.get_subpage_slices(is_set(FLAG_OFFSET_PER_SUBPAGE), num_pixels)?;
+        let alpha_pixels = reader.get_subpage_slices(is_set(FLAG_ALPHA_PER_SUBPAGE), num_pixels)?;
+        let k_v_pixels = reader.get_subpage_slices(is_set(FLAG_K_V_PER_SUBPAGE), num_pixels)?;
+        let k_ta_pixels = reader.get_subpage_slices(is_set(FLAG_K_TA_PER_SUBPAGE), num_pixels)?;
+        let mut access_pattern_compensation_pixels = [None; 2];
+        for (index, compensation) in access_pattern_compensation_pixels.iter_mut().enumerate() {
+            if is_set(FLAG_ACCESS_PATTERN_PIXELS << index) {
+                *compensation = Some(reader.get_slice(num_pixels)?);
+            }
+        }
+        let flag_words = flags_length(num_pixels) / size_of::<u32>();
+        let failed_pixels = reader.get_slice(flag_words)?;
+        let outlier_pixels = reader.get_slice(flag_words)?;
+        if !reader.0.is_empty() {
+            return Err(LibraryError::InvalidData(
+                "The calibration blob has an unexpected length",
+            ));
+        }
+
+        Ok(Self {
+            k_v_dd,
+            v_dd_25,
+            resolution,
+            v_dd_0,
+            k_v_ptat,
+            k_t_ptat,
+            v_ptat_25,
+            alpha_ptat,
+            gain,
+            k_s_ta,
+            emissivity: is_set(FLAG_EMISSIVITY).then_some(emissivity),
+            temperature_gradient_coefficient: is_set(FLAG_TEMPERATURE_GRADIENT).then_some(tgc),
+            offset_reference_cp,
+            alpha_cp,
+            k_v_cp,
+            k_ta_cp,
+            access_pattern_compensation_cp,
+            corner_temperatures,
+            k_s_to,
+            alpha_correction,
+            offset_reference_pixels,
+            alpha_pixels,
+            k_v_pixels,
+            k_ta_pixels,
+            access_pattern_compensation_pixels,
+            failed_pixels,
+            outlier_pixels,
+            camera: PhantomData,
+        })
+    }
+}
+
+impl<'a, 'b: 'a, Cam: BlobCamera> CalibrationData<'a> for CalibrationBlob<'b, Cam> {
+    type Camera = Cam;
+
+    expose_member!(k_v_dd, i16);
+    expose_member!(v_dd_25, i16);
+    expose_member!(resolution, Resolution);
+    expose_member!(v_dd_0, f32);
+    expose_member!(k_v_ptat, f32);
+    expose_member!(k_t_ptat, f32);
+    expose_member!(v_ptat_25, f32);
+    expose_member!(alpha_ptat, f32);
+    expose_member!(gain, f32);
+    expose_member!(k_s_ta, f32);
+
+    expose_member!(&corner_temperatures, [i16]);
+    expose_member!(&k_s_to, [f32]);
+    expose_member!(&alpha_correction, [f32]);
+
+    expose_member!(emissivity, Option<f32>);
+
+    type OffsetReferenceIterator = slice::Iter<'a, i16>;
+
+    fn offset_reference_pixels(&'a self, subpage: Subpage) -> Self::OffsetReferenceIterator {
+        self.offset_reference_pixels[subpage as usize].iter()
+    }
+
+    fn offset_reference_cp(&self, subpage: Subpage) -> i16 {
+        self.offset_reference_cp[subpage as usize]
+    }
+
+    type AlphaIterator = slice::Iter<'a, f32>;
+
+    fn alpha_pixels(&'a self, subpage: Subpage) -> Self::AlphaIterator {
+        self.alpha_pixels[subpage as usize].iter()
+    }
+
+    fn alpha_cp(&self, subpage: Subpage) -> f32 {
+        self.alpha_cp[subpage as usize]
+    }
+
+    type KvIterator = slice::Iter<'a, f32>;
+
+    fn k_v_pixels(&'a self, subpage: Subpage) -> Self::KvIterator {
+        self.k_v_pixels[subpage as usize].iter()
+    }
+
+    fn k_v_cp(&self, subpage: Subpage) -> f32 {
+        self.k_v_cp[subpage as usize]
+    }
+
+    type KtaIterator = slice::Iter<'a, f32>;
+
+    fn k_ta_pixels(&'a self, subpage: Subpage) -> Self::KtaIterator {
+        self.k_ta_pixels[subpage as usize].iter()
+    }
+
+    fn k_ta_cp(&self, subpage: Subpage) -> f32 {
+        self.k_ta_cp[subpage as usize]
+    }
+
+    expose_member!(temperature_gradient_coefficient, Option<f32>);
+
+    type AccessPatternCompensation = BlobAccessPatternCompensation<'a>;
+
+    fn access_pattern_compensation_pixels(
+        &'a self,
+        access_pattern: AccessPattern,
+    ) -> Self::AccessPatternCompensation {
+        match self.access_pattern_compensation_pixels[access_pattern_index(access_pattern)] {
+            Some(compensation) => BlobAccessPatternCompensation::Pixels(compensation.iter()),
+            None => BlobAccessPatternCompensation::Unused(iter::repeat(None).take(Cam::NUM_PIXELS)),
+        }
+    }
+
+    fn access_pattern_compensation_cp(
+        &self,
+        subpage: Subpage,
+        access_pattern: AccessPattern,
+    ) -> Option<f32> {
+        self.access_pattern_compensation_cp[subpage as usize][access_pattern_index(access_pattern)]
+    }
+
+    type FailedPixels = &'a BitSlice<u32, Lsb0>;
+
+    fn failed_pixels(&'a self) -> Self::FailedPixels {
+        &BitSlice::from_slice(self.failed_pixels)[..Cam::NUM_PIXELS]
+    }
+
+    type OutlierPixels = &'a BitSlice<u32, Lsb0>;
+
+    fn outlier_pixels(&'a self) -> Self::OutlierPixels {
+        &BitSlice::from_slice(self.outlier_pixels)[..Cam::NUM_PIXELS]
+    }
+}
+
+/// The access pattern compensation values from a [`CalibrationBlob`].
+#[derive(Clone, Debug)]
+pub enum BlobAccessPatternCompensation<'a> {
+    /// The access pattern has no compensation values.
+    Unused(iter::Take<iter::Repeat<Option<&'a f32>>>),
+
+    /// A compensation value for every pixel.
+    Pixels(slice::Iter<'a, f32>),
+}
+
+impl<'a> Iterator for BlobAccessPatternCompensation<'a> {
+    type Item = Option<&'a f32>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self {
+            BlobAccessPatternCompensation::Unused(inner) => inner.next(),
+            BlobAccessPatternCompensation::Pixels(inner) => inner.next().map(Some),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use mlx9064x_test_data::*;
+
+    use crate::common::{CalibrationData, FlaggedPixels, MelexisCamera};
+    use crate::mlx90640::{Mlx90640, Mlx90640Calibration};
+    use crate::mlx90641::{Mlx90641, Mlx90641Calibration};
+    use crate::{AccessPattern, CameraDriver, LibraryError, Mlx90640Driver, Subpage};
+
+    use super::{
+        crc32, max_blob_length, write_blob, AlignedBlob, BlobCamera, CalibrationBlob,
+        FORMAT_VERSION,
+    };
+
+    /// Large enough for a blob from any of the cameras.
+    const BUFFER_LENGTH: usize = max_blob_length::<Mlx90640>();
+
+    fn mlx90640_blob() -> (AlignedBlob<[u8; BUFFER_LENGTH]>, usize) {
+        let calibration = Mlx90640Calibration::from_data(mlx90640_example_data::EEPROM_DATA)
+            .expect("The example data should be parseable");
+        let mut buffer = AlignedBlob([0u8; BUFFER_LENGTH]);
+        let length = write_blob(&calibration, &mut buffer.0).unwrap();
+        (buffer, length)
+    }
+
+    fn assert_same_calibration<'a, 'b, A, B>(expected: &'a A, actual: &'b B)
+    where
+        A: CalibrationData<'a>,
+        B: CalibrationData<'b, Camera = A::Camera>,
+    {
+        assert_eq!(expected.k_v_dd(), actual.k_v_dd());
+        assert_eq!(expected.v_dd_25(), actual.v_dd_25());
+        assert_eq!(expected.resolution(), actual.resolution());
+        assert_eq!(expected.v_dd_0(), actual.v_dd_0());
+        assert_eq!(expected.k_v_ptat(), actual.k_v_ptat());
+        assert_eq!(expected.k_t_ptat(), actual.k_t_ptat());
+        assert_eq!(expected.v_ptat_25(), actual.v_ptat_25());
+        assert_eq!(expected.alpha_ptat(), actual.alpha_ptat());
+        assert_eq!(expected.gain(), actual.gain());
+        assert_eq!(expected.k_s_ta(), actual.k_s_ta());
+        assert_eq!(expected.corner_temperatures(), actual.corner_temperatures());
+        assert_eq!(expected.k_s_to(), actual.k_s_to());
+        assert_eq!(expected.alpha_correction(), actual.alpha_correction());
+        assert_eq!(expected.emissivity(), actual.emissivity());
+        assert_eq!(
+            expected.temperature_gradient_coefficient(),
+            actual.temperature_gradient_coefficient()
+        );
+        for subpage in [Subpage::Zero, Subpage::One] {
+            assert!(expected
+                .offset_reference_pixels(subpage)
+                .eq(actual.offset_reference_pixels(subpage)));
+            assert_eq!(
+                expected.offset_reference_cp(subpage),
+                actual.offset_reference_cp(subpage)
+            );
+            assert!(expected
+                .alpha_pixels(subpage)
+                .eq(actual.alpha_pixels(subpage)));
+            assert_eq!(expected.alpha_cp(subpage), actual.alpha_cp(subpage));
+            assert!(expected.k_v_pixels(subpage).eq(actual.k_v_pixels(subpage)));
+            assert_eq!(expected.k_v_cp(subpage), actual.k_v_cp(subpage));
+            assert!(expected
+                .k_ta_pixels(subpage)
+                .eq(actual.k_ta_pixels(subpage)));
+            assert_eq!(expected.k_ta_cp(subpage), actual.k_ta_cp(subpage));
+            for access_pattern in [AccessPattern::Chess, AccessPattern::Interleave] {
+                assert_eq!(
+                    expected.access_pattern_compensation_cp(subpage, access_pattern),
+                    actual.access_pattern_compensation_cp(subpage, access_pattern)
+                );
+            }
+        }
+        for access_pattern in [AccessPattern::Chess, AccessPattern::Interleave] {
+            let expected_compensation = expected
+                .access_pattern_compensation_pixels(access_pattern)
+                .map(|compensation| compensation.copied());
+            let actual_compensation = actual
+                .access_pattern_compensation_pixels(access_pattern)
+                .map(|compensation| compensation.copied());
+            assert!(expected_compensation.eq(actual_compensation));
+        }
+        assert!(expected
+            .failed_pixels()
+            .iter_flagged()
+            .eq(actual.failed_pixels().iter_flagged()));
+        assert!(expected
+            .outlier_pixels()
+            .iter_flagged()
+            .eq(actual.outlier_pixels().iter_flagged()));
+    }
+
+    /// Write a blob, check it has the same values, and that writing it again gives the same blob.
+    fn round_trip<'a, Clb>(calibration: &'a Clb)
+    where
+        Clb: CalibrationData<'a>,
+        Clb::Camera: BlobCamera,
+    {
+        let mut buffer = AlignedBlob([0u8; BUFFER_LENGTH]);
+        let length = write_blob(calibration, &mut buffer.0).unwrap();
+        assert!(length <= max_blob_length::<Clb::Camera>());
+        let blob = CalibrationBlob::<Clb::Camera>::new(&buffer.0[..length]).unwrap();
+        assert_same_calibration(calibration, &blob);
+        let mut rewritten = AlignedBlob([0u8; BUFFER_LENGTH]);
+        let rewritten_length = write_blob(&blob, &mut rewritten.0).unwrap();
+        assert_eq!(buffer.0[..length], rewritten.0[..rewritten_length]);
+    }
+
+    #[test]
+    fn crc() {
+        // The standard check value for CRC-32
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+    }
+
+    #[test]
+    fn round_trip_mlx90640() {
+        let example = Mlx90640Calibration::from_data(mlx90640_example_data::EEPROM_DATA).unwrap();
+        round_trip(&example);
+        // Mark a pixel as failed and another as an outlier. The per-pixel calibration data starts
+        // at 0x2440.
+        let mut eeprom = mlx90640_datasheet_eeprom();
+        eeprom[0x80 + 10 * 2..0x80 + 11 * 2].copy_from_slice(&[0, 0]);
+        eeprom[0x80 + 20 * 2 + 1] |= 1;
+        let datasheet = Mlx90640Calibration::from_data(&eeprom).unwrap();
+        assert!(datasheet.failed_pixels().any());
+        assert!(datasheet.outlier_pixels().any());
+        round_trip(&datasheet);
+    }
+
+    #[test]
+    fn round_trip_mlx90641() {
+        let datasheet = Mlx90641Calibration::from_data(&mlx90641_datasheet_eeprom()).unwrap();
+        round_trip(&datasheet);
+    }
+
+    #[test]
+    fn shared_subpage_values() {
+        // The MLX90640 has the same per-pixel values for both subpages, so they're only stored
+        // once. It also only has access pattern compensation values for interleaved mode.
+        let (_, length) = mlx90640_blob();
+        let num_pixels = Mlx90640::NUM_PIXELS;
+        // Header, scalars, then 4 corner temperatures, K_s_To, and alpha corrections
+        let fixed = 16 + 96 + (8 + 16 + 16);
+        // Offsets, then alpha, K_V, K_Ta and interleave compensation, then the two bitmaps
+        let pixels = num_pixels * 2 + num_pixels * 4 * 4 + num_pixels / 8 * 2;
+        assert_eq!(length, fixed + pixels);
+    }
+
+    #[test]
+    fn header() {
+        let (buffer, length) = mlx90640_blob();
+        assert_eq!(&buffer.0[0..4], b"MLXC");
+        assert_eq!(buffer.0[4..6], FORMAT_VERSION.to_le_bytes());
+        assert_eq!(buffer.0[6..8], Mlx90640::BLOB_ID.to_le_bytes());
+        assert_eq!(buffer.0[8..12], ((length - 16) as u32).to_le_bytes());
+        assert_eq!(buffer.0[12..16], crc32(&buffer.0[16..length]).to_le_bytes());
+    }
+
+    #[test]
+    fn trailing_bytes_ignored() {
+        let (buffer, _) = mlx90640_blob();
+        assert!(CalibrationBlob::<Mlx90640>::new(&buffer.0).is_ok());
+    }
+
+    #[test]
+    fn checksum_mismatch() {
+        let (mut buffer, length) = mlx90640_blob();
+        buffer.0[length / 2] ^= 0x10;
+        let result = CalibrationBlob::<Mlx90640>::new(&buffer.0[..length]);
+        assert!(matches!(result, Err(LibraryError::InvalidData(_))));
+    }
+
+    #[test]
+    fn wrong_camera() {
+        let (buffer, length) = mlx90640_blob();
+        assert!(CalibrationBlob::<Mlx90641>::new(&buffer.0[..length]).is_err());
+    }
+
+    #[test]
+    fn unsupported_version() {
+        let (mut buffer, length) = mlx90640_blob();
+        buffer.0[4..6].copy_from_slice(&(FORMAT_VERSION + 1).to_le_bytes());
+        assert!(CalibrationBlob::<Mlx90640>::new(&buffer.0[..length]).is_err());
+    }
+
+    #[test]
+    fn not_a_blob() {
+        let eeprom = AlignedBlob(mlx90640_datasheet_eeprom());
+        assert!(CalibrationBlob::<Mlx90640>::new(&eeprom.0).is_err());
+        assert!(CalibrationBlob::<Mlx90640>::new(&[]).is_err());
+    }
+
+    #[test]
+    fn truncated() {
+        let (buffer, length) = mlx90640_blob();
+        assert!(CalibrationBlob::<Mlx90640>::new(&buffer.0[..length - 4]).is_err());
+    }
+
+    #[test]
+    fn misaligned() {
+        let (buffer, length) = mlx90640_blob();
+        let mut shifted = AlignedBlob([0u8; BUFFER_LENGTH]);
+        shifted.0[1..=length].copy_from_slice(&buffer.0[..length]);
+        let result = CalibrationBlob::<Mlx90640>::new(&shifted.0[1..=length]);
+        assert!(matches!(result, Err(LibraryError::InvalidData(_))));
+    }
+
+    #[test]
+    fn buffer_too_small() {
+        let calibration =
+            Mlx90640Calibration::from_data(mlx90640_example_data::EEPROM_DATA).unwrap();
+        let mut buffer = [0u8; 1024];
+        let result = write_blob(&calibration, &mut buffer);
+        assert!(matches!(result, Err(LibraryError::Other(_))));
+    }
+
+    #[test]
+    fn driver() {
+        let address = 0x33;
+        let mut parsed_mock = example_mlx90640_at_address(address);
+        parsed_mock.set_data_available(true);
+        let mut parsed = Mlx90640Driver::new(parsed_mock, address).unwrap();
+        let mut expected = [0f32; Mlx90640::NUM_PIXELS];
+        assert!(parsed.generate_image_if_ready(&mut expected).unwrap());
+
+        let (buffer, length) = mlx90640_blob();
+        let blob = CalibrationBlob::<Mlx90640>::new(&buffer.0[..length]).unwrap();
+        let mut blob_mock = example_mlx90640_at_address(address);
+        blob_mock.set_data_available(true);
+        let mut stored = CameraDriver::<
+            _,
+            _,
+            { Mlx90640::HEIGHT },
+            { Mlx90640::NUM_PIXELS * 2 },
+        >::new_with_calibration(blob_mock, address, blob)
+        .unwrap();
+        let mut temperatures = [0f32; Mlx90640::NUM_PIXELS];
+        assert!(stored.generate_image_if_ready(&mut temperatures).unwrap());
+        assert_eq!(temperatures, expected);
+    }
+}
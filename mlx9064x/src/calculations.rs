@@ -24,7 +24,12 @@
 //!    [`raw_ir_to_temperatures`]) to the image generated in the previous step.
 //!
 //! [`raw_pixels_to_temperatures`] is a slightly optimized function that applies steps 3 and 4 in
-//! once pass through the input data.
+//! once pass through the input data. If the same calibration data is used for many frames,
+//! [`CompiledCalibration`][crate::compiled::CompiledCalibration] is faster still.
+//!
+//! The functions from steps 3 and 4 take an [`Emissivity`], which is usually a single value for
+//! the entire image, but can also be an [emissivity map][crate::emissivity] giving pixels their
+//! own emissivity and reflected temperature.
 //!
 //! # Reading the Datasheets
 //! The MLX90640 and MLX90641 datasheets have roughly a third of their pages dedicated to
@@ -131,7 +136,8 @@
 
 use core::convert::TryInto;
 
-use embedded_hal::blocking::i2c;
+use arrayvec::ArrayVec;
+use embedded_hal::i2c;
 
 // Various floating point operations are not implemented in core, so we use libm to provide them as
 // needed.
@@ -139,11 +145,13 @@ use embedded_hal::blocking::i2c;
 use num_traits::Float;
 
 use crate::common::{Address, CalibrationData, MelexisCamera};
+use crate::emissivity::{Emissivity, EmissivityMap};
+use crate::filter::CompensationPixelFilter;
 use crate::register::Subpage;
 use crate::AccessPattern;
 
 /// Constant needed a few times for the final pixel temperature calculations.
-const KELVINS_TO_CELSIUS: f32 = 273.15;
+pub(crate) const KELVINS_TO_CELSIUS: f32 = 273.15;
 
 /// Calculate $\Delta V$
 ///
@@ -243,7 +251,8 @@ pub fn ambient_temperature<'a, Clb: CalibrationData<'a>>(
 /// This structure is the non-EEPROM, non-register input when [creating
 /// `CommonIrData`][CommonIrData::new].
 #[doc = include_str!("katex.html")]
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct RamData {
     /// $T_{a_{V_{BE}}}$
     ///
@@ -279,7 +288,7 @@ impl RamData {
         ram_address: Address,
     ) -> Result<i16, I2C::Error>
     where
-        I2C: i2c::WriteRead,
+        I2C: i2c::I2c,
     {
         let address_bytes = ram_address.as_bytes();
         let mut scratch = [0u8; 2];
@@ -298,7 +307,7 @@ impl RamData {
         subpage: Subpage,
     ) -> Result<Self, I2C::Error>
     where
-        I2C: i2c::WriteRead,
+        I2C: i2c::I2c,
         Cam: MelexisCamera,
     {
         let t_a_v_be = Self::read_ram_value(bus, i2c_address, Cam::T_A_V_BE)?;
@@ -315,6 +324,43 @@ impl RamData {
             compensation_pixel,
         })
     }
+
+    /// Read the non-pixel values from the specified camera over an async I²C bus.
+    ///
+    /// See [`from_i2c`][RamData::from_i2c] for details.
+    #[cfg(feature = "async")]
+    pub async fn from_i2c_async<I2C, Cam>(
+        bus: &mut I2C,
+        i2c_address: u8,
+        subpage: Subpage,
+    ) -> Result<Self, I2C::Error>
+    where
+        I2C: embedded_hal_async::i2c::I2c,
+        Cam: MelexisCamera,
+    {
+        let addresses = [
+            Cam::T_A_V_BE,
+            Cam::T_A_PTAT,
+            Cam::V_DD_PIXEL,
+            Cam::GAIN,
+            Cam::compensation_pixel(subpage),
+        ];
+        let mut values = [0i16; 5];
+        for (ram_address, value) in addresses.iter().zip(values.iter_mut()) {
+            let mut scratch = [0u8; 2];
+            bus.write_read(i2c_address, &ram_address.as_bytes(), &mut scratch)
+                .await?;
+            *value = i16::from_be_bytes(scratch);
+        }
+        let [t_a_v_be, t_a_ptat, v_dd_pixel, gain, compensation_pixel] = values;
+        Ok(Self {
+            t_a_v_be,
+            t_a_ptat,
+            v_dd_pixel,
+            gain,
+            compensation_pixel,
+        })
+    }
 }
 
 /// Values that are common to all pixels in a given frame.
@@ -413,18 +459,19 @@ impl CommonIrData {
 /// [`k_ta_pixels`]: CalibrationData::k_ta_pixels
 ///
 /// This function is also used for calculating the compensation pixel values when using thermal
-/// gradient compensation.
+/// gradient compensation. The compensation pixel may be [filtered][CompensationPixelFilter], so
+/// `pixel_data` can be any value convertible to [`f32`] instead of only the raw [`i16`].
 #[doc = include_str!("katex.html")]
 #[inline]
 pub fn per_pixel_v_ir(
-    pixel_data: i16,
+    pixel_data: impl Into<f32>,
     common: &CommonIrData,
     reference_offset: i16,
     k_v: f32,
     k_ta: f32,
     access_mode_compensation: Option<f32>,
 ) -> f32 {
-    let pixel_gain = f32::from(pixel_data) * common.gain;
+    let pixel_gain = pixel_data.into() * common.gain;
     let mut pixel_offset = pixel_gain + access_mode_compensation.unwrap_or_default()
         - f32::from(reference_offset)
             * (1f32 + k_ta * (common.t_a - 25f32))
@@ -433,6 +480,125 @@ pub fn per_pixel_v_ir(
     pixel_offset
 }
 
+/// Calculate the compensation pixel offset for thermal gradient compensation.
+///
+/// If the camera doesn't support a [temperature gradient coefficient][tgc] (TGC), `None` is
+/// returned. Otherwise the compensation pixel for `subpage` is processed with [`per_pixel_v_ir`]
+/// and premultiplied by the TGC, ready to be subtracted from every other pixel in the subpage.
+///
+/// The datasheets advise a moving average filter (with a length of at least 16) over the
+/// compensation pixel. If `compensation_filter` is given, `compensation_pixel` is added to it and
+/// the filtered value is used instead of the raw value.
+///
+/// [tgc]: CalibrationData::temperature_gradient_coefficient
+pub fn compensation_pixel_offset<'a, Clb>(
+    calibration: &'a Clb,
+    common: &CommonIrData,
+    compensation_pixel: i16,
+    compensation_filter: Option<&mut CompensationPixelFilter>,
+    subpage: Subpage,
+    access_pattern: AccessPattern,
+) -> Option<f32>
+where
+    Clb: CalibrationData<'a>,
+{
+    let compensation_pixel =
+        filter_compensation_pixel(compensation_pixel, compensation_filter, subpage);
+    filtered_compensation_pixel_offset(
+        calibration,
+        common,
+        compensation_pixel,
+        subpage,
+        access_pattern,
+    )
+}
+
+/// Add the compensation pixel to the filter (if there is one), returning the value to use.
+fn filter_compensation_pixel(
+    compensation_pixel: i16,
+    compensation_filter: Option<&mut CompensationPixelFilter>,
+    subpage: Subpage,
+) -> f32 {
+    match compensation_filter {
+        Some(filter) => filter.update(subpage, compensation_pixel),
+        None => f32::from(compensation_pixel),
+    }
+}
+
+/// [`compensation_pixel_offset`], with the compensation pixel already filtered.
+fn filtered_compensation_pixel_offset<'a, Clb>(
+    calibration: &'a Clb,
+    common: &CommonIrData,
+    compensation_pixel: f32,
+    subpage: Subpage,
+    access_pattern: AccessPattern,
+) -> Option<f32>
+where
+    Clb: CalibrationData<'a>,
+{
+    // Compensation pixels are only used if temperature gradient compensation is being used.
+    calibration.temperature_gradient_coefficient().map(|tgc| {
+        let compensation_pixel_offset = per_pixel_v_ir(
+            compensation_pixel,
+            common,
+            calibration.offset_reference_cp(subpage),
+            calibration.k_v_cp(subpage),
+            calibration.k_ta_cp(subpage),
+            calibration.access_pattern_compensation_cp(subpage, access_pattern),
+        );
+        // Premultiplying by the TGC here
+        tgc * compensation_pixel_offset
+    })
+}
+
+/// The values shared by every pixel in a subpage, calculated before the per-pixel steps.
+///
+/// The high-level drivers keep these around for the [frame metadata][crate::metadata].
+#[derive(Debug, PartialEq)]
+pub(crate) struct SubpageValues {
+    pub(crate) common: CommonIrData,
+
+    /// The compensation pixel value, after filtering.
+    pub(crate) compensation_pixel: f32,
+
+    /// The offset subtracted from every pixel, see [`compensation_pixel_offset`].
+    pub(crate) compensation_pixel_offset: Option<f32>,
+}
+
+impl SubpageValues {
+    /// Calculate the values for a subpage, adding the compensation pixel to `compensation_filter`
+    /// if given.
+    pub(crate) fn new<'a, Clb>(
+        calibration: &'a Clb,
+        emissivity: Emissivity,
+        resolution_correction: f32,
+        ram: &RamData,
+        compensation_filter: Option<&mut CompensationPixelFilter>,
+        subpage: Subpage,
+        access_pattern: AccessPattern,
+    ) -> Self
+    where
+        Clb: CalibrationData<'a>,
+    {
+        let (emissivity, _) = split_emissivity(emissivity);
+        let common = CommonIrData::new(resolution_correction, emissivity, calibration, ram);
+        let compensation_pixel =
+            filter_compensation_pixel(ram.compensation_pixel, compensation_filter, subpage);
+        let compensation_pixel_offset = filtered_compensation_pixel_offset(
+            calibration,
+            &common,
+            compensation_pixel,
+            subpage,
+            access_pattern,
+        );
+        Self {
+            common,
+            compensation_pixel,
+            compensation_pixel_offset,
+        }
+    }
+}
+
 /// Calculate a measurement of raw IR data for all pixels
 ///
 /// This function applies [`per_pixel_v_ir`] to each pixel belonging to the given subpage, writing
@@ -445,16 +611,24 @@ pub fn per_pixel_v_ir(
 /// belonging to `subpage` are calculated. Determining which pixels belong to which subpage is done
 /// with the `valid_pixels` iterator (see [`MelexisCamera::pixels_in_subpage`] for more details).
 ///
+/// If given, `compensation_filter` is used to filter the compensation pixel values (see
+/// [`compensation_pixel_offset`]).
+///
+/// `emissivity` is usually a single [`f32`] for all pixels, but it can also be an
+/// [`Emissivity::Map`], in which case each pixel is divided by its own emissivity.
+///
 /// The datasheets suggest that stopping at this step (instead of continuing on with calculating
 /// the temperatures of each pixel) can be appropriate if an "image" is all that is required (with
-/// an example use case of machine vision).
+/// an example use case of machine vision). The [`agc`][crate::agc] module can convert the IR data
+/// to a grayscale image.
 #[allow(clippy::too_many_arguments)]
-pub fn raw_pixels_to_ir_data<'a, Clb, Px>(
+pub fn raw_pixels_to_ir_data<'a, 'm, Clb, Px>(
     calibration: &'a Clb,
-    emissivity: f32,
+    emissivity: impl Into<Emissivity<'m>>,
     resolution_correction: f32,
     pixel_data: &[u8],
     ram: RamData,
+    compensation_filter: Option<&mut CompensationPixelFilter>,
     subpage: Subpage,
     access_pattern: AccessPattern,
     valid_pixels: &mut Px,
@@ -464,22 +638,52 @@ where
     Clb: CalibrationData<'a>,
     Px: Iterator<Item = bool>,
 {
+    let emissivity = emissivity.into();
     // Knock out the values common to all pixels first.
-    let common = CommonIrData::new(resolution_correction, emissivity, calibration, &ram);
-    // Compensation pixels are only used if temperature gradient compensation is being used.
-    let compensation_pixel_offset = calibration.temperature_gradient_coefficient().map(|tgc| {
-        // TODO: There's a note in the datasheet advising a moving average filter (length >=
-        // 16) on the compensation pixel gain.
-        let compensation_pixel_offset = per_pixel_v_ir(
-            ram.compensation_pixel,
-            &common,
-            calibration.offset_reference_cp(subpage),
-            calibration.k_v_cp(subpage),
-            calibration.k_ta_cp(subpage),
-            calibration.access_pattern_compensation_cp(subpage, access_pattern),
-        );
-        // Premultiplying by the TGC here
-        tgc * compensation_pixel_offset
+    let values = SubpageValues::new(
+        calibration,
+        emissivity,
+        resolution_correction,
+        &ram,
+        compensation_filter,
+        subpage,
+        access_pattern,
+    );
+    ir_data_with_values(
+        calibration,
+        emissivity,
+        &values,
+        pixel_data,
+        subpage,
+        access_pattern,
+        valid_pixels,
+        destination,
+    )
+}
+
+/// [`raw_pixels_to_ir_data`], with the per-subpage values already calculated.
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn ir_data_with_values<'a, 'm, Clb, Px>(
+    calibration: &'a Clb,
+    emissivity: Emissivity<'m>,
+    values: &SubpageValues,
+    pixel_data: &[u8],
+    subpage: Subpage,
+    access_pattern: AccessPattern,
+    valid_pixels: &mut Px,
+    destination: &mut [f32],
+) -> f32
+where
+    Clb: CalibrationData<'a>,
+    Px: Iterator<Item = bool>,
+{
+    let (_, emissivity_map) = split_emissivity(emissivity);
+    let common = &values.common;
+    let compensation_pixel_offset = values.compensation_pixel_offset;
+    // The reflected temperature isn't needed for the IR data, so use the default.
+    let emissivity_map = emissivity_map.map(|(default_emissivity, map)| {
+        let t_r = common.t_a - Clb::Camera::SELF_HEATING;
+        MappedEmissivity::new::<Clb::Camera>(map, default_emissivity, common.t_a, t_r)
     });
     // At this point, we're now going to start calculating and copying over the pixel data. It
     // will *not* be actual temperatures, but it can be used for some imaging purposes.
@@ -488,6 +692,7 @@ where
         .map(|o| o.copied());
     destination
         .iter_mut()
+        .enumerate()
         // Chunk into two byte segments, for each 16-bit value
         .zip(pixel_data.chunks_exact(2))
         // Zip up the corresponding values from the calibration data.
@@ -502,7 +707,7 @@ where
         // feeling a little lispy in here with all these parentheses
         .for_each(
             |(
-                ((((output, pixel_slice), reference_offset), k_v), k_ta),
+                (((((index, output), pixel_slice), reference_offset), k_v), k_ta),
                 access_mode_compensation,
             )| {
                 // Safe to unwrap as this is from chunks_exact(2)
@@ -510,7 +715,7 @@ where
                 let pixel_data = i16::from_be_bytes(pixel_bytes);
                 let mut pixel_offset = per_pixel_v_ir(
                     pixel_data,
-                    &common,
+                    common,
                     *reference_offset,
                     *k_v,
                     *k_ta,
@@ -521,6 +726,9 @@ where
                 if let Some(compensation_pixel_offset) = compensation_pixel_offset {
                     pixel_offset -= compensation_pixel_offset;
                 }
+                if let Some(emissivity_map) = &emissivity_map {
+                    pixel_offset /= emissivity_map.pixel(index).0;
+                }
                 *output = pixel_offset;
             },
         );
@@ -561,6 +769,64 @@ pub fn t_ar(t_a: f32, t_r: f32, emissivity: f32) -> f32 {
     }
 }
 
+/// Split an [`Emissivity`] into the emissivity to use for [`CommonIrData`], and the default
+/// emissivity and map if there is one.
+///
+/// When there is a map the common emissivity is 1, and each pixel is divided by its own emissivity
+/// later on.
+fn split_emissivity<'m>(emissivity: Emissivity<'m>) -> (f32, Option<(f32, &'m EmissivityMap<'m>)>) {
+    match emissivity {
+        Emissivity::Uniform(emissivity) => (emissivity, None),
+        Emissivity::Map { default, map } => (1f32, Some((default, map))),
+    }
+}
+
+/// The emissivity and $T_{a - r}$ for each pixel when using an [`EmissivityMap`].
+#[derive(Clone, Copy, Debug)]
+struct MappedEmissivity<'m> {
+    map: &'m EmissivityMap<'m>,
+    width: usize,
+    emissivity: f32,
+    t_a: f32,
+    t_r: f32,
+    t_ar: f32,
+}
+
+impl<'m> MappedEmissivity<'m> {
+    fn new<Cam: MelexisCamera>(
+        map: &'m EmissivityMap<'m>,
+        emissivity: f32,
+        t_a: f32,
+        t_r: f32,
+    ) -> Self {
+        Self {
+            map,
+            width: Cam::WIDTH,
+            emissivity,
+            t_a,
+            t_r,
+            t_ar: t_ar(t_a, t_r, emissivity),
+        }
+    }
+
+    /// The emissivity and $T_{a - r}$ for the pixel at `index`.
+    ///
+    /// Pixels not covered by the map use the default values, which are only calculated once.
+    #[inline]
+    fn pixel(&self, index: usize) -> (f32, f32) {
+        match self
+            .map
+            .pixel(index % self.width, index / self.width, self.width)
+        {
+            Some((emissivity, t_r)) => (
+                emissivity,
+                t_ar(self.t_a, t_r.unwrap_or(self.t_r), emissivity),
+            ),
+            None => (self.emissivity, self.t_ar),
+        }
+    }
+}
+
 /// Calculate the sensitivity correction coefficient
 ///
 /// The sensitivity correction coefficient isn't explicitly named in the datasheet, but it's a
@@ -612,9 +878,9 @@ where
 /// [tgc]: CalibrationData::temperature_gradient_coefficient
 /// [alpha-cp]: CalibrationData::alpha_cp
 ///
-/// This function calculates the temperature for the basic temperature range only; extended
-/// temperature range calculations are not implemented yet and will probably be done in a separate
-/// function.
+/// This function calculates the temperature for the basic temperature range only. Temperatures
+/// outside of that range are handled by [`per_pixel_temperature_extended`], using the result of
+/// this function as the first estimate. [`TemperatureRanges`] combines both steps.
 #[doc = include_str!("katex.html")]
 #[inline]
 pub fn per_pixel_temperature(v_ir: f32, alpha: f32, t_ar: f32, k_s_to: f32) -> f32 {
@@ -625,16 +891,145 @@ pub fn per_pixel_temperature(v_ir: f32, alpha: f32, t_ar: f32, k_s_to: f32) -> f
     t_o_root - KELVINS_TO_CELSIUS
 }
 
+/// Calculate the temperature measured by a pixel in one of the extended temperature ranges.
+///
+/// $$
+/// T\_{o(i, j)} = \sqrt\[4\]{\frac{V\_{IR(i, j)}}{\alpha\_{\textit{comp}(i, j)} \*
+/// \alpha\_{\textit{corr}\_x} \* (1 + K\_{S\_{T\_o}\_x} \* (T\_{o(i, j)} - CT\_x))} + T\_{a - r}} -
+/// 273.15
+/// $$
+///
+/// This is the second step of the temperature calculation described in section 11.2.2.9 in both
+/// datasheets. `estimate` is the temperature calculated for the pixel using the basic range (from
+/// [`per_pixel_temperature`]), and is used to determine which range *x* the pixel is in. The
+/// [corner temperature][ct] ($CT\_x$), [sensitivity slope][k-s-to] ($K\_{S\_{T\_o}\_x}$), and
+/// [sensitivity correction][alpha-corr] ($\alpha\_{\textit{corr}\_x}$) given are for that range.
+/// The other arguments are the same as for `per_pixel_temperature`.
+///
+/// [ct]: CalibrationData::corner_temperatures
+/// [k-s-to]: CalibrationData::k_s_to
+/// [alpha-corr]: CalibrationData::alpha_correction
+#[doc = include_str!("katex.html")]
+#[inline]
+pub fn per_pixel_temperature_extended(
+    v_ir: f32,
+    alpha: f32,
+    t_ar: f32,
+    estimate: f32,
+    corner_temperature: i16,
+    k_s_to: f32,
+    alpha_correction: f32,
+) -> f32 {
+    let range_alpha =
+        alpha * alpha_correction * (1f32 + k_s_to * (estimate - f32::from(corner_temperature)));
+    (v_ir / range_alpha + t_ar).powf(0.25) - KELVINS_TO_CELSIUS
+}
+
+/// The most temperature ranges a camera can be calibrated for.
+pub const MAX_TEMPERATURE_RANGES: usize = 8;
+
+/// The temperature ranges a camera has been calibrated for.
+///
+/// The cameras are calibrated for a number of temperature ranges, with the boundaries between the
+/// ranges given by the [corner temperatures][ct]. Temperatures are first calculated using the
+/// [basic range][basic-range], and if that temperature falls into a different range it is
+/// recalculated using the calibration values for that range.
+///
+/// [ct]: CalibrationData::corner_temperatures
+/// [basic-range]: MelexisCamera::BASIC_TEMPERATURE_RANGE
+#[derive(Clone, Debug)]
+pub struct TemperatureRanges {
+    corner_temperatures: ArrayVec<i16, MAX_TEMPERATURE_RANGES>,
+    k_s_to: ArrayVec<f32, MAX_TEMPERATURE_RANGES>,
+    alpha_correction: ArrayVec<f32, MAX_TEMPERATURE_RANGES>,
+    basic_range: usize,
+}
+
+impl TemperatureRanges {
+    /// Collect the temperature range calibration values from a camera's calibration data.
+    ///
+    /// The values are copied, so this can be kept around after the calibration data is gone.
+    ///
+    /// # Panics
+    /// If the calibration data has more than [`MAX_TEMPERATURE_RANGES`] ranges.
+    pub fn new<'a, Clb: CalibrationData<'a>>(calibration: &'a Clb) -> Self {
+        fn copy<T: Copy>(values: &[T]) -> ArrayVec<T, MAX_TEMPERATURE_RANGES> {
+            values
+                .try_into()
+                .expect("Too many temperature ranges in the calibration data")
+        }
+        Self {
+            corner_temperatures: copy(calibration.corner_temperatures()),
+            k_s_to: copy(calibration.k_s_to()),
+            alpha_correction: copy(calibration.alpha_correction()),
+            basic_range: Clb::Camera::BASIC_TEMPERATURE_RANGE,
+        }
+    }
+
+    /// Find the index of the range a temperature (in degrees Celsius) falls into.
+    ///
+    /// Temperatures below the lowest corner temperature are treated as being in the lowest range,
+    /// and temperatures above the highest corner temperature are treated as being in the highest
+    /// range.
+    pub fn range(&self, temperature: f32) -> usize {
+        self.corner_temperatures
+            .iter()
+            .rposition(|corner| f32::from(*corner) <= temperature)
+            .unwrap_or_default()
+    }
+
+    /// Calculate the temperature measured by a pixel, using the extended temperature ranges if
+    /// needed.
+    ///
+    /// The arguments are the same as for [`per_pixel_temperature`], except the sensitivity slope
+    /// is taken from the calibration data.
+    #[inline]
+    pub fn per_pixel_temperature(&self, v_ir: f32, alpha: f32, t_ar: f32) -> f32 {
+        let estimate = per_pixel_temperature(v_ir, alpha, t_ar, self.basic_k_s_to());
+        self.refine_estimate(v_ir, alpha, t_ar, estimate)
+    }
+
+    /// The sensitivity slope for the basic temperature range.
+    #[inline]
+    pub(crate) fn basic_k_s_to(&self) -> f32 {
+        self.k_s_to[self.basic_range]
+    }
+
+    /// Recalculate a temperature from the basic range if it falls into one of the other ranges.
+    #[inline]
+    pub(crate) fn refine_estimate(&self, v_ir: f32, alpha: f32, t_ar: f32, estimate: f32) -> f32 {
+        let range = self.range(estimate);
+        if range == self.basic_range {
+            estimate
+        } else {
+            per_pixel_temperature_extended(
+                v_ir,
+                alpha,
+                t_ar,
+                estimate,
+                self.corner_temperatures[range],
+                self.k_s_to[range],
+                self.alpha_correction[range],
+            )
+        }
+    }
+}
+
 /// Calculate the temperature for all pixels, starting from the raw IR data
 ///
-/// This function applies [`per_pixel_temperature`] to all pixels in the given subpage. It modifies
-/// the `destination` array in-place, replacing the $V\_{IR}$ data with the temperatures. The
-/// ambient temperature (`t_a`, $T\_a$) needs to be given from the same frame that produced the IR
-/// data.
+/// This function applies [`per_pixel_temperature`] to all pixels in the given subpage, switching
+/// to [`per_pixel_temperature_extended`] for pixels outside of the basic temperature range. It
+/// modifies the `destination` array in-place, replacing the $V\_{IR}$ data with the temperatures.
+/// The ambient temperature (`t_a`, $T\_a$) needs to be given from the same frame that produced the
+/// IR data.
+///
+/// If `emissivity` is an [`Emissivity::Map`], $T\_{a - r}$ is calculated for each pixel from that
+/// pixel's emissivity and reflected temperature. The same emissivity should have been given to
+/// [`raw_pixels_to_ir_data`] when creating the IR data.
 #[doc = include_str!("katex.html")]
-pub fn raw_ir_to_temperatures<'a, Clb, Px>(
+pub fn raw_ir_to_temperatures<'a, 'm, Clb, Px>(
     calibration: &'a Clb,
-    emissivity: f32,
+    emissivity: impl Into<Emissivity<'m>>,
     t_a: f32,
     t_r: Option<f32>,
     subpage: Subpage,
@@ -649,38 +1044,53 @@ pub fn raw_ir_to_temperatures<'a, Clb, Px>(
     let alpha_compensation_pixel = calibration
         .temperature_gradient_coefficient()
         .map(|tgc| calibration.alpha_cp(subpage) * tgc);
-    let k_s_to_basic = calibration.k_s_to()[Clb::Camera::BASIC_TEMPERATURE_RANGE];
+    let temperature_ranges = TemperatureRanges::new(calibration);
     let alpha_coefficient = sensitivity_correction_coefficient(calibration, t_a);
     let t_r = t_r.unwrap_or(t_a - Clb::Camera::SELF_HEATING);
-    let t_ar = t_ar(t_a, t_r, emissivity);
+    let emissivity = emissivity.into();
+    // When there's a map, this is only used for the pixels not covered by it.
+    let t_ar = t_ar(t_a, t_r, emissivity.default_emissivity());
+    let emissivity_map = match emissivity {
+        Emissivity::Uniform(_) => None,
+        Emissivity::Map { default, map } => {
+            Some(MappedEmissivity::new::<Clb::Camera>(map, default, t_a, t_r))
+        }
+    };
 
     destination
         .iter_mut()
+        .enumerate()
         .zip(calibration.alpha_pixels(subpage))
         // filter out the pixels that aren't part of this subpage
         .filter(|_| valid_pixels.next().unwrap_or_default())
-        .for_each(|(output, alpha)| {
+        .for_each(|((index, output), alpha)| {
             let v_ir = *output;
             let compensated_alpha = match alpha_compensation_pixel {
                 Some(alpha_compensation_pixel) => alpha - alpha_compensation_pixel,
                 None => *alpha,
             } * alpha_coefficient;
-            *output = per_pixel_temperature(v_ir, compensated_alpha, t_ar, k_s_to_basic);
+            let t_ar = match &emissivity_map {
+                Some(emissivity_map) => emissivity_map.pixel(index).1,
+                None => t_ar,
+            };
+            *output = temperature_ranges.per_pixel_temperature(v_ir, compensated_alpha, t_ar);
         });
 }
 
 /// Calculate the temperature from all pixels, starting with the raw data from the camera
 ///
 /// This function combines [`raw_pixels_to_ir_data`] and [`raw_ir_to_temperatures`], performing all
-/// per-pixel operations in a single pass.
+/// per-pixel operations in a single pass. The arguments are the same as for those functions, and
+/// an [`Emissivity::Map`] is handled the same way.
 #[allow(clippy::too_many_arguments)]
-pub fn raw_pixels_to_temperatures<'a, Clb, Px>(
+pub fn raw_pixels_to_temperatures<'a, 'm, Clb, Px>(
     calibration: &'a Clb,
-    emissivity: f32,
+    emissivity: impl Into<Emissivity<'m>>,
     t_r: Option<f32>,
     resolution_correction: f32,
     pixel_data: &[u8],
     ram: RamData,
+    compensation_filter: Option<&mut CompensationPixelFilter>,
     subpage: Subpage,
     access_pattern: AccessPattern,
     valid_pixels: &mut Px,
@@ -690,32 +1100,62 @@ where
     Clb: CalibrationData<'a>,
     Px: Iterator<Item = bool>,
 {
+    let emissivity = emissivity.into();
     // Knock out the values common to all pixels first.
-    let common = CommonIrData::new(resolution_correction, emissivity, calibration, &ram);
-    // Compensation pixels are only used if temperature gradient compensation is being used.
-    let compensation_pixel_offset = calibration.temperature_gradient_coefficient().map(|tgc| {
-        // TODO: There's a note in the datasheet advising a moving average filter (length >=
-        // 16) on the compensation pixel gain.
-        let compensation_pixel_offset = per_pixel_v_ir(
-            ram.compensation_pixel,
-            &common,
-            calibration.offset_reference_cp(subpage),
-            calibration.k_v_cp(subpage),
-            calibration.k_ta_cp(subpage),
-            calibration.access_pattern_compensation_cp(subpage, access_pattern),
-        );
-        // Premultiplying by the TGC here
-        tgc * compensation_pixel_offset
-    });
-    // TODO: this step could probably be optimized a little bit (if needed) by pushing this
-    // calculation to the calibration loading step.
+    let values = SubpageValues::new(
+        calibration,
+        emissivity,
+        resolution_correction,
+        &ram,
+        compensation_filter,
+        subpage,
+        access_pattern,
+    );
+    temperatures_with_values(
+        calibration,
+        emissivity,
+        &values,
+        t_r,
+        pixel_data,
+        subpage,
+        access_pattern,
+        valid_pixels,
+        destination,
+    )
+}
+
+/// [`raw_pixels_to_temperatures`], with the per-subpage values already calculated.
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn temperatures_with_values<'a, 'm, Clb, Px>(
+    calibration: &'a Clb,
+    emissivity: Emissivity<'m>,
+    values: &SubpageValues,
+    t_r: Option<f32>,
+    pixel_data: &[u8],
+    subpage: Subpage,
+    access_pattern: AccessPattern,
+    valid_pixels: &mut Px,
+    destination: &mut [f32],
+) -> f32
+where
+    Clb: CalibrationData<'a>,
+    Px: Iterator<Item = bool>,
+{
+    let (emissivity, emissivity_map) = split_emissivity(emissivity);
+    let common = &values.common;
+    let compensation_pixel_offset = values.compensation_pixel_offset;
+    // CompiledCalibration does this (and flattens the calibration data) when loading the
+    // calibration data instead.
     let alpha_compensation_pixel = calibration
         .temperature_gradient_coefficient()
         .map(|tgc| calibration.alpha_cp(subpage) * tgc);
-    let k_s_to_basic = calibration.k_s_to()[Clb::Camera::BASIC_TEMPERATURE_RANGE];
+    let temperature_ranges = TemperatureRanges::new(calibration);
     let alpha_coefficient = sensitivity_correction_coefficient(calibration, common.t_a);
     let t_r = t_r.unwrap_or(common.t_a - Clb::Camera::SELF_HEATING);
     let t_ar = t_ar(common.t_a, t_r, emissivity);
+    let emissivity_map = emissivity_map.map(|(default_emissivity, map)| {
+        MappedEmissivity::new::<Clb::Camera>(map, default_emissivity, common.t_a, t_r)
+    });
     // At this point, we're now going to start calculating and copying over the pixel data. It
     // will *not* be actual temperatures, but it can be used for some imaging purposes.
     let access_mode_compensation = calibration
@@ -723,6 +1163,7 @@ where
         .map(|o| o.copied());
     destination
         .iter_mut()
+        .enumerate()
         // Chunk into two byte segments, for each 16-bit value
         .zip(pixel_data.chunks_exact(2))
         // Zip up the corresponding values from the calibration data.
@@ -737,7 +1178,7 @@ where
         // feeling a little lispy in here with all these parentheses
         .for_each(
             |(
-                (((((output, pixel_slice), reference_offset), k_v), k_ta), alpha),
+                ((((((index, output), pixel_slice), reference_offset), k_v), k_ta), alpha),
                 access_mode_compensation,
             )| {
                 // Safe to unwrap as this is from chunks_exact(2)
@@ -745,7 +1186,7 @@ where
                 let pixel_data = i16::from_be_bytes(pixel_bytes);
                 let mut v_ir = per_pixel_v_ir(
                     pixel_data,
-                    &common,
+                    common,
                     *reference_offset,
                     *k_v,
                     *k_ta,
@@ -760,7 +1201,15 @@ where
                     Some(alpha_compensation_pixel) => alpha - alpha_compensation_pixel,
                     None => *alpha,
                 } * alpha_coefficient;
-                *output = per_pixel_temperature(v_ir, compensated_alpha, t_ar, k_s_to_basic);
+                let t_ar = match &emissivity_map {
+                    Some(emissivity_map) => {
+                        let (emissivity, t_ar) = emissivity_map.pixel(index);
+                        v_ir /= emissivity;
+                        t_ar
+                    }
+                    None => t_ar,
+                };
+                *output = temperature_ranges.per_pixel_temperature(v_ir, compensated_alpha, t_ar);
             },
         );
     common.t_a
@@ -774,6 +1223,8 @@ mod test {
 
     use crate::{mlx90640, mlx90641, CalibrationData, MelexisCamera, Subpage};
 
+    use super::TemperatureRanges;
+
     fn mlx90640_calibration() -> mlx90640::Mlx90640Calibration {
         let eeprom_data = mlx90640_datasheet_eeprom();
         mlx90640::Mlx90640Calibration::from_data(&eeprom_data)
@@ -965,4 +1416,119 @@ mod test {
         // Extended precision from earlier in the datasheet calculations
         assert_approx_eq!(f32, t_o, 80.129812);
     }
+
+    #[test]
+    fn temperature_range() {
+        let clb_640 = mlx90640_calibration();
+        let ranges_640 = TemperatureRanges::new(&clb_640);
+        // The datasheet corner temperatures are -40, 0, 160, and 320
+        assert_eq!(ranges_640.range(-60.0), 0);
+        assert_eq!(ranges_640.range(-40.0), 0);
+        assert_eq!(ranges_640.range(-0.5), 0);
+        assert_eq!(ranges_640.range(0.0), 1);
+        assert_eq!(ranges_640.range(80.36331), 1);
+        assert_eq!(ranges_640.range(200.0), 2);
+        assert_eq!(ranges_640.range(400.0), 3);
+        let clb_641 = mlx90641_calibration();
+        let ranges_641 = TemperatureRanges::new(&clb_641);
+        // -40, -20, 0, 80, 120, 200, 400, 600
+        assert_eq!(ranges_641.range(-30.0), 0);
+        assert_eq!(ranges_641.range(-10.0), 1);
+        assert_eq!(ranges_641.range(25.0), 2);
+        assert_eq!(ranges_641.range(100.0), 3);
+        assert_eq!(ranges_641.range(500.0), 6);
+        assert_eq!(ranges_641.range(1000.0), 7);
+    }
+
+    #[test]
+    fn pixel_temperature_extended() {
+        let clb = mlx90640_calibration();
+        let alpha = 1.1876487360496E-7;
+        let t_ar = 9516495632.56;
+        let corner_temperature = clb.corner_temperatures()[3];
+        let k_s_to = clb.k_s_to()[3];
+        let alpha_correction = clb.alpha_correction()[3];
+        // Work backwards from a known temperature to get V_IR. If the estimate is exactly the
+        // object temperature, the formula is exact.
+        let t_o = 400f32;
+        let v_ir = ((t_o + super::KELVINS_TO_CELSIUS).powi(4) - t_ar)
+            * alpha
+            * alpha_correction
+            * (1f32 + k_s_to * (t_o - f32::from(corner_temperature)));
+        let extended = super::per_pixel_temperature_extended(
+            v_ir,
+            alpha,
+            t_ar,
+            t_o,
+            corner_temperature,
+            k_s_to,
+            alpha_correction,
+        );
+        assert_approx_eq!(f32, extended, t_o, epsilon = 0.01);
+    }
+
+    #[test]
+    fn pixel_temperature_ranges() {
+        let clb = mlx90640_calibration();
+        let ranges = TemperatureRanges::new(&clb);
+        let basic_range =
+            <mlx90640::Mlx90640Calibration as CalibrationData>::Camera::BASIC_TEMPERATURE_RANGE;
+        let k_s_to = clb.k_s_to()[basic_range];
+        let alpha = 1.1876487360496E-7;
+        let t_ar = 9516495632.56;
+        // Pixels in the basic range are unchanged
+        let v_ir = 679.250909123826;
+        assert_eq!(
+            ranges.per_pixel_temperature(v_ir, alpha, t_ar),
+            super::per_pixel_temperature(v_ir, alpha, t_ar, k_s_to)
+        );
+        // A hot pixel is in range 3, so the extended calculation should be used
+        let v_ir = 12000.0;
+        let basic = super::per_pixel_temperature(v_ir, alpha, t_ar, k_s_to);
+        assert_eq!(ranges.range(basic), 3);
+        let expected = super::per_pixel_temperature_extended(
+            v_ir,
+            alpha,
+            t_ar,
+            basic,
+            clb.corner_temperatures()[3],
+            clb.k_s_to()[3],
+            clb.alpha_correction()[3],
+        );
+        assert_eq!(ranges.per_pixel_temperature(v_ir, alpha, t_ar), expected);
+        // Same thing for a cold pixel in range 0
+        let v_ir = -500.0;
+        let basic = super::per_pixel_temperature(v_ir, alpha, t_ar, k_s_to);
+        assert_eq!(ranges.range(basic), 0);
+        let expected = super::per_pixel_temperature_extended(
+            v_ir,
+            alpha,
+            t_ar,
+            basic,
+            clb.corner_temperatures()[0],
+            clb.k_s_to()[0],
+            clb.alpha_correction()[0],
+        );
+        assert_eq!(ranges.per_pixel_temperature(v_ir, alpha, t_ar), expected);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn ram_data_serde() {
+        let ram = super::RamData {
+            t_a_v_be: 19_196,
+            t_a_ptat: 1_711,
+            v_dd_pixel: -13_115,
+            gain: 6_023,
+            compensation_pixel: -75,
+        };
+        let mut buffer = [0u8; 16];
+        let serialized = postcard::to_slice(&ram, &mut buffer).unwrap();
+        let deserialized: super::RamData = postcard::from_bytes(serialized).unwrap();
+        assert_eq!(deserialized.t_a_v_be, ram.t_a_v_be);
+        assert_eq!(deserialized.t_a_ptat, ram.t_a_ptat);
+        assert_eq!(deserialized.v_dd_pixel, ram.v_dd_pixel);
+        assert_eq!(deserialized.gain, ram.gain);
+        assert_eq!(deserialized.compensation_pixel, ram.compensation_pixel);
+    }
 }
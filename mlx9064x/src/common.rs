@@ -2,15 +2,17 @@
 // Copyright © 2021 Will Ross
 //! Common functionality shared by the MLX90640, MLX90641, and MLX90642 cameras.
 use core::fmt;
+#[cfg(feature = "async")]
+use core::future::Future;
 
 use arrayvec::ArrayVec;
 use bitvec::order::BitOrder;
 use bitvec::slice::{BitSlice, IterOnes};
 use bitvec::store::BitStore;
-use embedded_hal::blocking::i2c;
+use embedded_hal::i2c;
 
 use crate::calculations::RamData;
-use crate::error::Error;
+use crate::error::{Error, LibraryError};
 use crate::register::{AccessPattern, Resolution, Subpage};
 use crate::util::Sealed;
 
@@ -31,6 +33,32 @@ pub trait ToI2C<I2C> {
     fn to_i2c(&self, bus: &mut I2C, i2c_address: u8) -> Result<(), Self::Error>;
 }
 
+/// The async version of [`FromI2C`], for use with `embedded-hal-async` buses.
+#[cfg(feature = "async")]
+pub trait AsyncFromI2C<I2C> {
+    type Error;
+    type Ok;
+
+    /// Create an instance of a type using data retrieved over I²C.
+    fn from_i2c_async(
+        bus: &mut I2C,
+        i2c_address: u8,
+    ) -> impl Future<Output = Result<Self::Ok, Self::Error>>;
+}
+
+/// The async version of [`ToI2C`], for use with `embedded-hal-async` buses.
+#[cfg(feature = "async")]
+pub trait AsyncToI2C<I2C> {
+    type Error;
+
+    /// Write the value of this type to the specified I²C device.
+    fn to_i2c_async(
+        &self,
+        bus: &mut I2C,
+        i2c_address: u8,
+    ) -> impl Future<Output = Result<(), Self::Error>>;
+}
+
 /// A trait for flagging individual pixels.
 pub trait FlaggedPixels {
     /// Check if any pixels are flagged.
@@ -315,6 +343,13 @@ pub trait MelexisCamera: Sealed {
         access_pattern: AccessPattern,
     ) -> Self::PixelsInSubpageIterator;
 
+    /// Check if a single pixel is part of a subpage.
+    ///
+    /// This is the same as the value [`pixels_in_subpage`][MelexisCamera::pixels_in_subpage]
+    /// yields for the pixel at `index`, without iterating over the pixels before it. Pixels past
+    /// the end of the image are not part of any subpage.
+    fn pixel_in_subpage(subpage: Subpage, access_pattern: AccessPattern, index: usize) -> bool;
+
     /// The address for $T_{a_{V_{BE}}}$.
     const T_A_V_BE: Address;
 
@@ -359,6 +394,21 @@ pub trait MelexisCamera: Sealed {
 
     /// The total number of pixels in the thermal image.
     const NUM_PIXELS: usize;
+
+    /// The EEPROM address of the value loaded into the control register at power on.
+    const EEPROM_CONTROL_REGISTER: Address;
+
+    /// The EEPROM address of the value loaded into the I²C configuration register at power on.
+    const EEPROM_I2C_CONFIG_REGISTER: Address;
+
+    /// The EEPROM address of the camera's I²C address.
+    const EEPROM_I2C_ADDRESS: Address;
+
+    /// Convert a value to the word that should be stored at the given EEPROM address.
+    ///
+    /// Some cameras protect their EEPROM with extra bits (like the MLX90641's Hamming code), which
+    /// are added here. An error is returned if the value does not fit in the word.
+    fn encode_eeprom_word(address: Address, value: u16) -> Result<u16, LibraryError>;
 }
 
 /// A range of camera memory.
@@ -421,7 +471,7 @@ pub fn read_ram<Cam, I2C, const HEIGHT: usize>(
 ) -> Result<RamData, Error<I2C>>
 where
     Cam: MelexisCamera,
-    I2C: i2c::WriteRead + i2c::Write,
+    I2C: i2c::I2c,
 {
     // Pick a maximum size of HEIGHT, as the worst access pattern is still by rows
     let pixel_ranges: ArrayVec<PixelAddressRange, HEIGHT> =
@@ -436,8 +486,43 @@ where
             &address_bytes[..],
             &mut pixel_data_buffer[offset..(offset + range.length)],
         )
-        .map_err(Error::I2cWriteReadError)?;
+        .map_err(Error::I2cError)?;
     }
     // And now to read the non-pixel information out
-    RamData::from_i2c::<I2C, Cam>(bus, i2c_address, subpage).map_err(Error::I2cWriteReadError)
+    RamData::from_i2c::<I2C, Cam>(bus, i2c_address, subpage).map_err(Error::I2cError)
+}
+
+/// Read a frame of data from the camera's memory using an async I²C bus.
+///
+/// This is the async equivalent of [`read_ram`].
+#[cfg(feature = "async")]
+pub async fn read_ram_async<Cam, I2C, const HEIGHT: usize>(
+    bus: &mut I2C,
+    i2c_address: u8,
+    access_pattern: AccessPattern,
+    subpage: Subpage,
+    pixel_data_buffer: &mut [u8],
+) -> Result<RamData, Error<I2C>>
+where
+    Cam: MelexisCamera,
+    I2C: embedded_hal_async::i2c::I2c,
+{
+    let pixel_ranges: ArrayVec<PixelAddressRange, HEIGHT> =
+        Cam::pixel_ranges(subpage, access_pattern)
+            .into_iter()
+            .collect();
+    for range in pixel_ranges.iter() {
+        let offset = range.buffer_offset;
+        let address_bytes = range.start_address.as_bytes();
+        bus.write_read(
+            i2c_address,
+            &address_bytes[..],
+            &mut pixel_data_buffer[offset..(offset + range.length)],
+        )
+        .await
+        .map_err(Error::I2cError)?;
+    }
+    RamData::from_i2c_async::<I2C, Cam>(bus, i2c_address, subpage)
+        .await
+        .map_err(Error::I2cError)
 }
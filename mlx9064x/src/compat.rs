@@ -0,0 +1,179 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright © 2021 Will Ross
+
+//! Compatibility with `embedded-hal` 0.2 I²C buses.
+//!
+//! This crate uses the I²C traits from `embedded-hal` 1.0. Buses that only implement the 0.2
+//! [`Write`][i2c_0_2::Write] and [`WriteRead`][i2c_0_2::WriteRead] traits can be wrapped in a
+//! [`Compat`] to be used with the rest of this crate.
+//!
+//! ```no_run
+//! use linux_embedded_hal::I2cdev;
+//! use mlx9064x::compat::Compat;
+//! use mlx9064x::Mlx90640Driver;
+//!
+//! let i2c_bus = I2cdev::new("/dev/i2c-1").expect("/dev/i2c-1 needs to be an I2C controller");
+//! let mut camera = Mlx90640Driver::new(Compat::new(i2c_bus), 0x33)?;
+//! # Ok::<(), mlx9064x::Error<Compat<I2cdev>>>(())
+//! ```
+#[cfg(feature = "std")]
+extern crate std;
+
+use core::fmt;
+
+use embedded_hal::i2c;
+use embedded_hal_0_2::blocking::i2c as i2c_0_2;
+
+/// Errors from an `embedded-hal` 0.2 I²C bus wrapped in a [`Compat`].
+///
+/// The 0.2 error types don't provide an [`ErrorKind`][i2c::ErrorKind], so every error is reported
+/// as [`ErrorKind::Other`][i2c::ErrorKind::Other].
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CompatError<W, WR> {
+    /// The error from a [`Write`][i2c_0_2::Write] operation.
+    Write(W),
+
+    /// The error from a [`WriteRead`][i2c_0_2::WriteRead] operation.
+    WriteRead(WR),
+
+    /// The transaction can't be expressed with the 0.2 traits.
+    ///
+    /// Only single writes and a write followed by a read are supported, which is all this crate
+    /// uses.
+    UnsupportedTransaction,
+}
+
+impl<W, WR> fmt::Display for CompatError<W, WR>
+where
+    W: fmt::Debug,
+    WR: fmt::Debug,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CompatError::Write(err) => write!(f, "I2C write error: {:?}", err),
+            CompatError::WriteRead(err) => write!(f, "I2C write-read error: {:?}", err),
+            CompatError::UnsupportedTransaction => {
+                write!(f, "Transaction not supported by embedded-hal 0.2 I2C buses")
+            }
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl<W, WR> std::error::Error for CompatError<W, WR>
+where
+    W: std::error::Error + 'static,
+    WR: std::error::Error + 'static,
+{
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            CompatError::Write(err) => Some(err),
+            CompatError::WriteRead(err) => Some(err),
+            CompatError::UnsupportedTransaction => None,
+        }
+    }
+}
+
+impl<W, WR> i2c::Error for CompatError<W, WR>
+where
+    W: fmt::Debug,
+    WR: fmt::Debug,
+{
+    fn kind(&self) -> i2c::ErrorKind {
+        i2c::ErrorKind::Other
+    }
+}
+
+/// Adapt an `embedded-hal` 0.2 I²C bus to the `embedded-hal` 1.0 [`I2c`][i2c::I2c] trait.
+#[derive(Clone, Debug)]
+pub struct Compat<I2C>(I2C);
+
+impl<I2C> Compat<I2C> {
+    /// Wrap an `embedded-hal` 0.2 I²C bus.
+    pub fn new(bus: I2C) -> Self {
+        Self(bus)
+    }
+
+    /// Get a reference to the wrapped bus.
+    pub fn inner(&self) -> &I2C {
+        &self.0
+    }
+
+    /// Get a mutable reference to the wrapped bus.
+    pub fn inner_mut(&mut self) -> &mut I2C {
+        &mut self.0
+    }
+
+    /// Unwrap the `embedded-hal` 0.2 I²C bus.
+    pub fn into_inner(self) -> I2C {
+        self.0
+    }
+}
+
+impl<I2C> i2c::ErrorType for Compat<I2C>
+where
+    I2C: i2c_0_2::Write + i2c_0_2::WriteRead,
+    <I2C as i2c_0_2::Write>::Error: fmt::Debug,
+    <I2C as i2c_0_2::WriteRead>::Error: fmt::Debug,
+{
+    type Error = CompatError<<I2C as i2c_0_2::Write>::Error, <I2C as i2c_0_2::WriteRead>::Error>;
+}
+
+impl<I2C> i2c::I2c for Compat<I2C>
+where
+    I2C: i2c_0_2::Write + i2c_0_2::WriteRead,
+    <I2C as i2c_0_2::Write>::Error: fmt::Debug,
+    <I2C as i2c_0_2::WriteRead>::Error: fmt::Debug,
+{
+    fn transaction(
+        &mut self,
+        address: u8,
+        operations: &mut [i2c::Operation<'_>],
+    ) -> Result<(), Self::Error> {
+        match operations {
+            [i2c::Operation::Write(bytes)] => {
+                self.0.write(address, bytes).map_err(CompatError::Write)
+            }
+            [i2c::Operation::Write(bytes), i2c::Operation::Read(buffer)] => self
+                .0
+                .write_read(address, bytes, buffer)
+                .map_err(CompatError::WriteRead),
+            _ => Err(CompatError::UnsupportedTransaction),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use embedded_hal::i2c::{Error as _, ErrorKind, I2c};
+
+    use mlx9064x_test_data::*;
+
+    use crate::{mlx90640, MelexisCamera, Mlx90640Driver};
+
+    use super::{Compat, CompatError};
+
+    #[test]
+    fn driver() {
+        let address = 0x33;
+        let mut mocked = example_mlx90640_at_address(address);
+        mocked.set_data_available(true);
+        let mut cam = Mlx90640Driver::new(Compat::new(mocked), address).unwrap();
+        let mut temperatures = [0f32; mlx90640::Mlx90640::NUM_PIXELS];
+        assert!(cam.generate_image_if_ready(&mut temperatures).unwrap());
+    }
+
+    #[test]
+    fn errors() {
+        let mocked = example_mlx90640_at_address(0x33);
+        let mut bus = Compat::new(mocked);
+        let mut buffer = [0u8; 2];
+        // Reads without a write first can't be done with the 0.2 traits.
+        let unsupported = bus.read(0x33, &mut buffer).unwrap_err();
+        assert!(matches!(unsupported, CompatError::UnsupportedTransaction));
+        // The wrong address is still an error, but without the specific kind
+        let wrong_address = bus.write_read(0x34, &[0x80, 0x00], &mut buffer);
+        assert!(matches!(wrong_address, Err(CompatError::WriteRead(_))));
+        assert_eq!(wrong_address.unwrap_err().kind(), ErrorKind::Other);
+    }
+}
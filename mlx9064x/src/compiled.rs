@@ -0,0 +1,313 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright © 2021 Will Ross
+
+//! Calibration data flattened for faster per-pixel calculations.
+//!
+#![doc = include_str!("katex.html")]
+//!
+//! [`raw_pixels_to_temperatures`] works with any [`CalibrationData`], which means pulling each
+//! per-pixel value out of a separate iterator for every pixel, then skipping the pixels that
+//! aren't in the current subpage. [`CompiledCalibration`] does that work once when the calibration
+//! data is loaded. For each subpage it keeps the pixels in that subpage and their calibration
+//! values in contiguous arrays, with the sensitivity ($\alpha$) already compensated for the
+//! temperature gradient. The [temperature ranges][TemperatureRanges] are also only collected
+//! once. [`CompiledCalibration::raw_pixels_to_temperatures`] is then a single loop over those
+//! arrays.
+//!
+//! ```
+//! use mlx9064x::compiled::CompiledCalibration;
+//! use mlx9064x::mlx90640::{Mlx90640, Mlx90640Calibration};
+//! use mlx9064x::{AccessPattern, MelexisCamera, Subpage};
+//! use mlx9064x_test_data::mlx90640_datasheet_eeprom;
+//!
+//! let calibration = Mlx90640Calibration::from_data(&mlx90640_datasheet_eeprom())?;
+//! let compiled =
+//!     CompiledCalibration::<{ Mlx90640::NUM_PIXELS }>::new(&calibration, AccessPattern::Chess);
+//! // Each subpage of the MLX90640 covers half of the pixels
+//! assert_eq!(compiled.pixels(Subpage::Zero).len(), Mlx90640::NUM_PIXELS / 2);
+//! # Ok::<(), mlx9064x::LibraryError>(())
+//! ```
+//!
+//! The arrays are large enough for every pixel to be in both subpages, so the compiled data takes
+//! 44 bytes per pixel, about 33 KiB for the MLX90640 and 8.3 KiB for the MLX90641.
+//!
+//! [`raw_pixels_to_temperatures`]: crate::calculations::raw_pixels_to_temperatures
+
+use crate::calculations::{
+    compensation_pixel_offset, sensitivity_correction_coefficient, t_ar, CommonIrData, RamData,
+    TemperatureRanges,
+};
+use crate::common::{CalibrationData, MelexisCamera};
+use crate::filter::CompensationPixelFilter;
+use crate::register::{AccessPattern, Subpage};
+
+/// The calibration values for the pixels in one subpage.
+#[derive(Clone, Debug)]
+struct CompiledSubpage<const NUM_PIXELS: usize> {
+    /// How many pixels are in this subpage. Only this many values in the arrays are used.
+    length: usize,
+    /// The index of each pixel.
+    pixels: [u16; NUM_PIXELS],
+    offsets: [f32; NUM_PIXELS],
+    k_v: [f32; NUM_PIXELS],
+    k_ta: [f32; NUM_PIXELS],
+    /// The sensitivity, with thermal gradient compensation already applied.
+    alpha: [f32; NUM_PIXELS],
+    access_pattern_compensation: [f32; NUM_PIXELS],
+}
+
+impl<const NUM_PIXELS: usize> CompiledSubpage<NUM_PIXELS> {
+    fn new<'a, Clb>(calibration: &'a Clb, subpage: Subpage, access_pattern: AccessPattern) -> Self
+    where
+        Clb: CalibrationData<'a>,
+    {
+        let mut compiled = Self {
+            length: 0,
+            pixels: [0; NUM_PIXELS],
+            offsets: [0f32; NUM_PIXELS],
+            k_v: [0f32; NUM_PIXELS],
+            k_ta: [0f32; NUM_PIXELS],
+            alpha: [0f32; NUM_PIXELS],
+            access_pattern_compensation: [0f32; NUM_PIXELS],
+        };
+        let alpha_compensation_pixel = calibration
+            .temperature_gradient_coefficient()
+            .map(|tgc| calibration.alpha_cp(subpage) * tgc)
+            .unwrap_or_default();
+        Clb::Camera::pixels_in_subpage(subpage, access_pattern)
+            .into_iter()
+            .zip(calibration.offset_reference_pixels(subpage))
+            .zip(calibration.k_v_pixels(subpage))
+            .zip(calibration.k_ta_pixels(subpage))
+            .zip(calibration.alpha_pixels(subpage))
+            .zip(calibration.access_pattern_compensation_pixels(access_pattern))
+            .enumerate()
+            .filter(|(_, (((((valid, _), _), _), _), _))| *valid)
+            .take(NUM_PIXELS)
+            .for_each(
+                |(index, (((((_, offset), k_v), k_ta), alpha), access_pattern_compensation))| {
+                    let n = compiled.length;
+                    compiled.pixels[n] = index as u16;
+                    compiled.offsets[n] = f32::from(*offset);
+                    compiled.k_v[n] = *k_v;
+                    compiled.k_ta[n] = *k_ta;
+                    compiled.alpha[n] = alpha - alpha_compensation_pixel;
+                    compiled.access_pattern_compensation[n] =
+                        access_pattern_compensation.copied().unwrap_or_default();
+                    compiled.length += 1;
+                },
+            );
+        compiled
+    }
+}
+
+/// Calibration data compiled for one access pattern.
+///
+/// `NUM_PIXELS` is the number of pixels the camera has, [`MelexisCamera::NUM_PIXELS`].
+#[derive(Clone, Debug)]
+pub struct CompiledCalibration<const NUM_PIXELS: usize> {
+    access_pattern: AccessPattern,
+    subpages: [CompiledSubpage<NUM_PIXELS>; 2],
+    temperature_ranges: TemperatureRanges,
+}
+
+impl<const NUM_PIXELS: usize> CompiledCalibration<NUM_PIXELS> {
+    /// Compile calibration data for the given access pattern.
+    pub fn new<'a, Clb>(calibration: &'a Clb, access_pattern: AccessPattern) -> Self
+    where
+        Clb: CalibrationData<'a>,
+    {
+        Self {
+            access_pattern,
+            subpages: [
+                CompiledSubpage::new(calibration, Subpage::Zero, access_pattern),
+                CompiledSubpage::new(calibration, Subpage::One, access_pattern),
+            ],
+            temperature_ranges: TemperatureRanges::new(calibration),
+        }
+    }
+
+    /// The access pattern this calibration data was compiled for.
+    pub fn access_pattern(&self) -> AccessPattern {
+        self.access_pattern
+    }
+
+    /// The indices of the pixels in a subpage.
+    pub fn pixels(&self, subpage: Subpage) -> &[u16] {
+        let compiled = &self.subpages[subpage as usize];
+        &compiled.pixels[..compiled.length]
+    }
+
+    /// Calculate the temperature of all pixels in a subpage.
+    ///
+    /// This is equivalent to
+    /// [`raw_pixels_to_temperatures`][crate::calculations::raw_pixels_to_temperatures], and the
+    /// arguments are the same except that the access pattern (and so which pixels are in each
+    /// subpage) is the one this calibration data was compiled for. `calibration` must be the
+    /// calibration data this was compiled from. The ambient temperature is returned.
+    ///
+    /// # Panics
+    /// If `pixel_data` or `destination` are too small to hold every pixel.
+    #[allow(clippy::too_many_arguments)]
+    pub fn raw_pixels_to_temperatures<'a, Clb>(
+        &self,
+        calibration: &'a Clb,
+        emissivity: f32,
+        t_r: Option<f32>,
+        resolution_correction: f32,
+        pixel_data: &[u8],
+        ram: RamData,
+        compensation_filter: Option<&mut CompensationPixelFilter>,
+        subpage: Subpage,
+        destination: &mut [f32],
+    ) -> f32
+    where
+        Clb: CalibrationData<'a>,
+    {
+        let pixel_data = &pixel_data[..NUM_PIXELS * 2];
+        let destination = &mut destination[..NUM_PIXELS];
+        let common = CommonIrData::new(resolution_correction, emissivity, calibration, &ram);
+        let compensation_pixel_offset = compensation_pixel_offset(
+            calibration,
+            &common,
+            ram.compensation_pixel,
+            compensation_filter,
+            subpage,
+            self.access_pattern,
+        )
+        .unwrap_or_default();
+        let alpha_coefficient = sensitivity_correction_coefficient(calibration, common.t_a);
+        let t_r = t_r.unwrap_or(common.t_a - Clb::Camera::SELF_HEATING);
+        let t_ar = t_ar(common.t_a, t_r, emissivity);
+        let delta_t_a = common.t_a - 25f32;
+        let delta_v_dd = common.v_dd - 3.3f32;
+        let inverse_emissivity = emissivity.recip();
+
+        let compiled = &self.subpages[subpage as usize];
+        let length = compiled.length;
+        compiled.pixels[..length]
+            .iter()
+            .zip(&compiled.offsets[..length])
+            .zip(&compiled.k_v[..length])
+            .zip(&compiled.k_ta[..length])
+            .zip(&compiled.alpha[..length])
+            .zip(&compiled.access_pattern_compensation[..length])
+            .for_each(
+                |(((((index, offset), k_v), k_ta), alpha), access_pattern_compensation)| {
+                    let index = usize::from(*index);
+                    let pixel_data =
+                        i16::from_be_bytes([pixel_data[index * 2], pixel_data[index * 2 + 1]]);
+                    let pixel_offset = f32::from(pixel_data) * common.gain
+                        + access_pattern_compensation
+                        - offset * (1f32 + k_ta * delta_t_a) * (1f32 + k_v * delta_v_dd);
+                    let v_ir = pixel_offset * inverse_emissivity - compensation_pixel_offset;
+                    destination[index] = self.temperature_ranges.per_pixel_temperature(
+                        v_ir,
+                        alpha * alpha_coefficient,
+                        t_ar,
+                    );
+                },
+            );
+        common.t_a
+    }
+}
+
+#[cfg(test)]
+mod test {
+    extern crate std;
+
+    use std::vec;
+
+    use mlx9064x_test_data::{example_mlx90640_at_address, mock_mlx90641_at_address};
+
+    use crate::common::FromI2C;
+    use crate::mlx90640::{Mlx90640, Mlx90640Calibration};
+    use crate::mlx90641::{Mlx90641, Mlx90641Calibration};
+    use crate::test_frames::TestSubpage;
+    use crate::{AccessPattern, MelexisCamera, Subpage};
+
+    use super::CompiledCalibration;
+
+    #[test]
+    fn pixels() {
+        let mut bus = example_mlx90640_at_address(0x33);
+        let calibration = Mlx90640Calibration::from_i2c(&mut bus, 0x33).unwrap();
+        for access_pattern in [AccessPattern::Chess, AccessPattern::Interleave] {
+            let compiled =
+                CompiledCalibration::<{ Mlx90640::NUM_PIXELS }>::new(&calibration, access_pattern);
+            for subpage in [Subpage::Zero, Subpage::One] {
+                let expected: vec::Vec<u16> = Mlx90640::pixels_in_subpage(subpage, access_pattern)
+                    .into_iter()
+                    .enumerate()
+                    .filter_map(|(index, valid)| valid.then_some(index as u16))
+                    .collect();
+                assert_eq!(compiled.pixels(subpage), &expected[..]);
+            }
+        }
+    }
+
+    /// Multiplying by the inverse emissivity instead of dividing can change the last bit or so.
+    const MAX_ERROR: f32 = 0.0005;
+
+    #[test]
+    fn example_mlx90640() {
+        let mut bus = example_mlx90640_at_address(0x33);
+        let calibration = Mlx90640Calibration::from_i2c(&mut bus, 0x33).unwrap();
+        for access_pattern in [AccessPattern::Chess, AccessPattern::Interleave] {
+            let compiled =
+                CompiledCalibration::<{ Mlx90640::NUM_PIXELS }>::new(&calibration, access_pattern);
+            for emissivity in [1.0, 0.95] {
+                TestSubpage::compare_pipeline::<_, _, _, { Mlx90640::HEIGHT }>(
+                    &calibration,
+                    &mut bus,
+                    access_pattern,
+                    emissivity,
+                    MAX_ERROR,
+                    |subpage, destination| {
+                        compiled.raw_pixels_to_temperatures(
+                            &calibration,
+                            emissivity,
+                            None,
+                            subpage.resolution_correction,
+                            &subpage.pixel_bytes,
+                            subpage.ram,
+                            None,
+                            subpage.subpage,
+                            destination,
+                        )
+                    },
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn mock_mlx90641() {
+        let mut bus = mock_mlx90641_at_address(0x33);
+        let calibration = Mlx90641Calibration::from_i2c(&mut bus, 0x33).unwrap();
+        let compiled = CompiledCalibration::<{ Mlx90641::NUM_PIXELS }>::new(
+            &calibration,
+            AccessPattern::Interleave,
+        );
+        TestSubpage::compare_pipeline::<_, _, _, { Mlx90641::HEIGHT }>(
+            &calibration,
+            &mut bus,
+            AccessPattern::Interleave,
+            0.95,
+            MAX_ERROR,
+            |subpage, destination| {
+                compiled.raw_pixels_to_temperatures(
+                    &calibration,
+                    0.95,
+                    None,
+                    subpage.resolution_correction,
+                    &subpage.pixel_bytes,
+                    subpage.ram,
+                    None,
+                    subpage.subpage,
+                    destination,
+                )
+            },
+        );
+    }
+}
@@ -1,13 +1,23 @@
 // SPDX-License-Identifier: Apache-2.0
 // Copyright © 2021 Will Ross
 
-use embedded_hal::blocking::i2c;
+use core::time::Duration;
+
+use embedded_hal::delay::DelayNs;
+use embedded_hal::i2c;
 use paste::paste;
 
 use crate::calculations::*;
 use crate::common::*;
-use crate::error::Error;
+use crate::eeprom;
+use crate::emissivity::{Emissivity, EmissivityMap};
+use crate::error::{Error, LibraryError};
+use crate::filter::CompensationPixelFilter;
+use crate::frame::FrameAssembler;
+use crate::image::{replace_flagged_pixels, PixelReplacement};
+use crate::metadata::{Clock, FrameMetadata};
 use crate::register::*;
+use crate::util::{polls_within, saturating_micros};
 
 /// DRY macro for the set_* methods in `CameraDriver` that modify a register field.
 ///
@@ -35,6 +45,432 @@ macro_rules! set_register_field {
             }
         }
     }};
+    // The same thing, but for AsyncCameraDriver
+    { async $register_access:ident, $field:ident, $doc:literal } => {
+        set_register_field! {
+            async $register_access,
+            $field,
+            bool,
+            $doc
+        }
+    };
+    { async $register_access:ident, $field:ident, $typ:ty, $doc:literal } => {
+    paste! {
+        #[doc = $doc]
+        pub async fn [< set_ $field >](&mut self, new_value: $typ) -> Result<(), Error<I2C>> {
+            let mut current = self.$register_access().await?;
+            if current.$field() != new_value {
+                current.[< set_ $field >](new_value);
+                self.[< set_ $register_access >](current).await
+            } else {
+                Ok(())
+            }
+        }
+    }};
+}
+#[cfg(feature = "async")]
+pub(crate) use set_register_field;
+
+/// DRY macro for the methods that don't need to access the camera, shared between `CameraDriver`
+/// and `AsyncCameraDriver`.
+///
+/// Both drivers keep their settings in a [`FrameProcessor`] in a `processor` field.
+macro_rules! processor_accessors {
+    () => {
+        /// Get the emissivity value that is being used for calculations currently.
+        ///
+        /// The default emissivity is 1, unless a camera has a different value stored in EEPROM,
+        /// in which case that value is used. The default can also be
+        /// [overridden][Self::override_emissivity], but this change is not stored on the camera.
+        pub fn effective_emissivity(&self) -> f32 {
+            self.processor.emissivity
+        }
+
+        /// Override the emissivity value used in temperature calculations.
+        ///
+        /// The default emissivity is 1, unless a camera has a different value stored in EEPROM,
+        /// in which case that value is used. This method allows a new value to be used to
+        /// compensate for emissivity.
+        pub fn override_emissivity(&mut self, new_value: f32) {
+            self.processor.emissivity = new_value;
+        }
+
+        /// Use the default emissivity value.
+        ///
+        /// This is the opposite to `override_emissivity`, as it uses the default emissivity from
+        /// either the camera (if the camera has a value set) or 1.
+        pub fn use_default_emissivity(&mut self) {
+            let default_emissivity = self.processor.calibration.emissivity();
+            self.processor.emissivity = default_emissivity.unwrap_or(1f32);
+        }
+
+        /// Retrieve the current reflected temperature value.
+        ///
+        /// When the temperature of the ambient environment is not known, this function will
+        /// return `None`. See [`set_reflected_temperature`][Self::set_reflected_temperature] for
+        /// more information.
+        pub fn reflected_temperature(&self) -> Option<f32> {
+            self.processor.reflected_temperature
+        }
+
+        /// Set the reflected temperature value.
+        ///
+        /// This value is used to compensate for infrared radiation not being emitted by an object
+        /// itself, but being emitted by the ambient environment and reflected by an object being
+        /// measured. This value is distinct from the one from [`ambient_temperature`], but if not
+        /// explicitly known it can be estimated from that value.
+        ///
+        /// [`ambient_temperature`]: Self::ambient_temperature
+        pub fn set_reflected_temperature(&mut self, new_value: Option<f32>) {
+            self.processor.reflected_temperature = new_value;
+        }
+
+        /// Get the emissivity map being used for calculations, if there is one.
+        pub fn emissivity_map(&self) -> Option<&EmissivityMap<'static>> {
+            self.processor.emissivity_map.as_ref()
+        }
+
+        /// Set an emissivity map, giving parts of the image their own emissivity.
+        ///
+        /// Pixels not covered by the map use the [effective emissivity][Self::effective_emissivity]
+        /// and the [reflected temperature][Self::reflected_temperature]. Setting the map to `None`
+        /// uses the effective emissivity for every pixel again.
+        pub fn set_emissivity_map(&mut self, new_value: Option<EmissivityMap<'static>>) {
+            self.processor.emissivity_map = new_value;
+        }
+
+        /// Get the filter being applied to the compensation pixels.
+        ///
+        /// By default a moving average filter with a length of 16 is used, as recommended by the
+        /// datasheets. The filter only has an effect on cameras that use thermal gradient
+        /// compensation.
+        pub fn compensation_pixel_filter(&self) -> Option<&CompensationPixelFilter> {
+            self.processor.compensation_filter.as_ref()
+        }
+
+        /// Set (or remove) the filter being applied to the compensation pixels.
+        ///
+        /// The filter is reset automatically when the resolution or access pattern is changed
+        /// through this driver.
+        pub fn set_compensation_pixel_filter(&mut self, filter: Option<CompensationPixelFilter>) {
+            self.processor.compensation_filter = filter;
+        }
+
+        /// Get how failed pixels are replaced in generated images.
+        ///
+        /// By default failed pixels are replaced with the mean of their neighbours from the same
+        /// subpage, so that the replacement doesn't depend on data from an older frame.
+        pub fn pixel_replacement(&self) -> Option<PixelReplacement> {
+            self.processor.pixel_replacement
+        }
+
+        /// Set how failed pixels are replaced in generated images.
+        ///
+        /// If `None`, failed pixels are left as calculated, which will be garbage.
+        pub fn set_pixel_replacement(&mut self, replacement: Option<PixelReplacement>) {
+            self.processor.pixel_replacement = replacement;
+        }
+
+        /// Check if outlier pixels are replaced in generated images (in addition to failed
+        /// pixels).
+        ///
+        /// The default is to leave outlier pixels as they are.
+        pub fn replace_outlier_pixels(&self) -> bool {
+            self.processor.replace_outlier_pixels
+        }
+
+        /// Enable (or disable) replacing outlier pixels in generated images.
+        ///
+        /// Outlier pixels are replaced in the same way as [failed pixels][Self::pixel_replacement].
+        pub fn set_replace_outlier_pixels(&mut self, replace: bool) {
+            self.processor.replace_outlier_pixels = replace;
+        }
+
+        /// Check if temperatures are calculated with the [`simd`][crate::simd] module.
+        ///
+        /// The default is to use the scalar calculations.
+        #[cfg(feature = "simd")]
+        pub fn use_simd(&self) -> bool {
+            self.processor.use_simd
+        }
+
+        /// Enable (or disable) calculating temperatures with the [`simd`][crate::simd] module.
+        ///
+        /// The results are very close to, but not exactly the same as, the scalar calculations.
+        /// See the module documentation for details.
+        #[cfg(feature = "simd")]
+        pub fn set_use_simd(&mut self, use_simd: bool) {
+            self.processor.use_simd = use_simd;
+        }
+
+        /// Get the tracker for which subpages have been generated since the last full frame.
+        ///
+        /// See [`generate_full_frame_if_ready`][Self::generate_full_frame_if_ready] for more
+        /// details.
+        pub fn frame_assembler(&self) -> &FrameAssembler {
+            &self.processor.frame_assembler
+        }
+
+        /// Get the most recent ambient temperature calculation.
+        ///
+        /// What the datasheets (and this crate) refer to as "ambient temperature" should be better
+        /// understood as the ambient temperature of the camera itself, not of the area being
+        /// imaged. These values will usually be different because the camera generates some heat
+        /// itself, and the sensor used for this value is within the camera module. See
+        /// [`MelexisCamera::SELF_HEATING`] for more details.
+        ///
+        /// This value is calculated as part of the overall image calculations. If that
+        /// process hasn't been performed yet (by calling
+        /// [`generate_image_if_ready`][Self::generate_image_if_ready] or similar), this method
+        /// will return `None`.
+        pub fn ambient_temperature(&self) -> Option<f32> {
+            self.processor.ambient_temperature
+        }
+
+        /// Get the metadata for the most recently processed subpage.
+        ///
+        /// This is updated by all of the `generate_*` functions, but only
+        /// [`generate_image_with_metadata`][Self::generate_image_with_metadata] adds a timestamp.
+        pub fn frame_metadata(&self) -> Option<FrameMetadata> {
+            self.processor.metadata
+        }
+
+        /// The height of the thermal image, in pixels.
+        pub fn height(&self) -> usize {
+            // const generics make this silly.
+            Clb::Camera::HEIGHT
+        }
+
+        /// The width of the thermal image, in pixels.
+        pub fn width(&self) -> usize {
+            Clb::Camera::WIDTH
+        }
+    };
+}
+#[cfg(feature = "async")]
+pub(crate) use processor_accessors;
+
+/// The parts of a driver that don't involve communicating with the camera.
+///
+/// This is shared between [`CameraDriver`] and `AsyncCameraDriver`, covering the calibration data,
+/// the settings used when processing images, and the state carried between frames.
+#[derive(Clone, Debug)]
+pub(crate) struct FrameProcessor<Clb> {
+    /// The factory calibration data for a specific camera.
+    pub(crate) calibration: Clb,
+
+    /// ADC resolution correction factor.
+    pub(crate) resolution_correction: f32,
+
+    /// The most recent observed ambient temperature.
+    ///
+    /// The ambient temperature is calculated during image processing step. Save it for those
+    /// applications that want the ambient temperature so a full recalculation isn't necessary.
+    pub(crate) ambient_temperature: Option<f32>,
+
+    /// The emissivity value to use when calculating pixel temperature.
+    pub(crate) emissivity: f32,
+
+    /// Emissivity values for parts of the image, overriding `emissivity`.
+    pub(crate) emissivity_map: Option<EmissivityMap<'static>>,
+
+    /// The current access pattern the camera is using.
+    pub(crate) access_pattern: AccessPattern,
+
+    /// The temperature of the ambient environment.
+    ///
+    /// This value is used as the "reflected temperature" for converting the observed IR data to
+    /// actual temperatures.
+    pub(crate) reflected_temperature: Option<f32>,
+
+    /// The moving average filter applied to the compensation pixels.
+    pub(crate) compensation_filter: Option<CompensationPixelFilter>,
+
+    /// How failed (and optionally outlier) pixels are replaced in generated images.
+    pub(crate) pixel_replacement: Option<PixelReplacement>,
+
+    /// Whether outlier pixels are replaced as well as failed pixels.
+    pub(crate) replace_outlier_pixels: bool,
+
+    /// Whether temperatures are calculated with the SIMD functions.
+    #[cfg(feature = "simd")]
+    pub(crate) use_simd: bool,
+
+    /// Which subpages have been written out since the last complete frame.
+    pub(crate) frame_assembler: FrameAssembler,
+
+    /// The ADC resolution the camera is using.
+    pub(crate) resolution: Resolution,
+
+    /// The frame rate the camera is using.
+    pub(crate) frame_rate: FrameRate,
+
+    /// The sequence number for the next processed subpage.
+    pub(crate) sequence: u64,
+
+    /// Details about the most recently processed subpage.
+    pub(crate) metadata: Option<FrameMetadata>,
+}
+
+impl<'a, Clb> FrameProcessor<Clb>
+where
+    Clb: CalibrationData<'a>,
+{
+    /// Create a new `FrameProcessor` for a camera with the given control register value.
+    pub(crate) fn new(calibration: Clb, control: &ControlRegister) -> Self {
+        // Cache these values
+        let resolution_correction =
+            Clb::Camera::resolution_correction(calibration.resolution(), control.resolution());
+        let access_pattern = control.access_pattern();
+        // Choose an emissivity value to start with.
+        let emissivity = calibration.emissivity().unwrap_or(1f32);
+        Self {
+            calibration,
+            resolution_correction,
+            ambient_temperature: None,
+            emissivity,
+            emissivity_map: None,
+            access_pattern,
+            reflected_temperature: None,
+            compensation_filter: Some(CompensationPixelFilter::default()),
+            pixel_replacement: Some(PixelReplacement::SubpageNeighbourMean),
+            replace_outlier_pixels: false,
+            #[cfg(feature = "simd")]
+            use_simd: false,
+            frame_assembler: FrameAssembler::new::<Clb::Camera>(access_pattern),
+            resolution: control.resolution(),
+            frame_rate: control.frame_rate(),
+            sequence: 0,
+            metadata: None,
+        }
+    }
+
+    /// Update the cached values derived from the control register.
+    pub(crate) fn update_control_register(&mut self, register: &ControlRegister) {
+        // Update the resolution as well
+        let calibrated_resolution = self.calibration.resolution();
+        let resolution_correction =
+            Clb::Camera::resolution_correction(calibrated_resolution, register.resolution());
+        let access_pattern = register.access_pattern();
+        // The old compensation pixel values aren't comparable after either of these change.
+        if resolution_correction != self.resolution_correction
+            || access_pattern != self.access_pattern
+        {
+            if let Some(filter) = self.compensation_filter.as_mut() {
+                filter.reset();
+            }
+        }
+        // The pixels in each subpage change with the access pattern
+        if access_pattern != self.access_pattern {
+            self.frame_assembler = FrameAssembler::new::<Clb::Camera>(access_pattern);
+        }
+        self.resolution_correction = resolution_correction;
+        self.access_pattern = access_pattern;
+        self.resolution = register.resolution();
+        self.frame_rate = register.frame_rate();
+    }
+
+    /// Process a subpage of data read from the camera into `destination`.
+    ///
+    /// If `temperatures` is false, the $V_{IR}$ values are written to `destination` instead of
+    /// temperatures. The metadata for the subpage (including whether or not a full frame is now
+    /// available) is returned, as `self` can't be used by the caller after borrowing it for `'a`.
+    pub(crate) fn process(
+        &'a mut self,
+        subpage: Subpage,
+        ram: RamData,
+        pixel_data: &[u8],
+        temperatures: bool,
+        timestamp: Option<Duration>,
+        destination: &mut [f32],
+    ) -> FrameMetadata {
+        let emissivity = match &self.emissivity_map {
+            Some(map) => Emissivity::Map {
+                default: self.emissivity,
+                map,
+            },
+            None => Emissivity::Uniform(self.emissivity),
+        };
+        // The per-subpage values are calculated here (instead of within the per-pixel
+        // calculations) so they can be kept in the metadata.
+        let values = SubpageValues::new(
+            &self.calibration,
+            emissivity,
+            self.resolution_correction,
+            &ram,
+            self.compensation_filter.as_mut(),
+            subpage,
+            self.access_pattern,
+        );
+        let mut valid_pixels =
+            Clb::Camera::pixels_in_subpage(subpage, self.access_pattern).into_iter();
+        let ambient_temperature = if temperatures {
+            // The SIMD version takes the same arguments.
+            #[cfg(feature = "simd")]
+            let temperatures_with_values = if self.use_simd {
+                crate::simd::temperatures_with_values
+            } else {
+                temperatures_with_values
+            };
+            temperatures_with_values(
+                &self.calibration,
+                emissivity,
+                &values,
+                self.reflected_temperature,
+                pixel_data,
+                subpage,
+                self.access_pattern,
+                &mut valid_pixels,
+                destination,
+            )
+        } else {
+            ir_data_with_values(
+                &self.calibration,
+                emissivity,
+                &values,
+                pixel_data,
+                subpage,
+                self.access_pattern,
+                &mut valid_pixels,
+                destination,
+            )
+        };
+        if let Some(replacement) = self.pixel_replacement {
+            replace_flagged_pixels::<Clb::Camera, _>(
+                &self.calibration.failed_pixels(),
+                replacement,
+                self.access_pattern,
+                destination,
+            );
+            if self.replace_outlier_pixels {
+                replace_flagged_pixels::<Clb::Camera, _>(
+                    &self.calibration.outlier_pixels(),
+                    replacement,
+                    self.access_pattern,
+                    destination,
+                );
+            }
+        }
+        self.ambient_temperature = Some(ambient_temperature);
+        self.frame_assembler.record(subpage, ambient_temperature);
+        let metadata = FrameMetadata {
+            subpage,
+            sequence: self.sequence,
+            frame_complete: self.frame_assembler.is_complete(),
+            timestamp,
+            ambient_temperature,
+            v_dd: values.common.v_dd,
+            gain: values.common.gain,
+            ram,
+            compensation_pixel: values.compensation_pixel,
+            compensation_pixel_offset: values.compensation_pixel_offset,
+            resolution: self.resolution,
+            frame_rate: self.frame_rate,
+        };
+        self.sequence += 1;
+        self.metadata = Some(metadata);
+        metadata
+    }
 }
 
 /// The shared camera driver for the MLX90640 and MLX90641 thermopiles.
@@ -45,6 +481,8 @@ macro_rules! set_register_field {
 ///
 /// The biggest impact to users of these modules is that one of the  `generate_image_*` functions
 /// will need to be called twice (once for each subpage) before a full image is available.
+/// [`generate_full_frame_if_ready`][CameraDriver::generate_full_frame_if_ready] keeps track of
+/// this for you.
 
 // HEIGHT and NUM_BYTES are const generics until generic_const_expr is stabilized (maybe). After
 // that, point the associated constants on Clb::Camera (Clb::Camera::HEIGHT and {
@@ -57,40 +495,19 @@ pub struct CameraDriver<Clb, I2C, const HEIGHT: usize, const NUM_BYTES: usize> {
     /// The I²C address this camera is accessible at.
     address: u8,
 
-    /// The factory calibration data for a specific camera.
-    calibration: Clb,
-
     /// Buffer for reading pixel data off of the camera.
     // I wish I could use const generics for computer parameters :/
     pixel_buffer: [u8; NUM_BYTES],
 
-    /// ADC resolution correction factor.
-    resolution_correction: f32,
-
-    /// The most recent observed ambient temperature.
-    ///
-    /// The ambient temperature is calculated during image processing step. Save it for those
-    /// applications that want the ambient temperature so a full recalculation isn't necessary.
-    ambient_temperature: Option<f32>,
-
-    /// The emissivity value to use when calculating pixel temperature.
-    emissivity: f32,
-
-    /// The current access pattern the camera is using.
-    access_pattern: AccessPattern,
-
-    /// The temperature of the ambient environment.
-    ///
-    /// This value is used as the "reflected temperature" for converting the observed IR data to
-    /// actual temperatures.
-    reflected_temperature: Option<f32>,
+    /// Calibration data, settings, and state for processing images.
+    processor: FrameProcessor<Clb>,
 }
 
 impl<'a, Clb, I2C, const HEIGHT: usize, const BUFFER_SIZE: usize>
     CameraDriver<Clb, I2C, HEIGHT, BUFFER_SIZE>
 where
     Clb: CalibrationData<'a>,
-    I2C: i2c::WriteRead + i2c::Write,
+    I2C: i2c::I2c,
 {
     /// Create a new `CameraDriver`, obtaining the calibration data from the camera over I²C.
     pub fn new(bus: I2C, address: u8) -> Result<Self, Error<I2C>>
@@ -114,26 +531,30 @@ where
         // We own the bus now, make it mutable.
         let mut bus = bus;
         // Grab the control register values first
-        // Need to map from I2C::Error manually as it's an associated type without bounds, so we
-        // can't implement From<I2C:Error>
         let control = ControlRegister::from_i2c(&mut bus, address)?;
-        // Cache these values
-        let resolution_correction =
-            Clb::Camera::resolution_correction(calibration.resolution(), control.resolution());
-        let access_pattern = control.access_pattern();
-        // Choose an emissivity value to start with.
-        let emissivity = calibration.emissivity().unwrap_or(1f32);
-        Ok(Self {
+        Ok(Self::from_parts(bus, address, calibration, &control))
+    }
+
+    /// Create a `CameraDriver` from values that have already been read from the camera.
+    ///
+    /// Nothing is read from the camera, so the bus does not need to be usable yet.
+    pub(crate) fn from_parts(
+        bus: I2C,
+        address: u8,
+        calibration: Clb,
+        control: &ControlRegister,
+    ) -> Self {
+        Self {
             bus,
             address,
-            calibration,
             pixel_buffer: [0u8; BUFFER_SIZE],
-            resolution_correction,
-            ambient_temperature: None,
-            emissivity,
-            access_pattern,
-            reflected_temperature: None,
-        })
+            processor: FrameProcessor::new(calibration, control),
+        }
+    }
+
+    /// Access the bus the camera is on.
+    pub(crate) fn bus_mut(&mut self) -> &mut I2C {
+        &mut self.bus
     }
 
     fn status_register(&mut self) -> Result<StatusRegister, Error<I2C>> {
@@ -145,23 +566,15 @@ where
         register.to_i2c(&mut self.bus, self.address)
     }
 
-    fn update_control_register(&mut self, register: &ControlRegister) {
-        // Update the resolution as well
-        let calibrated_resolution = self.calibration.resolution();
-        self.resolution_correction =
-            Clb::Camera::resolution_correction(calibrated_resolution, register.resolution());
-        self.access_pattern = register.access_pattern();
-    }
-
     fn control_register(&mut self) -> Result<ControlRegister, Error<I2C>> {
         let register = ControlRegister::from_i2c(&mut self.bus, self.address)?;
         // Update the resolution as well
-        self.update_control_register(&register);
+        self.processor.update_control_register(&register);
         Ok(register)
     }
 
     fn set_control_register(&mut self, register: ControlRegister) -> Result<(), Error<I2C>> {
-        self.update_control_register(&register);
+        self.processor.update_control_register(&register);
         register.to_i2c(&mut self.bus, self.address)?;
         Ok(())
     }
@@ -308,84 +721,28 @@ where
         "Set the access pattern used by the camera."
     }
 
-    /// Get the emissivity value that is being used for calculations currently.
-    ///
-    /// The default emissivity is 1, unless a camera has a different value stored in EEPROM, in
-    /// which case that value is used. The default can also be
-    /// [overridden][CameraDriver::override_emissivity], but this change is not stored on the camera.
-    pub fn effective_emissivity(&self) -> f32 {
-        self.emissivity
-    }
-
-    /// Override the emissivity value used in temperature calculations.
-    ///
-    /// The default emissivity is 1, unless a camera has a different value stored in EEPROM, in
-    /// which case that value is used. This method allows a new value to be used to compensate for
-    /// emissivity.
-    pub fn override_emissivity(&mut self, new_value: f32) {
-        self.emissivity = new_value;
-    }
-
-    /// Use the default emissivity value.
+    /// Check if the camera is in step mode.
     ///
-    /// This is the opposite to `override_emissivity`, as it uses the default emissivity from
-    /// either the camera (if the camera has a value set) or 1.
-    pub fn use_default_emissivity(&mut self) {
-        let default_emissivity = self.calibration.emissivity();
-        self.emissivity = default_emissivity.unwrap_or(1f32);
+    /// In step mode the camera is idle until a measurement is triggered. See
+    /// [`measure_frame`][Self::measure_frame] for a way to take a frame in step mode, and
+    /// [`ControlRegister::step_mode`] for the caveats. The default is continuous mode.
+    pub fn step_mode(&mut self) -> Result<bool, Error<I2C>> {
+        Ok(self.control_register()?.step_mode())
     }
 
-    /// Retrieve the current reflected temperature value.
-    ///
-    /// When the temperature of the ambient environment is not known, this function will return
-    /// `None`. See [`set_reflected_temperature`][Self::set_reflected_temperature] for more
-    /// information.
-    pub fn reflected_temperature(&self) -> Option<f32> {
-        self.reflected_temperature
-    }
-
-    /// Set the reflected temperature value.
-    ///
-    /// This value is used to compensate for infrared radiation not being emitted by an object
-    /// itself, but being emitted by the ambient environment and reflected by an object being
-    /// measured. This value is distinct from the one from [`ambient_temperature`], but if not
-    /// explicitly known it can be estimated from that value.
-    pub fn set_reflected_temperature(&mut self, new_value: Option<f32>) {
-        self.reflected_temperature = new_value;
-    }
-
-    /// Get the most recent ambient temperature calculation.
-    ///
-    /// What the datasheets (and this crate) refer to as "ambient temperature" should be better
-    /// understood as the ambient temperature of the camera itself, not of the area being imaged.
-    /// These values will usually be different because the camera generates some heat itself, and
-    /// the sensor used for this value is within the camera module. See
-    /// [`MelexisCamera::SELF_HEATING`] for more details.
-    ///
-    /// This value is calculated as part of the overall image calculations. If that
-    /// process hasn't been performed yet (by calling
-    /// [`generate_image_if_ready`][Self::generate_image_if_ready] or similar), this method will
-    /// return `None`.
-    pub fn ambient_temperature(&self) -> Option<f32> {
-        self.ambient_temperature
-    }
-
-    /// The height of the thermal image, in pixels.
-    pub fn height(&self) -> usize {
-        // const generics make this silly.
-        Clb::Camera::HEIGHT
+    set_register_field! {
+        control_register,
+        step_mode,
+        "Enable (or disable) step mode."
     }
 
-    /// The width of the thermal image, in pixels.
-    pub fn width(&self) -> usize {
-        Clb::Camera::WIDTH
-    }
+    processor_accessors!();
 
     fn read_ram(&mut self, subpage: Subpage) -> Result<RamData, Error<I2C>> {
         read_ram::<Clb::Camera, I2C, HEIGHT>(
             &mut self.bus,
             self.address,
-            self.access_pattern,
+            self.processor.access_pattern,
             subpage,
             &mut self.pixel_buffer,
         )
@@ -397,20 +754,8 @@ where
         destination: &mut [f32],
     ) -> Result<(), Error<I2C>> {
         let ram = self.read_ram(subpage)?;
-        let mut valid_pixels =
-            Clb::Camera::pixels_in_subpage(subpage, self.access_pattern).into_iter();
-        let t_a = raw_pixels_to_ir_data(
-            &self.calibration,
-            self.emissivity,
-            self.resolution_correction,
-            &self.pixel_buffer,
-            ram,
-            subpage,
-            self.access_pattern,
-            &mut valid_pixels,
-            destination,
-        );
-        self.ambient_temperature = Some(t_a);
+        self.processor
+            .process(subpage, ram, &self.pixel_buffer, false, None, destination);
         Ok(())
     }
 
@@ -420,21 +765,8 @@ where
         destination: &mut [f32],
     ) -> Result<(), Error<I2C>> {
         let ram = self.read_ram(subpage)?;
-        let mut valid_pixels =
-            Clb::Camera::pixels_in_subpage(subpage, self.access_pattern).into_iter();
-        let t_a = raw_pixels_to_temperatures(
-            &self.calibration,
-            self.emissivity,
-            self.reflected_temperature,
-            self.resolution_correction,
-            &self.pixel_buffer,
-            ram,
-            subpage,
-            self.access_pattern,
-            &mut valid_pixels,
-            destination,
-        );
-        self.ambient_temperature = Some(t_a);
+        self.processor
+            .process(subpage, ram, &self.pixel_buffer, true, None, destination);
         Ok(())
     }
 
@@ -460,54 +792,121 @@ where
         &'a mut self,
         destination: &mut [f32],
     ) -> Result<bool, Error<I2C>> {
+        Ok(self.process_new_data(None, destination)?.is_some())
+    }
+
+    /// Generate a thermal "image" from the camera's current data if there's new data, returning
+    /// the metadata for it.
+    ///
+    /// This function works like [`generate_image_if_ready`][Self::generate_image_if_ready], but
+    /// returns the [metadata][FrameMetadata] for the new subpage instead of a boolean. If `clock`
+    /// is given, the metadata is timestamped with the time the new data was found.
+    pub fn generate_image_with_metadata(
+        &'a mut self,
+        destination: &mut [f32],
+        clock: Option<&mut dyn Clock>,
+    ) -> Result<Option<FrameMetadata>, Error<I2C>> {
+        self.process_new_data(clock, destination)
+    }
+
+    /// Process new data from the camera into `destination` if there is any.
+    ///
+    /// If there was no new data, `None` is returned. Otherwise the metadata for the new subpage is
+    /// returned, which includes whether or not a full frame is available. This is checked here as
+    /// `self` can't be used again by the caller after borrowing it for `'a`. If a clock is given,
+    /// the metadata is timestamped with it.
+    fn process_new_data(
+        &'a mut self,
+        clock: Option<&mut dyn Clock>,
+        destination: &mut [f32],
+    ) -> Result<Option<FrameMetadata>, Error<I2C>> {
         // Not going through the helper methods on self to avoid infecting them with 'a
         let address = self.address;
         let bus = &mut self.bus;
-        let pixel_buffer = &mut self.pixel_buffer;
         let mut status_register = StatusRegister::from_i2c(bus, address)?;
         if status_register.new_data() {
+            let timestamp = clock.map(|clock| clock.now());
             let subpage = status_register.last_updated_subpage();
-            let mut valid_pixels =
-                Clb::Camera::pixels_in_subpage(subpage, self.access_pattern).into_iter();
             let ram = read_ram::<Clb::Camera, I2C, HEIGHT>(
                 bus,
                 address,
-                self.access_pattern,
+                self.processor.access_pattern,
                 subpage,
-                pixel_buffer,
+                &mut self.pixel_buffer,
             )?;
-            let ambient_temperature = raw_pixels_to_temperatures(
-                &self.calibration,
-                self.emissivity,
-                self.reflected_temperature,
-                self.resolution_correction,
-                &self.pixel_buffer,
-                ram,
+            let metadata = self.processor.process(
                 subpage,
-                self.access_pattern,
-                &mut valid_pixels,
+                ram,
+                &self.pixel_buffer,
+                true,
+                timestamp,
                 destination,
             );
-            self.ambient_temperature = Some(ambient_temperature);
             status_register.reset_new_data();
             status_register.to_i2c(bus, address)?;
-            Ok(true)
+            Ok(Some(metadata))
         } else {
-            Ok(false)
+            Ok(None)
         }
     }
 
+    /// Generate a thermal "image" from the camera's current data if there's new data, reporting
+    /// whether a full frame is now available.
+    ///
+    /// This function works like [`generate_image_if_ready`][Self::generate_image_if_ready], but
+    /// the `Ok` value is only `true` once every pixel in `destination` has been updated since the
+    /// previous full frame. For the MLX90640 that means both subpages have been processed, while
+    /// each subpage of the MLX90641 is a full frame. The ambient temperature used for each subpage
+    /// is available from [`frame_assembler`][Self::frame_assembler].
+    ///
+    /// The same `destination` should be used for every call (and for the other `generate_*`
+    /// functions), as the subpages are tracked across all of them.
+    pub fn generate_full_frame_if_ready(
+        &'a mut self,
+        destination: &mut [f32],
+    ) -> Result<bool, Error<I2C>> {
+        Ok(self
+            .process_new_data(None, destination)?
+            .is_some_and(|metadata| metadata.frame_complete))
+    }
+
+    /// Store a new I²C address for the camera in its EEPROM.
+    ///
+    /// The camera (and this driver) keep using the current address until the camera is power
+    /// cycled. See the [`eeprom`] module for how the EEPROM is written.
+    pub fn set_i2c_address_persistent<D: DelayNs>(
+        &mut self,
+        delay: &mut D,
+        new_address: u8,
+    ) -> Result<(), Error<I2C>> {
+        eeprom::set_i2c_address_persistent::<Clb::Camera, _, _>(
+            &mut self.bus,
+            delay,
+            self.address,
+            new_address,
+        )
+    }
+
+    /// Store the current control register settings (frame rate, resolution and so on) as the
+    /// camera's power on defaults.
+    pub fn store_default_control_register<D: DelayNs>(
+        &mut self,
+        delay: &mut D,
+    ) -> Result<(), Error<I2C>> {
+        eeprom::store_default_control_register::<Clb::Camera, _, _>(
+            &mut self.bus,
+            delay,
+            self.address,
+        )
+    }
+
     /// Synchronize with the camera's frame update timing
     ///
     /// This function ignores any new data, then forces a new measurement by the camera, only
     /// returning when that measurement is complete. This can be used to synchronize frame access
     /// from the controller to the update time of the camera.
     pub fn synchronize(&mut self) -> Result<(), Error<I2C>> {
-        let mut status_register = self.status_register()?;
-        status_register.reset_new_data();
-        status_register.set_overwrite_enabled(true);
-        status_register.set_start_measurement();
-        status_register.to_i2c(&mut self.bus, self.address)?;
+        let mut status_register = self.start_synchronized_measurement()?;
         // Spin while we wait for data
         while !status_register.new_data() {
             status_register = StatusRegister::from_i2c(&mut self.bus, self.address)?;
@@ -515,6 +914,109 @@ where
         }
         Ok(())
     }
+
+    /// Synchronize with the camera's frame update timing, giving up after `timeout`.
+    ///
+    /// This works like [`synchronize`][Self::synchronize], but instead of spinning the status
+    /// register is checked every [poll interval][FrameRate::poll_interval] for the current frame
+    /// rate, with `delay` used to wait in between. If there is still no new data after `timeout`,
+    /// [`LibraryError::Timeout`] is returned.
+    pub fn synchronize_with_timeout<D: DelayNs>(
+        &mut self,
+        delay: &mut D,
+        timeout: Duration,
+    ) -> Result<(), Error<I2C>> {
+        let poll_interval = self.frame_rate()?.poll_interval();
+        let max_polls = polls_within(timeout, poll_interval);
+        self.synchronize_polling(delay, poll_interval, max_polls)
+    }
+
+    /// Synchronize with the camera's frame update timing, giving up after checking the status
+    /// register `max_polls` times.
+    ///
+    /// See [`synchronize_with_timeout`][Self::synchronize_with_timeout] for details.
+    pub fn synchronize_with_poll_limit<D: DelayNs>(
+        &mut self,
+        delay: &mut D,
+        max_polls: u32,
+    ) -> Result<(), Error<I2C>> {
+        let poll_interval = self.frame_rate()?.poll_interval();
+        self.synchronize_polling(delay, poll_interval, max_polls)
+    }
+
+    /// Ignore any new data and start a new measurement, returning the status register as written.
+    fn start_synchronized_measurement(&mut self) -> Result<StatusRegister, Error<I2C>> {
+        let mut status_register = self.status_register()?;
+        status_register.reset_new_data();
+        status_register.set_overwrite_enabled(true);
+        status_register.set_start_measurement();
+        self.set_status_register(status_register)?;
+        Ok(status_register)
+    }
+
+    fn synchronize_polling<D: DelayNs>(
+        &mut self,
+        delay: &mut D,
+        poll_interval: Duration,
+        max_polls: u32,
+    ) -> Result<(), Error<I2C>> {
+        self.start_synchronized_measurement()?;
+        let poll_interval = saturating_micros(poll_interval);
+        for _ in 0..max_polls {
+            delay.delay_us(poll_interval);
+            if self.status_register()?.new_data() {
+                return Ok(());
+            }
+        }
+        Err(LibraryError::Timeout.into())
+    }
+}
+
+// Taking a frame means processing more than one subpage in one call, so the calibration data
+// needs to be usable for any lifetime (which is true for everything besides `CalibrationBlob`).
+impl<Clb, I2C, const HEIGHT: usize, const BUFFER_SIZE: usize>
+    CameraDriver<Clb, I2C, HEIGHT, BUFFER_SIZE>
+where
+    Clb: for<'a> CalibrationData<'a>,
+    I2C: i2c::I2c,
+{
+    /// Take a single frame in step mode, writing the temperatures into `destination`.
+    ///
+    /// The camera is put into step mode if it isn't already, then measurements are triggered until
+    /// every pixel in `destination` has been updated. Each measurement only covers one subpage, so
+    /// the MLX90640 needs two measurements while the MLX90641 only needs one. The camera then stays
+    /// idle until the next call, which makes this suitable for taking infrequent frames.
+    ///
+    /// `delay` is used to wait between checks of the status register, as with
+    /// [`synchronize_with_timeout`][Self::synchronize_with_timeout]. If a measurement hasn't
+    /// finished after two frame periods, [`LibraryError::Timeout`] is returned. If two
+    /// measurements do not cover the full frame (for example if [subpage repeat] is enabled), an
+    /// error is returned instead of triggering more measurements.
+    ///
+    /// [subpage repeat]: Self::subpage_repeat
+    pub fn measure_frame<D: DelayNs>(
+        &mut self,
+        delay: &mut D,
+        destination: &mut [f32],
+    ) -> Result<(), Error<I2C>> {
+        let mut control = self.control_register()?;
+        if !control.step_mode() {
+            control.set_step_mode(true);
+            self.set_control_register(control)?;
+        }
+        let frame_rate = control.frame_rate();
+        let poll_interval = frame_rate.poll_interval();
+        let max_polls = polls_within(Duration::from(frame_rate) * 2, poll_interval);
+        self.processor.frame_assembler.reset();
+        for _ in 0..2 {
+            self.synchronize_polling(delay, poll_interval, max_polls)?;
+            let metadata = self.process_new_data(None, destination)?;
+            if metadata.is_some_and(|metadata| metadata.frame_complete) {
+                return Ok(());
+            }
+        }
+        Err(LibraryError::Other("Step mode measurements did not cover a full frame").into())
+    }
 }
 
 #[cfg(test)]
@@ -522,15 +1024,21 @@ where
 mod test {
     extern crate std;
 
+    use embedded_hal::i2c::{Error as _, ErrorKind, NoAcknowledgeSource};
     use float_cmp::{approx_eq, assert_approx_eq};
 
     use mlx9064x_test_data::*;
 
+    use crate::calculations::{compensation_pixel_offset, CommonIrData};
     use crate::common::{FromI2C, ToI2C};
-    use crate::{mlx90640, mlx90641, mlx90642, Subpage};
-    use crate::{
-        I2cRegister, MelexisCamera, Mlx90640Driver, Mlx90641Driver, Mlx90642Driver, StatusRegister,
-    };
+    use crate::emissivity::EmissivityMap;
+    use crate::filter::CompensationPixelFilter;
+    use crate::image::PixelReplacement;
+    use crate::{mlx90640, mlx90641, Subpage};
+    use crate::{Error, LibraryError};
+    use crate::{I2cRegister, MelexisCamera, Mlx90640Driver, Mlx90641Driver, StatusRegister};
+
+    use super::Duration;
 
     fn create_mlx90640() -> Mlx90640Driver<MockCameraBus<MLX90640_RAM_LENGTH>> {
         // Specifically using a non-default address to make sure assumptions aren't being made
@@ -549,18 +1057,10 @@ mod test {
             .expect("A MLX90641 camera should be created after loading its data")
     }
 
-    fn create_mlx90642() -> Mlx90642Driver<MockCameraBus<MLX90642_RAM_LENGTH>> {
-        let address: u8 = 0x29;
-        let mock_bus = mock_mlx90642_at_address(address);
-        Mlx90642Driver::new(mock_bus, address)
-            .expect("A MLX90642 camera should be created after loading its data")
-    }
-
     #[test]
     fn smoke_test() {
         create_mlx90640();
         create_mlx90641();
-        create_mlx90642();
         // Test passes if we get this far.
     }
 
@@ -594,6 +1094,20 @@ mod test {
         assert_eq!(mock_bus.recent_operations().len(), 2);
     }
 
+    #[test]
+    fn error_kind() {
+        // The mock doesn't acknowledge addresses other than its own
+        let mock_bus = datasheet_mlx90640_at_address(0x30);
+        let err = Mlx90640Driver::new(mock_bus, 0x31).unwrap_err();
+        assert_eq!(
+            err.kind(),
+            ErrorKind::NoAcknowledge(NoAcknowledgeSource::Address)
+        );
+        let err: crate::Error<MockCameraBus<MLX90640_RAM_LENGTH>> =
+            crate::LibraryError::Other("Not an I2C error").into();
+        assert_eq!(err.kind(), ErrorKind::Other);
+    }
+
     #[test]
     fn default_emissivity() {
         // The MLX90640 doesn't store emissivity in EEPROM, so it should *always* default to 1
@@ -622,6 +1136,22 @@ mod test {
         assert!(cam.reflected_temperature().is_none());
     }
 
+    #[test]
+    fn set_emissivity_map() {
+        static EMISSIVITIES: [f32; 4] = [0.9, 0.8, 0.7, 0.6];
+        let mut cam = create_mlx90640();
+        assert!(cam.emissivity_map().is_none());
+        cam.set_emissivity_map(Some(EmissivityMap::Pixels(&EMISSIVITIES)));
+        assert_eq!(
+            cam.emissivity_map(),
+            Some(&EmissivityMap::Pixels(&EMISSIVITIES))
+        );
+        // The map doesn't change the default emissivity
+        assert_eq!(cam.effective_emissivity(), 1f32);
+        cam.set_emissivity_map(None);
+        assert!(cam.emissivity_map().is_none());
+    }
+
     #[test]
     fn mlx90640_datasheet_integration() {
         let mut cam = create_mlx90640();
@@ -650,18 +1180,6 @@ mod test {
         assert_approx_eq!(f32, temperatures[PIXEL_INDEX], 80.129812, epsilon = 0.5);
     }
 
-    #[test]
-    fn mlx90642_datasheet_integration() {
-        let mut cam = create_mlx90642();
-        let mut temperatures = [0f32; mlx90642::Mlx90642::NUM_PIXELS];
-        let res = cam.generate_image_if_ready(&mut temperatures);
-        assert!(res.is_ok());
-        assert!(res.unwrap());
-        // Same worked-example pixel as the MLX90641 mock
-        const PIXEL_INDEX: usize = 5 * mlx90642::Mlx90642::WIDTH + 8;
-        assert_approx_eq!(f32, temperatures[PIXEL_INDEX], 80.129812, epsilon = 0.5);
-    }
-
     #[test]
     fn mlx90640_example_integration() {
         let i2c_address = 0x43;
@@ -702,6 +1220,129 @@ mod test {
         }
     }
 
+    #[cfg(feature = "simd")]
+    #[test]
+    fn use_simd() {
+        let i2c_address = 0x43;
+        let generate = |use_simd: bool| {
+            let mut mocked = example_mlx90640_at_address(i2c_address);
+            let mut cam = Mlx90640Driver::new(mocked.clone(), i2c_address).unwrap();
+            // The scalar calculations are used unless SIMD is asked for.
+            assert!(!cam.use_simd());
+            cam.set_use_simd(use_simd);
+            assert_eq!(cam.use_simd(), use_simd);
+            let mut temperatures = [f32::NAN; mlx90640::Mlx90640::NUM_PIXELS];
+            mocked.set_data_available(true);
+            assert!(cam.generate_image_if_ready(&mut temperatures).unwrap());
+            temperatures
+        };
+        let scalar = generate(false);
+        let simd = generate(true);
+        compare_images(&scalar, &simd, 1e-4);
+    }
+
+    #[test]
+    fn compensation_pixel_filter() {
+        let i2c_address = 0x43;
+        let mut mocked = example_mlx90640_at_address(i2c_address);
+        let mut cam = Mlx90640Driver::new(mocked.clone(), i2c_address).unwrap();
+        let default_filter = CompensationPixelFilter::default();
+        assert_eq!(cam.compensation_pixel_filter(), Some(&default_filter));
+        let mut temperatures = [0f32; mlx90640::Mlx90640::NUM_PIXELS];
+        mocked.set_data_available(true);
+        assert!(cam.generate_image_if_ready(&mut temperatures).unwrap());
+        assert_ne!(cam.compensation_pixel_filter(), Some(&default_filter));
+        // Running the same frame through again should give the same results, as the average of
+        // the same compensation pixel value is unchanged.
+        let first_temperatures = temperatures;
+        mocked.set_data_available(true);
+        assert!(cam.generate_image_if_ready(&mut temperatures).unwrap());
+        assert_eq!(temperatures, first_temperatures);
+        // Changing the resolution resets the filter
+        cam.set_resolution(crate::Resolution::Nineteen).unwrap();
+        assert_eq!(cam.compensation_pixel_filter(), Some(&default_filter));
+        // And it can be disabled
+        cam.set_compensation_pixel_filter(None);
+        assert!(cam.compensation_pixel_filter().is_none());
+    }
+
+    #[test]
+    fn failed_pixel_replacement() {
+        // Mark pixel 10 as failed by zeroing out its calibration word (starting at 0x2440).
+        const PIXEL_NUM: usize = 10;
+        let mut eeprom_bytes = mlx90640_datasheet_eeprom();
+        let pixel_offset = (0x2440 - 0x2400 + PIXEL_NUM) * 2;
+        eeprom_bytes[pixel_offset..(pixel_offset + 2)].copy_from_slice(&[0, 0]);
+        let calibration = mlx90640::Mlx90640Calibration::from_data(&eeprom_bytes).unwrap();
+        let address = 0x30;
+        let mock_bus = datasheet_mlx90640_at_address(address);
+        let mut cam = Mlx90640Driver::new_with_calibration(mock_bus, address, calibration).unwrap();
+        assert_eq!(
+            cam.pixel_replacement(),
+            Some(PixelReplacement::SubpageNeighbourMean)
+        );
+        let mut temperatures = [0f32; mlx90640::Mlx90640::NUM_PIXELS];
+        cam.generate_image_subpage_to(Subpage::Zero, &mut temperatures)
+            .unwrap();
+        let replaced = temperatures[PIXEL_NUM];
+        // Pixel 10 is on the top row, so with the chess pattern only the two diagonals below it
+        // are in the same subpage.
+        let width = mlx90640::Mlx90640::WIDTH;
+        let expected =
+            (temperatures[PIXEL_NUM + width - 1] + temperatures[PIXEL_NUM + width + 1]) / 2.0;
+        assert_eq!(replaced, expected);
+        cam.set_pixel_replacement(Some(PixelReplacement::Nan));
+        cam.generate_image_subpage_to(Subpage::Zero, &mut temperatures)
+            .unwrap();
+        assert!(temperatures[PIXEL_NUM].is_nan());
+        cam.set_pixel_replacement(None);
+        cam.generate_image_subpage_to(Subpage::Zero, &mut temperatures)
+            .unwrap();
+        assert_ne!(temperatures[PIXEL_NUM], replaced);
+    }
+
+    #[test]
+    fn mlx90640_full_frame() {
+        let i2c_address = 0x43;
+        let mut mocked = example_mlx90640_at_address(i2c_address);
+        let mut cam = Mlx90640Driver::new(mocked.clone(), i2c_address).unwrap();
+        let mut temperatures = [f32::NAN; mlx90640::Mlx90640::NUM_PIXELS];
+        mocked.set_data_available(false);
+        assert!(!cam.generate_full_frame_if_ready(&mut temperatures).unwrap());
+        // Only half a frame
+        mocked.set_data_available(true);
+        assert!(!cam.generate_full_frame_if_ready(&mut temperatures).unwrap());
+        assert!(cam.frame_assembler().is_fresh(Subpage::Zero));
+        mocked.update_frame(
+            mlx90640_example_data::FRAME_1_DATA,
+            mlx90640_example_data::FRAME_1_STATUS_REGISTER,
+        );
+        mocked.set_data_available(true);
+        assert!(cam.generate_full_frame_if_ready(&mut temperatures).unwrap());
+        let ambient_temperatures = cam.frame_assembler().ambient_temperatures();
+        assert!(ambient_temperatures.iter().all(Option::is_some));
+        assert_eq!(ambient_temperatures[1], cam.ambient_temperature());
+        assert!(temperatures.iter().all(|t| !t.is_nan()));
+        // Starting the next frame
+        mocked.update_frame(
+            mlx90640_example_data::FRAME_0_DATA,
+            mlx90640_example_data::FRAME_0_STATUS_REGISTER,
+        );
+        mocked.set_data_available(true);
+        assert!(!cam.generate_full_frame_if_ready(&mut temperatures).unwrap());
+        // Changing the access pattern starts over
+        cam.set_access_pattern(crate::AccessPattern::Interleave)
+            .unwrap();
+        assert!(!cam.frame_assembler().is_fresh(Subpage::Zero));
+    }
+
+    #[test]
+    fn mlx90641_full_frame() {
+        let mut cam = create_mlx90641();
+        let mut temperatures = [0f32; mlx90641::Mlx90641::NUM_PIXELS];
+        assert!(cam.generate_full_frame_if_ready(&mut temperatures).unwrap());
+    }
+
     fn create_sentinel_buffer() -> [u8; mlx90641::Mlx90641::NUM_PIXELS * 2] {
         let mut buf = [0u8; mlx90641::Mlx90641::NUM_PIXELS * 2];
         // Initialize to 0xDEADBEEF to mark untouched memory
@@ -798,4 +1439,186 @@ mod test {
             "There should only be two operations to update a register"
         );
     }
+
+    /// A delay that doesn't wait, but marks new data as available after a number of delays.
+    struct MockDelay<const RAM_LENGTH: usize> {
+        bus: MockCameraBus<RAM_LENGTH>,
+        delays_until_ready: Option<usize>,
+        total_us: u64,
+        delay_count: usize,
+    }
+
+    impl<const RAM_LENGTH: usize> embedded_hal::delay::DelayNs for MockDelay<RAM_LENGTH> {
+        fn delay_ns(&mut self, ns: u32) {
+            self.delay_us(ns / 1000)
+        }
+
+        fn delay_us(&mut self, us: u32) {
+            self.delay_count += 1;
+            self.total_us += u64::from(us);
+            if Some(self.delay_count) == self.delays_until_ready {
+                self.bus.set_data_available(true);
+            }
+        }
+    }
+
+    #[test]
+    fn synchronize_with_timeout() {
+        let address = 0x30;
+        let mocked = datasheet_mlx90640_at_address(address);
+        let mut cam = Mlx90640Driver::new(mocked.clone(), address).unwrap();
+        let mut delay = MockDelay {
+            bus: mocked,
+            delays_until_ready: Some(3),
+            total_us: 0,
+            delay_count: 0,
+        };
+        cam.synchronize_with_timeout(&mut delay, Duration::from_secs(1))
+            .unwrap();
+        // 2 Hz, so polling every 31.25ms
+        assert_eq!(delay.total_us, 3 * 31_250);
+        // Never ready
+        delay.delays_until_ready = None;
+        delay.total_us = 0;
+        assert!(matches!(
+            cam.synchronize_with_timeout(&mut delay, Duration::from_millis(100)),
+            Err(Error::LibraryError(LibraryError::Timeout))
+        ));
+        assert_eq!(delay.total_us, 4 * 31_250);
+        delay.delay_count = 0;
+        assert!(matches!(
+            cam.synchronize_with_poll_limit(&mut delay, 5),
+            Err(Error::LibraryError(LibraryError::Timeout))
+        ));
+        assert_eq!(delay.delay_count, 5);
+    }
+
+    #[test]
+    fn measure_frame() {
+        let address = 0x30;
+        let mocked = datasheet_mlx90640_at_address(address);
+        let mut cam = Mlx90640Driver::new(mocked.clone(), address).unwrap();
+        let mut delay = MockDelay {
+            bus: mocked.clone(),
+            delays_until_ready: None,
+            total_us: 0,
+            delay_count: 0,
+        };
+        let mut temperatures = [f32::NAN; mlx90640::Mlx90640::NUM_PIXELS];
+        cam.measure_frame(&mut delay, &mut temperatures).unwrap();
+        assert!(cam.step_mode().unwrap());
+        // One measurement per subpage
+        assert_eq!(mocked.step_measurements(), 2);
+        assert!(temperatures.iter().all(|t| !t.is_nan()));
+        // The camera then sits idle
+        assert_eq!(cam.data_available().unwrap(), None);
+        cam.measure_frame(&mut delay, &mut temperatures).unwrap();
+        assert_eq!(mocked.step_measurements(), 4);
+        // Subpage repeat mode never covers the whole frame
+        cam.set_subpage_repeat(true).unwrap();
+        assert!(matches!(
+            cam.measure_frame(&mut delay, &mut temperatures),
+            Err(Error::LibraryError(LibraryError::Other(_)))
+        ));
+        // Each subpage of the MLX90641 is a full frame
+        let address = 0x33;
+        let mocked = mock_mlx90641_at_address(address);
+        let mut cam = Mlx90641Driver::new(mocked.clone(), address).unwrap();
+        let mut delay = MockDelay {
+            bus: mocked.clone(),
+            delays_until_ready: None,
+            total_us: 0,
+            delay_count: 0,
+        };
+        let mut temperatures = [f32::NAN; mlx90641::Mlx90641::NUM_PIXELS];
+        cam.measure_frame(&mut delay, &mut temperatures).unwrap();
+        assert_eq!(mocked.step_measurements(), 1);
+        assert!(temperatures.iter().all(|t| !t.is_nan()));
+        // Leaving step mode
+        cam.set_step_mode(false).unwrap();
+        assert!(!cam.step_mode().unwrap());
+    }
+
+    #[test]
+    fn measure_frame_timeout() {
+        let address = 0x30;
+        let mut mocked = datasheet_mlx90640_at_address(address);
+        let mut cam = Mlx90640Driver::new(mocked.clone(), address).unwrap();
+        mocked.set_new_data_stuck(Some(false));
+        let mut delay = MockDelay {
+            bus: mocked,
+            delays_until_ready: None,
+            total_us: 0,
+            delay_count: 0,
+        };
+        let mut temperatures = [0f32; mlx90640::Mlx90640::NUM_PIXELS];
+        assert!(matches!(
+            cam.measure_frame(&mut delay, &mut temperatures),
+            Err(Error::LibraryError(LibraryError::Timeout))
+        ));
+        // Two frame periods at 2Hz
+        assert_eq!(delay.total_us, 1_000_000);
+    }
+
+    #[test]
+    fn frame_metadata() {
+        let address = 0x30;
+        let mut mocked = datasheet_mlx90640_at_address(address);
+        let mut cam = Mlx90640Driver::new(mocked.clone(), address).unwrap();
+        assert_eq!(cam.frame_metadata(), None);
+        let mut temperatures = [0f32; mlx90640::Mlx90640::NUM_PIXELS];
+        let mut now = Duration::from_secs(5);
+        let mut clock = || {
+            now += Duration::from_secs(1);
+            now
+        };
+        let metadata = cam
+            .generate_image_with_metadata(&mut temperatures, Some(&mut clock))
+            .unwrap()
+            .unwrap();
+        assert_eq!(metadata.subpage, Subpage::One);
+        assert_eq!(metadata.sequence, 0);
+        assert!(!metadata.frame_complete);
+        assert_eq!(metadata.timestamp, Some(Duration::from_secs(6)));
+        // Values from the worked example in the datasheet
+        assert_approx_eq!(f32, metadata.ambient_temperature, 39.184, epsilon = 0.001);
+        assert_approx_eq!(f32, metadata.v_dd, 3.31862, epsilon = 0.00001);
+        assert_approx_eq!(f32, metadata.gain, 6383.0 / 6273.0);
+        assert_eq!(metadata.ram.gain, 6273);
+        assert_eq!(metadata.ram.compensation_pixel, -56);
+        // The filter only has the one compensation pixel in it so far.
+        assert_eq!(metadata.compensation_pixel, -56.0);
+        let processor = &cam.processor;
+        let common = CommonIrData::new(
+            processor.resolution_correction,
+            processor.emissivity,
+            &processor.calibration,
+            &metadata.ram,
+        );
+        let expected_offset = compensation_pixel_offset(
+            &processor.calibration,
+            &common,
+            metadata.ram.compensation_pixel,
+            None,
+            metadata.subpage,
+            processor.access_pattern,
+        );
+        assert!(expected_offset.is_some());
+        assert_eq!(metadata.compensation_pixel_offset, expected_offset);
+        assert_eq!(metadata.resolution, crate::Resolution::Eighteen);
+        assert_eq!(metadata.frame_rate, crate::FrameRate::Two);
+        assert_eq!(cam.frame_metadata(), Some(metadata));
+        // No new data
+        assert_eq!(
+            cam.generate_image_with_metadata(&mut temperatures, Some(&mut clock))
+                .unwrap(),
+            None
+        );
+        // The other generate functions update the metadata too, without a timestamp
+        mocked.set_data_available(true);
+        assert!(cam.generate_image_if_ready(&mut temperatures).unwrap());
+        let metadata = cam.frame_metadata().unwrap();
+        assert_eq!(metadata.sequence, 1);
+        assert_eq!(metadata.timestamp, None);
+    }
 }
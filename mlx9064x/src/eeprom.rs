@@ -0,0 +1,363 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright © 2021 Will Ross
+//! Writing to a camera's EEPROM.
+//!
+//! Along with the calibration data, the EEPROM holds the values loaded into the control and I²C
+//! configuration registers at power on, and the camera's I²C address. Changing the EEPROM is the
+//! only way to change those defaults, which is needed to put more than one camera on a bus.
+//!
+//! Writing a word follows the sequence from the datasheets: the word is erased by writing 0 to
+//! it, then the new value is written, waiting [`WRITE_DELAY_MS`] after each step. The word is then
+//! read back to check that it was stored. On the MLX90641 the Hamming code is added to calibration
+//! words as they are written (see [`MelexisCamera::encode_eeprom_word`]).
+//!
+//! EEPROM has a limited number of write cycles, and overwriting calibration data cannot be undone
+//! without a backup. If the word already has the requested value nothing is written.
+use embedded_hal::delay::DelayNs;
+use embedded_hal::i2c;
+
+use crate::common::{Address, MelexisCamera};
+use crate::error::{Error, LibraryError};
+use crate::register::{ControlRegister, Register};
+use crate::util::WORD_SIZE;
+
+/// How long to wait after erasing or writing an EEPROM word, in milliseconds.
+pub const WRITE_DELAY_MS: u32 = 10;
+
+/// The bits of the I²C address word that hold the address.
+const I2C_ADDRESS_MASK: u16 = 0x00FF;
+
+/// Read a single word from the EEPROM.
+pub fn read_word<I2C>(bus: &mut I2C, i2c_address: u8, address: Address) -> Result<u16, Error<I2C>>
+where
+    I2C: i2c::I2c,
+{
+    let mut word = [0u8; WORD_SIZE];
+    bus.write_read(i2c_address, &address.as_bytes(), &mut word)
+        .map_err(Error::I2cError)?;
+    Ok(u16::from_be_bytes(word))
+}
+
+/// Write a single word to the EEPROM, erasing it first and verifying it afterwards.
+///
+/// `value` is the data to store; any error correction bits are added for the camera. If the word
+/// does not read back as expected, [`LibraryError::Verification`] is returned.
+pub fn write_word<Cam, I2C, D>(
+    bus: &mut I2C,
+    delay: &mut D,
+    i2c_address: u8,
+    address: Address,
+    value: u16,
+) -> Result<(), Error<I2C>>
+where
+    Cam: MelexisCamera,
+    I2C: i2c::I2c,
+    D: DelayNs,
+{
+    let encoded = Cam::encode_eeprom_word(address, value)?;
+    if read_word(bus, i2c_address, address)? == encoded {
+        return Ok(());
+    }
+    bus.write(i2c_address, &write_bytes(address, 0))
+        .map_err(Error::I2cError)?;
+    delay.delay_ms(WRITE_DELAY_MS);
+    bus.write(i2c_address, &write_bytes(address, encoded))
+        .map_err(Error::I2cError)?;
+    delay.delay_ms(WRITE_DELAY_MS);
+    if read_word(bus, i2c_address, address)? == encoded {
+        Ok(())
+    } else {
+        Err(LibraryError::Verification(address.into()).into())
+    }
+}
+
+/// Store a new I²C address for the camera in the EEPROM.
+///
+/// The camera keeps using its current address until it is power cycled. Any address from 0x01 to
+/// 0x7F is allowed.
+pub fn set_i2c_address_persistent<Cam, I2C, D>(
+    bus: &mut I2C,
+    delay: &mut D,
+    i2c_address: u8,
+    new_address: u8,
+) -> Result<(), Error<I2C>>
+where
+    Cam: MelexisCamera,
+    I2C: i2c::I2c,
+    D: DelayNs,
+{
+    let current = read_word(bus, i2c_address, Cam::EEPROM_I2C_ADDRESS)?;
+    let value = i2c_address_word(current, new_address)?;
+    write_word::<Cam, _, _>(bus, delay, i2c_address, Cam::EEPROM_I2C_ADDRESS, value)
+}
+
+/// Store the camera's current control register as the value it uses at power on.
+///
+/// Reserved bits in the EEPROM word are preserved.
+pub fn store_default_control_register<Cam, I2C, D>(
+    bus: &mut I2C,
+    delay: &mut D,
+    i2c_address: u8,
+) -> Result<(), Error<I2C>>
+where
+    Cam: MelexisCamera,
+    I2C: i2c::I2c,
+    D: DelayNs,
+{
+    let register = read_word(bus, i2c_address, ControlRegister::address())?;
+    let current = read_word(bus, i2c_address, Cam::EEPROM_CONTROL_REGISTER)?;
+    let value = control_register_word(current, register);
+    write_word::<Cam, _, _>(bus, delay, i2c_address, Cam::EEPROM_CONTROL_REGISTER, value)
+}
+
+/// The async version of [`read_word`].
+#[cfg(feature = "async")]
+pub async fn read_word_async<I2C>(
+    bus: &mut I2C,
+    i2c_address: u8,
+    address: Address,
+) -> Result<u16, Error<I2C>>
+where
+    I2C: embedded_hal_async::i2c::I2c,
+{
+    let mut word = [0u8; WORD_SIZE];
+    bus.write_read(i2c_address, &address.as_bytes(), &mut word)
+        .await
+        .map_err(Error::I2cError)?;
+    Ok(u16::from_be_bytes(word))
+}
+
+/// The async version of [`write_word`].
+#[cfg(feature = "async")]
+pub async fn write_word_async<Cam, I2C, D>(
+    bus: &mut I2C,
+    delay: &mut D,
+    i2c_address: u8,
+    address: Address,
+    value: u16,
+) -> Result<(), Error<I2C>>
+where
+    Cam: MelexisCamera,
+    I2C: embedded_hal_async::i2c::I2c,
+    D: embedded_hal_async::delay::DelayNs,
+{
+    let encoded = Cam::encode_eeprom_word(address, value)?;
+    if read_word_async(bus, i2c_address, address).await? == encoded {
+        return Ok(());
+    }
+    bus.write(i2c_address, &write_bytes(address, 0))
+        .await
+        .map_err(Error::I2cError)?;
+    delay.delay_ms(WRITE_DELAY_MS).await;
+    bus.write(i2c_address, &write_bytes(address, encoded))
+        .await
+        .map_err(Error::I2cError)?;
+    delay.delay_ms(WRITE_DELAY_MS).await;
+    if read_word_async(bus, i2c_address, address).await? == encoded {
+        Ok(())
+    } else {
+        Err(LibraryError::Verification(address.into()).into())
+    }
+}
+
+/// The async version of [`set_i2c_address_persistent`].
+#[cfg(feature = "async")]
+pub async fn set_i2c_address_persistent_async<Cam, I2C, D>(
+    bus: &mut I2C,
+    delay: &mut D,
+    i2c_address: u8,
+    new_address: u8,
+) -> Result<(), Error<I2C>>
+where
+    Cam: MelexisCamera,
+    I2C: embedded_hal_async::i2c::I2c,
+    D: embedded_hal_async::delay::DelayNs,
+{
+    let current = read_word_async(bus, i2c_address, Cam::EEPROM_I2C_ADDRESS).await?;
+    let value = i2c_address_word(current, new_address)?;
+    write_word_async::<Cam, _, _>(bus, delay, i2c_address, Cam::EEPROM_I2C_ADDRESS, value).await
+}
+
+/// The async version of [`store_default_control_register`].
+#[cfg(feature = "async")]
+pub async fn store_default_control_register_async<Cam, I2C, D>(
+    bus: &mut I2C,
+    delay: &mut D,
+    i2c_address: u8,
+) -> Result<(), Error<I2C>>
+where
+    Cam: MelexisCamera,
+    I2C: embedded_hal_async::i2c::I2c,
+    D: embedded_hal_async::delay::DelayNs,
+{
+    let register = read_word_async(bus, i2c_address, ControlRegister::address()).await?;
+    let current = read_word_async(bus, i2c_address, Cam::EEPROM_CONTROL_REGISTER).await?;
+    let value = control_register_word(current, register);
+    write_word_async::<Cam, _, _>(bus, delay, i2c_address, Cam::EEPROM_CONTROL_REGISTER, value)
+        .await
+}
+
+/// The bytes to write to the camera to store `value` at `address`.
+fn write_bytes(address: Address, value: u16) -> [u8; 4] {
+    let [address_high, address_low] = address.as_bytes();
+    let [value_high, value_low] = value.to_be_bytes();
+    [address_high, address_low, value_high, value_low]
+}
+
+/// Replace the address in the current I²C address word with `new_address`.
+fn i2c_address_word(current: u16, new_address: u8) -> Result<u16, LibraryError> {
+    if new_address == 0 || new_address > 0x7F {
+        Err(LibraryError::InvalidData(
+            "I²C addresses must be between 0x01 and 0x7F",
+        ))
+    } else {
+        Ok((current & !I2C_ADDRESS_MASK) | u16::from(new_address))
+    }
+}
+
+/// Merge the writable bits of a control register value into the current EEPROM word.
+fn control_register_word(current: u16, register: u16) -> u16 {
+    let mask = u16::from_be_bytes(ControlRegister::write_mask());
+    (current & !mask) | (register & mask)
+}
+
+#[cfg(test)]
+mod test {
+    use embedded_hal::delay::DelayNs;
+    use embedded_hal::i2c::I2c;
+
+    use mlx9064x_test_data::{
+        datasheet_mlx90640_at_address, mock_mlx90641_at_address, I2cOperation,
+    };
+
+    use crate::mlx90640::Mlx90640;
+    use crate::mlx90641::{hamming, Mlx90641};
+    use crate::{Address, Error, LibraryError, MelexisCamera};
+
+    use super::*;
+
+    const ADDRESS: u8 = 0x33;
+
+    /// A delay that only records how long it was asked to wait.
+    #[derive(Default)]
+    struct MockDelay(u64);
+
+    impl DelayNs for MockDelay {
+        fn delay_ns(&mut self, ns: u32) {
+            self.0 += u64::from(ns);
+        }
+    }
+
+    #[test]
+    fn write_sequence() {
+        let mut bus = datasheet_mlx90640_at_address(ADDRESS);
+        let mut delay = MockDelay::default();
+        bus.clear_recent_operations();
+        set_i2c_address_persistent::<Mlx90640, _, _>(&mut bus, &mut delay, ADDRESS, 0x34).unwrap();
+        // Erase then write, with a delay after each
+        let writes = bus
+            .recent_operations()
+            .iter()
+            .filter(|op| matches!(op, I2cOperation::Write { length: 2, .. }))
+            .count();
+        assert_eq!(writes, 2);
+        assert_eq!(delay.0, 2 * u64::from(WRITE_DELAY_MS) * 1_000_000);
+        let stored = read_word(&mut bus, ADDRESS, Mlx90640::EEPROM_I2C_ADDRESS).unwrap();
+        assert_eq!(stored & I2C_ADDRESS_MASK, 0x34);
+        // The camera keeps its current address until power cycled
+        assert!(bus
+            .write_read(ADDRESS, &[0x24, 0x0F], &mut [0u8; 2])
+            .is_ok());
+    }
+
+    #[test]
+    fn unchanged_word_not_written() {
+        let mut bus = datasheet_mlx90640_at_address(ADDRESS);
+        let mut delay = MockDelay::default();
+        let current = read_word(&mut bus, ADDRESS, Mlx90640::EEPROM_I2C_ADDRESS).unwrap();
+        bus.clear_recent_operations();
+        write_word::<Mlx90640, _, _>(
+            &mut bus,
+            &mut delay,
+            ADDRESS,
+            Mlx90640::EEPROM_I2C_ADDRESS,
+            current,
+        )
+        .unwrap();
+        assert!(bus
+            .recent_operations()
+            .iter()
+            .all(|op| matches!(op, I2cOperation::Read { .. })));
+        assert_eq!(delay.0, 0);
+    }
+
+    #[test]
+    fn invalid_i2c_address() {
+        let mut bus = datasheet_mlx90640_at_address(ADDRESS);
+        let mut delay = MockDelay::default();
+        for new_address in [0x00, 0x80] {
+            assert!(matches!(
+                set_i2c_address_persistent::<Mlx90640, _, _>(
+                    &mut bus,
+                    &mut delay,
+                    ADDRESS,
+                    new_address
+                ),
+                Err(Error::LibraryError(LibraryError::InvalidData(_)))
+            ));
+        }
+    }
+
+    #[test]
+    fn default_control_register() {
+        let mut bus = datasheet_mlx90640_at_address(ADDRESS);
+        let mut delay = MockDelay::default();
+        // Change the frame rate bits in the control register, then store it.
+        let mut register = [0u8; 2];
+        bus.write_read(ADDRESS, &[0x80, 0x0D], &mut register)
+            .unwrap();
+        let register = u16::from_be_bytes(register) ^ 0x0080;
+        bus.write(ADDRESS, &write_bytes(ControlRegister::address(), register))
+            .unwrap();
+        store_default_control_register::<Mlx90640, _, _>(&mut bus, &mut delay, ADDRESS).unwrap();
+        let stored = read_word(&mut bus, ADDRESS, Mlx90640::EEPROM_CONTROL_REGISTER).unwrap();
+        let mask = u16::from_be_bytes(ControlRegister::write_mask());
+        assert_eq!(stored & mask, register & mask);
+    }
+
+    #[test]
+    fn mlx90641_checksum() {
+        let mut bus = mock_mlx90641_at_address(ADDRESS);
+        let mut delay = MockDelay::default();
+        let address = Address::new(0x2423);
+        write_word::<Mlx90641, _, _>(&mut bus, &mut delay, ADDRESS, address, 0x01F0).unwrap();
+        let stored = read_word(&mut bus, ADDRESS, address).unwrap();
+        assert_eq!(stored, hamming::add_checksum(0x01F0).unwrap());
+        assert_eq!(hamming::validate_checksum(stored), Ok(0x01F0));
+        // Values that don't fit with the checksum are rejected before anything is written
+        assert!(matches!(
+            write_word::<Mlx90641, _, _>(&mut bus, &mut delay, ADDRESS, address, 0x0800),
+            Err(Error::LibraryError(LibraryError::Other(_)))
+        ));
+    }
+
+    #[test]
+    fn verification_failure() {
+        let mut bus = datasheet_mlx90640_at_address(ADDRESS);
+        let mut delay = MockDelay::default();
+        let current = read_word(&mut bus, ADDRESS, Mlx90640::EEPROM_I2C_ADDRESS).unwrap();
+        // Every read back is corrupted, so the write can never be verified.
+        bus.corrupt_reads(7, 1.0);
+        let value = i2c_address_word(current, 0x34).unwrap();
+        assert!(matches!(
+            write_word::<Mlx90640, _, _>(
+                &mut bus,
+                &mut delay,
+                ADDRESS,
+                Mlx90640::EEPROM_I2C_ADDRESS,
+                value
+            ),
+            Err(Error::LibraryError(LibraryError::Verification(0x240F)))
+        ));
+    }
+}
@@ -0,0 +1,383 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright © 2021 Will Ross
+
+//! Different emissivities for different parts of an image.
+//!
+//! The temperature calculations normally use one [emissivity] (and one reflected temperature) for
+//! the entire image, but a scene can have materials with very different emissivities, like painted
+//! metal, skin and glass. An [`EmissivityMap`] gives pixels their own emissivity, either with a
+//! value for every pixel, or with [`Region`]s of the image that have their own emissivity and
+//! (optionally) reflected temperature.
+//!
+//! The calculation functions in [`calculations`][crate::calculations] take an [`Emissivity`],
+//! which can be created from a single value (the existing behaviour, and the fastest) or from a
+//! default emissivity and a map.
+//!
+//! ```
+//! use mlx9064x::emissivity::{Emissivity, EmissivityMap, Region, Shape};
+//!
+//! // A painted box, with some glass in front of the top left corner.
+//! static REGIONS: [Region; 2] = [
+//!     Region {
+//!         shape: Shape::Rectangle { x: 4, y: 2, width: 8, height: 6 },
+//!         emissivity: 0.93,
+//!         reflected_temperature: None,
+//!     },
+//!     Region {
+//!         shape: Shape::Polygon(&[(2.0, 1.0), (8.0, 1.0), (2.0, 5.0)]),
+//!         emissivity: 0.85,
+//!         reflected_temperature: Some(20.0),
+//!     },
+//! ];
+//! static MAP: EmissivityMap = EmissivityMap::Regions(&REGIONS);
+//! // Later regions take priority over earlier ones.
+//! assert_eq!(MAP.pixel(4, 2, 32), Some((0.85, Some(20.0))));
+//! assert_eq!(MAP.pixel(10, 6, 32), Some((0.93, None)));
+//! // Pixels outside of every region use the default emissivity.
+//! assert_eq!(MAP.pixel(0, 0, 32), None);
+//! let emissivity = Emissivity::Map { default: 0.98, map: &MAP };
+//! assert_eq!(emissivity.default_emissivity(), 0.98);
+//! ```
+//!
+//! [emissivity]: https://en.wikipedia.org/wiki/Emissivity
+
+/// The area of an image covered by a [`Region`].
+///
+/// Coordinates are in pixels, with (0, 0) being the top left pixel, *x* increasing to the right
+/// and *y* increasing downwards.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Shape<'m> {
+    /// A rectangle of pixels, with the top left pixel at (`x`, `y`).
+    Rectangle {
+        x: usize,
+        y: usize,
+        width: usize,
+        height: usize,
+    },
+
+    /// A polygon, given by its vertices.
+    ///
+    /// A pixel is inside the polygon if the center of the pixel is inside it, using the even-odd
+    /// rule. The center of pixel (*x*, *y*) is at (*x* + 0.5, *y* + 0.5).
+    Polygon(&'m [(f32, f32)]),
+}
+
+impl Shape<'_> {
+    /// Check if a pixel is inside this shape.
+    pub fn contains(&self, x: usize, y: usize) -> bool {
+        match self {
+            Self::Rectangle {
+                x: left,
+                y: top,
+                width,
+                height,
+            } => {
+                // Subtracting instead of adding so large rectangles (like a width of
+                // `usize::MAX` to go to the edge of the image) don't overflow.
+                x >= *left && x - left < *width && y >= *top && y - top < *height
+            }
+            Self::Polygon(vertices) => {
+                let point_x = x as f32 + 0.5;
+                let point_y = y as f32 + 0.5;
+                let mut inside = false;
+                // Walk the edges, counting how many are crossed by a ray going right from the
+                // point.
+                let previous_vertices = vertices
+                    .iter()
+                    .cycle()
+                    .skip(vertices.len().saturating_sub(1));
+                for (current, previous) in vertices.iter().zip(previous_vertices) {
+                    let (x1, y1) = *current;
+                    let (x2, y2) = *previous;
+                    if (y1 > point_y) != (y2 > point_y)
+                        && point_x < (x2 - x1) * (point_y - y1) / (y2 - y1) + x1
+                    {
+                        inside = !inside;
+                    }
+                }
+                inside
+            }
+        }
+    }
+}
+
+/// A part of the image with its own emissivity.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Region<'m> {
+    /// The pixels in this region.
+    pub shape: Shape<'m>,
+
+    /// The emissivity of the pixels in this region.
+    pub emissivity: f32,
+
+    /// The reflected temperature for this region, in degrees Celsius.
+    ///
+    /// If `None`, the reflected temperature for the rest of the image is used.
+    pub reflected_temperature: Option<f32>,
+}
+
+/// Emissivity values for individual pixels.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum EmissivityMap<'m> {
+    /// An emissivity for every pixel, in the same order as the pixels in an image (rows, then
+    /// columns).
+    ///
+    /// If the slice is shorter than the image, the default emissivity is used for the remaining
+    /// pixels.
+    Pixels(&'m [f32]),
+
+    /// Regions of the image with their own emissivity.
+    ///
+    /// If regions overlap, the later region is used. Pixels outside of every region use the
+    /// default emissivity.
+    Regions(&'m [Region<'m>]),
+}
+
+impl EmissivityMap<'_> {
+    /// The emissivity and reflected temperature for a pixel, if they're set by this map.
+    ///
+    /// `width` is the width of the image. If the map doesn't cover the pixel, `None` is returned
+    /// and the default emissivity and reflected temperature should be used. If the map covers the
+    /// pixel but doesn't set a reflected temperature, that value is `None`.
+    pub fn pixel(&self, x: usize, y: usize, width: usize) -> Option<(f32, Option<f32>)> {
+        match self {
+            Self::Pixels(emissivities) => emissivities
+                .get(y * width + x)
+                .map(|emissivity| (*emissivity, None)),
+            Self::Regions(regions) => regions
+                .iter()
+                .rev()
+                .find(|region| region.shape.contains(x, y))
+                .map(|region| (region.emissivity, region.reflected_temperature)),
+        }
+    }
+}
+
+/// The emissivity to use for an image.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Emissivity<'m> {
+    /// The same emissivity for every pixel.
+    Uniform(f32),
+
+    /// An emissivity map, with a default emissivity for pixels not covered by the map.
+    Map {
+        default: f32,
+        map: &'m EmissivityMap<'m>,
+    },
+}
+
+impl Emissivity<'_> {
+    /// The emissivity used for pixels that aren't covered by a map.
+    pub fn default_emissivity(&self) -> f32 {
+        match self {
+            Self::Uniform(emissivity) => *emissivity,
+            Self::Map { default, .. } => *default,
+        }
+    }
+}
+
+impl From<f32> for Emissivity<'_> {
+    fn from(emissivity: f32) -> Self {
+        Self::Uniform(emissivity)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use mlx9064x_test_data::{compare_images, example_mlx90640_at_address};
+
+    use crate::calculations::{raw_ir_to_temperatures, raw_pixels_to_ir_data};
+    use crate::common::FromI2C;
+    use crate::mlx90640::{Mlx90640, Mlx90640Calibration};
+    use crate::test_frames::TestSubpage;
+    use crate::{AccessPattern, MelexisCamera};
+
+    use super::{Emissivity, EmissivityMap, Region, Shape};
+
+    /// Calculate the temperatures for the first subpage of the example MLX90640 frame.
+    ///
+    /// If `two_pass` is true, the IR data is calculated first, then the temperatures.
+    fn example_frame(
+        emissivity: Emissivity,
+        t_r: Option<f32>,
+        two_pass: bool,
+    ) -> [f32; Mlx90640::NUM_PIXELS] {
+        let mut bus = example_mlx90640_at_address(0x33);
+        let calibration = Mlx90640Calibration::from_i2c(&mut bus, 0x33).unwrap();
+        let [subpage, _] = TestSubpage::read_both::<_, _, { Mlx90640::HEIGHT }>(
+            &calibration,
+            &mut bus,
+            AccessPattern::Chess,
+        );
+        let mut temperatures = [f32::NAN; Mlx90640::NUM_PIXELS];
+        if two_pass {
+            let t_a = raw_pixels_to_ir_data(
+                &calibration,
+                emissivity,
+                subpage.resolution_correction,
+                &subpage.pixel_bytes,
+                subpage.ram,
+                None,
+                subpage.subpage,
+                subpage.access_pattern,
+                &mut subpage.valid_pixels::<Mlx90640>().into_iter(),
+                &mut temperatures,
+            );
+            raw_ir_to_temperatures(
+                &calibration,
+                emissivity,
+                t_a,
+                t_r,
+                subpage.subpage,
+                &mut subpage.valid_pixels::<Mlx90640>().into_iter(),
+                &mut temperatures,
+            );
+        } else {
+            subpage.temperatures(&calibration, emissivity, t_r, &mut temperatures);
+        }
+        temperatures
+    }
+
+    /// The largest difference allowed between a uniform emissivity and a map.
+    ///
+    /// The compensation pixel is divided by the emissivity at a different step when using a map,
+    /// which can change the last bit or so.
+    const MAX_ERROR: f32 = 0.0005;
+
+    #[test]
+    fn rectangle() {
+        let shape = Shape::Rectangle {
+            x: 2,
+            y: 3,
+            width: 4,
+            height: 2,
+        };
+        assert!(shape.contains(2, 3));
+        assert!(shape.contains(5, 4));
+        assert!(!shape.contains(6, 4));
+        assert!(!shape.contains(5, 5));
+        assert!(!shape.contains(1, 3));
+    }
+
+    #[test]
+    fn rectangle_to_edge() {
+        // Rectangles reaching past the edge of the image don't overflow.
+        let shape = Shape::Rectangle {
+            x: 10,
+            y: 1,
+            width: usize::MAX,
+            height: usize::MAX,
+        };
+        assert!(shape.contains(10, 1));
+        assert!(shape.contains(31, 23));
+        assert!(shape.contains(usize::MAX, usize::MAX));
+        assert!(!shape.contains(9, 1));
+        assert!(!shape.contains(10, 0));
+    }
+
+    #[test]
+    fn polygon() {
+        // A triangle with the right angle at the top left
+        let shape = Shape::Polygon(&[(0.0, 0.0), (10.0, 0.0), (0.0, 10.0)]);
+        assert!(shape.contains(0, 0));
+        assert!(shape.contains(8, 0));
+        assert!(shape.contains(0, 8));
+        assert!(shape.contains(4, 4));
+        assert!(!shape.contains(5, 5));
+        assert!(!shape.contains(9, 9));
+        assert!(!shape.contains(11, 0));
+        // An empty polygon doesn't contain anything
+        assert!(!Shape::Polygon(&[]).contains(0, 0));
+    }
+
+    #[test]
+    fn pixels_map() {
+        let emissivities = [0.5, 0.6, 0.7, 0.8];
+        let map = EmissivityMap::Pixels(&emissivities);
+        assert_eq!(map.pixel(1, 0, 2), Some((0.6, None)));
+        assert_eq!(map.pixel(0, 1, 2), Some((0.7, None)));
+        // Out of range pixels aren't covered
+        assert_eq!(map.pixel(0, 2, 2), None);
+    }
+
+    #[test]
+    fn regions_map() {
+        let regions = [
+            Region {
+                shape: Shape::Rectangle {
+                    x: 0,
+                    y: 0,
+                    width: 4,
+                    height: 4,
+                },
+                emissivity: 0.9,
+                reflected_temperature: None,
+            },
+            Region {
+                shape: Shape::Rectangle {
+                    x: 2,
+                    y: 2,
+                    width: 4,
+                    height: 4,
+                },
+                emissivity: 0.8,
+                reflected_temperature: Some(30.0),
+            },
+        ];
+        let map = EmissivityMap::Regions(&regions);
+        assert_eq!(map.pixel(0, 0, 8), Some((0.9, None)));
+        assert_eq!(map.pixel(3, 3, 8), Some((0.8, Some(30.0))));
+        assert_eq!(map.pixel(5, 5, 8), Some((0.8, Some(30.0))));
+        assert_eq!(map.pixel(7, 7, 8), None);
+    }
+
+    #[test]
+    fn uniform_pixels_map() {
+        let expected = example_frame(Emissivity::Uniform(0.95), None, false);
+        let emissivities = [0.95; Mlx90640::NUM_PIXELS];
+        let map = EmissivityMap::Pixels(&emissivities);
+        let emissivity = Emissivity::Map {
+            default: 1.0,
+            map: &map,
+        };
+        for two_pass in [false, true] {
+            let actual = example_frame(emissivity, None, two_pass);
+            compare_images(&expected, &actual, MAX_ERROR);
+        }
+    }
+
+    #[test]
+    fn region_frame() {
+        let default_pixels = example_frame(Emissivity::Uniform(1.0), None, false);
+        let region_pixels = example_frame(Emissivity::Uniform(0.9), Some(30.0), false);
+        let regions = [Region {
+            shape: Shape::Rectangle {
+                x: 8,
+                y: 4,
+                width: 16,
+                height: 12,
+            },
+            emissivity: 0.9,
+            reflected_temperature: Some(30.0),
+        }];
+        let map = EmissivityMap::Regions(&regions);
+        let emissivity = Emissivity::Map {
+            default: 1.0,
+            map: &map,
+        };
+        let mut expected = default_pixels;
+        for (index, expected) in expected.iter_mut().enumerate() {
+            let (x, y) = (index % Mlx90640::WIDTH, index / Mlx90640::WIDTH);
+            if regions[0].shape.contains(x, y) {
+                *expected = region_pixels[index];
+            }
+        }
+        for two_pass in [false, true] {
+            let actual = example_frame(emissivity, None, two_pass);
+            compare_images(&expected, &actual, MAX_ERROR);
+        }
+        // Make sure the region actually changed something
+        let index = 10 * Mlx90640::WIDTH + 10;
+        assert!((default_pixels[index] - region_pixels[index]).abs() > 0.1);
+    }
+}
@@ -5,7 +5,7 @@ extern crate std;
 
 use core::fmt;
 
-use embedded_hal::blocking::i2c;
+use embedded_hal::i2c;
 
 /// Specific kinds of errors that don't involve I²C.
 #[derive(Clone, Debug, PartialEq)]
@@ -21,6 +21,14 @@ pub enum LibraryError {
     /// The MLX90641 uses a checksum with its [EEPROM][crate::mlx90641::hamming], and will return
     /// this error is it encounters an uncorrectable error.
     Checksum(u16),
+
+    /// An EEPROM word did not have the expected value after it was written.
+    ///
+    /// The address of the word is included. See [`eeprom`][crate::eeprom] for details.
+    Verification(u16),
+
+    /// The camera did not have new data before the timeout.
+    Timeout,
 }
 
 impl fmt::Display for LibraryError {
@@ -31,6 +39,14 @@ impl fmt::Display for LibraryError {
             LibraryError::Checksum(invalid_word) => {
                 write!(f, "Invalid checksum for data {:#06X}", invalid_word)
             }
+            LibraryError::Verification(address) => {
+                write!(
+                    f,
+                    "EEPROM word {:#06X} did not verify after writing",
+                    address
+                )
+            }
+            LibraryError::Timeout => write!(f, "Timed out waiting for new data"),
         }
     }
 }
@@ -39,17 +55,17 @@ impl fmt::Display for LibraryError {
 impl std::error::Error for LibraryError {}
 
 /// Errors originating from this library.
+///
+/// Both the blocking and async drivers use this type, as `embedded-hal` and `embedded-hal-async`
+/// share the same I²C error types. The general [kind][i2c::Error::kind] of error is available
+/// through the [`embedded_hal::i2c::Error`] trait, with [`LibraryError`]s reported as
+/// [`ErrorKind::Other`][i2c::ErrorKind::Other].
 pub enum Error<I2C>
 where
-    I2C: i2c::WriteRead + i2c::Write,
+    I2C: i2c::ErrorType,
 {
-    /// Errors involved with reading from I²C.
-    ///
-    /// All reads done by this library are preceded by a short write.
-    I2cWriteReadError(<I2C as i2c::WriteRead>::Error),
-
-    /// Errors involved with only writing to I²C.
-    I2cWriteError(<I2C as i2c::Write>::Error),
+    /// Errors from the I²C bus.
+    I2cError(I2C::Error),
 
     /// Other errors originating from within this library.
     LibraryError(LibraryError),
@@ -59,20 +75,13 @@ where
 // linux-embedded-hal).
 impl<I2C> fmt::Debug for Error<I2C>
 where
-    I2C: i2c::WriteRead + i2c::Write,
-    <I2C as i2c::WriteRead>::Error: fmt::Debug,
-    <I2C as i2c::Write>::Error: fmt::Debug,
+    I2C: i2c::ErrorType,
 {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
-            Error::I2cWriteReadError(i2c_error) => f
-                .debug_tuple("Error::I2cWriteReadError")
-                .field(i2c_error)
-                .finish(),
-            Error::I2cWriteError(i2c_error) => f
-                .debug_tuple("Error::I2cWriteError")
-                .field(i2c_error)
-                .finish(),
+            Error::I2cError(i2c_error) => {
+                f.debug_tuple("Error::I2cError").field(i2c_error).finish()
+            }
             Error::LibraryError(err) => f.debug_tuple("Error::LibraryError").field(err).finish(),
         }
     }
@@ -80,14 +89,11 @@ where
 
 impl<I2C> fmt::Display for Error<I2C>
 where
-    I2C: i2c::WriteRead + i2c::Write,
-    <I2C as i2c::WriteRead>::Error: fmt::Debug,
-    <I2C as i2c::Write>::Error: fmt::Debug,
+    I2C: i2c::ErrorType,
 {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
-            Error::I2cWriteReadError(i2c_error) => write!(f, "I2C Error: {:?}", i2c_error),
-            Error::I2cWriteError(i2c_error) => write!(f, "I2C Error: {:?}", i2c_error),
+            Error::I2cError(i2c_error) => write!(f, "I2C Error: {:?}", i2c_error),
             Error::LibraryError(err) => write!(f, "Library Error: {:?}", err),
         }
     }
@@ -96,22 +102,32 @@ where
 #[cfg(feature = "std")]
 impl<I2C> std::error::Error for Error<I2C>
 where
-    I2C: i2c::WriteRead + i2c::Write,
-    <I2C as i2c::WriteRead>::Error: std::error::Error + 'static,
-    <I2C as i2c::Write>::Error: std::error::Error + 'static,
+    I2C: i2c::ErrorType,
+    I2C::Error: std::error::Error + 'static,
 {
     fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
         match self {
-            Error::I2cWriteReadError(err) => Some(err),
-            Error::I2cWriteError(err) => Some(err),
+            Error::I2cError(err) => Some(err),
             Error::LibraryError(lib_err) => Some(lib_err),
         }
     }
 }
 
+impl<I2C> i2c::Error for Error<I2C>
+where
+    I2C: i2c::ErrorType,
+{
+    fn kind(&self) -> i2c::ErrorKind {
+        match self {
+            Error::I2cError(err) => err.kind(),
+            Error::LibraryError(_) => i2c::ErrorKind::Other,
+        }
+    }
+}
+
 impl<I2C> From<LibraryError> for Error<I2C>
 where
-    I2C: i2c::WriteRead + i2c::Write,
+    I2C: i2c::ErrorType,
 {
     fn from(lib_err: LibraryError) -> Self {
         Self::LibraryError(lib_err)
@@ -0,0 +1,582 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright © 2021 Will Ross
+
+//! Filters for smoothing values across frames.
+//!
+//! The datasheets advise running a moving average filter (with a length of 16 or more) over the
+//! compensation pixel when using thermal gradient compensation. [`CompensationPixelFilter`]
+//! provides that filter, keeping a separate moving average for each subpage as each subpage has
+//! its own compensation pixel.
+//!
+//! The images themselves are also noisy, especially at lower [resolutions][crate::Resolution] and
+//! higher [frame rates][crate::FrameRate]. The [`TemporalFilter`]s filter each pixel of an image
+//! across frames:
+//!
+//! * [`ExponentialMovingAverage`] is the cheapest, only keeping the current value for each pixel.
+//! * [`BoxcarAverage`] averages the last few values of each pixel.
+//! * [`KalmanFilter`] adapts to how noisy the pixels are, and can reset pixels that change
+//!   suddenly (for example when something moves through the scene) instead of smearing them out.
+//!
+//! The filters can update only the pixels in a subpage, so that the pixels that weren't updated
+//! by the camera (and still have the values from an earlier frame) aren't counted twice.
+
+use crate::common::MelexisCamera;
+use crate::error::LibraryError;
+use crate::register::{AccessPattern, Subpage};
+
+/// The longest moving average supported by [`CompensationPixelFilter`].
+pub const MAX_COMPENSATION_FILTER_LENGTH: usize = 64;
+
+/// The filter length recommended by the datasheets.
+const DEFAULT_COMPENSATION_FILTER_LENGTH: usize = 16;
+
+/// A simple moving average over the most recent values.
+///
+/// The running sum is kept as an integer so that there's no accumulated rounding error no matter
+/// how many values are pushed through the filter.
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct MovingAverage {
+    samples: [i16; MAX_COMPENSATION_FILTER_LENGTH],
+    next: usize,
+    count: usize,
+    sum: i32,
+}
+
+impl MovingAverage {
+    const fn new() -> Self {
+        Self {
+            samples: [0; MAX_COMPENSATION_FILTER_LENGTH],
+            next: 0,
+            count: 0,
+            sum: 0,
+        }
+    }
+
+    fn update(&mut self, length: usize, value: i16) -> f32 {
+        if self.count == length {
+            self.sum -= i32::from(self.samples[self.next]);
+        } else {
+            self.count += 1;
+        }
+        self.samples[self.next] = value;
+        self.sum += i32::from(value);
+        self.next = (self.next + 1) % length;
+        // The sum of at most 64 i16 values will fit in the 24 bits of an f32 mantissa
+        self.sum as f32 / self.count as f32
+    }
+}
+
+/// A moving average filter for the compensation pixels.
+///
+/// Each subpage has its own compensation pixel, so a separate average is kept for each subpage.
+/// Until `length` values have been seen for a subpage, the average of the values seen so far is
+/// used.
+///
+/// The filter should be [reset][CompensationPixelFilter::reset] when the camera's resolution or
+/// access pattern is changed, as the compensation pixel values from before the change will not be
+/// comparable to the values afterwards. [`CameraDriver`][crate::CameraDriver] does this
+/// automatically.
+#[derive(Clone, Debug, PartialEq)]
+pub struct CompensationPixelFilter {
+    length: usize,
+    subpages: [MovingAverage; 2],
+}
+
+impl CompensationPixelFilter {
+    /// Create a new filter averaging the most recent `length` values.
+    ///
+    /// `length` must be between 1 and [`MAX_COMPENSATION_FILTER_LENGTH`] (inclusive).
+    pub fn new(length: usize) -> Result<Self, LibraryError> {
+        if length == 0 || length > MAX_COMPENSATION_FILTER_LENGTH {
+            Err(LibraryError::InvalidData(
+                "The compensation pixel filter length must be between 1 and 64",
+            ))
+        } else {
+            Ok(Self {
+                length,
+                subpages: [MovingAverage::new(); 2],
+            })
+        }
+    }
+
+    /// The number of values being averaged.
+    pub fn length(&self) -> usize {
+        self.length
+    }
+
+    /// Add a new compensation pixel value for a subpage, returning the current average for that
+    /// subpage.
+    pub fn update(&mut self, subpage: Subpage, compensation_pixel: i16) -> f32 {
+        self.subpages[subpage as usize].update(self.length, compensation_pixel)
+    }
+
+    /// Discard all previous values.
+    pub fn reset(&mut self) {
+        self.subpages = [MovingAverage::new(); 2];
+    }
+}
+
+impl Default for CompensationPixelFilter {
+    /// Create a filter with the length recommended by the datasheets (16).
+    fn default() -> Self {
+        // Safe to unwrap, the default length is within bounds
+        Self::new(DEFAULT_COMPENSATION_FILTER_LENGTH).unwrap()
+    }
+}
+
+/// A filter applied to each pixel of an image across frames.
+///
+/// The filters keep their own state for each pixel, and replace the pixels in the image with the
+/// filtered values. NaN pixels (like failed pixels replaced with NaN) are left as NaN and don't
+/// change the state of the filter.
+pub trait TemporalFilter {
+    /// Filter the pixels of `image` marked as updated by `updated_pixels`.
+    ///
+    /// `updated_pixels` is in the same format as [`MelexisCamera::pixels_in_subpage`], and the
+    /// pixels not marked as updated are left as-is in both the image and the filter. Any pixels
+    /// past the number of pixels the filter was created for are also left as-is.
+    fn filter_pixels<Px>(&mut self, updated_pixels: Px, image: &mut [f32])
+    where
+        Px: IntoIterator<Item = bool>;
+
+    /// Discard the state of every pixel.
+    fn reset(&mut self);
+
+    /// Filter every pixel of `image`.
+    ///
+    /// This is for images where every pixel has been updated, like complete frames.
+    fn filter(&mut self, image: &mut [f32]) {
+        self.filter_pixels(core::iter::repeat(true), image)
+    }
+
+    /// Filter the pixels of `image` in a subpage.
+    ///
+    /// This is for images generated one subpage at a time, where the pixels in the other subpage
+    /// are from an older frame.
+    fn filter_subpage<Cam: MelexisCamera>(
+        &mut self,
+        subpage: Subpage,
+        access_pattern: AccessPattern,
+        image: &mut [f32],
+    ) {
+        self.filter_pixels(Cam::pixels_in_subpage(subpage, access_pattern), image)
+    }
+}
+
+/// The pixels of an image being filtered, along with their index.
+///
+/// Only the first `NUM_PIXELS` pixels are included, so the index is always within the state of the
+/// filter.
+fn updated<const NUM_PIXELS: usize, Px>(
+    updated_pixels: Px,
+    image: &mut [f32],
+) -> impl Iterator<Item = (usize, &mut f32)>
+where
+    Px: IntoIterator<Item = bool>,
+{
+    image
+        .iter_mut()
+        .take(NUM_PIXELS)
+        .enumerate()
+        .zip(updated_pixels)
+        .filter_map(|(pixel, updated)| updated.then_some(pixel))
+        .filter(|(_, value)| !value.is_nan())
+}
+
+/// An [exponential moving average][ema] of each pixel.
+///
+/// Each new value is weighted by `alpha`, and the previous average by `1 - alpha`. The first
+/// value for a pixel is used as-is.
+///
+/// [ema]: https://en.wikipedia.org/wiki/Moving_average#Exponential_moving_average
+#[derive(Clone, Debug, PartialEq)]
+pub struct ExponentialMovingAverage<const NUM_PIXELS: usize> {
+    alpha: f32,
+    averages: [f32; NUM_PIXELS],
+}
+
+impl<const NUM_PIXELS: usize> ExponentialMovingAverage<NUM_PIXELS> {
+    /// Create a new filter, with `alpha` greater than 0 and at most 1.
+    ///
+    /// Smaller values of `alpha` filter out more noise, but respond more slowly to changes. An
+    /// `alpha` of 1 disables filtering.
+    pub fn new(alpha: f32) -> Result<Self, LibraryError> {
+        if alpha > 0f32 && alpha <= 1f32 {
+            Ok(Self {
+                alpha,
+                averages: [f32::NAN; NUM_PIXELS],
+            })
+        } else {
+            Err(LibraryError::InvalidData(
+                "The moving average alpha must be greater than 0 and at most 1",
+            ))
+        }
+    }
+
+    /// The weight given to new values.
+    pub fn alpha(&self) -> f32 {
+        self.alpha
+    }
+}
+
+impl<const NUM_PIXELS: usize> TemporalFilter for ExponentialMovingAverage<NUM_PIXELS> {
+    fn filter_pixels<Px>(&mut self, updated_pixels: Px, image: &mut [f32])
+    where
+        Px: IntoIterator<Item = bool>,
+    {
+        for (index, value) in updated::<NUM_PIXELS, _>(updated_pixels, image) {
+            let average = &mut self.averages[index];
+            if average.is_nan() {
+                *average = *value;
+            } else {
+                *average += self.alpha * (*value - *average);
+            }
+            *value = *average;
+        }
+    }
+
+    fn reset(&mut self) {
+        self.averages = [f32::NAN; NUM_PIXELS];
+    }
+}
+
+/// The mean of the last `LENGTH` values of each pixel.
+///
+/// Until `LENGTH` values have been seen for a pixel, the mean of the values seen so far is used.
+/// The last values are stored for every pixel, so this filter needs `LENGTH` times as much memory
+/// as an image.
+#[derive(Clone, Debug, PartialEq)]
+pub struct BoxcarAverage<const NUM_PIXELS: usize, const LENGTH: usize> {
+    history: [[f32; NUM_PIXELS]; LENGTH],
+    next: [u8; NUM_PIXELS],
+    count: [u8; NUM_PIXELS],
+}
+
+impl<const NUM_PIXELS: usize, const LENGTH: usize> BoxcarAverage<NUM_PIXELS, LENGTH> {
+    /// Create a new filter.
+    ///
+    /// `LENGTH` must be between 1 and 255 (inclusive).
+    pub fn new() -> Result<Self, LibraryError> {
+        if LENGTH == 0 || LENGTH > usize::from(u8::MAX) {
+            Err(LibraryError::InvalidData(
+                "The boxcar filter length must be between 1 and 255",
+            ))
+        } else {
+            Ok(Self {
+                history: [[0f32; NUM_PIXELS]; LENGTH],
+                next: [0; NUM_PIXELS],
+                count: [0; NUM_PIXELS],
+            })
+        }
+    }
+}
+
+impl<const NUM_PIXELS: usize, const LENGTH: usize> TemporalFilter
+    for BoxcarAverage<NUM_PIXELS, LENGTH>
+{
+    fn filter_pixels<Px>(&mut self, updated_pixels: Px, image: &mut [f32])
+    where
+        Px: IntoIterator<Item = bool>,
+    {
+        for (index, value) in updated::<NUM_PIXELS, _>(updated_pixels, image) {
+            let next = usize::from(self.next[index]);
+            self.history[next][index] = *value;
+            self.next[index] = ((next + 1) % LENGTH) as u8;
+            self.count[index] = self.count[index].saturating_add(1).min(LENGTH as u8);
+            // Summing the values each time (instead of keeping a running sum) avoids accumulating
+            // rounding errors.
+            let count = usize::from(self.count[index]);
+            let sum: f32 = self.history[..count].iter().map(|frame| frame[index]).sum();
+            *value = sum / count as f32;
+        }
+    }
+
+    fn reset(&mut self) {
+        self.next = [0; NUM_PIXELS];
+        self.count = [0; NUM_PIXELS];
+    }
+}
+
+/// A [Kalman filter][kalman] for each pixel, treating each pixel as a constant value with noisy
+/// measurements.
+///
+/// The filter estimates the value of each pixel along with the variance of that estimate. Each
+/// frame the variance grows by `process_noise` (how much the scene is expected to change between
+/// frames), and each new value is weighted by comparing the variance of the estimate to
+/// `measurement_noise` (the variance of the pixel noise). The estimates settle down as more frames
+/// are seen, filtering out more noise in static scenes than a moving average does.
+///
+/// If `motion_threshold` is set and a new value is further than that from the estimate, the
+/// change is assumed to be real (like something moving through the scene) instead of noise, and
+/// the pixel is reset to the new value.
+///
+/// [kalman]: https://en.wikipedia.org/wiki/Kalman_filter
+#[derive(Clone, Debug, PartialEq)]
+pub struct KalmanFilter<const NUM_PIXELS: usize> {
+    process_noise: f32,
+    measurement_noise: f32,
+    motion_threshold: Option<f32>,
+    estimates: [f32; NUM_PIXELS],
+    variances: [f32; NUM_PIXELS],
+}
+
+impl<const NUM_PIXELS: usize> KalmanFilter<NUM_PIXELS> {
+    /// Create a new filter.
+    ///
+    /// `process_noise` must not be negative, and `measurement_noise` must be greater than 0. Both
+    /// are variances, in the units of the image squared.
+    pub fn new(
+        process_noise: f32,
+        measurement_noise: f32,
+        motion_threshold: Option<f32>,
+    ) -> Result<Self, LibraryError> {
+        if !(process_noise >= 0f32 && measurement_noise > 0f32) {
+            Err(LibraryError::InvalidData(
+                "The Kalman filter noise values must be positive",
+            ))
+        } else {
+            Ok(Self {
+                process_noise,
+                measurement_noise,
+                motion_threshold,
+                estimates: [f32::NAN; NUM_PIXELS],
+                variances: [0f32; NUM_PIXELS],
+            })
+        }
+    }
+
+    /// The variance of the current estimate for a pixel, or `None` if there isn't an estimate.
+    pub fn variance(&self, index: usize) -> Option<f32> {
+        self.estimates
+            .get(index)
+            .filter(|estimate| !estimate.is_nan())
+            .map(|_| self.variances[index])
+    }
+}
+
+impl<const NUM_PIXELS: usize> TemporalFilter for KalmanFilter<NUM_PIXELS> {
+    fn filter_pixels<Px>(&mut self, updated_pixels: Px, image: &mut [f32])
+    where
+        Px: IntoIterator<Item = bool>,
+    {
+        for (index, value) in updated::<NUM_PIXELS, _>(updated_pixels, image) {
+            let estimate = &mut self.estimates[index];
+            let variance = &mut self.variances[index];
+            let moved = self
+                .motion_threshold
+                .is_some_and(|threshold| (*value - *estimate).abs() > threshold);
+            if estimate.is_nan() || moved {
+                *estimate = *value;
+                *variance = self.measurement_noise;
+            } else {
+                *variance += self.process_noise;
+                let gain = *variance / (*variance + self.measurement_noise);
+                *estimate += gain * (*value - *estimate);
+                *variance *= 1f32 - gain;
+            }
+            *value = *estimate;
+        }
+    }
+
+    fn reset(&mut self) {
+        self.estimates = [f32::NAN; NUM_PIXELS];
+        self.variances = [0f32; NUM_PIXELS];
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::mlx90640::Mlx90640;
+    use crate::{AccessPattern, MelexisCamera, Subpage};
+
+    use super::{
+        BoxcarAverage, CompensationPixelFilter, ExponentialMovingAverage, KalmanFilter,
+        TemporalFilter, MAX_COMPENSATION_FILTER_LENGTH,
+    };
+
+    #[test]
+    fn invalid_length() {
+        assert!(CompensationPixelFilter::new(0).is_err());
+        assert!(CompensationPixelFilter::new(MAX_COMPENSATION_FILTER_LENGTH).is_ok());
+        assert!(CompensationPixelFilter::new(MAX_COMPENSATION_FILTER_LENGTH + 1).is_err());
+    }
+
+    #[test]
+    fn default_length() {
+        assert_eq!(CompensationPixelFilter::default().length(), 16);
+    }
+
+    #[test]
+    fn partial_average() {
+        let mut filter = CompensationPixelFilter::new(4).unwrap();
+        assert_eq!(filter.update(Subpage::Zero, -10), -10.0);
+        assert_eq!(filter.update(Subpage::Zero, -20), -15.0);
+        assert_eq!(filter.update(Subpage::Zero, -30), -20.0);
+    }
+
+    #[test]
+    fn full_average() {
+        let mut filter = CompensationPixelFilter::new(3).unwrap();
+        for value in [1, 2, 3] {
+            filter.update(Subpage::Zero, value);
+        }
+        // The oldest value (1) falls out
+        assert_eq!(filter.update(Subpage::Zero, 6), 11.0 / 3.0);
+        // And the next oldest (2)
+        assert_eq!(filter.update(Subpage::Zero, 6), 5.0);
+    }
+
+    #[test]
+    fn separate_subpages() {
+        let mut filter = CompensationPixelFilter::new(2).unwrap();
+        filter.update(Subpage::Zero, 100);
+        assert_eq!(filter.update(Subpage::One, -100), -100.0);
+        assert_eq!(filter.update(Subpage::Zero, 200), 150.0);
+    }
+
+    #[test]
+    fn reset() {
+        let mut filter = CompensationPixelFilter::new(4).unwrap();
+        filter.update(Subpage::Zero, 100);
+        filter.update(Subpage::One, 100);
+        filter.reset();
+        assert_eq!(filter.update(Subpage::Zero, 7), 7.0);
+        assert_eq!(filter.update(Subpage::One, 9), 9.0);
+    }
+
+    #[test]
+    fn invalid_temporal_filters() {
+        assert!(ExponentialMovingAverage::<4>::new(0.0).is_err());
+        assert!(ExponentialMovingAverage::<4>::new(1.5).is_err());
+        assert!(ExponentialMovingAverage::<4>::new(f32::NAN).is_err());
+        assert!(ExponentialMovingAverage::<4>::new(1.0).is_ok());
+        assert!(BoxcarAverage::<4, 0>::new().is_err());
+        assert!(BoxcarAverage::<4, 256>::new().is_err());
+        assert!(BoxcarAverage::<4, 255>::new().is_ok());
+        assert!(KalmanFilter::<4>::new(-1.0, 1.0, None).is_err());
+        assert!(KalmanFilter::<4>::new(0.0, 0.0, None).is_err());
+        assert!(KalmanFilter::<4>::new(0.0, 1.0, None).is_ok());
+    }
+
+    #[test]
+    fn exponential_moving_average() {
+        let mut filter = ExponentialMovingAverage::<2>::new(0.25).unwrap();
+        let mut image = [10.0, 20.0];
+        filter.filter(&mut image);
+        // The first values are used as-is
+        assert_eq!(image, [10.0, 20.0]);
+        let mut image = [14.0, 20.0];
+        filter.filter(&mut image);
+        assert_eq!(image, [11.0, 20.0]);
+        filter.reset();
+        let mut image = [14.0, 0.0];
+        filter.filter(&mut image);
+        assert_eq!(image, [14.0, 0.0]);
+    }
+
+    #[test]
+    fn boxcar_average() {
+        let mut filter = BoxcarAverage::<1, 3>::new().unwrap();
+        let mut averages = [0f32; 5];
+        for (value, average) in [3.0, 6.0, 9.0, 0.0, 30.0].iter().zip(averages.iter_mut()) {
+            let mut image = [*value];
+            filter.filter(&mut image);
+            *average = image[0];
+        }
+        assert_eq!(averages, [3.0, 4.5, 6.0, 5.0, 13.0]);
+        filter.reset();
+        let mut image = [1.0];
+        filter.filter(&mut image);
+        assert_eq!(image, [1.0]);
+    }
+
+    #[test]
+    fn kalman_filter() {
+        let mut filter = KalmanFilter::<1>::new(0.0, 1.0, Some(5.0)).unwrap();
+        assert_eq!(filter.variance(0), None);
+        // Alternate around 20, the estimate should converge on it
+        for frame in 0..20 {
+            let mut image = [if frame % 2 == 0 { 19.0 } else { 21.0 }];
+            filter.filter(&mut image);
+        }
+        let mut image = [21.0];
+        filter.filter(&mut image);
+        assert!((image[0] - 20.0).abs() < 0.1, "{}", image[0]);
+        assert!(filter.variance(0).unwrap() < 0.1);
+        // A big change resets the pixel
+        let mut image = [40.0];
+        filter.filter(&mut image);
+        assert_eq!(image, [40.0]);
+        assert_eq!(filter.variance(0), Some(1.0));
+    }
+
+    #[test]
+    fn nan_pixels() {
+        let mut filter = ExponentialMovingAverage::<2>::new(0.5).unwrap();
+        let mut image = [2.0, f32::NAN];
+        filter.filter(&mut image);
+        let mut image = [f32::NAN, 4.0];
+        filter.filter(&mut image);
+        assert!(image[0].is_nan());
+        assert_eq!(image[1], 4.0);
+        let mut image = [4.0, 4.0];
+        filter.filter(&mut image);
+        // The NaN didn't affect the first pixel
+        assert_eq!(image, [3.0, 4.0]);
+    }
+
+    fn check_oversized_image<F: TemporalFilter>(mut filter: F) {
+        // Only every other pixel is updated and some are NaN, so the number of pixels filtered is
+        // less than the length of the image.
+        let mut image = [1f32; 12];
+        image[1] = f32::NAN;
+        image[2] = f32::NAN;
+        let alternating = [true, false].into_iter().cycle();
+        filter.filter_pixels(alternating, &mut image);
+        // The pixels past the end of the filter are left alone
+        filter.filter(&mut image);
+        let mut image = [1f32; Mlx90640::NUM_PIXELS];
+        image[0] = f32::NAN;
+        filter.filter_subpage::<Mlx90640>(Subpage::One, AccessPattern::Chess, &mut image);
+        // Pixel 10 is in subpage 0, but past the end of the filter
+        image[10] = 5.0;
+        filter.filter_subpage::<Mlx90640>(Subpage::Zero, AccessPattern::Chess, &mut image);
+        assert!(image[0].is_nan());
+        assert_eq!(image[1..10], [1f32; 9]);
+        assert_eq!(image[10], 5.0);
+    }
+
+    #[test]
+    fn oversized_image() {
+        check_oversized_image(ExponentialMovingAverage::<4>::new(0.5).unwrap());
+        check_oversized_image(BoxcarAverage::<4, 2>::new().unwrap());
+        check_oversized_image(KalmanFilter::<4>::new(0.1, 1.0, None).unwrap());
+    }
+
+    #[test]
+    fn subpage_pixels() {
+        let mut filter = BoxcarAverage::<{ Mlx90640::NUM_PIXELS }, 4>::new().unwrap();
+        let mut image = [10f32; Mlx90640::NUM_PIXELS];
+        filter.filter_subpage::<Mlx90640>(Subpage::Zero, AccessPattern::Chess, &mut image);
+        filter.filter_subpage::<Mlx90640>(Subpage::One, AccessPattern::Chess, &mut image);
+        // The next subpage updates half of the pixels, and the other half still have the old
+        // values. Only the updated pixels should be filtered.
+        for (pixel, updated) in image.iter_mut().zip(Mlx90640::pixels_in_subpage(
+            Subpage::Zero,
+            AccessPattern::Chess,
+        )) {
+            if updated {
+                *pixel = 20.0;
+            }
+        }
+        filter.filter_subpage::<Mlx90640>(Subpage::Zero, AccessPattern::Chess, &mut image);
+        // Pixel 0 is in subpage 0, pixel 1 is in subpage 1
+        assert_eq!(image[0], 15.0);
+        assert_eq!(image[1], 10.0);
+        // The pixels from subpage 1 have only been filtered once, so a new value is only averaged
+        // with that one value.
+        image[1] = 40.0;
+        filter.filter_subpage::<Mlx90640>(Subpage::One, AccessPattern::Chess, &mut image);
+        assert_eq!(image[1], 25.0);
+    }
+}
@@ -0,0 +1,138 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright © 2021 Will Ross
+
+//! Tracking when a full frame has been assembled from subpages.
+//!
+//! Each time a camera updates its data only one [subpage][Subpage] is updated. For the MLX90640
+//! each subpage only covers half of the pixels, so two subpages need to be processed into the
+//! same buffer before there is a complete image. The MLX90641's subpages cover all of the pixels,
+//! so each subpage is a complete image by itself. [`FrameAssembler`] keeps track of
+//! which subpages have been processed and reports when the image is complete.
+
+use crate::common::MelexisCamera;
+use crate::register::{AccessPattern, Subpage};
+
+/// Keep track of which subpages have been written to an image buffer.
+///
+/// After a complete frame has been assembled, the next subpage that is
+/// [recorded][FrameAssembler::record] starts a new frame.
+#[derive(Clone, Debug, PartialEq)]
+pub struct FrameAssembler {
+    /// Whether each subpage covers all of the pixels on its own.
+    all_pixel_subpages: bool,
+
+    /// The ambient temperature used when processing each subpage, if that subpage is fresh.
+    ambient_temperatures: [Option<f32>; 2],
+}
+
+impl FrameAssembler {
+    /// Create a new `FrameAssembler` for a camera using the given access pattern.
+    ///
+    /// Changing the access pattern changes which pixels belong to a subpage, so a new
+    /// `FrameAssembler` should be created when that happens.
+    pub fn new<Cam: MelexisCamera>(access_pattern: AccessPattern) -> Self {
+        let all_pixel_subpages = Cam::pixels_in_subpage(Subpage::Zero, access_pattern)
+            .into_iter()
+            .all(|valid| valid);
+        Self {
+            all_pixel_subpages,
+            ambient_temperatures: [None; 2],
+        }
+    }
+
+    /// Record that a subpage has been processed into the image.
+    ///
+    /// `ambient_temperature` is the ambient temperature calculated when processing the subpage.
+    pub fn record(&mut self, subpage: Subpage, ambient_temperature: f32) {
+        if self.is_complete() {
+            self.reset();
+        }
+        self.ambient_temperatures[subpage as usize] = Some(ambient_temperature);
+    }
+
+    /// Check if a subpage has been recorded in the current frame.
+    pub fn is_fresh(&self, subpage: Subpage) -> bool {
+        self.ambient_temperatures[subpage as usize].is_some()
+    }
+
+    /// Check if every pixel in the image has been updated for the current frame.
+    pub fn is_complete(&self) -> bool {
+        if self.all_pixel_subpages {
+            self.ambient_temperatures.iter().any(Option::is_some)
+        } else {
+            self.ambient_temperatures.iter().all(Option::is_some)
+        }
+    }
+
+    /// The ambient temperature used for each subpage (indexed by subpage) in the current frame.
+    ///
+    /// Subpages that haven't been recorded for the current frame are `None`.
+    pub fn ambient_temperatures(&self) -> [Option<f32>; 2] {
+        self.ambient_temperatures
+    }
+
+    /// Forget all recorded subpages, starting a new frame.
+    pub fn reset(&mut self) {
+        self.ambient_temperatures = [None; 2];
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::mlx90640::Mlx90640;
+    use crate::mlx90641::Mlx90641;
+    use crate::{AccessPattern, Subpage};
+
+    use super::FrameAssembler;
+
+    fn check_two_subpages(access_pattern: AccessPattern) {
+        let mut assembler = FrameAssembler::new::<Mlx90640>(access_pattern);
+        assert!(!assembler.is_complete());
+        assembler.record(Subpage::One, 30.0);
+        assert!(!assembler.is_complete());
+        assert!(assembler.is_fresh(Subpage::One));
+        assert!(!assembler.is_fresh(Subpage::Zero));
+        // The same subpage again doesn't complete the frame
+        assembler.record(Subpage::One, 30.5);
+        assert!(!assembler.is_complete());
+        assembler.record(Subpage::Zero, 31.0);
+        assert!(assembler.is_complete());
+        assert_eq!(assembler.ambient_temperatures(), [Some(31.0), Some(30.5)]);
+        // The next subpage starts a new frame
+        assembler.record(Subpage::One, 32.0);
+        assert!(!assembler.is_complete());
+        assert_eq!(assembler.ambient_temperatures(), [None, Some(32.0)]);
+    }
+
+    #[test]
+    fn mlx90640_chess() {
+        check_two_subpages(AccessPattern::Chess);
+    }
+
+    #[test]
+    fn mlx90640_interleave() {
+        check_two_subpages(AccessPattern::Interleave);
+    }
+
+    #[test]
+    fn all_pixel_subpages() {
+        let mut assembler = FrameAssembler::new::<Mlx90641>(AccessPattern::Interleave);
+        assert!(!assembler.is_complete());
+        assembler.record(Subpage::Zero, 40.0);
+        assert!(assembler.is_complete());
+        assert_eq!(assembler.ambient_temperatures(), [Some(40.0), None]);
+        assembler.record(Subpage::One, 41.0);
+        assert!(assembler.is_complete());
+        assert_eq!(assembler.ambient_temperatures(), [None, Some(41.0)]);
+    }
+
+    #[test]
+    fn reset() {
+        let mut assembler = FrameAssembler::new::<Mlx90640>(AccessPattern::Chess);
+        assembler.record(Subpage::Zero, 30.0);
+        assembler.reset();
+        assert!(!assembler.is_fresh(Subpage::Zero));
+        assembler.record(Subpage::One, 30.0);
+        assert!(!assembler.is_complete());
+    }
+}
@@ -0,0 +1,305 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright © 2021 Will Ross
+
+//! Post-processing for thermal images.
+//!
+//! The functions in this module work on images that have already been generated, either the
+//! $V_{IR}$ data from [`raw_pixels_to_ir_data`] or the temperatures from
+//! [`raw_pixels_to_temperatures`]. Images are laid out in rows, then columns, with the dimensions
+//! given by the camera's [`MelexisCamera::WIDTH`] and [`MelexisCamera::HEIGHT`].
+//!
+//! [`raw_pixels_to_ir_data`]: crate::calculations::raw_pixels_to_ir_data
+//! [`raw_pixels_to_temperatures`]: crate::calculations::raw_pixels_to_temperatures
+#![doc = include_str!("katex.html")]
+
+use arrayvec::ArrayVec;
+
+use crate::common::{FlaggedPixels, MelexisCamera};
+use crate::register::{AccessPattern, Subpage};
+
+/// How to replace the values of [flagged pixels][crate::CalibrationData::failed_pixels].
+///
+/// Neighbouring pixels that are also flagged (or are already NaN) are not used when calculating a
+/// replacement value. If there are no usable neighbours, the pixel is set to NaN.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum PixelReplacement {
+    /// Replace flagged pixels with NaN.
+    Nan,
+
+    /// Use the mean of the (up to) 8 surrounding pixels.
+    NeighbourMean,
+
+    /// Use the median of the (up to) 8 surrounding pixels.
+    NeighbourMedian,
+
+    /// Use the mean of the surrounding pixels that are updated in the same subpage.
+    ///
+    /// This avoids mixing data from different frames. With the MLX90640's [chess
+    /// pattern][AccessPattern::Chess] only the diagonal neighbours are used, and with the
+    /// [interleaved pattern][AccessPattern::Interleave] only the neighbours on the same row are
+    /// used. Cameras with subpages covering every pixel (like the MLX90641) use all 8 neighbours.
+    SubpageNeighbourMean,
+}
+
+/// The indices of the (up to) 8 pixels surrounding a pixel.
+fn neighbours<Cam: MelexisCamera>(index: usize) -> impl Iterator<Item = usize> {
+    let row = index / Cam::WIDTH;
+    let column = index % Cam::WIDTH;
+    (-1isize..=1)
+        .flat_map(|row_delta| (-1isize..=1).map(move |column_delta| (row_delta, column_delta)))
+        .filter(|deltas| *deltas != (0, 0))
+        .filter_map(move |(row_delta, column_delta)| {
+            let row = row.checked_add_signed(row_delta)?;
+            let column = column.checked_add_signed(column_delta)?;
+            if row < Cam::HEIGHT && column < Cam::WIDTH {
+                Some(row * Cam::WIDTH + column)
+            } else {
+                None
+            }
+        })
+}
+
+/// Check if two pixels are updated in the same subpages.
+fn same_subpage<Cam: MelexisCamera>(
+    access_pattern: AccessPattern,
+    first: usize,
+    second: usize,
+) -> bool {
+    [Subpage::Zero, Subpage::One].into_iter().all(|subpage| {
+        Cam::pixel_in_subpage(subpage, access_pattern, first)
+            == Cam::pixel_in_subpage(subpage, access_pattern, second)
+    })
+}
+
+/// Replace the values of flagged pixels in an image.
+///
+/// Each pixel flagged in `flagged` (typically from [`failed_pixels`] or [`outlier_pixels`]) is
+/// replaced according to `replacement`. `access_pattern` is only used for
+/// [`PixelReplacement::SubpageNeighbourMean`], to determine which pixels share a subpage.
+///
+/// [`failed_pixels`]: crate::CalibrationData::failed_pixels
+/// [`outlier_pixels`]: crate::CalibrationData::outlier_pixels
+pub fn replace_flagged_pixels<Cam, F>(
+    flagged: &F,
+    replacement: PixelReplacement,
+    access_pattern: AccessPattern,
+    image: &mut [f32],
+) where
+    Cam: MelexisCamera,
+    F: FlaggedPixels,
+{
+    // There's usually only a handful of flagged pixels (if any), so searching for them instead of
+    // building a lookup table is fine.
+    let is_flagged = |index: usize| flagged.iter_flagged().any(|flagged| flagged == index);
+    for index in flagged.iter_flagged() {
+        if index >= image.len() {
+            continue;
+        }
+        let mut values: ArrayVec<f32, 8> = neighbours::<Cam>(index)
+            .filter(|neighbour| !is_flagged(*neighbour))
+            .filter(|neighbour| {
+                replacement != PixelReplacement::SubpageNeighbourMean
+                    || same_subpage::<Cam>(access_pattern, index, *neighbour)
+            })
+            .filter_map(|neighbour| image.get(neighbour).copied())
+            .filter(|value| !value.is_nan())
+            .collect();
+        image[index] = if values.is_empty() {
+            f32::NAN
+        } else {
+            match replacement {
+                PixelReplacement::Nan => f32::NAN,
+                PixelReplacement::NeighbourMean | PixelReplacement::SubpageNeighbourMean => {
+                    values.iter().sum::<f32>() / values.len() as f32
+                }
+                PixelReplacement::NeighbourMedian => {
+                    values.sort_unstable_by(f32::total_cmp);
+                    let middle = values.len() / 2;
+                    if values.len() % 2 == 0 {
+                        (values[middle - 1] + values[middle]) / 2f32
+                    } else {
+                        values[middle]
+                    }
+                }
+            }
+        };
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use arrayvec::ArrayVec;
+    use bitvec::prelude::*;
+
+    use crate::mlx90640::Mlx90640;
+    use crate::mlx90641::Mlx90641;
+    use crate::{AccessPattern, MelexisCamera};
+
+    use super::{replace_flagged_pixels, PixelReplacement};
+
+    /// An image where each pixel is its index
+    fn index_image<const N: usize>() -> [f32; N] {
+        let mut image = [0f32; N];
+        image
+            .iter_mut()
+            .enumerate()
+            .for_each(|(index, pixel)| *pixel = index as f32);
+        image
+    }
+
+    #[test]
+    fn neighbours() {
+        let corner: ArrayVec<usize, 8> = super::neighbours::<Mlx90640>(0).collect();
+        assert_eq!(corner.as_slice(), [1, 32, 33]);
+        let middle: ArrayVec<usize, 8> = super::neighbours::<Mlx90640>(33).collect();
+        assert_eq!(middle.as_slice(), [0, 1, 2, 32, 34, 64, 65, 66]);
+        let last = Mlx90640::NUM_PIXELS - 1;
+        assert_eq!(super::neighbours::<Mlx90640>(last).count(), 3);
+    }
+
+    #[test]
+    fn replace_nan() {
+        let mut image = index_image::<{ Mlx90640::NUM_PIXELS }>();
+        let mut flagged = bitarr![0; Mlx90640::NUM_PIXELS];
+        flagged.set(40, true);
+        replace_flagged_pixels::<Mlx90640, _>(
+            &flagged.as_bitslice(),
+            PixelReplacement::Nan,
+            AccessPattern::Chess,
+            &mut image,
+        );
+        assert!(image[40].is_nan());
+        assert_eq!(image[41], 41.0);
+    }
+
+    #[test]
+    fn replace_mean() {
+        let mut image = index_image::<{ Mlx90640::NUM_PIXELS }>();
+        let mut flagged = bitarr![0; Mlx90640::NUM_PIXELS];
+        flagged.set(40, true);
+        replace_flagged_pixels::<Mlx90640, _>(
+            &flagged.as_bitslice(),
+            PixelReplacement::NeighbourMean,
+            AccessPattern::Chess,
+            &mut image,
+        );
+        // The surrounding pixels are symmetric about 40
+        assert_eq!(image[40], 40.0);
+    }
+
+    #[test]
+    fn replace_median() {
+        let mut image = [0f32; Mlx90640::NUM_PIXELS];
+        // Two hot pixels next to the failed one shouldn't affect the median
+        image[39] = 500.0;
+        image[41] = 500.0;
+        let mut flagged = bitarr![0; Mlx90640::NUM_PIXELS];
+        flagged.set(40, true);
+        image[40] = 1000.0;
+        replace_flagged_pixels::<Mlx90640, _>(
+            &flagged.as_bitslice(),
+            PixelReplacement::NeighbourMedian,
+            AccessPattern::Chess,
+            &mut image,
+        );
+        assert_eq!(image[40], 0.0);
+    }
+
+    #[test]
+    fn skip_flagged_neighbours() {
+        let mut image = [1f32; Mlx90640::NUM_PIXELS];
+        let mut flagged = bitarr![0; Mlx90640::NUM_PIXELS];
+        flagged.set(40, true);
+        flagged.set(41, true);
+        image[40] = f32::INFINITY;
+        image[41] = f32::INFINITY;
+        replace_flagged_pixels::<Mlx90640, _>(
+            &flagged.as_bitslice(),
+            PixelReplacement::NeighbourMean,
+            AccessPattern::Chess,
+            &mut image,
+        );
+        assert_eq!(image[40], 1.0);
+        assert_eq!(image[41], 1.0);
+    }
+
+    #[test]
+    fn replace_subpage_chess() {
+        // Mark pixels in the other subpage with a value that would skew the mean
+        let mut image = [0f32; Mlx90640::NUM_PIXELS];
+        let index = 2 * Mlx90640::WIDTH + 2;
+        for neighbour in [
+            index - 1,
+            index + 1,
+            index - Mlx90640::WIDTH,
+            index + Mlx90640::WIDTH,
+        ] {
+            image[neighbour] = 100.0;
+        }
+        for neighbour in [
+            index - Mlx90640::WIDTH - 1,
+            index - Mlx90640::WIDTH + 1,
+            index + Mlx90640::WIDTH - 1,
+            index + Mlx90640::WIDTH + 1,
+        ] {
+            image[neighbour] = 20.0;
+        }
+        let mut flagged = bitarr![0; Mlx90640::NUM_PIXELS];
+        flagged.set(index, true);
+        replace_flagged_pixels::<Mlx90640, _>(
+            &flagged.as_bitslice(),
+            PixelReplacement::SubpageNeighbourMean,
+            AccessPattern::Chess,
+            &mut image,
+        );
+        assert_eq!(image[index], 20.0);
+    }
+
+    #[test]
+    fn replace_subpage_interleave() {
+        let mut image = [100f32; Mlx90640::NUM_PIXELS];
+        let index = 2 * Mlx90640::WIDTH + 2;
+        image[index - 1] = 10.0;
+        image[index + 1] = 30.0;
+        let mut flagged = bitarr![0; Mlx90640::NUM_PIXELS];
+        flagged.set(index, true);
+        replace_flagged_pixels::<Mlx90640, _>(
+            &flagged.as_bitslice(),
+            PixelReplacement::SubpageNeighbourMean,
+            AccessPattern::Interleave,
+            &mut image,
+        );
+        assert_eq!(image[index], 20.0);
+    }
+
+    #[test]
+    fn replace_subpage_all_pixels() {
+        // Every pixel is in both subpages on the MLX90641, so all neighbours are used.
+        let mut image = index_image::<{ Mlx90641::NUM_PIXELS }>();
+        let index = 2 * Mlx90641::WIDTH + 2;
+        let mut flagged = bitarr![0; Mlx90641::NUM_PIXELS];
+        flagged.set(index, true);
+        replace_flagged_pixels::<Mlx90641, _>(
+            &flagged.as_bitslice(),
+            PixelReplacement::SubpageNeighbourMean,
+            AccessPattern::Interleave,
+            &mut image,
+        );
+        assert_eq!(image[index], index as f32);
+    }
+
+    #[test]
+    fn no_neighbours() {
+        let mut image = [f32::NAN; Mlx90641::NUM_PIXELS];
+        let mut flagged = bitarr![0; Mlx90641::NUM_PIXELS];
+        flagged.set(0, true);
+        image[0] = 5.0;
+        replace_flagged_pixels::<Mlx90641, _>(
+            &flagged.as_bitslice(),
+            PixelReplacement::NeighbourMedian,
+            AccessPattern::Interleave,
+            &mut image,
+        );
+        assert!(image[0].is_nan());
+    }
+}
@@ -6,58 +6,92 @@
 //! data processing for you, and a low-level API if you need to go beyond what the high-level API
 //! can do for you.
 //!
-//! This library uses the [`embedded-hal`][embedded-hal] I²C traits, meaning you should be able to
-//! use this library on other platforms, as long as there's an `embedded-hal` I²C implementation
-//! available. This library is also `no_std` compatible (there is a large memory requirement
-//! though).
+//! This library uses the [`embedded-hal`][embedded-hal] 1.0 I²C traits, meaning you should be able
+//! to use this library on other platforms, as long as there's an `embedded-hal` I²C implementation
+//! available. I²C buses that only implement the `embedded-hal` 0.2 traits can be used with the
+//! adapter in the `compat` module (enabled with the `embedded-hal-02` feature). This library is
+//! also `no_std` compatible (there is a large memory requirement though).
 //!
-//! [embedded-hal]: https://docs.rs/embedded-hal/*/embedded_hal/blocking/i2c/index.html
+//! [embedded-hal]: https://docs.rs/embedded-hal/1/embedded_hal/i2c/index.html
 //!
 //! # High-Level API
 //! ```no_run
 //! use std::thread::sleep;
 //! use std::time::Duration;
-//! use mlx9064x::Mlx90640Driver;
-//! use linux_embedded_hal::I2cdev;
+//! use embedded_hal::i2c::I2c;
+//! use mlx9064x::{Error, Mlx90640Driver};
 //!
-//! let i2c_bus = I2cdev::new("/dev/i2c-1").expect("/dev/i2c-1 needs to be an I2C controller");
-//! // Default address for these cameras is 0x33
-//! let mut camera = Mlx90640Driver::new(i2c_bus, 0x33)?;
-//! // Get the current frame rate of the camera
-//! let frame_rate = camera.frame_rate()?;
-//! // A buffer for storing the temperature "image"
-//! let mut temperatures = vec![0f32; camera.height() * camera.width()];
-//! camera.generate_image_if_ready(&mut temperatures)?;
-//! // FrameRate can be converted into a Duration
-//! sleep(Duration::from(frame_rate));
-//! camera.generate_image_if_ready(&mut temperatures)?;
-//! # Ok::<(), mlx9064x::Error<I2cdev>>(())
+//! fn capture<I2C: I2c>(i2c_bus: I2C) -> Result<Vec<f32>, Error<I2C>> {
+//!     // Default address for these cameras is 0x33
+//!     let mut camera = Mlx90640Driver::new(i2c_bus, 0x33)?;
+//!     // Get the current frame rate of the camera
+//!     let frame_rate = camera.frame_rate()?;
+//!     // A buffer for storing the temperature "image"
+//!     let mut temperatures = vec![0f32; camera.height() * camera.width()];
+//!     camera.generate_image_if_ready(&mut temperatures)?;
+//!     // FrameRate can be converted into a Duration
+//!     sleep(Duration::from(frame_rate));
+//!     camera.generate_image_if_ready(&mut temperatures)?;
+//!     Ok(temperatures)
+//! }
 //! ```
-//! This snippet gives a quick example of using the high-level API with an MLX90640 on Linux. The
-//! camera is using the I²C bus #1 (`/dev/i2c-1`) and the default I²C address (`0x33`). The
-//! calibration data is loaded from the camera over I²C and saved into an
-//! [`Mlx90640Calibration`][mlx90640::Mlx90640Calibration] within `camera`. A destination buffer is
-//! created to store the temperature data from the camera using a `Vec`, and then the temperature
-//! data is retrieved twice to cover both [subpages](#subpages-and-access-patterns), with a delay
-//! between the accesses to allow the next frame of data to become available.
+//! This snippet gives a quick example of using the high-level API with an MLX90640 at the default
+//! I²C address (`0x33`). `i2c_bus` can be any `embedded-hal` I²C bus, for example `I2cdev` from
+//! `linux-embedded-hal` on Linux. The calibration data is loaded from the camera over I²C and saved
+//! into an [`Mlx90640Calibration`][mlx90640::Mlx90640Calibration] within `camera`. A destination
+//! buffer is created to store the temperature data from the camera using a `Vec`, and then the
+//! temperature data is retrieved twice to cover both [subpages](#subpages-and-access-patterns),
+//! with a delay between the accesses to allow the next frame of data to become available.
 //!
 //! The high-level API is exposed through [`CameraDriver`], and also makes it easy to configure the
 //! camera settings like frame rate or access mode. If you need to tailor the functionality beyond
 //! what `CameraDriver` provides for you, the low-level API is probably a better choice for you.
 //!
+//! The MLX90642 is different from the other two cameras, as it calculates the temperatures itself.
+//! It has its own, much simpler, driver ([`Mlx90642Driver`]) that reads the temperatures directly
+//! and configures the camera with the MLX90642's commands.
+//!
+//! If the camera model isn't known until runtime, [`AnyCameraDriver`]
+//! [detects][CameraModel::detect] which camera is attached and wraps the matching driver. To run
+//! several cameras on one bus, a [`CameraManager`] owns the bus and reads frames from each camera
+//! in turn.
+//!
+//! ## Async
+//! With the `async` feature enabled, `AsyncCameraDriver` provides the same API as `CameraDriver`
+//! using the [`embedded-hal-async`][embedded-hal-async] I²C traits, with an async delay used when
+//! waiting on the camera. There are also `AsyncMlx90640Driver`, `AsyncMlx90641Driver`, and
+//! `AsyncMlx90642Driver` aliases, similar to the blocking drivers.
+//!
+//! [embedded-hal-async]: https://docs.rs/embedded-hal-async/1/embedded_hal_async/i2c/index.html
+//!
 //! # Low-Level API
 //! The low-level API is the foundation for the high-level API, exposed for those cases where a
 //! more customized approach is needed. A common example is customizing how the calibration data is
 //! loaded. To reduce startup time and memory usage, you might want to pre-process the calibration
-//! data for a specific camera and store it in a microcontroller's flash memory. This can be done
-//! by implementing [`CalibrationData`][common::CalibrationData]. Because `CameraDriver` is generic
-//! over `CalibrationData`, you can use your custom `CalibrationData` with the rest of the
-//! high-level API with almost no changes.
+//! data for a specific camera and store it in a microcontroller's flash memory. The [`blob`] module
+//! provides a format for this, or you can implement [`CalibrationData`][common::CalibrationData]
+//! yourself. Because `CameraDriver` is generic over `CalibrationData`, you can use your custom
+//! `CalibrationData` with the rest of the high-level API with almost no changes. If you'd rather
+//! keep calibration data in your own format, the `serde` feature implements `Serialize` and
+//! `Deserialize` for the calibration types, the register values, and
+//! [`RamData`][calculations::RamData].
 //!
 //! Most users of the low-level API will probably find the [`common`], [`register`], and
 //! [`calculations`] modules most relevant to their needs, with camera-model specific constants and
-//! types available in the [`mlx90640`], [`mlx90641`], and [`mlx90642`] modules. The `raw_to_temperatures`
+//! types available in the [`mlx90640`] and [`mlx90641`] modules. The `raw_to_temperatures`
 //! benchmark is an example of using the low-level API (but without actually waiting for subpages).
+//! The [`simulation`] module goes the other direction, generating raw RAM contents from a scene of
+//! temperatures so the calculations can be exercised without a camera. Scenes with a mix of
+//! materials can give parts of the image their own emissivity with the [`emissivity`] module. For
+//! microcontrollers without a floating point unit, the `fixed-point` feature enables the
+//! `fixed_point` module, which calculates temperatures with integer math. On hosts processing
+//! frames from several cameras, the `simd` feature enables the `simd` module, which calculates
+//! several temperatures at once.
+//!
+//! Once an image has been generated, the [`image`] module can replace the values of failed
+//! pixels, the [`filter`] module can filter out noise across frames, the [`upscale`] module
+//! resizes images for display, and the [`palette`] module renders them as RGB888 or RGB565 pixels.
+//! For machine vision, the [`agc`] module converts images to 8-bit or 16-bit grayscale.
 //!
 //! # Subpages and Access Patterns
 //! A significant difference between these Melexis cameras and other common thermal cameras is how
@@ -73,23 +107,56 @@
 #[cfg(not(any(feature = "std", feature = "libm")))]
 compile_error!("Either the 'std' or 'libm' feature must be enabled.");
 
+pub mod agc;
+#[doc(hidden)]
+pub mod any_driver;
+#[cfg(feature = "async")]
+#[doc(hidden)]
+pub mod async_driver;
+pub mod blob;
 pub mod calculations;
 pub mod common;
+#[cfg(feature = "embedded-hal-02")]
+pub mod compat;
+pub mod compiled;
 #[doc(hidden)]
 pub mod driver;
+pub mod eeprom;
+pub mod emissivity;
 #[doc(hidden)]
 pub mod error;
+pub mod filter;
+#[cfg(feature = "fixed-point")]
+pub mod fixed_point;
+pub mod frame;
+pub mod image;
+pub mod manager;
+pub mod metadata;
 pub mod mlx90640;
 pub mod mlx90641;
 pub mod mlx90642;
+pub mod palette;
 pub mod register;
+#[cfg(feature = "simd")]
+pub mod simd;
+pub mod simulation;
+#[cfg(test)]
+mod test_frames;
+pub mod upscale;
 mod util;
 
+#[doc(inline)]
+pub use any_driver::{AnyCameraDriver, CameraModel};
+#[cfg(feature = "async")]
+#[doc(inline)]
+pub use async_driver::AsyncCameraDriver;
 pub use common::{Address, CalibrationData, MelexisCamera};
 #[doc(inline)]
 pub use driver::CameraDriver;
 #[doc(inline)]
 pub use error::{Error, LibraryError};
+pub use manager::CameraManager;
+pub use metadata::{Clock, FrameMetadata};
 pub use register::*;
 
 /// High-level MLX90640 driver.
@@ -109,9 +176,31 @@ pub type Mlx90641Driver<I2C> = CameraDriver<
 >;
 
 /// High-level MLX90642 driver.
-pub type Mlx90642Driver<I2C> = CameraDriver<
-    mlx90642::Mlx90642Calibration,
+///
+/// The MLX90642 calculates temperatures itself, so this is not a [`CameraDriver`]. See the
+/// [`mlx90642`] module for details.
+pub type Mlx90642Driver<I2C> = mlx90642::Driver<I2C>;
+
+/// Async high-level MLX90640 driver.
+#[cfg(feature = "async")]
+pub type AsyncMlx90640Driver<I2C, D> = AsyncCameraDriver<
+    mlx90640::Mlx90640Calibration,
+    I2C,
+    D,
+    { mlx90640::Mlx90640::HEIGHT },
+    { mlx90640::Mlx90640::NUM_PIXELS * 2 },
+>;
+
+/// Async high-level MLX90641 driver.
+#[cfg(feature = "async")]
+pub type AsyncMlx90641Driver<I2C, D> = AsyncCameraDriver<
+    mlx90641::Mlx90641Calibration,
     I2C,
-    { mlx90642::Mlx90642::HEIGHT },
-    { mlx90642::Mlx90642::NUM_PIXELS * 2 },
+    D,
+    { mlx90641::Mlx90641::HEIGHT },
+    { mlx90641::Mlx90641::NUM_PIXELS * 2 },
 >;
+
+/// Async high-level MLX90642 driver.
+#[cfg(feature = "async")]
+pub type AsyncMlx90642Driver<I2C, D> = mlx90642::AsyncDriver<I2C, D>;
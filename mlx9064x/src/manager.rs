@@ -0,0 +1,502 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright © 2021 Will Ross
+//! Running several cameras on one I²C bus.
+//!
+//! The drivers each take ownership of their bus, so putting more than one camera on a bus would
+//! otherwise need a bus sharing wrapper. [`CameraManager`] owns the bus instead, and lends it to
+//! each camera's driver (through a [`LentBus`]) only while that camera is being used. The cameras
+//! can be any mix of models, but they each need their own address (see
+//! [`set_i2c_address_persistent`][crate::CameraDriver::set_i2c_address_persistent]).
+//!
+//! Frames are read with [`next_frame`][CameraManager::next_frame], which checks the cameras in
+//! order of when each one's next frame is due (based on its frame rate), so faster cameras are
+//! checked more often. Every frame is tagged with the address of the camera it came from.
+//!
+//! For stereo or wide-area coverage, [`start_measurements`][CameraManager::start_measurements]
+//! starts a measurement on every camera as close together as the bus allows.
+use core::mem;
+use core::time::Duration;
+
+use arrayvec::ArrayVec;
+use embedded_hal::i2c;
+
+use crate::any_driver::{AnyCameraDriver, CameraModel};
+use crate::common::{FromI2C, ToI2C};
+use crate::error::{Error, LibraryError};
+use crate::mlx90640::Mlx90640Calibration;
+use crate::mlx90641::Mlx90641Calibration;
+use crate::mlx90642::{self, Command};
+use crate::register::{ControlRegister, FrameRate, StatusRegister};
+use crate::{Mlx90640Driver, Mlx90641Driver, Mlx90642Driver};
+
+/// The most pixels any of the cameras have (the MLX90640 has the same number as the MLX90642).
+const MAX_PIXELS: usize = mlx90642::Mlx90642::NUM_PIXELS;
+
+/// An I²C bus lent to one of the cameras in a [`CameraManager`].
+///
+/// The manager keeps the bus, and only moves it into a camera's driver while that driver is being
+/// used. Camera drivers are only accessible through [`CameraManager::with_camera`], so the bus is
+/// always present when a driver uses it.
+#[derive(Debug)]
+pub struct LentBus<I2C>(Option<I2C>);
+
+impl<I2C> LentBus<I2C> {
+    fn inner_mut(&mut self) -> &mut I2C {
+        self.0
+            .as_mut()
+            .expect("The bus is lent to a camera before it is used")
+    }
+}
+
+impl<I2C> i2c::ErrorType for LentBus<I2C>
+where
+    I2C: i2c::ErrorType,
+{
+    type Error = I2C::Error;
+}
+
+impl<I2C> i2c::I2c for LentBus<I2C>
+where
+    I2C: i2c::I2c,
+{
+    fn transaction(
+        &mut self,
+        address: u8,
+        operations: &mut [i2c::Operation<'_>],
+    ) -> Result<(), Self::Error> {
+        self.inner_mut().transaction(address, operations)
+    }
+}
+
+/// A camera being driven by a [`CameraManager`].
+#[derive(Debug)]
+struct ManagedCamera<I2C> {
+    address: u8,
+
+    driver: AnyCameraDriver<LentBus<I2C>>,
+
+    /// The most recent frame from this camera.
+    ///
+    /// Each camera needs its own buffer, as the MLX90640 assembles a frame from two subpages.
+    temperatures: [f32; MAX_PIXELS],
+
+    /// How long this camera takes to produce a frame, in microseconds.
+    frame_period: u64,
+
+    /// When the next frame from this camera is expected, in microseconds from when the camera was
+    /// added.
+    ///
+    /// This isn't a real clock, it only advances as frames are read.
+    next_frame_due: u64,
+}
+
+/// A frame of temperatures from one of the cameras in a [`CameraManager`].
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ManagedFrame<'a> {
+    /// The I²C address of the camera the frame is from.
+    pub address: u8,
+
+    /// The model of the camera.
+    pub model: CameraModel,
+
+    /// The temperatures, in degrees Celsius. The length is the number of pixels for `model`.
+    pub temperatures: &'a [f32],
+
+    /// The ambient temperature of the camera when the frame was taken.
+    pub ambient_temperature: Option<f32>,
+}
+
+/// Drives up to `N` cameras on a single I²C bus.
+///
+/// See the [module documentation][self] for details.
+#[derive(Debug)]
+pub struct CameraManager<I2C, const N: usize> {
+    /// The bus, except while it is lent to a camera.
+    bus: LentBus<I2C>,
+
+    cameras: ArrayVec<ManagedCamera<I2C>, N>,
+}
+
+impl<I2C, const N: usize> CameraManager<I2C, N>
+where
+    I2C: i2c::I2c,
+{
+    /// Create a manager for the cameras on `bus`.
+    ///
+    /// Cameras are added with [`add_camera`][Self::add_camera].
+    pub fn new(bus: I2C) -> Self {
+        Self {
+            bus: LentBus(Some(bus)),
+            cameras: ArrayVec::new(),
+        }
+    }
+
+    /// Release the bus, dropping the camera drivers.
+    pub fn release(self) -> I2C {
+        let mut bus = self.bus;
+        bus.0
+            .take()
+            .expect("The bus is returned to the manager after it is lent")
+    }
+
+    /// Detect the camera at the given address, then add it.
+    ///
+    /// The detected model is returned.
+    pub fn add_camera(&mut self, address: u8) -> Result<CameraModel, Error<I2C>> {
+        let model = CameraModel::detect(self.bus.inner_mut(), address)?;
+        self.add_camera_with_model(address, model)?;
+        Ok(model)
+    }
+
+    /// Add a camera of a known model.
+    ///
+    /// An error is returned if the manager is full or there is already a camera at `address`.
+    pub fn add_camera_with_model(
+        &mut self,
+        address: u8,
+        model: CameraModel,
+    ) -> Result<(), Error<I2C>> {
+        if self.cameras.is_full() {
+            return Err(LibraryError::Other("There is no room for another camera").into());
+        }
+        if self.index_of(address).is_some() {
+            return Err(LibraryError::Other("There is already a camera at that address").into());
+        }
+        // Everything needed from the camera is read before creating the driver, as the driver
+        // can't use the bus until it's lent.
+        let bus = self.bus.inner_mut();
+        let lent = LentBus(None);
+        let driver = match model {
+            CameraModel::Mlx90640 => {
+                let calibration = Mlx90640Calibration::from_i2c(bus, address)?;
+                let control = ControlRegister::from_i2c(bus, address)?;
+                AnyCameraDriver::Mlx90640(Mlx90640Driver::from_parts(
+                    lent,
+                    address,
+                    calibration,
+                    &control,
+                ))
+            }
+            CameraModel::Mlx90641 => {
+                let calibration = Mlx90641Calibration::from_i2c(bus, address)?;
+                let control = ControlRegister::from_i2c(bus, address)?;
+                AnyCameraDriver::Mlx90641(Mlx90641Driver::from_parts(
+                    lent,
+                    address,
+                    calibration,
+                    &control,
+                ))
+            }
+            CameraModel::Mlx90642 => {
+                AnyCameraDriver::Mlx90642(Mlx90642Driver::from_parts(lent, address))
+            }
+        };
+        self.cameras.push(ManagedCamera {
+            address,
+            driver,
+            temperatures: [f32::NAN; MAX_PIXELS],
+            frame_period: 0,
+            next_frame_due: 0,
+        });
+        let index = self.cameras.len() - 1;
+        // Reading the frame rate also checks that the MLX90642 is present.
+        match self.lend(index, |camera| camera.driver.frame_rate()) {
+            Ok(frame_rate) => {
+                self.cameras[index].frame_period = frame_period(frame_rate);
+                Ok(())
+            }
+            Err(err) => {
+                self.cameras.pop();
+                Err(unlend_error(err))
+            }
+        }
+    }
+
+    /// The number of cameras being managed.
+    pub fn len(&self) -> usize {
+        self.cameras.len()
+    }
+
+    /// Whether there are no cameras being managed.
+    pub fn is_empty(&self) -> bool {
+        self.cameras.is_empty()
+    }
+
+    /// The addresses of the cameras, in the order they were added.
+    pub fn addresses(&self) -> impl Iterator<Item = u8> + '_ {
+        self.cameras.iter().map(|camera| camera.address)
+    }
+
+    /// The model of the camera at `address`, if there is one.
+    pub fn model(&self, address: u8) -> Option<CameraModel> {
+        self.index_of(address)
+            .map(|index| self.cameras[index].driver.model())
+    }
+
+    /// Use the driver for the camera at `address`.
+    ///
+    /// The bus is lent to the driver for the duration of `f`. `None` is returned if there isn't a
+    /// camera at `address`. The frame rate should be changed with
+    /// [`set_frame_rate`][Self::set_frame_rate] instead of through the driver, so the manager
+    /// knows how often to check for new frames.
+    pub fn with_camera<R, F>(&mut self, address: u8, f: F) -> Option<R>
+    where
+        F: FnOnce(&mut AnyCameraDriver<LentBus<I2C>>) -> R,
+    {
+        let index = self.index_of(address)?;
+        Some(self.lend(index, |camera| f(&mut camera.driver)))
+    }
+
+    /// Set the frame rate of the camera at `address`.
+    pub fn set_frame_rate(&mut self, address: u8, frame_rate: FrameRate) -> Result<(), Error<I2C>> {
+        let index = self
+            .index_of(address)
+            .ok_or(LibraryError::Other("There is no camera at that address"))?;
+        self.lend(index, |camera| camera.driver.set_frame_rate(frame_rate))
+            .map_err(unlend_error)?;
+        self.cameras[index].frame_period = frame_period(frame_rate);
+        Ok(())
+    }
+
+    /// Start a measurement on every camera.
+    ///
+    /// The status registers are all read first, so that the commands to start the measurements
+    /// are sent back-to-back. Any new data that hasn't been read yet is discarded. The MLX90642
+    /// only starts measurements on request in step mode.
+    pub fn start_measurements(&mut self) -> Result<(), Error<I2C>> {
+        let bus = self.bus.inner_mut();
+        let mut status_registers: ArrayVec<Option<StatusRegister>, N> = ArrayVec::new();
+        for camera in self.cameras.iter() {
+            status_registers.push(match camera.driver.model() {
+                CameraModel::Mlx90640 | CameraModel::Mlx90641 => {
+                    Some(StatusRegister::from_i2c(bus, camera.address)?)
+                }
+                CameraModel::Mlx90642 => None,
+            });
+        }
+        for (camera, status_register) in self.cameras.iter().zip(status_registers) {
+            match status_register {
+                Some(mut status_register) => {
+                    status_register.reset_new_data();
+                    status_register.set_overwrite_enabled(true);
+                    status_register.set_start_measurement();
+                    status_register.to_i2c(bus, camera.address)?;
+                }
+                None => bus
+                    .write(
+                        camera.address,
+                        &mlx90642::command_bytes(Command::StartMeasurement),
+                    )
+                    .map_err(Error::I2cError)?,
+            }
+        }
+        Ok(())
+    }
+
+    /// Read the next available frame from any of the cameras.
+    ///
+    /// The cameras are checked in order of when their next frame is due, and the first full frame
+    /// found is returned. If none of the cameras have a new frame, `None` is returned. For the
+    /// MLX90640 a subpage may be read without completing a frame, in which case the remaining
+    /// cameras are checked.
+    pub fn next_frame(&mut self) -> Result<Option<ManagedFrame<'_>>, Error<I2C>> {
+        let mut order: ArrayVec<(u64, usize), N> = self
+            .cameras
+            .iter()
+            .enumerate()
+            .map(|(index, camera)| (camera.next_frame_due, index))
+            .collect();
+        // The index breaks ties, so cameras that are due at the same time are checked in the order
+        // they were added.
+        order.sort_unstable();
+        let mut ready = None;
+        for (_, index) in order {
+            let is_complete = self.lend(index, |camera| {
+                let num_pixels = camera.driver.model().num_pixels();
+                camera
+                    .driver
+                    .generate_full_frame_if_ready(&mut camera.temperatures[..num_pixels])
+            });
+            if is_complete.map_err(unlend_error)? {
+                let camera = &mut self.cameras[index];
+                camera.next_frame_due += camera.frame_period;
+                ready = Some(index);
+                break;
+            }
+        }
+        Ok(ready.map(|index| {
+            let camera = &self.cameras[index];
+            let model = camera.driver.model();
+            ManagedFrame {
+                address: camera.address,
+                model,
+                temperatures: &camera.temperatures[..model.num_pixels()],
+                ambient_temperature: camera.driver.ambient_temperature(),
+            }
+        }))
+    }
+
+    fn index_of(&self, address: u8) -> Option<usize> {
+        self.cameras
+            .iter()
+            .position(|camera| camera.address == address)
+    }
+
+    /// Lend the bus to the camera at `index` while running `f`.
+    fn lend<R, F>(&mut self, index: usize, f: F) -> R
+    where
+        F: FnOnce(&mut ManagedCamera<I2C>) -> R,
+    {
+        let camera = &mut self.cameras[index];
+        mem::swap(&mut self.bus, camera.driver.bus_mut());
+        let result = f(camera);
+        mem::swap(&mut self.bus, camera.driver.bus_mut());
+        result
+    }
+}
+
+/// The time between frames at `frame_rate`, in microseconds.
+fn frame_period(frame_rate: FrameRate) -> u64 {
+    let period: Duration = frame_rate.into();
+    period.as_micros() as u64
+}
+
+/// Convert an error from a camera driver using a [`LentBus`] to an error for the bus itself.
+fn unlend_error<I2C: i2c::ErrorType>(error: Error<LentBus<I2C>>) -> Error<I2C> {
+    match error {
+        Error::I2cError(err) => Error::I2cError(err),
+        Error::LibraryError(err) => Error::LibraryError(err),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use mlx9064x_test_data::*;
+
+    use crate::mlx90642::Mlx90642;
+    use crate::{AnyCameraDriver, Error, FrameRate, LibraryError};
+
+    use super::{CameraManager, CameraModel};
+
+    type Bus = SharedMockBus<
+        MockCameraBus<MLX90640_RAM_LENGTH>,
+        SharedMockBus<MockCameraBus<MLX90641_RAM_LENGTH>, MockMlx90642Bus>,
+    >;
+
+    fn create() -> (
+        MockCameraBus<MLX90640_RAM_LENGTH>,
+        MockCameraBus<MLX90641_RAM_LENGTH>,
+        MockMlx90642Bus,
+        CameraManager<Bus, 3>,
+    ) {
+        let mlx90640 = example_mlx90640_at_address(0x33);
+        let mlx90641 = mock_mlx90641_at_address(0x34);
+        let mlx90642 = mock_mlx90642_at_address(0x66);
+        let bus = SharedMockBus::new(
+            mlx90640.clone(),
+            SharedMockBus::new(mlx90641.clone(), mlx90642.clone()),
+        );
+        let mut manager = CameraManager::new(bus);
+        assert_eq!(manager.add_camera(0x33).unwrap(), CameraModel::Mlx90640);
+        assert_eq!(manager.add_camera(0x34).unwrap(), CameraModel::Mlx90641);
+        assert_eq!(manager.add_camera(0x66).unwrap(), CameraModel::Mlx90642);
+        (mlx90640, mlx90641, mlx90642, manager)
+    }
+
+    #[test]
+    fn add_cameras() {
+        let (_, _, _, mut manager) = create();
+        assert_eq!(manager.len(), 3);
+        assert!(manager.addresses().eq([0x33, 0x34, 0x66]));
+        assert_eq!(manager.model(0x34), Some(CameraModel::Mlx90641));
+        assert_eq!(manager.model(0x35), None);
+        // Full
+        assert!(matches!(
+            manager.add_camera_with_model(0x35, CameraModel::Mlx90641),
+            Err(Error::LibraryError(LibraryError::Other(_)))
+        ));
+        let bus = manager.release();
+        let mut manager: CameraManager<_, 4> = CameraManager::new(bus);
+        manager.add_camera(0x33).unwrap();
+        // Duplicate address
+        assert!(matches!(
+            manager.add_camera_with_model(0x33, CameraModel::Mlx90640),
+            Err(Error::LibraryError(LibraryError::Other(_)))
+        ));
+        // Missing camera
+        assert!(matches!(
+            manager.add_camera_with_model(0x67, CameraModel::Mlx90642),
+            Err(Error::I2cError(MockError::UnknownI2cAddress(0x67)))
+        ));
+        assert_eq!(manager.len(), 1);
+    }
+
+    #[test]
+    fn tagged_frames() {
+        let (mut mlx90640, _, mut mlx90642, mut manager) = create();
+        // Only the MLX90641 mock starts with new data
+        let frame = manager.next_frame().unwrap().unwrap();
+        assert_eq!(frame.address, 0x34);
+        assert_eq!(frame.model, CameraModel::Mlx90641);
+        assert_eq!(frame.temperatures.len(), 192);
+        assert!(manager.next_frame().unwrap().is_none());
+        mlx90642.update_frame(&[36.6; Mlx90642::NUM_PIXELS], 28.0);
+        let frame = manager.next_frame().unwrap().unwrap();
+        assert_eq!(frame.address, 0x66);
+        assert!(frame.temperatures.iter().all(|t| *t == 36.6));
+        assert_eq!(frame.ambient_temperature, Some(28.0));
+        // The MLX90640 needs both subpages for a frame
+        mlx90640.set_data_available(true);
+        assert!(manager.next_frame().unwrap().is_none());
+        mlx90640.update_frame(
+            mlx90640_example_data::FRAME_1_DATA,
+            mlx90640_example_data::FRAME_1_STATUS_REGISTER,
+        );
+        mlx90640.set_data_available(true);
+        let frame = manager.next_frame().unwrap().unwrap();
+        assert_eq!(frame.address, 0x33);
+        assert_eq!(frame.temperatures.len(), 768);
+        assert!(frame.temperatures.iter().all(|t| !t.is_nan()));
+    }
+
+    #[test]
+    fn interleaving() {
+        let (_, mut mlx90641, mut mlx90642, mut manager) = create();
+        manager.set_frame_rate(0x34, FrameRate::Two).unwrap();
+        manager.set_frame_rate(0x66, FrameRate::Sixteen).unwrap();
+        let mut addresses = [0u8; 10];
+        for address in addresses.iter_mut() {
+            mlx90641.set_data_available(true);
+            mlx90642.set_data_available(true);
+            *address = manager.next_frame().unwrap().unwrap().address;
+        }
+        // Both start due at the same time, then the MLX90642 is checked first until it has
+        // produced a half second of frames.
+        assert_eq!(
+            addresses,
+            [0x34, 0x66, 0x66, 0x66, 0x66, 0x66, 0x66, 0x66, 0x66, 0x34]
+        );
+    }
+
+    #[test]
+    fn start_measurements() {
+        let (mlx90640, mlx90641, mlx90642, mut manager) = create();
+        manager
+            .with_camera(0x66, |driver| match driver {
+                AnyCameraDriver::Mlx90642(driver) => driver.set_step_mode(true),
+                _ => unreachable!(),
+            })
+            .unwrap()
+            .unwrap();
+        manager.start_measurements().unwrap();
+        assert_eq!(mlx90642.measurements(), 1);
+        // The start measurement bit is set, and any new data cleared
+        for status in [
+            mlx90640.get(0x8000.into(), 2).unwrap()[1],
+            mlx90641.get(0x8000.into(), 2).unwrap()[1],
+        ] {
+            assert_eq!(status & 0x20, 0x20);
+            assert_eq!(status & 0x08, 0x00);
+        }
+    }
+}
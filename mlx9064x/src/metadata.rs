@@ -0,0 +1,125 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright © 2021 Will Ross
+
+//! Information about each frame processed by the high-level drivers.
+//!
+//! Along with the temperatures, processing a subpage calculates a number of per-frame values that
+//! are useful for diagnosing problems with a camera, like the supply voltage and the ambient
+//! temperature. [`FrameMetadata`] collects those values (and some of the camera settings in effect
+//! at the time) so they can be logged alongside each frame. Frames can also be timestamped with a
+//! user-supplied [`Clock`].
+#[cfg(feature = "std")]
+extern crate std;
+
+use core::time::Duration;
+
+use crate::calculations::RamData;
+use crate::register::{FrameRate, Resolution, Subpage};
+
+/// A source of timestamps for frames.
+///
+/// The timestamps are the time since an arbitrary (but fixed) point, for example when the system
+/// started. Closures returning a [`Duration`] can be used as a clock, and with the `std` feature an
+/// [`Instant`][std::time::Instant] is a clock measuring the time since that instant.
+pub trait Clock {
+    /// The current time.
+    fn now(&mut self) -> Duration;
+}
+
+impl<F> Clock for F
+where
+    F: FnMut() -> Duration,
+{
+    fn now(&mut self) -> Duration {
+        self()
+    }
+}
+
+#[cfg(feature = "std")]
+impl Clock for std::time::Instant {
+    fn now(&mut self) -> Duration {
+        self.elapsed()
+    }
+}
+
+/// Details about a processed subpage of data.
+#[doc = include_str!("katex.html")]
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct FrameMetadata {
+    /// The subpage that was processed.
+    pub subpage: Subpage,
+
+    /// A counter of the subpages processed by a driver.
+    ///
+    /// The first subpage processed is number 0, and every subpage after that increments the
+    /// counter by one.
+    pub sequence: u64,
+
+    /// Whether the destination buffer now holds a complete frame.
+    ///
+    /// See [`FrameAssembler`][crate::frame::FrameAssembler] for details.
+    pub frame_complete: bool,
+
+    /// When the new data was found, if a [`Clock`] was given.
+    pub timestamp: Option<Duration>,
+
+    /// The ambient temperature ($T_a$) calculated for this subpage, in degrees Celsius.
+    pub ambient_temperature: f32,
+
+    /// The pixel supply voltage ($V_{DD}$), in volts.
+    pub v_dd: f32,
+
+    /// The gain parameter ($K_{gain}$) for this subpage.
+    pub gain: f32,
+
+    /// The raw values read from RAM for this subpage, including the gain and compensation pixel.
+    pub ram: RamData,
+
+    /// The compensation pixel value used for this subpage.
+    ///
+    /// This is the raw value from [`ram`][FrameMetadata::ram] after the driver's
+    /// [compensation pixel filter][crate::filter::CompensationPixelFilter] (if there is one).
+    pub compensation_pixel: f32,
+
+    /// The offset subtracted from every pixel's $V_{IR}$ for this subpage.
+    ///
+    /// This is calculated from [`compensation_pixel`][FrameMetadata::compensation_pixel], and is
+    /// `None` if the camera doesn't use temperature gradient compensation. See
+    /// [`compensation_pixel_offset`][crate::calculations::compensation_pixel_offset].
+    pub compensation_pixel_offset: Option<f32>,
+
+    /// The ADC resolution in effect.
+    pub resolution: Resolution,
+
+    /// The frame rate in effect.
+    pub frame_rate: FrameRate,
+}
+
+#[cfg(test)]
+mod test {
+    extern crate std;
+
+    use core::time::Duration;
+
+    use super::Clock;
+
+    #[test]
+    fn closure_clock() {
+        let mut ticks = 0;
+        let mut clock = || {
+            ticks += 1;
+            Duration::from_millis(ticks * 10)
+        };
+        assert_eq!(clock.now(), Duration::from_millis(10));
+        assert_eq!(clock.now(), Duration::from_millis(20));
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn instant_clock() {
+        let mut start = std::time::Instant::now();
+        let first = start.now();
+        assert!(start.now() >= first);
+    }
+}
@@ -6,7 +6,7 @@ use core::slice;
 use arrayvec::ArrayVec;
 use bitvec::array::BitArray;
 use bitvec::slice::BitSlice;
-use embedded_hal::blocking::i2c;
+use embedded_hal::i2c;
 
 // Various floating point operations are not implemented in core, so we use libm to provide them as
 // needed.
@@ -25,10 +25,16 @@ use super::Mlx90640;
 /// The number of corner temperatures an MLX90640 has.
 const NUM_CORNER_TEMPERATURES: usize = 4;
 
-type FlagSlice = BitSlice<usize>;
-type FlagArray = BitArray<[usize; Mlx90640::NUM_PIXELS / usize::BITS as usize]>;
+/// The length of the EEPROM in bytes.
+///
+/// Both cameras use the same size and starting offset for their EEPROM.
+const EEPROM_LENGTH: usize = (EepromAddress::End as usize - EepromAddress::Base as usize + 1) * 2;
+
+type FlagSlice = BitSlice<u32>;
+type FlagArray = BitArray<[u32; Mlx90640::NUM_PIXELS / u32::BITS as usize]>;
 
 #[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Mlx90640Calibration {
     k_v_dd: i16,
 
@@ -54,10 +60,12 @@ pub struct Mlx90640Calibration {
 
     alpha_correction: [f32; NUM_CORNER_TEMPERATURES],
 
+    #[cfg_attr(feature = "serde", serde(with = "crate::util::serde_array"))]
     alpha_pixels: [f32; <Self as CalibrationData>::Camera::NUM_PIXELS],
 
     alpha_cp: [f32; 2],
 
+    #[cfg_attr(feature = "serde", serde(with = "crate::util::serde_array"))]
     offset_reference_pixels: [i16; <Self as CalibrationData>::Camera::NUM_PIXELS],
 
     offset_reference_cp: [i16; 2],
@@ -66,6 +74,7 @@ pub struct Mlx90640Calibration {
 
     k_v_cp: f32,
 
+    #[cfg_attr(feature = "serde", serde(with = "crate::util::serde_array"))]
     k_ta_pixels: [f32; <Self as CalibrationData>::Camera::NUM_PIXELS],
 
     k_ta_cp: f32,
@@ -76,8 +85,10 @@ pub struct Mlx90640Calibration {
 
     interleave_correction_cp: f32,
 
+    #[cfg_attr(feature = "serde", serde(with = "crate::util::serde_array::flags"))]
     failed_pixels: FlagArray,
 
+    #[cfg_attr(feature = "serde", serde(with = "crate::util::serde_array::flags"))]
     outlier_pixels: FlagArray,
 }
 
@@ -405,19 +416,34 @@ impl Mlx90640Calibration {
 
 impl<I2C> FromI2C<I2C> for Mlx90640Calibration
 where
-    I2C: i2c::WriteRead + i2c::Write,
+    I2C: i2c::I2c,
 {
     type Error = Error<I2C>;
     type Ok = Self;
 
     fn from_i2c(bus: &mut I2C, i2c_address: u8) -> Result<Self, Error<I2C>> {
-        // Dump the EEPROM. Both cameras use the same size and starting offset for their EEPROM.
-        const EEPROM_LENGTH: usize =
-            (EepromAddress::End as usize - EepromAddress::Base as usize + 1) * 2;
         let mut eeprom_buf = [0u8; EEPROM_LENGTH];
         let eeprom_base: Address = EepromAddress::Base.into();
         bus.write_read(i2c_address, &eeprom_base.as_bytes(), &mut eeprom_buf)
-            .map_err(Error::I2cWriteReadError)?;
+            .map_err(Error::I2cError)?;
+        Ok(Self::from_data(&eeprom_buf)?)
+    }
+}
+
+#[cfg(feature = "async")]
+impl<I2C> AsyncFromI2C<I2C> for Mlx90640Calibration
+where
+    I2C: embedded_hal_async::i2c::I2c,
+{
+    type Error = Error<I2C>;
+    type Ok = Self;
+
+    async fn from_i2c_async(bus: &mut I2C, i2c_address: u8) -> Result<Self, Error<I2C>> {
+        let mut eeprom_buf = [0u8; EEPROM_LENGTH];
+        let eeprom_base: Address = EepromAddress::Base.into();
+        bus.write_read(i2c_address, &eeprom_base.as_bytes(), &mut eeprom_buf)
+            .await
+            .map_err(Error::I2cError)?;
         Ok(Self::from_data(&eeprom_buf)?)
     }
 }
@@ -1359,4 +1385,21 @@ pub(crate) mod test {
         let failures = datasheet.failed_pixels();
         assert!(!failures.any());
     }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn serde_round_trip() {
+        // Flag a failed pixel and an outlier so that both bit arrays have something in them
+        let mut eeprom_bytes = mlx90640_datasheet_eeprom();
+        const PIXEL_OFFSET: usize = EepromAddress::PixelCalibrationStart.byte_offset();
+        eeprom_bytes[PIXEL_OFFSET + 10 * WORD_SIZE..PIXEL_OFFSET + 11 * WORD_SIZE].fill(0);
+        eeprom_bytes[PIXEL_OFFSET + 20 * WORD_SIZE + 1] |= 1;
+        let calibration = Mlx90640Calibration::from_data(&eeprom_bytes).unwrap();
+        let mut buffer = [0u8; 16 * 1024];
+        let serialized = postcard::to_slice(&calibration, &mut buffer).unwrap();
+        let deserialized: Mlx90640Calibration = postcard::from_bytes(serialized).unwrap();
+        assert_eq!(deserialized, calibration);
+        assert!(deserialized.failed_pixels().iter_flagged().eq([10]));
+        assert!(deserialized.outlier_pixels().iter_flagged().eq([20]));
+    }
 }
@@ -12,9 +12,11 @@ use core::iter;
 use num_traits::Float;
 
 use crate::common::{Address, MelexisCamera, PixelAddressRange};
+use crate::error::LibraryError;
 use crate::register::{AccessPattern, Resolution, Subpage};
 use crate::util::Sealed;
 
+use address::EepromAddress;
 pub use address::RamAddress;
 // Expose PixelAccessPatternCompensation so that it is shown in the documentation.
 pub use eeprom::{Mlx90640Calibration, PixelAccessPatternCompensation};
@@ -43,6 +45,10 @@ impl MelexisCamera for Mlx90640 {
         Mlx90640PixelSubpage::new(access_pattern, subpage)
     }
 
+    fn pixel_in_subpage(subpage: Subpage, access_pattern: AccessPattern, index: usize) -> bool {
+        index < Self::NUM_PIXELS && in_subpage(access_pattern, subpage as usize, index)
+    }
+
     //const T_A_V_BE: Address = RamAddress::AmbientTemperatureVoltageBe.into();
     const T_A_V_BE: Address = Address::new(RamAddress::AmbientTemperatureVoltageBe as u16);
 
@@ -81,6 +87,18 @@ impl MelexisCamera for Mlx90640 {
     const WIDTH: usize = 32;
 
     const NUM_PIXELS: usize = Self::HEIGHT * Self::WIDTH;
+
+    const EEPROM_CONTROL_REGISTER: Address = Address::new(EepromAddress::ControlRegister1 as u16);
+
+    const EEPROM_I2C_CONFIG_REGISTER: Address =
+        Address::new(EepromAddress::I2cConfigRegister as u16);
+
+    const EEPROM_I2C_ADDRESS: Address = Address::new(EepromAddress::I2cAddress as u16);
+
+    fn encode_eeprom_word(_address: Address, value: u16) -> Result<u16, LibraryError> {
+        // The MLX90640 EEPROM has no error correction, words are stored as-is.
+        Ok(value)
+    }
 }
 
 /// An iterator of memory ranges to read from the camera.
@@ -176,25 +194,31 @@ impl Iterator for Mlx90640PixelSubpage {
 
     fn next(&mut self) -> Option<Self::Item> {
         if self.index < Mlx90640::NUM_PIXELS {
-            let row = self.index / Mlx90640::WIDTH;
-            let column = self.index % Mlx90640::WIDTH;
+            let valid = in_subpage(self.access_pattern, self.subpage_num, self.index);
             self.index += 1;
-            Some(match self.access_pattern {
-                AccessPattern::Chess => {
-                    if self.subpage_num == 0 {
-                        row % 2 == column % 2
-                    } else {
-                        row % 2 != column % 2
-                    }
-                }
-                AccessPattern::Interleave => row % 2 == self.subpage_num,
-            })
+            Some(valid)
         } else {
             None
         }
     }
 }
 
+/// Check if the pixel at `index` is part of a subpage (given as a number).
+fn in_subpage(access_pattern: AccessPattern, subpage_num: usize, index: usize) -> bool {
+    let row = index / Mlx90640::WIDTH;
+    let column = index % Mlx90640::WIDTH;
+    match access_pattern {
+        AccessPattern::Chess => {
+            if subpage_num == 0 {
+                row % 2 == column % 2
+            } else {
+                row % 2 != column % 2
+            }
+        }
+        AccessPattern::Interleave => row % 2 == subpage_num,
+    }
+}
+
 #[cfg(test)]
 mod test {
     use core::iter::repeat;
@@ -366,4 +390,25 @@ mod test {
             assert_eq!(subpage == Subpage::One, seq1);
         }
     }
+    #[test]
+    fn pixel_in_subpage() {
+        for access_pattern in [AccessPattern::Chess, AccessPattern::Interleave] {
+            for subpage in [Subpage::Zero, Subpage::One] {
+                let sequence = Mlx90640PixelSubpage::new(access_pattern, subpage);
+                for (index, expected) in sequence.enumerate() {
+                    assert_eq!(
+                        Mlx90640::pixel_in_subpage(subpage, access_pattern, index),
+                        expected,
+                        "pixel {} is incorrect",
+                        index
+                    );
+                }
+                assert!(!Mlx90640::pixel_in_subpage(
+                    subpage,
+                    access_pattern,
+                    Mlx90640::NUM_PIXELS
+                ));
+            }
+        }
+    }
 }
@@ -16,6 +16,18 @@ pub(super) enum EepromAddress {
     /// For calibration purposes, the first 16 words (word size is 16 bits) can be ignored.
     Base = 0x2400,
 
+    /// The value loaded into the control register at power on.
+    ControlRegister1 = 0x240C,
+
+    /// Reserved, but part of the device configuration.
+    ControlRegister2 = 0x240D,
+
+    /// The value loaded into the I²C configuration register at power on.
+    I2cConfigRegister = 0x240E,
+
+    /// The I²C address of the camera, in the lower byte.
+    I2cAddress = 0x240F,
+
     /// The bits 5 through 10 are the offset compensation scaling factor.
     OffsetCompensationScale = 0x2410,
 
@@ -8,7 +8,7 @@ use core::slice;
 use arrayvec::ArrayVec;
 use bitvec::array::BitArray;
 use bitvec::slice::BitSlice;
-use embedded_hal::blocking::i2c;
+use embedded_hal::i2c;
 
 // Various floating point operations are not implemented in core, so we use libm to provide them as
 // needed.
@@ -28,11 +28,17 @@ use super::Mlx90641;
 /// The number of corner temperatures an MLX90641 has.
 const NUM_CORNER_TEMPERATURES: usize = 8;
 
-type FlagSlice = BitSlice<usize>;
-type FlagArray = BitArray<[usize; Mlx90641::NUM_PIXELS / usize::BITS as usize]>;
+/// The length of the EEPROM in bytes.
+///
+/// Both cameras use the same size and starting offset for their EEPROM.
+const EEPROM_LENGTH: usize = (EepromAddress::End as usize - EepromAddress::Base as usize + 1) * 2;
+
+type FlagSlice = BitSlice<u32>;
+type FlagArray = BitArray<[u32; Mlx90641::NUM_PIXELS / u32::BITS as usize]>;
 
 /// MLX90641-specific calibration processing.
 #[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Mlx90641Calibration {
     k_v_dd: i16,
 
@@ -60,24 +66,29 @@ pub struct Mlx90641Calibration {
 
     emissivity: Option<f32>,
 
+    #[cfg_attr(feature = "serde", serde(with = "crate::util::serde_array"))]
     alpha_pixels: [f32; <Self as CalibrationData>::Camera::NUM_PIXELS],
 
     alpha_cp: f32,
 
+    #[cfg_attr(feature = "serde", serde(with = "crate::util::serde_array::nested"))]
     offset_reference_pixels: [[i16; <Self as CalibrationData>::Camera::NUM_PIXELS]; 2],
 
     offset_reference_cp: i16,
 
+    #[cfg_attr(feature = "serde", serde(with = "crate::util::serde_array"))]
     k_v_pixels: [f32; <Self as CalibrationData>::Camera::NUM_PIXELS],
 
     k_v_cp: f32,
 
+    #[cfg_attr(feature = "serde", serde(with = "crate::util::serde_array"))]
     k_ta_pixels: [f32; <Self as CalibrationData>::Camera::NUM_PIXELS],
 
     k_ta_cp: f32,
 
     temperature_gradient_coefficient: Option<f32>,
 
+    #[cfg_attr(feature = "serde", serde(with = "crate::util::serde_array::flags"))]
     failed_pixels: FlagArray,
 }
 
@@ -208,19 +219,34 @@ impl Mlx90641Calibration {
 
 impl<I2C> FromI2C<I2C> for Mlx90641Calibration
 where
-    I2C: i2c::WriteRead + i2c::Write,
+    I2C: i2c::I2c,
 {
     type Error = Error<I2C>;
     type Ok = Self;
 
     fn from_i2c(bus: &mut I2C, i2c_address: u8) -> Result<Self, Error<I2C>> {
-        // Dump the EEPROM. Both cameras use the same size and starting offset for their EEPROM.
-        const EEPROM_LENGTH: usize =
-            (EepromAddress::End as usize - EepromAddress::Base as usize + 1) * 2;
         let mut eeprom_buf = [0u8; EEPROM_LENGTH];
         let eeprom_base: Address = EepromAddress::Base.into();
         bus.write_read(i2c_address, &eeprom_base.as_bytes(), &mut eeprom_buf)
-            .map_err(Error::I2cWriteReadError)?;
+            .map_err(Error::I2cError)?;
+        Ok(Self::from_data(&eeprom_buf)?)
+    }
+}
+
+#[cfg(feature = "async")]
+impl<I2C> AsyncFromI2C<I2C> for Mlx90641Calibration
+where
+    I2C: embedded_hal_async::i2c::I2c,
+{
+    type Error = Error<I2C>;
+    type Ok = Self;
+
+    async fn from_i2c_async(bus: &mut I2C, i2c_address: u8) -> Result<Self, Error<I2C>> {
+        let mut eeprom_buf = [0u8; EEPROM_LENGTH];
+        let eeprom_base: Address = EepromAddress::Base.into();
+        bus.write_read(i2c_address, &eeprom_base.as_bytes(), &mut eeprom_buf)
+            .await
+            .map_err(Error::I2cError)?;
         Ok(Self::from_data(&eeprom_buf)?)
     }
 }
@@ -883,4 +909,28 @@ pub(crate) mod test {
         assert_eq!(outlier_iter.next(), Some(PIXEL_NUM));
         assert_eq!(outlier_iter.next(), None);
     }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn serde_round_trip() {
+        // Flag pixel 7, which means zeroing it in every per-pixel table
+        const PIXEL_OFFSET: usize = 7 * WORD_SIZE;
+        let mut eeprom_bytes = mlx90641_datasheet_eeprom();
+        for start in [
+            EepromAddress::PixelOffsetSubpage0Start,
+            EepromAddress::PixelSensitivityStart,
+            EepromAddress::PixelConstantsStart,
+            EepromAddress::PixelOffsetSubpage1Start,
+        ] {
+            let offset = start.byte_offset() + PIXEL_OFFSET;
+            eeprom_bytes[offset..offset + WORD_SIZE].fill(0);
+        }
+        let calibration =
+            Mlx90641Calibration::from_data(&eeprom_bytes).expect("EEPROM data should be parsed.");
+        let mut buffer = [0u8; 8 * 1024];
+        let serialized = postcard::to_slice(&calibration, &mut buffer).unwrap();
+        let deserialized: Mlx90641Calibration = postcard::from_bytes(serialized).unwrap();
+        assert_eq!(deserialized, calibration);
+        assert!(deserialized.failed_pixels().iter_flagged().eq([7]));
+    }
 }
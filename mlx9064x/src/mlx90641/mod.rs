@@ -14,9 +14,11 @@ use core::cmp::Ordering;
 use core::iter;
 
 use crate::common::{Address, MelexisCamera, PixelAddressRange};
+use crate::error::{Error, LibraryError};
 use crate::register::{AccessPattern, Resolution, Subpage};
 use crate::util::Sealed;
 
+use address::EepromAddress;
 pub use address::RamAddress;
 pub use eeprom::Mlx90641Calibration;
 
@@ -46,6 +48,10 @@ impl MelexisCamera for Mlx90641 {
         iter::repeat(true).take(Self::NUM_PIXELS)
     }
 
+    fn pixel_in_subpage(_subpage: Subpage, _access_pattern: AccessPattern, index: usize) -> bool {
+        index < Self::NUM_PIXELS
+    }
+
     const T_A_V_BE: Address = Address::new(RamAddress::AmbientTemperatureVoltageBe as u16);
 
     const T_A_PTAT: Address = Address::new(RamAddress::AmbientTemperatureVoltage as u16);
@@ -84,6 +90,23 @@ impl MelexisCamera for Mlx90641 {
     const WIDTH: usize = 16;
 
     const NUM_PIXELS: usize = Self::HEIGHT * Self::WIDTH;
+
+    const EEPROM_CONTROL_REGISTER: Address = Address::new(EepromAddress::ControlRegister1 as u16);
+
+    const EEPROM_I2C_CONFIG_REGISTER: Address =
+        Address::new(EepromAddress::I2cConfigRegister as u16);
+
+    const EEPROM_I2C_ADDRESS: Address = Address::new(EepromAddress::I2cAddress as u16);
+
+    fn encode_eeprom_word(address: Address, value: u16) -> Result<u16, LibraryError> {
+        // The device configuration words (the first 16) are stored as-is, only the calibration
+        // data has a Hamming code.
+        if address < EepromAddress::OffsetCompensationScale.into() {
+            Ok(value)
+        } else {
+            hamming::add_checksum(value)
+        }
+    }
 }
 
 #[derive(Clone, Copy, Debug)]
@@ -145,11 +168,75 @@ impl iter::Iterator for SubpageInterleave {
     }
 }
 
+/// Convert an emissivity to the value stored in the MLX90641 EEPROM (without the Hamming code).
+///
+/// The emissivity must be greater than 0 and at most 1.
+fn emissivity_to_eeprom(emissivity: f32) -> Result<u16, LibraryError> {
+    if emissivity > 0.0 && emissivity <= 1.0 {
+        // Stored as a signed 11-bit value, scaled by 2^9. Valid emissivities are always positive.
+        Ok((emissivity * 9f32.exp2()).round() as u16)
+    } else {
+        Err(LibraryError::InvalidData(
+            "Emissivity must be greater than 0 and no more than 1",
+        ))
+    }
+}
+
+/// Store a new default emissivity in an MLX90641's EEPROM.
+///
+/// The new value is used by [`Mlx90641Calibration`] the next time the calibration data is loaded.
+/// The emissivity is rounded to the nearest 1/512. See the [`eeprom`][crate::eeprom] module for
+/// how the EEPROM is written.
+pub fn store_emissivity<I2C, D>(
+    bus: &mut I2C,
+    delay: &mut D,
+    i2c_address: u8,
+    emissivity: f32,
+) -> Result<(), Error<I2C>>
+where
+    I2C: embedded_hal::i2c::I2c,
+    D: embedded_hal::delay::DelayNs,
+{
+    let value = emissivity_to_eeprom(emissivity)?;
+    let address = EepromAddress::Emissivity.into();
+    crate::eeprom::write_word::<Mlx90641, _, _>(bus, delay, i2c_address, address, value)
+}
+
+/// The async version of [`store_emissivity`].
+#[cfg(feature = "async")]
+pub async fn store_emissivity_async<I2C, D>(
+    bus: &mut I2C,
+    delay: &mut D,
+    i2c_address: u8,
+    emissivity: f32,
+) -> Result<(), Error<I2C>>
+where
+    I2C: embedded_hal_async::i2c::I2c,
+    D: embedded_hal_async::delay::DelayNs,
+{
+    let value = emissivity_to_eeprom(emissivity)?;
+    let address = EepromAddress::Emissivity.into();
+    crate::eeprom::write_word_async::<Mlx90641, _, _>(bus, delay, i2c_address, address, value).await
+}
+
 #[cfg(test)]
 mod test {
-    use crate::{AccessPattern, MelexisCamera, Resolution, Subpage};
+    use embedded_hal::delay::DelayNs;
+
+    use mlx9064x_test_data::mock_mlx90641_at_address;
+
+    use crate::common::FromI2C;
+    use crate::{AccessPattern, CalibrationData, MelexisCamera, Resolution, Subpage};
+
+    use super::{Mlx90641, Mlx90641Calibration};
 
-    use super::Mlx90641;
+    const ADDRESS: u8 = 0x33;
+
+    struct NoDelay;
+
+    impl DelayNs for NoDelay {
+        fn delay_ns(&mut self, _ns: u32) {}
+    }
 
     #[test]
     fn pixels_in_subpage() {
@@ -167,6 +254,16 @@ mod test {
             Mlx90641::NUM_PIXELS,
             "Ever pixels needs a value for pixels_in_subpage()"
         );
+        assert!(Mlx90641::pixel_in_subpage(
+            Subpage::One,
+            AccessPattern::Interleave,
+            Mlx90641::NUM_PIXELS - 1
+        ));
+        assert!(!Mlx90641::pixel_in_subpage(
+            Subpage::One,
+            AccessPattern::Interleave,
+            Mlx90641::NUM_PIXELS
+        ));
     }
 
     #[test]
@@ -188,4 +285,16 @@ mod test {
             )
         }
     }
+
+    #[test]
+    fn store_emissivity() {
+        let mut bus = mock_mlx90641_at_address(ADDRESS);
+        super::store_emissivity(&mut bus, &mut NoDelay, ADDRESS, 0.95).unwrap();
+        let calibration = Mlx90641Calibration::from_i2c(&mut bus, ADDRESS).unwrap();
+        assert_eq!(calibration.emissivity(), Some(486.0 / 512.0));
+        super::store_emissivity(&mut bus, &mut NoDelay, ADDRESS, 1.0).unwrap();
+        let calibration = Mlx90641Calibration::from_i2c(&mut bus, ADDRESS).unwrap();
+        assert_eq!(calibration.emissivity(), Some(1.0));
+        assert!(super::store_emissivity(&mut bus, &mut NoDelay, ADDRESS, 1.5).is_err());
+    }
 }
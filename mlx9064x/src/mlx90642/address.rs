@@ -0,0 +1,76 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright © 2021 Will Ross
+use core::convert::TryFrom;
+
+use num_enum::{IntoPrimitive, TryFromPrimitive, TryFromPrimitiveError};
+
+use crate::common::Address;
+
+/// Significant addresses in the MLX90642's memory map.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, PartialOrd, Ord, IntoPrimitive, TryFromPrimitive)]
+#[repr(u16)]
+pub enum RamAddress {
+    /// The frame rate, using the same values as the MLX90640 and MLX90641 [frame
+    /// rates][crate::FrameRate].
+    RefreshRate = 0x11F2,
+
+    /// The object emissivity used by the camera, as a fraction of 2<sup>14</sup>.
+    Emissivity = 0x11F4,
+
+    /// The reflected temperature used by the camera, in units of 0.02 °C.
+    ReflectedTemperature = 0x11F6,
+
+    /// 0 for continuous measurements, 1 for step (on demand) mode.
+    MeasurementMode = 0x11F8,
+
+    /// The start of the three word device ID.
+    DeviceId = 0x2407,
+
+    /// The start of the object temperatures, in row-major order.
+    ///
+    /// Each temperature is a signed word in units of 0.02 °C.
+    ObjectTemperatureStart = 0x342C,
+
+    /// The last object temperature.
+    ObjectTemperatureEnd = 0x372B,
+
+    /// The sensor's ambient temperature, in units of 0.02 °C.
+    AmbientTemperature = 0x3A2C,
+
+    /// The status of measurements. See [`Status`][super::Status] for the individual flags.
+    Status = 0x3C14,
+}
+
+impl TryFrom<Address> for RamAddress {
+    type Error = TryFromPrimitiveError<RamAddress>;
+
+    fn try_from(value: Address) -> Result<Self, Self::Error> {
+        let raw_address: u16 = value.into();
+        Self::try_from(raw_address)
+    }
+}
+
+impl From<RamAddress> for Address {
+    fn from(ram_address: RamAddress) -> Self {
+        let raw_address: u16 = ram_address.into();
+        raw_address.into()
+    }
+}
+
+/// Commands that the MLX90642 accepts, written to it as a single word.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, IntoPrimitive, TryFromPrimitive)]
+#[repr(u16)]
+pub enum Command {
+    /// Start a measurement when in step mode.
+    StartMeasurement = 0x1801,
+
+    /// Put the camera into its low power sleep mode.
+    Sleep = 0x3630,
+
+    /// Wake the camera up from sleep mode.
+    WakeUp = 0x5701,
+
+    /// Write a configuration word. This command is followed by the address of the word, then the
+    /// new value.
+    Configure = 0x3A2E,
+}
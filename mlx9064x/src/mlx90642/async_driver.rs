@@ -0,0 +1,389 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright © 2021 Will Ross
+
+use core::time::Duration;
+
+use embedded_hal_async::delay::DelayNs;
+use embedded_hal_async::i2c::I2c;
+
+use crate::common::Address;
+use crate::error::Error;
+use crate::register::FrameRate;
+use crate::util::{polls_within, saturating_micros, WORD_SIZE};
+
+use super::*;
+
+/// The async version of the MLX90642 [`Driver`], using `embedded-hal-async`.
+///
+/// The API is the same as `Driver`, except the methods that access the camera are `async` and a
+/// [`DelayNs`] is used to wait between status checks in [`synchronize`][Self::synchronize].
+#[derive(Clone, Debug)]
+pub struct AsyncDriver<I2C, D> {
+    /// The I²C bus this camera is accessible on.
+    bus: I2C,
+
+    /// Used to wait without blocking while the camera is busy.
+    delay: D,
+
+    /// The I²C address this camera is accessible at.
+    address: u8,
+
+    /// Buffer for reading temperatures off of the camera.
+    pixel_buffer: [u8; Mlx90642::NUM_PIXELS * WORD_SIZE],
+
+    /// The ambient temperature from the last frame that was read.
+    ambient_temperature: Option<f32>,
+}
+
+impl<I2C, D> AsyncDriver<I2C, D>
+where
+    I2C: I2c,
+    D: DelayNs,
+{
+    /// Create an `AsyncDriver` for the camera at the given I²C address.
+    ///
+    /// The status of the camera is read to check that it is present.
+    pub async fn new(bus: I2C, delay: D, address: u8) -> Result<Self, Error<I2C>> {
+        let mut driver = Self {
+            bus,
+            delay,
+            address,
+            pixel_buffer: [0u8; Mlx90642::NUM_PIXELS * WORD_SIZE],
+            ambient_temperature: None,
+        };
+        driver.status().await?;
+        Ok(driver)
+    }
+
+    async fn read_words(
+        &mut self,
+        address: RamAddress,
+        words: &mut [u8],
+    ) -> Result<(), Error<I2C>> {
+        let address = Address::from(address);
+        self.bus
+            .write_read(self.address, &address.as_bytes(), words)
+            .await
+            .map_err(Error::I2cError)
+    }
+
+    async fn read_word(&mut self, address: RamAddress) -> Result<u16, Error<I2C>> {
+        let mut word = [0u8; WORD_SIZE];
+        self.read_words(address, &mut word).await?;
+        Ok(u16::from_be_bytes(word))
+    }
+
+    async fn configure(&mut self, address: RamAddress, value: u16) -> Result<(), Error<I2C>> {
+        self.bus
+            .write(self.address, &configure_bytes(address, value))
+            .await
+            .map_err(Error::I2cError)
+    }
+
+    async fn command(&mut self, command: Command) -> Result<(), Error<I2C>> {
+        self.bus
+            .write(self.address, &command_bytes(command))
+            .await
+            .map_err(Error::I2cError)
+    }
+
+    /// Read the status word from the camera.
+    pub async fn status(&mut self) -> Result<Status, Error<I2C>> {
+        self.read_word(RamAddress::Status).await.map(Status::from)
+    }
+
+    /// Read the camera's device ID.
+    pub async fn device_id(&mut self) -> Result<[u16; 3], Error<I2C>> {
+        let mut bytes = [0u8; 3 * WORD_SIZE];
+        self.read_words(RamAddress::DeviceId, &mut bytes).await?;
+        let mut id = [0u16; 3];
+        for (word, bytes) in id.iter_mut().zip(bytes.chunks_exact(WORD_SIZE)) {
+            *word = u16::from_be_bytes([bytes[0], bytes[1]]);
+        }
+        Ok(id)
+    }
+
+    /// Read the frame rate from the camera.
+    pub async fn frame_rate(&mut self) -> Result<FrameRate, Error<I2C>> {
+        let raw = self.read_word(RamAddress::RefreshRate).await?;
+        Ok(FrameRate::from_raw(raw)?)
+    }
+
+    /// Set the camera's frame rate.
+    pub async fn set_frame_rate(&mut self, frame_rate: FrameRate) -> Result<(), Error<I2C>> {
+        self.configure(RamAddress::RefreshRate, frame_rate.as_raw())
+            .await
+    }
+
+    /// Read the emissivity the camera is using for its temperature calculations.
+    pub async fn emissivity(&mut self) -> Result<f32, Error<I2C>> {
+        Ok(emissivity_from_raw(
+            self.read_word(RamAddress::Emissivity).await?,
+        ))
+    }
+
+    /// Set the emissivity the camera uses for its temperature calculations.
+    pub async fn set_emissivity(&mut self, emissivity: f32) -> Result<(), Error<I2C>> {
+        let raw = emissivity_to_raw(emissivity)?;
+        self.configure(RamAddress::Emissivity, raw).await
+    }
+
+    /// Read the reflected temperature the camera is using, in degrees Celsius.
+    pub async fn reflected_temperature(&mut self) -> Result<f32, Error<I2C>> {
+        let raw = self.read_word(RamAddress::ReflectedTemperature).await?;
+        Ok(temperature_from_raw(raw as i16))
+    }
+
+    /// Set the reflected temperature the camera uses, in degrees Celsius.
+    pub async fn set_reflected_temperature(&mut self, temperature: f32) -> Result<(), Error<I2C>> {
+        let raw = temperature_to_raw(temperature)?;
+        self.configure(RamAddress::ReflectedTemperature, raw as u16)
+            .await
+    }
+
+    /// Check if the camera is in step mode.
+    pub async fn step_mode(&mut self) -> Result<bool, Error<I2C>> {
+        Ok(self.read_word(RamAddress::MeasurementMode).await? != 0)
+    }
+
+    /// Enable (or disable) step mode.
+    pub async fn set_step_mode(&mut self, step_mode: bool) -> Result<(), Error<I2C>> {
+        self.configure(RamAddress::MeasurementMode, u16::from(step_mode))
+            .await
+    }
+
+    /// Start a measurement while in step mode.
+    pub async fn start_measurement(&mut self) -> Result<(), Error<I2C>> {
+        self.command(Command::StartMeasurement).await
+    }
+
+    /// Put the camera into its low power sleep mode.
+    pub async fn sleep(&mut self) -> Result<(), Error<I2C>> {
+        self.command(Command::Sleep).await
+    }
+
+    /// Wake the camera up from sleep mode.
+    pub async fn wake_up(&mut self) -> Result<(), Error<I2C>> {
+        self.command(Command::WakeUp).await
+    }
+
+    /// Check if there is a new frame of temperatures available.
+    pub async fn data_available(&mut self) -> Result<bool, Error<I2C>> {
+        Ok(self.status().await?.data_ready())
+    }
+
+    /// Clear the data ready flag, signaling to the camera that the controller has read the frame.
+    pub async fn reset_data_available(&mut self) -> Result<(), Error<I2C>> {
+        let mut status = self.status().await?;
+        status.reset_data_ready();
+        self.configure(RamAddress::Status, status.into()).await
+    }
+
+    /// The ambient temperature of the camera from the last frame that was read.
+    pub fn ambient_temperature(&self) -> Option<f32> {
+        self.ambient_temperature
+    }
+
+    /// Copy the camera's current temperatures into `destination`.
+    ///
+    /// This function does *not* check if there is new data, it just copies the current frame.
+    pub async fn generate_image_to(&mut self, destination: &mut [f32]) -> Result<(), Error<I2C>> {
+        let object_address = Address::from(RamAddress::ObjectTemperatureStart);
+        self.bus
+            .write_read(
+                self.address,
+                &object_address.as_bytes(),
+                &mut self.pixel_buffer,
+            )
+            .await
+            .map_err(Error::I2cError)?;
+        let ambient = self.read_word(RamAddress::AmbientTemperature).await?;
+        self.ambient_temperature = Some(temperature_from_raw(ambient as i16));
+        temperatures_from_bytes(&self.pixel_buffer, destination);
+        Ok(())
+    }
+
+    /// Copy the camera's temperatures into `destination` if there is a new frame.
+    ///
+    /// The data ready flag is cleared after the frame has been copied. The `Ok` value is whether
+    /// or not there was a new frame.
+    pub async fn generate_image_if_ready(
+        &mut self,
+        destination: &mut [f32],
+    ) -> Result<bool, Error<I2C>> {
+        let mut status = self.status().await?;
+        if status.data_ready() {
+            self.generate_image_to(destination).await?;
+            status.reset_data_ready();
+            self.configure(RamAddress::Status, status.into()).await?;
+            Ok(true)
+        } else {
+            Ok(false)
+        }
+    }
+
+    /// Synchronize with the camera's frame timing.
+    ///
+    /// Any new data is ignored, and then this function waits until the next frame is ready,
+    /// checking the status every [poll interval][FrameRate::poll_interval]. In step mode a
+    /// measurement is started first.
+    pub async fn synchronize(&mut self) -> Result<(), Error<I2C>> {
+        let poll_interval = self.frame_rate().await?.poll_interval();
+        self.synchronize_polling(poll_interval, None).await
+    }
+
+    /// Synchronize with the camera's frame timing, giving up after `timeout`.
+    ///
+    /// This works like [`synchronize`][Self::synchronize], but if there is still no new data
+    /// after `timeout`, [`LibraryError::Timeout`] is returned.
+    pub async fn synchronize_with_timeout(&mut self, timeout: Duration) -> Result<(), Error<I2C>> {
+        let poll_interval = self.frame_rate().await?.poll_interval();
+        let max_polls = polls_within(timeout, poll_interval);
+        self.synchronize_polling(poll_interval, Some(max_polls))
+            .await
+    }
+
+    /// Synchronize with the camera's frame timing, giving up after checking the status `max_polls`
+    /// times.
+    pub async fn synchronize_with_poll_limit(&mut self, max_polls: u32) -> Result<(), Error<I2C>> {
+        let poll_interval = self.frame_rate().await?.poll_interval();
+        self.synchronize_polling(poll_interval, Some(max_polls))
+            .await
+    }
+
+    /// Take a single frame in step mode, writing the temperatures into `destination`.
+    ///
+    /// See [`Driver::measure_frame`][super::Driver::measure_frame] for details.
+    pub async fn measure_frame(&mut self, destination: &mut [f32]) -> Result<(), Error<I2C>> {
+        if !self.step_mode().await? {
+            self.set_step_mode(true).await?;
+        }
+        let frame_rate = self.frame_rate().await?;
+        let poll_interval = frame_rate.poll_interval();
+        let max_polls = polls_within(Duration::from(frame_rate) * 2, poll_interval);
+        self.synchronize_polling(poll_interval, Some(max_polls))
+            .await?;
+        self.generate_image_to(destination).await?;
+        self.reset_data_available().await
+    }
+
+    /// Start a new measurement, then wait for it, checking the status at most `max_polls` times
+    /// (or forever if it is `None`).
+    async fn synchronize_polling(
+        &mut self,
+        poll_interval: Duration,
+        max_polls: Option<u32>,
+    ) -> Result<(), Error<I2C>> {
+        let poll_interval = saturating_micros(poll_interval);
+        self.reset_data_available().await?;
+        if self.step_mode().await? {
+            self.start_measurement().await?;
+        }
+        let mut polls = 0;
+        while !self.data_available().await? {
+            if max_polls.is_some_and(|max_polls| polls >= max_polls) {
+                return Err(LibraryError::Timeout.into());
+            }
+            self.delay.delay_us(poll_interval).await;
+            polls += 1;
+        }
+        Ok(())
+    }
+
+    /// The height of the thermal image, in pixels.
+    pub fn height(&self) -> usize {
+        Mlx90642::HEIGHT
+    }
+
+    /// The width of the thermal image, in pixels.
+    pub fn width(&self) -> usize {
+        Mlx90642::WIDTH
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use embassy_futures::block_on;
+    use embedded_hal_async::delay::DelayNs;
+
+    use mlx9064x_test_data::{mock_mlx90642_at_address, MockMlx90642Bus};
+
+    use crate::mlx90642::{self, Mlx90642};
+    use crate::FrameRate;
+
+    const ADDRESS: u8 = mlx90642::DEFAULT_ADDRESS;
+
+    /// A delay that makes a new frame available after a number of delays.
+    struct MockDelay {
+        bus: MockMlx90642Bus,
+        delays_until_ready: usize,
+        delay_count: usize,
+    }
+
+    impl DelayNs for MockDelay {
+        async fn delay_ns(&mut self, _ns: u32) {
+            self.delay_count += 1;
+            if self.delay_count == self.delays_until_ready {
+                self.bus.set_data_available(true);
+            }
+        }
+    }
+
+    fn create(
+        delays_until_ready: usize,
+    ) -> (
+        MockMlx90642Bus,
+        mlx90642::AsyncDriver<MockMlx90642Bus, MockDelay>,
+    ) {
+        let mocked = mock_mlx90642_at_address(ADDRESS);
+        let delay = MockDelay {
+            bus: mocked.clone(),
+            delays_until_ready,
+            delay_count: 0,
+        };
+        let cam = block_on(mlx90642::AsyncDriver::new(mocked.clone(), delay, ADDRESS)).unwrap();
+        (mocked, cam)
+    }
+
+    #[test]
+    fn generate_image() {
+        let (mut mocked, mut cam) = create(0);
+        let mut temperatures = [f32::NAN; Mlx90642::NUM_PIXELS];
+        assert!(!block_on(cam.generate_image_if_ready(&mut temperatures)).unwrap());
+        mocked.update_frame(&[36.6; Mlx90642::NUM_PIXELS], 28.0);
+        assert!(block_on(cam.generate_image_if_ready(&mut temperatures)).unwrap());
+        assert!(temperatures.iter().all(|t| *t == 36.6));
+        assert_eq!(cam.ambient_temperature(), Some(28.0));
+        assert!(!block_on(cam.data_available()).unwrap());
+    }
+
+    #[test]
+    fn configuration() {
+        let (_mocked, mut cam) = create(0);
+        block_on(cam.set_frame_rate(FrameRate::Four)).unwrap();
+        assert_eq!(block_on(cam.frame_rate()).unwrap(), FrameRate::Four);
+        block_on(cam.set_emissivity(0.95)).unwrap();
+        assert!((block_on(cam.emissivity()).unwrap() - 0.95).abs() < 1e-4);
+        block_on(cam.set_reflected_temperature(40.0)).unwrap();
+        assert_eq!(block_on(cam.reflected_temperature()).unwrap(), 40.0);
+    }
+
+    #[test]
+    fn synchronize() {
+        let (mocked, mut cam) = create(3);
+        mocked.clone().set_data_available(true);
+        block_on(cam.synchronize()).unwrap();
+        assert_eq!(cam.delay.delay_count, 3);
+        assert_eq!(mocked.measurements(), 0);
+    }
+
+    #[test]
+    fn measure_frame() {
+        let (mocked, mut cam) = create(0);
+        let mut temperatures = [f32::NAN; Mlx90642::NUM_PIXELS];
+        block_on(cam.measure_frame(&mut temperatures)).unwrap();
+        assert!(block_on(cam.step_mode()).unwrap());
+        assert_eq!(mocked.measurements(), 1);
+        assert!(temperatures.iter().all(|t| !t.is_nan()));
+    }
+}
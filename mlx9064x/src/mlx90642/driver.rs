@@ -0,0 +1,456 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright © 2021 Will Ross
+
+use core::time::Duration;
+
+use embedded_hal::delay::DelayNs;
+use embedded_hal::i2c;
+
+use crate::common::Address;
+use crate::error::Error;
+use crate::register::FrameRate;
+use crate::util::{polls_within, saturating_micros, WORD_SIZE};
+
+use super::*;
+
+/// A high-level driver for the MLX90642.
+///
+/// As the camera calculates the temperatures itself, this driver is mostly a thin wrapper over the
+/// camera's configuration words and temperature readout. The API follows
+/// [`CameraDriver`][crate::CameraDriver] where the cameras overlap.
+#[derive(Clone, Debug)]
+pub struct Driver<I2C> {
+    /// The I²C bus this camera is accessible on.
+    bus: I2C,
+
+    /// The I²C address this camera is accessible at.
+    address: u8,
+
+    /// Buffer for reading temperatures off of the camera.
+    pixel_buffer: [u8; Mlx90642::NUM_PIXELS * WORD_SIZE],
+
+    /// The ambient temperature from the last frame that was read.
+    ambient_temperature: Option<f32>,
+}
+
+impl<I2C> Driver<I2C>
+where
+    I2C: i2c::I2c,
+{
+    /// Create a `Driver` for the camera at the given I²C address.
+    ///
+    /// The status of the camera is read to check that it is present. The default address is
+    /// [`DEFAULT_ADDRESS`].
+    pub fn new(bus: I2C, address: u8) -> Result<Self, Error<I2C>> {
+        let mut driver = Self::from_parts(bus, address);
+        driver.status()?;
+        Ok(driver)
+    }
+
+    /// Create a `Driver` without checking that the camera is present.
+    pub(crate) fn from_parts(bus: I2C, address: u8) -> Self {
+        Self {
+            bus,
+            address,
+            pixel_buffer: [0u8; Mlx90642::NUM_PIXELS * WORD_SIZE],
+            ambient_temperature: None,
+        }
+    }
+
+    /// Access the bus the camera is on.
+    pub(crate) fn bus_mut(&mut self) -> &mut I2C {
+        &mut self.bus
+    }
+
+    fn read_words(&mut self, address: RamAddress, words: &mut [u8]) -> Result<(), Error<I2C>> {
+        let address = Address::from(address);
+        self.bus
+            .write_read(self.address, &address.as_bytes(), words)
+            .map_err(Error::I2cError)
+    }
+
+    fn read_word(&mut self, address: RamAddress) -> Result<u16, Error<I2C>> {
+        let mut word = [0u8; WORD_SIZE];
+        self.read_words(address, &mut word)?;
+        Ok(u16::from_be_bytes(word))
+    }
+
+    fn configure(&mut self, address: RamAddress, value: u16) -> Result<(), Error<I2C>> {
+        self.bus
+            .write(self.address, &configure_bytes(address, value))
+            .map_err(Error::I2cError)
+    }
+
+    fn command(&mut self, command: Command) -> Result<(), Error<I2C>> {
+        self.bus
+            .write(self.address, &command_bytes(command))
+            .map_err(Error::I2cError)
+    }
+
+    /// Read the status word from the camera.
+    pub fn status(&mut self) -> Result<Status, Error<I2C>> {
+        self.read_word(RamAddress::Status).map(Status::from)
+    }
+
+    /// Read the camera's device ID.
+    pub fn device_id(&mut self) -> Result<[u16; 3], Error<I2C>> {
+        let mut bytes = [0u8; 3 * WORD_SIZE];
+        self.read_words(RamAddress::DeviceId, &mut bytes)?;
+        let mut id = [0u16; 3];
+        for (word, bytes) in id.iter_mut().zip(bytes.chunks_exact(WORD_SIZE)) {
+            *word = u16::from_be_bytes([bytes[0], bytes[1]]);
+        }
+        Ok(id)
+    }
+
+    /// Read the frame rate from the camera.
+    pub fn frame_rate(&mut self) -> Result<FrameRate, Error<I2C>> {
+        let raw = self.read_word(RamAddress::RefreshRate)?;
+        Ok(FrameRate::from_raw(raw)?)
+    }
+
+    /// Set the camera's frame rate.
+    pub fn set_frame_rate(&mut self, frame_rate: FrameRate) -> Result<(), Error<I2C>> {
+        self.configure(RamAddress::RefreshRate, frame_rate.as_raw())
+    }
+
+    /// Read the emissivity the camera is using for its temperature calculations.
+    pub fn emissivity(&mut self) -> Result<f32, Error<I2C>> {
+        Ok(emissivity_from_raw(self.read_word(RamAddress::Emissivity)?))
+    }
+
+    /// Set the emissivity the camera uses for its temperature calculations.
+    pub fn set_emissivity(&mut self, emissivity: f32) -> Result<(), Error<I2C>> {
+        let raw = emissivity_to_raw(emissivity)?;
+        self.configure(RamAddress::Emissivity, raw)
+    }
+
+    /// Read the reflected temperature the camera is using, in degrees Celsius.
+    pub fn reflected_temperature(&mut self) -> Result<f32, Error<I2C>> {
+        let raw = self.read_word(RamAddress::ReflectedTemperature)?;
+        Ok(temperature_from_raw(raw as i16))
+    }
+
+    /// Set the reflected temperature the camera uses, in degrees Celsius.
+    pub fn set_reflected_temperature(&mut self, temperature: f32) -> Result<(), Error<I2C>> {
+        let raw = temperature_to_raw(temperature)?;
+        self.configure(RamAddress::ReflectedTemperature, raw as u16)
+    }
+
+    /// Check if the camera is in step mode.
+    ///
+    /// In step mode the camera only measures a frame when it is sent
+    /// [`start_measurement`][Self::start_measurement]. The default is continuous measurement.
+    pub fn step_mode(&mut self) -> Result<bool, Error<I2C>> {
+        Ok(self.read_word(RamAddress::MeasurementMode)? != 0)
+    }
+
+    /// Enable (or disable) step mode.
+    pub fn set_step_mode(&mut self, step_mode: bool) -> Result<(), Error<I2C>> {
+        self.configure(RamAddress::MeasurementMode, u16::from(step_mode))
+    }
+
+    /// Start a measurement while in step mode.
+    pub fn start_measurement(&mut self) -> Result<(), Error<I2C>> {
+        self.command(Command::StartMeasurement)
+    }
+
+    /// Put the camera into its low power sleep mode.
+    pub fn sleep(&mut self) -> Result<(), Error<I2C>> {
+        self.command(Command::Sleep)
+    }
+
+    /// Wake the camera up from sleep mode.
+    pub fn wake_up(&mut self) -> Result<(), Error<I2C>> {
+        self.command(Command::WakeUp)
+    }
+
+    /// Check if there is a new frame of temperatures available.
+    pub fn data_available(&mut self) -> Result<bool, Error<I2C>> {
+        Ok(self.status()?.data_ready())
+    }
+
+    /// Clear the data ready flag, signaling to the camera that the controller has read the frame.
+    pub fn reset_data_available(&mut self) -> Result<(), Error<I2C>> {
+        let mut status = self.status()?;
+        status.reset_data_ready();
+        self.configure(RamAddress::Status, status.into())
+    }
+
+    /// The ambient temperature of the camera from the last frame that was read.
+    ///
+    /// This is `None` until an image has been generated.
+    pub fn ambient_temperature(&self) -> Option<f32> {
+        self.ambient_temperature
+    }
+
+    /// Copy the camera's current temperatures into `destination`.
+    ///
+    /// This function does *not* check if there is new data, it just copies the current frame.
+    /// `destination` must have room for at least [`Mlx90642::NUM_PIXELS`] temperatures.
+    pub fn generate_image_to(&mut self, destination: &mut [f32]) -> Result<(), Error<I2C>> {
+        let object_address = Address::from(RamAddress::ObjectTemperatureStart);
+        self.bus
+            .write_read(
+                self.address,
+                &object_address.as_bytes(),
+                &mut self.pixel_buffer,
+            )
+            .map_err(Error::I2cError)?;
+        let ambient = self.read_word(RamAddress::AmbientTemperature)?;
+        self.ambient_temperature = Some(temperature_from_raw(ambient as i16));
+        temperatures_from_bytes(&self.pixel_buffer, destination);
+        Ok(())
+    }
+
+    /// Copy the camera's temperatures into `destination` if there is a new frame.
+    ///
+    /// The data ready flag is cleared after the frame has been copied. The `Ok` value is whether
+    /// or not there was a new frame.
+    pub fn generate_image_if_ready(&mut self, destination: &mut [f32]) -> Result<bool, Error<I2C>> {
+        let mut status = self.status()?;
+        if status.data_ready() {
+            self.generate_image_to(destination)?;
+            status.reset_data_ready();
+            self.configure(RamAddress::Status, status.into())?;
+            Ok(true)
+        } else {
+            Ok(false)
+        }
+    }
+
+    /// Synchronize with the camera's frame timing.
+    ///
+    /// Any new data is ignored, and then this function waits until the next frame is ready. In
+    /// step mode a measurement is started first.
+    pub fn synchronize(&mut self) -> Result<(), Error<I2C>> {
+        self.start_synchronized_measurement()?;
+        while !self.data_available()? {
+            core::hint::spin_loop();
+        }
+        Ok(())
+    }
+
+    /// Synchronize with the camera's frame timing, giving up after `timeout`.
+    ///
+    /// This works like [`synchronize`][Self::synchronize], but instead of spinning the status is
+    /// checked every [poll interval][FrameRate::poll_interval] for the current frame rate, with
+    /// `delay` used to wait in between. If there is still no new data after `timeout`,
+    /// [`LibraryError::Timeout`] is returned.
+    pub fn synchronize_with_timeout<D: DelayNs>(
+        &mut self,
+        delay: &mut D,
+        timeout: Duration,
+    ) -> Result<(), Error<I2C>> {
+        let poll_interval = self.frame_rate()?.poll_interval();
+        let max_polls = polls_within(timeout, poll_interval);
+        self.synchronize_polling(delay, poll_interval, max_polls)
+    }
+
+    /// Synchronize with the camera's frame timing, giving up after checking the status `max_polls`
+    /// times.
+    pub fn synchronize_with_poll_limit<D: DelayNs>(
+        &mut self,
+        delay: &mut D,
+        max_polls: u32,
+    ) -> Result<(), Error<I2C>> {
+        let poll_interval = self.frame_rate()?.poll_interval();
+        self.synchronize_polling(delay, poll_interval, max_polls)
+    }
+
+    /// Take a single frame in step mode, writing the temperatures into `destination`.
+    ///
+    /// The camera is put into step mode if it isn't already, then one measurement is triggered.
+    /// The camera stays idle until the next call, which makes this suitable for taking infrequent
+    /// frames. `delay` is used to wait between checks of the status, and if the measurement hasn't
+    /// finished after two frame periods, [`LibraryError::Timeout`] is returned.
+    pub fn measure_frame<D: DelayNs>(
+        &mut self,
+        delay: &mut D,
+        destination: &mut [f32],
+    ) -> Result<(), Error<I2C>> {
+        if !self.step_mode()? {
+            self.set_step_mode(true)?;
+        }
+        let frame_rate = self.frame_rate()?;
+        let poll_interval = frame_rate.poll_interval();
+        let max_polls = polls_within(Duration::from(frame_rate) * 2, poll_interval);
+        self.synchronize_polling(delay, poll_interval, max_polls)?;
+        self.generate_image_to(destination)?;
+        self.reset_data_available()
+    }
+
+    /// Ignore any new data, and start a measurement if in step mode.
+    fn start_synchronized_measurement(&mut self) -> Result<(), Error<I2C>> {
+        self.reset_data_available()?;
+        if self.step_mode()? {
+            self.start_measurement()?;
+        }
+        Ok(())
+    }
+
+    fn synchronize_polling<D: DelayNs>(
+        &mut self,
+        delay: &mut D,
+        poll_interval: Duration,
+        max_polls: u32,
+    ) -> Result<(), Error<I2C>> {
+        self.start_synchronized_measurement()?;
+        let poll_interval = saturating_micros(poll_interval);
+        for _ in 0..max_polls {
+            delay.delay_us(poll_interval);
+            if self.data_available()? {
+                return Ok(());
+            }
+        }
+        Err(LibraryError::Timeout.into())
+    }
+
+    /// The height of the thermal image, in pixels.
+    pub fn height(&self) -> usize {
+        Mlx90642::HEIGHT
+    }
+
+    /// The width of the thermal image, in pixels.
+    pub fn width(&self) -> usize {
+        Mlx90642::WIDTH
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use float_cmp::approx_eq;
+
+    use mlx9064x_test_data::{mock_mlx90642_at_address, MockError};
+
+    use crate::mlx90642::{self, Mlx90642};
+    use crate::{Error, FrameRate, LibraryError};
+
+    use super::Duration;
+
+    const ADDRESS: u8 = mlx90642::DEFAULT_ADDRESS;
+
+    fn scene() -> [f32; Mlx90642::NUM_PIXELS] {
+        let mut scene = [0f32; Mlx90642::NUM_PIXELS];
+        for (index, temperature) in scene.iter_mut().enumerate() {
+            *temperature = -20.0 + index as f32 * 0.2;
+        }
+        scene
+    }
+
+    #[test]
+    fn missing_camera() {
+        let mocked = mock_mlx90642_at_address(ADDRESS);
+        let err = mlx90642::Driver::new(mocked, ADDRESS + 1).unwrap_err();
+        assert!(matches!(
+            err,
+            Error::I2cError(MockError::UnknownI2cAddress(_))
+        ));
+    }
+
+    #[test]
+    fn generate_image() {
+        let mut mocked = mock_mlx90642_at_address(ADDRESS);
+        let mut cam = mlx90642::Driver::new(mocked.clone(), ADDRESS).unwrap();
+        assert_eq!(cam.height() * cam.width(), Mlx90642::NUM_PIXELS);
+        let mut temperatures = [0f32; Mlx90642::NUM_PIXELS];
+        assert!(!cam.generate_image_if_ready(&mut temperatures).unwrap());
+        assert_eq!(cam.ambient_temperature(), None);
+        let scene = scene();
+        mocked.update_frame(&scene, 31.5);
+        assert!(cam.data_available().unwrap());
+        assert!(cam.generate_image_if_ready(&mut temperatures).unwrap());
+        for (expected, actual) in scene.iter().zip(temperatures.iter()) {
+            assert!(approx_eq!(f32, *expected, *actual, epsilon = 0.01));
+        }
+        assert_eq!(cam.ambient_temperature(), Some(31.5));
+        // The flag was cleared
+        assert!(!cam.data_available().unwrap());
+        assert!(!cam.generate_image_if_ready(&mut temperatures).unwrap());
+    }
+
+    #[test]
+    fn configuration() {
+        let mocked = mock_mlx90642_at_address(ADDRESS);
+        let mut cam = mlx90642::Driver::new(mocked.clone(), ADDRESS).unwrap();
+        assert_eq!(cam.frame_rate().unwrap(), FrameRate::Two);
+        cam.set_frame_rate(FrameRate::Sixteen).unwrap();
+        assert_eq!(cam.frame_rate().unwrap(), FrameRate::Sixteen);
+        assert_eq!(cam.emissivity().unwrap(), 1.0);
+        cam.set_emissivity(0.5).unwrap();
+        assert_eq!(cam.emissivity().unwrap(), 0.5);
+        assert!(matches!(
+            cam.set_emissivity(1.5),
+            Err(Error::LibraryError(LibraryError::InvalidData(_)))
+        ));
+        cam.set_reflected_temperature(-12.5).unwrap();
+        assert_eq!(cam.reflected_temperature().unwrap(), -12.5);
+        assert!(!cam.step_mode().unwrap());
+        cam.set_step_mode(true).unwrap();
+        assert!(cam.step_mode().unwrap());
+        assert_eq!(cam.device_id().unwrap(), mocked.device_id());
+    }
+
+    #[test]
+    fn step_mode() {
+        let mocked = mock_mlx90642_at_address(ADDRESS);
+        let mut cam = mlx90642::Driver::new(mocked.clone(), ADDRESS).unwrap();
+        cam.set_step_mode(true).unwrap();
+        // The mock measures immediately when told to.
+        cam.synchronize().unwrap();
+        assert!(cam.data_available().unwrap());
+        assert_eq!(mocked.measurements(), 1);
+    }
+
+    #[test]
+    fn sleep() {
+        let mocked = mock_mlx90642_at_address(ADDRESS);
+        let mut cam = mlx90642::Driver::new(mocked.clone(), ADDRESS).unwrap();
+        cam.sleep().unwrap();
+        assert!(mocked.is_asleep());
+        // Only the wake up command is accepted while asleep.
+        assert!(cam.frame_rate().is_err());
+        cam.wake_up().unwrap();
+        assert!(!mocked.is_asleep());
+        assert!(cam.frame_rate().is_ok());
+    }
+
+    /// A delay that only counts how many times it was used.
+    struct CountingDelay(usize);
+
+    impl embedded_hal::delay::DelayNs for CountingDelay {
+        fn delay_ns(&mut self, _ns: u32) {
+            self.0 += 1;
+        }
+    }
+
+    #[test]
+    fn synchronize_with_timeout() {
+        let mocked = mock_mlx90642_at_address(ADDRESS);
+        let mut cam = mlx90642::Driver::new(mocked, ADDRESS).unwrap();
+        let mut delay = CountingDelay(0);
+        // Continuous mode, and the mock never makes a new frame on its own
+        assert!(matches!(
+            cam.synchronize_with_timeout(&mut delay, Duration::from_secs(1)),
+            Err(Error::LibraryError(LibraryError::Timeout))
+        ));
+        // 2 FPS, 16 polls per frame
+        assert_eq!(delay.0, 32);
+        // In step mode the mock measures instantly
+        cam.set_step_mode(true).unwrap();
+        cam.synchronize_with_poll_limit(&mut delay, 1).unwrap();
+    }
+
+    #[test]
+    fn measure_frame() {
+        let mocked = mock_mlx90642_at_address(ADDRESS);
+        let mut cam = mlx90642::Driver::new(mocked.clone(), ADDRESS).unwrap();
+        let mut delay = CountingDelay(0);
+        let mut temperatures = [f32::NAN; Mlx90642::NUM_PIXELS];
+        cam.measure_frame(&mut delay, &mut temperatures).unwrap();
+        assert!(cam.step_mode().unwrap());
+        assert_eq!(mocked.measurements(), 1);
+        assert!(temperatures.iter().all(|t| !t.is_nan()));
+        assert!(!cam.data_available().unwrap());
+    }
+}
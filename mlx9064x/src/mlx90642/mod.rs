@@ -1,71 +1,228 @@
 // SPDX-License-Identifier: Apache-2.0
+// Copyright © 2021 Will Ross
 //! MLX90642 specific details.
-mod eeprom;
+//!
+//! Unlike the MLX90640 and MLX90641, the MLX90642 calculates object temperatures itself. There is
+//! no calibration data to load and process; instead the camera is configured with a few words (the
+//! refresh rate, emissivity and so on) and the temperatures are read directly from its memory. As
+//! a result the MLX90642 has its own [`Driver`] instead of using
+//! [`CameraDriver`][crate::CameraDriver], and [`Mlx90642`] does not implement
+//! [`MelexisCamera`][crate::MelexisCamera].
+//!
+//! Configuration words are written with the [`Configure`][Command::Configure] command followed by
+//! the address and value, while the other [commands][Command] are written on their own. Reads are
+//! the same as the other cameras: write the address, then read words back.
+//!
+//! | Address  | Contents                                   |
+//! |----------|--------------------------------------------|
+//! | `0x11F2` | Refresh rate                               |
+//! | `0x11F4` | Emissivity (2<sup>14</sup> = 1.0)          |
+//! | `0x11F6` | Reflected temperature (0.02 °C per LSB)    |
+//! | `0x11F8` | Measurement mode (0 continuous, 1 step)    |
+//! | `0x2407` | Device ID (three words)                    |
+//! | `0x342C` | Object temperatures (768 words, 0.02 °C)   |
+//! | `0x3A2C` | Ambient temperature (0.02 °C)              |
+//! | `0x3C14` | Status                                     |
+mod address;
+#[cfg(feature = "async")]
+mod async_driver;
+mod driver;
+
+#[cfg_attr(feature = "std", allow(unused_imports))]
+use num_traits::Float;
+
+use crate::error::LibraryError;
+use crate::util::is_bit_set;
+
+pub use address::{Command, RamAddress};
+#[cfg(feature = "async")]
+pub use async_driver::AsyncDriver;
+pub use driver::Driver;
+
+/// The default I²C address of the MLX90642.
+pub const DEFAULT_ADDRESS: u8 = 0x66;
+
+/// How many raw temperature units there are in a degree Celsius.
+const TEMPERATURE_SCALE: f32 = 50.0;
+
+/// The raw emissivity value that corresponds to an emissivity of 1.
+const EMISSIVITY_SCALE: f32 = (1 << 14) as f32;
+
+/// MLX90642-specific constants.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Mlx90642();
 
-use core::iter;
+impl Mlx90642 {
+    /// The height of the image, in pixels.
+    pub const HEIGHT: usize = 24;
 
-use crate::common::{Address, MelexisCamera};
-use crate::register::{AccessPattern, Resolution, Subpage};
-use crate::util::Sealed;
+    /// The width of the image, in pixels.
+    pub const WIDTH: usize = 32;
 
-pub use crate::mlx90641::RamAddress;
-pub use eeprom::Mlx90642Calibration;
+    /// The total number of pixels in an image.
+    pub const NUM_PIXELS: usize = Self::HEIGHT * Self::WIDTH;
+}
 
-/// MLX90642-specific constants and supporting functions.
-///
-/// The MLX90642 shares the same memory map and per-frame behaviour as the MLX90641. The
-/// high-level driver still provides a dedicated type so applications can differentiate between the
-/// two sensor families while re-using the same calibration pipeline internally.
-#[derive(Clone, Debug, PartialEq)]
-pub struct Mlx90642();
+/// The MLX90642 status word.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Status(u16);
 
-impl Sealed for Mlx90642 {}
+impl Status {
+    const DATA_READY_BIT: usize = 0;
 
-impl MelexisCamera for Mlx90642 {
-    type PixelRangeIterator = crate::mlx90641::SubpageInterleave;
-    type PixelsInSubpageIterator = iter::Take<iter::Repeat<bool>>;
+    const BUSY_BIT: usize = 1;
 
-    fn pixel_ranges(subpage: Subpage, access_pattern: AccessPattern) -> Self::PixelRangeIterator {
-        <crate::mlx90641::Mlx90641 as MelexisCamera>::pixel_ranges(subpage, access_pattern)
+    /// Whether a new frame of temperatures is ready to be read.
+    ///
+    /// This flag is only cleared by the controller.
+    pub fn data_ready(&self) -> bool {
+        is_bit_set(self.0, Self::DATA_READY_BIT)
     }
 
-    fn pixels_in_subpage(
-        subpage: Subpage,
-        access_pattern: AccessPattern,
-    ) -> Self::PixelsInSubpageIterator {
-        <crate::mlx90641::Mlx90641 as MelexisCamera>::pixels_in_subpage(subpage, access_pattern)
+    /// Clear the data ready flag.
+    pub fn reset_data_ready(&mut self) {
+        self.0 &= !(1 << Self::DATA_READY_BIT);
     }
 
-    const T_A_V_BE: Address = <crate::mlx90641::Mlx90641 as MelexisCamera>::T_A_V_BE;
+    /// Whether the camera is in the middle of a measurement.
+    pub fn busy(&self) -> bool {
+        is_bit_set(self.0, Self::BUSY_BIT)
+    }
+}
 
-    const T_A_PTAT: Address = <crate::mlx90641::Mlx90641 as MelexisCamera>::T_A_PTAT;
+impl From<u16> for Status {
+    fn from(raw: u16) -> Self {
+        Self(raw)
+    }
+}
 
-    fn compensation_pixel(subpage: Subpage) -> Address {
-        <crate::mlx90641::Mlx90641 as MelexisCamera>::compensation_pixel(subpage)
+impl From<Status> for u16 {
+    fn from(status: Status) -> Self {
+        status.0
     }
+}
 
-    const GAIN: Address = <crate::mlx90641::Mlx90641 as MelexisCamera>::GAIN;
+/// Convert a raw temperature from the camera to degrees Celsius.
+pub fn temperature_from_raw(raw: i16) -> f32 {
+    f32::from(raw) / TEMPERATURE_SCALE
+}
+
+/// Convert a temperature in degrees Celsius to the camera's representation.
+///
+/// Temperatures are rounded to the nearest 0.02 °C, and must be within about ±655 °C.
+pub fn temperature_to_raw(celsius: f32) -> Result<i16, LibraryError> {
+    let raw = (celsius * TEMPERATURE_SCALE).round();
+    if raw >= f32::from(i16::MIN) && raw <= f32::from(i16::MAX) {
+        Ok(raw as i16)
+    } else {
+        Err(LibraryError::InvalidData(
+            "Temperature is out of range for the MLX90642",
+        ))
+    }
+}
 
-    const V_DD_PIXEL: Address = <crate::mlx90641::Mlx90641 as MelexisCamera>::V_DD_PIXEL;
+/// Convert a raw emissivity from the camera to a fraction.
+pub fn emissivity_from_raw(raw: u16) -> f32 {
+    f32::from(raw) / EMISSIVITY_SCALE
+}
 
-    fn resolution_correction(
-        calibrated_resolution: Resolution,
-        current_resolution: Resolution,
-    ) -> f32 {
-        <crate::mlx90641::Mlx90641 as MelexisCamera>::resolution_correction(
-            calibrated_resolution,
-            current_resolution,
-        )
+/// Convert an emissivity to the camera's representation.
+///
+/// The emissivity must be greater than 0 and at most 1.
+pub fn emissivity_to_raw(emissivity: f32) -> Result<u16, LibraryError> {
+    if emissivity > 0.0 && emissivity <= 1.0 {
+        Ok((emissivity * EMISSIVITY_SCALE).round() as u16)
+    } else {
+        Err(LibraryError::InvalidData(
+            "Emissivity must be greater than 0 and no more than 1",
+        ))
     }
+}
 
-    const BASIC_TEMPERATURE_RANGE: usize =
-        <crate::mlx90641::Mlx90641 as MelexisCamera>::BASIC_TEMPERATURE_RANGE;
+/// Convert a buffer of raw temperatures (as read from the camera) to degrees Celsius.
+pub(crate) fn temperatures_from_bytes(bytes: &[u8], destination: &mut [f32]) {
+    bytes
+        .chunks_exact(2)
+        .zip(destination.iter_mut())
+        .for_each(|(word, temperature)| {
+            *temperature = temperature_from_raw(i16::from_be_bytes([word[0], word[1]]));
+        });
+}
 
-    const SELF_HEATING: f32 = <crate::mlx90641::Mlx90641 as MelexisCamera>::SELF_HEATING;
+/// The bytes to write to the camera for a [`Command`].
+pub(crate) fn command_bytes(command: Command) -> [u8; 2] {
+    u16::from(command).to_be_bytes()
+}
 
-    const HEIGHT: usize = <crate::mlx90641::Mlx90641 as MelexisCamera>::HEIGHT;
+/// The bytes to write to the camera to set a configuration word.
+pub(crate) fn configure_bytes(address: RamAddress, value: u16) -> [u8; 6] {
+    let [command_high, command_low] = command_bytes(Command::Configure);
+    let [address_high, address_low] = u16::from(address).to_be_bytes();
+    let [value_high, value_low] = value.to_be_bytes();
+    [
+        command_high,
+        command_low,
+        address_high,
+        address_low,
+        value_high,
+        value_low,
+    ]
+}
 
-    const WIDTH: usize = <crate::mlx90641::Mlx90641 as MelexisCamera>::WIDTH;
+#[cfg(test)]
+mod test {
+    use crate::LibraryError;
+
+    use super::*;
+
+    #[test]
+    fn temperature_conversion() {
+        assert_eq!(temperature_from_raw(1250), 25.0);
+        assert_eq!(temperature_from_raw(-1000), -20.0);
+        assert_eq!(temperature_to_raw(25.0), Ok(1250));
+        assert_eq!(temperature_to_raw(-20.0), Ok(-1000));
+        // Rounded to the nearest step
+        assert_eq!(temperature_to_raw(0.011), Ok(1));
+        assert!(matches!(
+            temperature_to_raw(700.0),
+            Err(LibraryError::InvalidData(_))
+        ));
+    }
 
-    const NUM_PIXELS: usize = <crate::mlx90641::Mlx90641 as MelexisCamera>::NUM_PIXELS;
+    #[test]
+    fn emissivity_conversion() {
+        assert_eq!(emissivity_from_raw(0x4000), 1.0);
+        assert_eq!(emissivity_to_raw(1.0), Ok(0x4000));
+        assert_eq!(emissivity_to_raw(0.95), Ok(15565));
+        assert!(emissivity_to_raw(0.0).is_err());
+        assert!(emissivity_to_raw(1.01).is_err());
+    }
+
+    #[test]
+    fn status() {
+        let mut status = Status::from(0x0003);
+        assert!(status.data_ready());
+        assert!(status.busy());
+        status.reset_data_ready();
+        assert!(!status.data_ready());
+        assert!(status.busy());
+        assert_eq!(u16::from(status), 0x0002);
+    }
+
+    #[test]
+    fn configure_bytes_layout() {
+        assert_eq!(
+            configure_bytes(RamAddress::Emissivity, 0x4000),
+            [0x3A, 0x2E, 0x11, 0xF4, 0x40, 0x00]
+        );
+        assert_eq!(command_bytes(Command::StartMeasurement), [0x18, 0x01]);
+    }
+
+    #[test]
+    fn temperatures_from_bytes_order() {
+        let bytes = [0x04, 0xE2, 0xFC, 0x18];
+        let mut temperatures = [0f32; 2];
+        temperatures_from_bytes(&bytes, &mut temperatures);
+        assert_eq!(temperatures, [25.0, -20.0]);
+    }
 }
@@ -0,0 +1,369 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright © 2021 Will Ross
+
+//! False color rendering of thermal images.
+//!
+//! A [`Renderer`] maps temperatures (or any other image data) onto a [`Palette`], writing the
+//! colors into a caller-provided buffer as either 24-bit RGB888 or 16-bit RGB565 pixels. The
+//! latter is the format used by many small SPI displays. The range of temperatures covered by the
+//! palette can either be [fixed][Range::Fixed] or [follow the image][Range::Automatic], and an
+//! [`Isotherm`] can highlight the pixels in a temperature band.
+//!
+//! ```
+//! use mlx9064x::palette::{Isotherm, Palette, Range, Renderer, Rgb};
+//!
+//! let temperatures = [20.0, 25.0, 30.0, 37.0];
+//! let mut renderer = Renderer::new(Palette::WhiteHot);
+//! renderer.range = Range::Fixed { min: 20.0, max: 30.0 };
+//! // Highlight anything around body temperature in red
+//! renderer.isotherm = Some(Isotherm {
+//!     min: 35.0,
+//!     max: 40.0,
+//!     color: Rgb::new(255, 0, 0),
+//! });
+//! let mut pixels = [0u8; 4 * 3];
+//! renderer.render_rgb888(&temperatures, &mut pixels);
+//! assert_eq!(pixels, [0, 0, 0, 128, 128, 128, 255, 255, 255, 255, 0, 0]);
+//! ```
+
+/// A color with 8 bits per channel.
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, PartialEq)]
+pub struct Rgb {
+    pub red: u8,
+    pub green: u8,
+    pub blue: u8,
+}
+
+impl Rgb {
+    /// Create a new color.
+    pub const fn new(red: u8, green: u8, blue: u8) -> Self {
+        Self { red, green, blue }
+    }
+
+    /// The color as a 16-bit RGB565 value.
+    ///
+    /// The lowest bits of each channel are dropped. Displays usually expect the value to be sent
+    /// most significant byte first (see [`u16::to_be_bytes`]).
+    pub const fn to_rgb565(self) -> u16 {
+        ((self.red as u16 >> 3) << 11) | ((self.green as u16 >> 2) << 5) | (self.blue as u16 >> 3)
+    }
+
+    /// Linear interpolation between two colors, with `fraction` between 0 and 1.
+    fn lerp(self, other: Self, fraction: f32) -> Self {
+        let channel = |start: u8, end: u8| {
+            let start = f32::from(start);
+            (start + (f32::from(end) - start) * fraction + 0.5) as u8
+        };
+        Self {
+            red: channel(self.red, other.red),
+            green: channel(self.green, other.green),
+            blue: channel(self.blue, other.blue),
+        }
+    }
+}
+
+const IRONBOW: [Rgb; 8] = [
+    Rgb::new(0, 0, 0),
+    Rgb::new(32, 0, 104),
+    Rgb::new(112, 0, 152),
+    Rgb::new(184, 24, 128),
+    Rgb::new(228, 76, 40),
+    Rgb::new(248, 148, 0),
+    Rgb::new(255, 216, 48),
+    Rgb::new(255, 255, 255),
+];
+
+const RAINBOW: [Rgb; 6] = [
+    Rgb::new(32, 0, 96),
+    Rgb::new(0, 0, 255),
+    Rgb::new(0, 255, 255),
+    Rgb::new(0, 255, 0),
+    Rgb::new(255, 255, 0),
+    Rgb::new(255, 0, 0),
+];
+
+const WHITE_HOT: [Rgb; 2] = [Rgb::new(0, 0, 0), Rgb::new(255, 255, 255)];
+
+const BLACK_HOT: [Rgb; 2] = [Rgb::new(255, 255, 255), Rgb::new(0, 0, 0)];
+
+/// The colors temperatures are mapped onto, from coldest to hottest.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Palette<'p> {
+    /// Black through purple, red, orange and yellow to white, like a heated piece of iron.
+    Ironbow,
+
+    /// Dark blue through blue, cyan, green and yellow to red.
+    Rainbow,
+
+    /// Grayscale, with hotter temperatures being lighter.
+    #[doc(alias = "Grayscale")]
+    WhiteHot,
+
+    /// Grayscale, with hotter temperatures being darker.
+    BlackHot,
+
+    /// A custom gradient, with the colors spaced evenly from coldest to hottest.
+    ///
+    /// An empty gradient maps everything to black, and a gradient with only one color maps
+    /// everything to that color.
+    Gradient(&'p [Rgb]),
+}
+
+impl Palette<'_> {
+    /// The colors making up the palette.
+    pub fn colors(&self) -> &[Rgb] {
+        match self {
+            Self::Ironbow => &IRONBOW,
+            Self::Rainbow => &RAINBOW,
+            Self::WhiteHot => &WHITE_HOT,
+            Self::BlackHot => &BLACK_HOT,
+            Self::Gradient(colors) => colors,
+        }
+    }
+
+    /// The color for a position in the palette, with 0 being the coldest and 1 the hottest.
+    ///
+    /// Positions outside of that range are clamped to it.
+    pub fn color(&self, position: f32) -> Rgb {
+        let colors = self.colors();
+        match colors {
+            [] => Rgb::default(),
+            [color] => *color,
+            _ => {
+                let position = position.clamp(0f32, 1f32) * (colors.len() - 1) as f32;
+                // Truncating is fine as the position is positive
+                let index = (position as usize).min(colors.len() - 2);
+                colors[index].lerp(colors[index + 1], position - index as f32)
+            }
+        }
+    }
+}
+
+/// The range of temperatures covered by a palette.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Range {
+    /// Use the lowest and highest temperatures in each image.
+    Automatic,
+
+    /// Use the same range for every image.
+    ///
+    /// Temperatures outside of the range use the color at that end of the palette.
+    Fixed { min: f32, max: f32 },
+}
+
+impl Range {
+    /// The lowest and highest temperatures for an image, ignoring NaN.
+    ///
+    /// If the range is automatic and every temperature is NaN, `None` is returned.
+    pub fn limits(&self, temperatures: &[f32]) -> Option<(f32, f32)> {
+        match self {
+            Self::Fixed { min, max } => Some((*min, *max)),
+            Self::Automatic => temperatures
+                .iter()
+                .filter(|temperature| !temperature.is_nan())
+                .fold(None, |limits, temperature| match limits {
+                    None => Some((*temperature, *temperature)),
+                    Some((min, max)) => Some((temperature.min(min), temperature.max(max))),
+                }),
+        }
+    }
+}
+
+/// A band of temperatures highlighted with a single color.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Isotherm {
+    /// The lowest temperature in the band.
+    pub min: f32,
+
+    /// The highest temperature in the band.
+    pub max: f32,
+
+    /// The color used for temperatures in the band, instead of the palette.
+    pub color: Rgb,
+}
+
+/// Renders images using a palette.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Renderer<'p> {
+    /// The palette the temperatures are mapped onto.
+    pub palette: Palette<'p>,
+
+    /// The temperatures covered by the palette.
+    pub range: Range,
+
+    /// A band of temperatures to highlight, if any.
+    pub isotherm: Option<Isotherm>,
+
+    /// The color used for NaN pixels (like failed pixels that couldn't be replaced).
+    pub nan_color: Rgb,
+}
+
+impl<'p> Renderer<'p> {
+    /// Create a new renderer with an automatic range, no isotherm, and NaN pixels rendered black.
+    pub fn new(palette: Palette<'p>) -> Self {
+        Self {
+            palette,
+            range: Range::Automatic,
+            isotherm: None,
+            nan_color: Rgb::default(),
+        }
+    }
+
+    /// The color for a single temperature, with the palette covering `min` to `max`.
+    pub fn color(&self, temperature: f32, min: f32, max: f32) -> Rgb {
+        if temperature.is_nan() {
+            return self.nan_color;
+        }
+        if let Some(isotherm) = &self.isotherm {
+            if (isotherm.min..=isotherm.max).contains(&temperature) {
+                return isotherm.color;
+            }
+        }
+        let position = if max > min {
+            (temperature - min) / (max - min)
+        } else {
+            // Every pixel is the same temperature, so use the middle of the palette.
+            0.5
+        };
+        self.palette.color(position)
+    }
+
+    /// Map each pixel to a color, calling `write` with the index and color of each pixel.
+    ///
+    /// The range used is returned (see [`Range::limits`]).
+    fn render<W>(&self, temperatures: &[f32], mut write: W) -> Option<(f32, f32)>
+    where
+        W: FnMut(usize, Rgb),
+    {
+        let limits = self.range.limits(temperatures);
+        let (min, max) = limits.unwrap_or((f32::NAN, f32::NAN));
+        for (index, temperature) in temperatures.iter().enumerate() {
+            write(index, self.color(*temperature, min, max));
+        }
+        limits
+    }
+
+    /// Render an image as RGB888, with three bytes (red, green, then blue) for each pixel.
+    ///
+    /// If `output` is too short for the whole image, only the pixels that fit are written. The
+    /// range the palette covered is returned, which is `None` if the range is automatic and the
+    /// image is entirely NaN.
+    pub fn render_rgb888(&self, temperatures: &[f32], output: &mut [u8]) -> Option<(f32, f32)> {
+        self.render(temperatures, |index, color| {
+            if let Some(pixel) = output.get_mut(index * 3..index * 3 + 3) {
+                pixel.copy_from_slice(&[color.red, color.green, color.blue]);
+            }
+        })
+    }
+
+    /// Render an image as RGB565, with one 16-bit value for each pixel.
+    ///
+    /// The values are in native byte order, so they might need to be swapped before being sent to
+    /// a display. Otherwise this is the same as [`render_rgb888`][Self::render_rgb888].
+    pub fn render_rgb565(&self, temperatures: &[f32], output: &mut [u16]) -> Option<(f32, f32)> {
+        self.render(temperatures, |index, color| {
+            if let Some(pixel) = output.get_mut(index) {
+                *pixel = color.to_rgb565();
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Isotherm, Palette, Range, Renderer, Rgb};
+
+    #[test]
+    fn rgb565() {
+        assert_eq!(Rgb::new(0, 0, 0).to_rgb565(), 0x0000);
+        assert_eq!(Rgb::new(255, 255, 255).to_rgb565(), 0xFFFF);
+        assert_eq!(Rgb::new(255, 0, 0).to_rgb565(), 0xF800);
+        assert_eq!(Rgb::new(0, 255, 0).to_rgb565(), 0x07E0);
+        assert_eq!(Rgb::new(0, 0, 255).to_rgb565(), 0x001F);
+        assert_eq!(Rgb::new(0x84, 0x82, 0x08).to_rgb565(), 0x8401);
+    }
+
+    #[test]
+    fn palette_ends() {
+        for palette in [Palette::Ironbow, Palette::Rainbow, Palette::WhiteHot] {
+            let colors = palette.colors();
+            assert_eq!(palette.color(0.0), colors[0]);
+            assert_eq!(palette.color(1.0), colors[colors.len() - 1]);
+            // Clamped outside of the palette
+            assert_eq!(palette.color(-3.0), colors[0]);
+            assert_eq!(palette.color(7.0), colors[colors.len() - 1]);
+        }
+        assert_eq!(Palette::BlackHot.color(0.0), Rgb::new(255, 255, 255));
+        assert_eq!(Palette::BlackHot.color(1.0), Rgb::new(0, 0, 0));
+    }
+
+    #[test]
+    fn gradient() {
+        let colors = [
+            Rgb::new(0, 0, 0),
+            Rgb::new(200, 100, 0),
+            Rgb::new(200, 200, 200),
+        ];
+        let palette = Palette::Gradient(&colors);
+        assert_eq!(palette.color(0.25), Rgb::new(100, 50, 0));
+        assert_eq!(palette.color(0.5), Rgb::new(200, 100, 0));
+        assert_eq!(palette.color(0.75), Rgb::new(200, 150, 100));
+        let single = [Rgb::new(1, 2, 3)];
+        assert_eq!(Palette::Gradient(&single).color(0.7), single[0]);
+        assert_eq!(Palette::Gradient(&[]).color(0.7), Rgb::default());
+    }
+
+    #[test]
+    fn automatic_range() {
+        let temperatures = [f32::NAN, 22.0, 18.5, 30.25];
+        assert_eq!(Range::Automatic.limits(&temperatures), Some((18.5, 30.25)));
+        assert_eq!(Range::Automatic.limits(&[f32::NAN]), None);
+        let fixed = Range::Fixed {
+            min: 0.0,
+            max: 100.0,
+        };
+        assert_eq!(fixed.limits(&temperatures), Some((0.0, 100.0)));
+    }
+
+    #[test]
+    fn render_rgb888() {
+        let temperatures = [10.0, f32::NAN, 20.0, 15.0];
+        let mut renderer = Renderer::new(Palette::WhiteHot);
+        renderer.nan_color = Rgb::new(0, 0, 255);
+        let mut output = [0u8; 12];
+        let limits = renderer.render_rgb888(&temperatures, &mut output);
+        assert_eq!(limits, Some((10.0, 20.0)));
+        assert_eq!(output, [0, 0, 0, 0, 0, 255, 255, 255, 255, 128, 128, 128]);
+    }
+
+    #[test]
+    fn render_rgb565() {
+        let temperatures = [0.0, 50.0, 100.0, 200.0];
+        let mut renderer = Renderer::new(Palette::BlackHot);
+        renderer.range = Range::Fixed {
+            min: 0.0,
+            max: 100.0,
+        };
+        renderer.isotherm = Some(Isotherm {
+            min: 45.0,
+            max: 55.0,
+            color: Rgb::new(0, 255, 0),
+        });
+        // The last pixel doesn't fit, and is skipped
+        let mut output = [0u16; 3];
+        renderer.render_rgb565(&temperatures, &mut output);
+        assert_eq!(output, [0xFFFF, 0x07E0, 0x0000]);
+    }
+
+    #[test]
+    fn uniform_image() {
+        // All the same temperature uses the middle of the palette
+        let temperatures = [25.0; 4];
+        let renderer = Renderer::new(Palette::WhiteHot);
+        let mut output = [0u16; 4];
+        renderer.render_rgb565(&temperatures, &mut output);
+        assert!(output
+            .iter()
+            .all(|pixel| *pixel == Rgb::new(128, 128, 128).to_rgb565()));
+    }
+}
@@ -3,10 +3,12 @@
 use core::convert::{TryFrom, TryInto};
 use core::time::Duration;
 
-use embedded_hal::blocking::i2c;
+use embedded_hal::i2c;
 use num_enum::{IntoPrimitive, TryFromPrimitive};
 
 use crate::common::{Address, FromI2C, ToI2C};
+#[cfg(feature = "async")]
+use crate::common::{AsyncFromI2C, AsyncToI2C};
 use crate::error::{Error, LibraryError};
 use crate::util::is_bit_set;
 
@@ -26,7 +28,7 @@ pub(crate) trait Register: Into<[u8; 2]> + for<'a> From<&'a [u8]> {
 impl<I2C, R> FromI2C<I2C> for R
 where
     R: Register,
-    I2C: i2c::WriteRead + i2c::Write,
+    I2C: i2c::I2c,
 {
     type Error = Error<I2C>;
 
@@ -36,7 +38,7 @@ where
         // Inner function to reduce the impact of monomorphization for Register. It'll still get
         // duplicated, but it should just be duplicated on I2C, and there should only be one of those
         // in an application (usually).
-        fn read_register<I2C: i2c::WriteRead>(
+        fn read_register<I2C: i2c::I2c>(
             bus: &mut I2C,
             address: u8,
             register_address: Address,
@@ -49,7 +51,7 @@ where
 
         let register_address = R::address();
         let register_value =
-            read_register(bus, i2c_address, register_address).map_err(Error::I2cWriteReadError)?;
+            read_register(bus, i2c_address, register_address).map_err(Error::I2cError)?;
         let register = R::from(&register_value[..]);
         Ok(register)
     }
@@ -58,12 +60,12 @@ where
 impl<I2C, R> ToI2C<I2C> for R
 where
     R: Copy + Register,
-    I2C: i2c::WriteRead + i2c::Write,
+    I2C: i2c::I2c,
 {
     type Error = Error<I2C>;
 
     fn to_i2c(&self, bus: &mut I2C, i2c_address: u8) -> Result<(), Self::Error> {
-        fn write_raw_register<I2C: i2c::Write>(
+        fn write_raw_register<I2C: i2c::I2c>(
             bus: &mut I2C,
             address: u8,
             register_address: [u8; 2],
@@ -87,11 +89,53 @@ where
             register_address.as_bytes(),
             register_bytes,
         )
-        .map_err(Error::I2cWriteError)?;
+        .map_err(Error::I2cError)?;
         Ok(())
     }
 }
 
+#[cfg(feature = "async")]
+impl<I2C, R> AsyncFromI2C<I2C> for R
+where
+    R: Register,
+    I2C: embedded_hal_async::i2c::I2c,
+{
+    type Error = Error<I2C>;
+
+    type Ok = R;
+
+    async fn from_i2c_async(bus: &mut I2C, i2c_address: u8) -> Result<Self::Ok, Self::Error> {
+        let mut register_bytes = [0u8; 2];
+        bus.write_read(i2c_address, &R::address().as_bytes(), &mut register_bytes)
+            .await
+            .map_err(Error::I2cError)?;
+        Ok(R::from(&register_bytes[..]))
+    }
+}
+
+#[cfg(feature = "async")]
+impl<I2C, R> AsyncToI2C<I2C> for R
+where
+    R: Copy + Register,
+    I2C: embedded_hal_async::i2c::I2c,
+{
+    type Error = Error<I2C>;
+
+    async fn to_i2c_async(&self, bus: &mut I2C, i2c_address: u8) -> Result<(), Self::Error> {
+        let register_address = R::address().as_bytes();
+        let register_bytes: [u8; 2] = (*self).into();
+        let combined: [u8; 4] = [
+            register_address[0],
+            register_address[1],
+            register_bytes[0],
+            register_bytes[1],
+        ];
+        bus.write(i2c_address, &combined)
+            .await
+            .map_err(Error::I2cError)
+    }
+}
+
 /// Expose the fields of the status register (0x8000).
 #[derive(Clone, Copy, Debug)]
 pub struct StatusRegister(u16);
@@ -206,6 +250,7 @@ impl From<StatusRegister> for [u8; 2] {
 
 /// Expose the fields of the control register (0x800D).
 #[derive(Clone, Copy, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct ControlRegister(u16);
 
 impl ControlRegister {
@@ -533,6 +578,7 @@ impl From<I2cRegister> for [u8; 2] {
 
 /// Identify which subpage to access.
 #[derive(Clone, Copy, Debug, Eq, PartialEq, PartialOrd, Ord, IntoPrimitive, TryFromPrimitive)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 #[repr(usize)]
 pub enum Subpage {
     Zero = 0,
@@ -578,6 +624,7 @@ pub enum Subpage {
 /// ```
 // NOTE: For the maximum frame rates, see "mlx9064x_timing.ods" in the repo for the calculations.
 #[derive(Clone, Copy, Debug, Eq, PartialEq, PartialOrd, Ord)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum FrameRate {
     /// 0.5 Hz, one frame every two seconds.
     Half,
@@ -605,6 +652,22 @@ pub enum FrameRate {
 }
 
 impl FrameRate {
+    /// How many times the camera's status is checked per frame while waiting for new data.
+    const POLLS_PER_FRAME: u32 = 16;
+
+    /// How long to wait between checks of the camera's status while waiting for new data.
+    ///
+    /// The status is checked 16 times per frame, so new data is noticed soon after it is ready
+    /// without keeping the bus busy.
+    /// ```
+    /// # use core::time::Duration;
+    /// # use mlx9064x::FrameRate;
+    /// assert_eq!(FrameRate::Two.poll_interval(), Duration::from_micros(31_250));
+    /// ```
+    pub fn poll_interval(&self) -> Duration {
+        Duration::from(*self) / Self::POLLS_PER_FRAME
+    }
+
     /// Attempt to create a `FrameRate` from a raw value from the camera.
     pub(crate) fn from_raw(raw_value: u16) -> Result<Self, LibraryError> {
         match raw_value {
@@ -635,6 +698,7 @@ impl FrameRate {
     }
 }
 
+#[allow(clippy::derivable_impls)]
 impl Default for FrameRate {
     fn default() -> Self {
         Self::Two
@@ -728,6 +792,7 @@ impl From<FrameRate> for Duration {
 ///
 /// [adc]: https://en.wikipedia.org/wiki/Analog-to-digital_converter
 #[derive(Clone, Copy, Debug, Eq, PartialEq, PartialOrd, Ord)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 #[repr(u8)]
 pub enum Resolution {
     /// 16-bit.
@@ -795,6 +860,7 @@ impl From<Resolution> for u8 {
     }
 }
 
+#[allow(clippy::derivable_impls)]
 impl Default for Resolution {
     fn default() -> Self {
         Self::Eighteen
@@ -820,6 +886,7 @@ impl Default for Resolution {
 /// 1 1 1 1 1 1 1 1
 /// ```
 #[derive(Clone, Copy, Debug, Eq, PartialEq, PartialOrd, Ord, IntoPrimitive, TryFromPrimitive)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 #[repr(u8)]
 pub enum AccessPattern {
     /// Pixels alternate between subpages, resulting in a chess or checker board pattern.
@@ -1208,4 +1275,30 @@ mod test {
     fn default_resolution() {
         assert_eq!(Resolution::default(), Resolution::Eighteen);
     }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn serde_round_trip() {
+        fn round_trip<T>(value: &T) -> T
+        where
+            T: serde::Serialize + serde::de::DeserializeOwned,
+        {
+            let mut buffer = [0u8; 8];
+            let serialized = postcard::to_slice(value, &mut buffer).unwrap();
+            postcard::from_bytes(serialized).unwrap()
+        }
+        let mut control = ControlRegister::default_mlx90640();
+        control.set_frame_rate(FrameRate::ThirtyTwo);
+        control.set_resolution(Resolution::Sixteen);
+        control.set_access_pattern(AccessPattern::Interleave);
+        let control_bytes: [u8; 2] = round_trip(&control).into();
+        assert_eq!(control_bytes, <[u8; 2]>::from(control));
+        assert_eq!(round_trip(&FrameRate::SixtyFour), FrameRate::SixtyFour);
+        assert_eq!(round_trip(&Resolution::Nineteen), Resolution::Nineteen);
+        assert_eq!(
+            round_trip(&AccessPattern::Interleave),
+            AccessPattern::Interleave
+        );
+        assert_eq!(round_trip(&Subpage::One), Subpage::One);
+    }
 }
@@ -0,0 +1,567 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright © 2021 Will Ross
+
+//! Calculating temperatures several pixels at a time with SIMD instructions.
+//!
+#![doc = include_str!("katex.html")]
+//!
+//! Most of the time spent calculating temperatures is in the two fourth roots per pixel in
+//! [`per_pixel_temperature`]. The functions in this module calculate [`LANES`] temperatures at
+//! once using SSE on x86 and NEON on 64-bit ARM, with the fourth roots calculated as two square
+//! roots. On other architectures the scalar calculations are used instead. The pixels are first
+//! calculated for the basic temperature range, and any that fall into one of the extended ranges
+//! are then recalculated one at a time (see [`TemperatureRanges`]).
+//!
+//! The results are not bit-for-bit identical to the scalar calculations in [`calculations`], as
+//! $\sqrt{\sqrt{x}}$ is rounded differently than $x^{0.25}$. For the example frames in the test
+//! data the temperatures are within two units in the last place (less than 0.0001 ℃) of the scalar
+//! calculations.
+//!
+//! The high-level drivers can use this module instead of [`calculations`] by calling
+//! [`set_use_simd`][crate::CameraDriver::set_use_simd].
+//!
+//! Each batch of pixels shares one $T_{a - r}$, so when given an [emissivity
+//! map][crate::emissivity] the functions in this module use the scalar calculations instead.
+//!
+//! [`calculations`]: crate::calculations
+//! [`per_pixel_temperature`]: crate::calculations::per_pixel_temperature
+use core::convert::TryInto;
+
+use crate::calculations::{
+    self, per_pixel_v_ir, sensitivity_correction_coefficient, t_ar, RamData, SubpageValues,
+    TemperatureRanges, KELVINS_TO_CELSIUS,
+};
+use crate::common::{CalibrationData, MelexisCamera};
+use crate::emissivity::Emissivity;
+use crate::filter::CompensationPixelFilter;
+use crate::register::{AccessPattern, Subpage};
+
+/// The number of pixels calculated at once.
+pub const LANES: usize = 4;
+
+/// Calculate the temperatures for [`LANES`] pixels in the basic temperature range.
+///
+/// This is the vector equivalent of
+/// [`per_pixel_temperature`][crate::calculations::per_pixel_temperature].
+#[cfg(any(
+    target_arch = "x86_64",
+    all(target_arch = "x86", target_feature = "sse")
+))]
+#[inline]
+fn basic_temperatures(
+    v_ir: [f32; LANES],
+    alpha: [f32; LANES],
+    t_ar: f32,
+    k_s_to: f32,
+) -> [f32; LANES] {
+    // SAFETY: SSE is always available on x86_64, and the cfg above checks that it's enabled for
+    // 32-bit x86.
+    unsafe { basic_temperatures_sse(v_ir, alpha, t_ar, k_s_to) }
+}
+
+#[cfg(any(
+    target_arch = "x86_64",
+    all(target_arch = "x86", target_feature = "sse")
+))]
+#[target_feature(enable = "sse")]
+fn basic_temperatures_sse(
+    v_ir: [f32; LANES],
+    alpha: [f32; LANES],
+    t_ar: f32,
+    k_s_to: f32,
+) -> [f32; LANES] {
+    #[cfg(target_arch = "x86")]
+    use core::arch::x86::*;
+    #[cfg(target_arch = "x86_64")]
+    use core::arch::x86_64::*;
+
+    let fourth_root = |value| _mm_sqrt_ps(_mm_sqrt_ps(value));
+    let v_ir: __m128 = bytemuck::cast(v_ir);
+    let alpha: __m128 = bytemuck::cast(alpha);
+    let t_ar_lanes = _mm_set1_ps(t_ar);
+    // Matching how powi(3) and powi(4) are calculated in the scalar version.
+    let alpha_2 = _mm_mul_ps(alpha, alpha);
+    let alpha_3 = _mm_mul_ps(alpha, alpha_2);
+    let alpha_4 = _mm_mul_ps(alpha_2, alpha_2);
+    let s_x = _mm_mul_ps(
+        _mm_set1_ps(k_s_to),
+        fourth_root(_mm_add_ps(
+            _mm_mul_ps(alpha_3, v_ir),
+            _mm_mul_ps(alpha_4, t_ar_lanes),
+        )),
+    );
+    let denominator = _mm_add_ps(
+        _mm_mul_ps(alpha, _mm_set1_ps(1f32 - k_s_to * KELVINS_TO_CELSIUS)),
+        s_x,
+    );
+    let t_o_root = fourth_root(_mm_add_ps(_mm_div_ps(v_ir, denominator), t_ar_lanes));
+    bytemuck::cast(_mm_sub_ps(t_o_root, _mm_set1_ps(KELVINS_TO_CELSIUS)))
+}
+
+#[cfg(target_arch = "aarch64")]
+#[inline]
+fn basic_temperatures(
+    v_ir: [f32; LANES],
+    alpha: [f32; LANES],
+    t_ar: f32,
+    k_s_to: f32,
+) -> [f32; LANES] {
+    // SAFETY: NEON is always available on aarch64.
+    unsafe { basic_temperatures_neon(v_ir, alpha, t_ar, k_s_to) }
+}
+
+#[cfg(target_arch = "aarch64")]
+#[target_feature(enable = "neon")]
+fn basic_temperatures_neon(
+    v_ir: [f32; LANES],
+    alpha: [f32; LANES],
+    t_ar: f32,
+    k_s_to: f32,
+) -> [f32; LANES] {
+    use core::arch::aarch64::*;
+
+    let fourth_root = |value| vsqrtq_f32(vsqrtq_f32(value));
+    let v_ir: float32x4_t = bytemuck::cast(v_ir);
+    let alpha: float32x4_t = bytemuck::cast(alpha);
+    let t_ar_lanes = vdupq_n_f32(t_ar);
+    // Matching how powi(3) and powi(4) are calculated in the scalar version.
+    let alpha_2 = vmulq_f32(alpha, alpha);
+    let alpha_3 = vmulq_f32(alpha, alpha_2);
+    let alpha_4 = vmulq_f32(alpha_2, alpha_2);
+    let s_x = vmulq_f32(
+        vdupq_n_f32(k_s_to),
+        fourth_root(vaddq_f32(
+            vmulq_f32(alpha_3, v_ir),
+            vmulq_f32(alpha_4, t_ar_lanes),
+        )),
+    );
+    let denominator = vaddq_f32(
+        vmulq_f32(alpha, vdupq_n_f32(1f32 - k_s_to * KELVINS_TO_CELSIUS)),
+        s_x,
+    );
+    let t_o_root = fourth_root(vaddq_f32(vdivq_f32(v_ir, denominator), t_ar_lanes));
+    bytemuck::cast(vsubq_f32(t_o_root, vdupq_n_f32(KELVINS_TO_CELSIUS)))
+}
+
+#[cfg(not(any(
+    target_arch = "x86_64",
+    all(target_arch = "x86", target_feature = "sse"),
+    target_arch = "aarch64"
+)))]
+#[inline]
+fn basic_temperatures(
+    v_ir: [f32; LANES],
+    alpha: [f32; LANES],
+    t_ar: f32,
+    k_s_to: f32,
+) -> [f32; LANES] {
+    let mut temperatures = [0f32; LANES];
+    temperatures
+        .iter_mut()
+        .zip(v_ir.iter().zip(alpha.iter()))
+        .for_each(|(temperature, (v_ir, alpha))| {
+            *temperature = crate::calculations::per_pixel_temperature(*v_ir, *alpha, t_ar, k_s_to)
+        });
+    temperatures
+}
+
+/// Pixels waiting to have their temperatures calculated.
+#[derive(Debug, Default)]
+struct Batch {
+    indices: [usize; LANES],
+    v_ir: [f32; LANES],
+    alpha: [f32; LANES],
+    length: usize,
+}
+
+impl Batch {
+    /// Add a pixel to the batch, calculating the batch if it's full.
+    #[inline]
+    fn push(
+        &mut self,
+        index: usize,
+        v_ir: f32,
+        alpha: f32,
+        ranges: &TemperatureRanges,
+        t_ar: f32,
+        destination: &mut [f32],
+    ) {
+        self.indices[self.length] = index;
+        self.v_ir[self.length] = v_ir;
+        self.alpha[self.length] = alpha;
+        self.length += 1;
+        if self.length == LANES {
+            self.flush(ranges, t_ar, destination);
+        }
+    }
+
+    /// Calculate the temperatures for the pixels in the batch, and write them to `destination`.
+    fn flush(&mut self, ranges: &TemperatureRanges, t_ar: f32, destination: &mut [f32]) {
+        if self.length == 0 {
+            return;
+        }
+        // Pad out a partial batch by repeating the first pixel, so every pixel is calculated the
+        // same way.
+        for lane in self.length..LANES {
+            self.v_ir[lane] = self.v_ir[0];
+            self.alpha[lane] = self.alpha[0];
+        }
+        let estimates = basic_temperatures(self.v_ir, self.alpha, t_ar, ranges.basic_k_s_to());
+        for lane in 0..self.length {
+            destination[self.indices[lane]] =
+                ranges.refine_estimate(self.v_ir[lane], self.alpha[lane], t_ar, estimates[lane]);
+        }
+        self.length = 0;
+    }
+}
+
+/// Calculate the temperatures for contiguous slices of pixels.
+///
+/// This is equivalent to calling
+/// [`TemperatureRanges::per_pixel_temperature`] for each pixel, with the $V_{IR}$ values in
+/// `v_ir`, the compensated sensitivities in `alpha`, and $T_{a - r}$ in `t_ar`. The temperatures
+/// are written to `destination`. If the slices are different lengths, the extra values in the
+/// longer slices are ignored.
+#[doc = include_str!("katex.html")]
+pub fn per_pixel_temperatures(
+    ranges: &TemperatureRanges,
+    v_ir: &[f32],
+    alpha: &[f32],
+    t_ar: f32,
+    destination: &mut [f32],
+) {
+    let length = v_ir.len().min(alpha.len()).min(destination.len());
+    let mut batch = Batch::default();
+    v_ir[..length]
+        .iter()
+        .zip(alpha[..length].iter())
+        .enumerate()
+        .for_each(|(index, (v_ir, alpha))| {
+            batch.push(index, *v_ir, *alpha, ranges, t_ar, destination)
+        });
+    batch.flush(ranges, t_ar, destination);
+}
+
+/// Calculate the temperature for all pixels, starting from the raw IR data.
+///
+/// This is the SIMD equivalent of
+/// [`raw_ir_to_temperatures`][crate::calculations::raw_ir_to_temperatures], and takes the same
+/// arguments. If `emissivity` is an [`Emissivity::Map`], the scalar version is used instead.
+pub fn raw_ir_to_temperatures<'a, 'm, Clb, Px>(
+    calibration: &'a Clb,
+    emissivity: impl Into<Emissivity<'m>>,
+    t_a: f32,
+    t_r: Option<f32>,
+    subpage: Subpage,
+    valid_pixels: &mut Px,
+    destination: &mut [f32],
+) where
+    Clb: CalibrationData<'a>,
+    Px: Iterator<Item = bool>,
+{
+    let emissivity = match emissivity.into() {
+        Emissivity::Uniform(emissivity) => emissivity,
+        map => {
+            return calculations::raw_ir_to_temperatures(
+                calibration,
+                map,
+                t_a,
+                t_r,
+                subpage,
+                valid_pixels,
+                destination,
+            )
+        }
+    };
+    let alpha_compensation_pixel = calibration
+        .temperature_gradient_coefficient()
+        .map(|tgc| calibration.alpha_cp(subpage) * tgc);
+    let temperature_ranges = TemperatureRanges::new(calibration);
+    let alpha_coefficient = sensitivity_correction_coefficient(calibration, t_a);
+    let t_r = t_r.unwrap_or(t_a - Clb::Camera::SELF_HEATING);
+    let t_ar = t_ar(t_a, t_r, emissivity);
+
+    let mut batch = Batch::default();
+    for (index, alpha) in calibration
+        .alpha_pixels(subpage)
+        .enumerate()
+        .take(destination.len())
+        // filter out the pixels that aren't part of this subpage
+        .filter(|_| valid_pixels.next().unwrap_or_default())
+    {
+        let compensated_alpha = match alpha_compensation_pixel {
+            Some(alpha_compensation_pixel) => alpha - alpha_compensation_pixel,
+            None => *alpha,
+        } * alpha_coefficient;
+        let v_ir = destination[index];
+        batch.push(
+            index,
+            v_ir,
+            compensated_alpha,
+            &temperature_ranges,
+            t_ar,
+            destination,
+        );
+    }
+    batch.flush(&temperature_ranges, t_ar, destination);
+}
+
+/// Calculate the temperature from all pixels, starting with the raw data from the camera.
+///
+/// This is the SIMD equivalent of
+/// [`raw_pixels_to_temperatures`][crate::calculations::raw_pixels_to_temperatures], and takes the
+/// same arguments. The $V_{IR}$ values are calculated one pixel at a time, then the temperatures
+/// [`LANES`] pixels at a time. If `emissivity` is an [`Emissivity::Map`], the scalar version is
+/// used instead.
+#[doc = include_str!("katex.html")]
+#[allow(clippy::too_many_arguments)]
+pub fn raw_pixels_to_temperatures<'a, 'm, Clb, Px>(
+    calibration: &'a Clb,
+    emissivity: impl Into<Emissivity<'m>>,
+    t_r: Option<f32>,
+    resolution_correction: f32,
+    pixel_data: &[u8],
+    ram: RamData,
+    compensation_filter: Option<&mut CompensationPixelFilter>,
+    subpage: Subpage,
+    access_pattern: AccessPattern,
+    valid_pixels: &mut Px,
+    destination: &mut [f32],
+) -> f32
+where
+    Clb: CalibrationData<'a>,
+    Px: Iterator<Item = bool>,
+{
+    let emissivity = emissivity.into();
+    let values = SubpageValues::new(
+        calibration,
+        emissivity,
+        resolution_correction,
+        &ram,
+        compensation_filter,
+        subpage,
+        access_pattern,
+    );
+    temperatures_with_values(
+        calibration,
+        emissivity,
+        &values,
+        t_r,
+        pixel_data,
+        subpage,
+        access_pattern,
+        valid_pixels,
+        destination,
+    )
+}
+
+/// [`raw_pixels_to_temperatures`], with the per-subpage values already calculated.
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn temperatures_with_values<'a, 'm, Clb, Px>(
+    calibration: &'a Clb,
+    emissivity: Emissivity<'m>,
+    values: &SubpageValues,
+    t_r: Option<f32>,
+    pixel_data: &[u8],
+    subpage: Subpage,
+    access_pattern: AccessPattern,
+    valid_pixels: &mut Px,
+    destination: &mut [f32],
+) -> f32
+where
+    Clb: CalibrationData<'a>,
+    Px: Iterator<Item = bool>,
+{
+    let emissivity = match emissivity {
+        Emissivity::Uniform(emissivity) => emissivity,
+        map => {
+            return calculations::temperatures_with_values(
+                calibration,
+                map,
+                values,
+                t_r,
+                pixel_data,
+                subpage,
+                access_pattern,
+                valid_pixels,
+                destination,
+            )
+        }
+    };
+    let common = &values.common;
+    let compensation_pixel_offset = values.compensation_pixel_offset;
+    let alpha_compensation_pixel = calibration
+        .temperature_gradient_coefficient()
+        .map(|tgc| calibration.alpha_cp(subpage) * tgc);
+    let temperature_ranges = TemperatureRanges::new(calibration);
+    let alpha_coefficient = sensitivity_correction_coefficient(calibration, common.t_a);
+    let t_r = t_r.unwrap_or(common.t_a - Clb::Camera::SELF_HEATING);
+    let t_ar = t_ar(common.t_a, t_r, emissivity);
+    let access_mode_compensation = calibration
+        .access_pattern_compensation_pixels(access_pattern)
+        .map(|o| o.copied());
+
+    let mut batch = Batch::default();
+    for (
+        index,
+        (((((pixel_slice, reference_offset), k_v), k_ta), alpha), access_mode_compensation),
+    ) in pixel_data
+        .chunks_exact(2)
+        .take(destination.len())
+        .zip(calibration.offset_reference_pixels(subpage))
+        .zip(calibration.k_v_pixels(subpage))
+        .zip(calibration.k_ta_pixels(subpage))
+        .zip(calibration.alpha_pixels(subpage))
+        .zip(access_mode_compensation)
+        .enumerate()
+        // filter out the pixels that aren't part of this subpage
+        .filter(|_| valid_pixels.next().unwrap_or_default())
+    {
+        // Safe to unwrap as this is from chunks_exact(2)
+        let pixel_bytes: [u8; 2] = pixel_slice.try_into().unwrap();
+        let pixel_data = i16::from_be_bytes(pixel_bytes);
+        let mut v_ir = per_pixel_v_ir(
+            pixel_data,
+            common,
+            *reference_offset,
+            *k_v,
+            *k_ta,
+            access_mode_compensation,
+        );
+        if let Some(compensation_pixel_offset) = compensation_pixel_offset {
+            v_ir -= compensation_pixel_offset;
+        }
+        let compensated_alpha = match alpha_compensation_pixel {
+            Some(alpha_compensation_pixel) => alpha - alpha_compensation_pixel,
+            None => *alpha,
+        } * alpha_coefficient;
+        batch.push(
+            index,
+            v_ir,
+            compensated_alpha,
+            &temperature_ranges,
+            t_ar,
+            destination,
+        );
+    }
+    batch.flush(&temperature_ranges, t_ar, destination);
+    common.t_a
+}
+
+#[cfg(test)]
+#[allow(clippy::excessive_precision)]
+mod test {
+    extern crate std;
+
+    use std::vec;
+
+    use mlx9064x_test_data::{compare_images, example_mlx90640_at_address, mlx90640_example_data};
+
+    use crate::calculations::{self, TemperatureRanges};
+    use crate::common::FromI2C;
+    use crate::mlx90640::{Mlx90640, Mlx90640Calibration};
+    use crate::test_frames::TestSubpage;
+    use crate::{AccessPattern, MelexisCamera};
+
+    /// The largest difference allowed between the scalar and SIMD temperatures, in units in the
+    /// last place of the temperature in Kelvin (where the fourth roots are taken).
+    const MAX_ULPS: u32 = 2;
+
+    /// The largest difference allowed between the scalar and SIMD temperatures for whole frames.
+    ///
+    /// This is a few units in the last place for room temperatures in Kelvin.
+    const MAX_FRAME_ERROR: f32 = 1e-4;
+
+    /// Assert that two temperatures (in Celsius) are within [`MAX_ULPS`] of each other in Kelvin.
+    fn assert_close(expected: f32, actual: f32) {
+        let expected_kelvin = expected + calculations::KELVINS_TO_CELSIUS;
+        let actual_kelvin = actual + calculations::KELVINS_TO_CELSIUS;
+        let ulps = expected_kelvin.to_bits().abs_diff(actual_kelvin.to_bits());
+        assert!(
+            ulps <= MAX_ULPS,
+            "{} is {} ULPs away from {}",
+            actual,
+            ulps,
+            expected
+        );
+    }
+
+    #[test]
+    fn batch_ranges() {
+        let calibration = Mlx90640Calibration::from_data(mlx90640_example_data::EEPROM_DATA)
+            .expect("The example EEPROM data should be valid");
+        let ranges = TemperatureRanges::new(&calibration);
+        let alpha = 1.1876487360496E-7;
+        let t_ar = 9516495632.56;
+        // From a very cold pixel up to a very hot one, covering every temperature range. 4001
+        // values also leaves a partial batch at the end.
+        let v_ir: vec::Vec<f32> = (0..=4_000).map(|n| -500.0 + n as f32 * 5.0).collect();
+        let alphas = vec![alpha; v_ir.len()];
+        let mut actual = vec![f32::NAN; v_ir.len()];
+        super::per_pixel_temperatures(&ranges, &v_ir, &alphas, t_ar, &mut actual);
+        for (v_ir, actual) in v_ir.iter().zip(actual.iter()) {
+            assert_close(ranges.per_pixel_temperature(*v_ir, alpha, t_ar), *actual);
+        }
+    }
+
+    #[test]
+    fn example_frames() {
+        let mut bus = example_mlx90640_at_address(0x33);
+        let calibration = Mlx90640Calibration::from_i2c(&mut bus, 0x33).unwrap();
+        for (access_pattern, emissivity) in [
+            (AccessPattern::Chess, 1.0),
+            (AccessPattern::Chess, 0.95),
+            (AccessPattern::Interleave, 1.0),
+        ] {
+            let subpages = TestSubpage::read_both::<_, _, { Mlx90640::HEIGHT }>(
+                &calibration,
+                &mut bus,
+                access_pattern,
+            );
+            for subpage in subpages {
+                let mut expected = [f32::NAN; Mlx90640::NUM_PIXELS];
+                let t_a = subpage.temperatures(&calibration, emissivity, None, &mut expected);
+                let mut actual = [f32::NAN; Mlx90640::NUM_PIXELS];
+                let simd_t_a = super::raw_pixels_to_temperatures(
+                    &calibration,
+                    emissivity,
+                    None,
+                    subpage.resolution_correction,
+                    &subpage.pixel_bytes,
+                    subpage.ram,
+                    None,
+                    subpage.subpage,
+                    access_pattern,
+                    &mut subpage.valid_pixels::<Mlx90640>().into_iter(),
+                    &mut actual,
+                );
+                assert_eq!(t_a, simd_t_a);
+                compare_images(&expected, &actual, MAX_FRAME_ERROR);
+                // The split version should give the same results
+                let mut split = [f32::NAN; Mlx90640::NUM_PIXELS];
+                calculations::raw_pixels_to_ir_data(
+                    &calibration,
+                    emissivity,
+                    subpage.resolution_correction,
+                    &subpage.pixel_bytes,
+                    subpage.ram,
+                    None,
+                    subpage.subpage,
+                    access_pattern,
+                    &mut subpage.valid_pixels::<Mlx90640>().into_iter(),
+                    &mut split,
+                );
+                super::raw_ir_to_temperatures(
+                    &calibration,
+                    emissivity,
+                    t_a,
+                    None,
+                    subpage.subpage,
+                    &mut subpage.valid_pixels::<Mlx90640>().into_iter(),
+                    &mut split,
+                );
+                compare_images(&actual, &split, 0.0);
+            }
+        }
+    }
+}
@@ -0,0 +1,545 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright © 2021 Will Ross
+
+//! Generating raw camera RAM from a simulated scene.
+//!
+//! This module runs the [temperature calculations][crate::calculations] in reverse. Given a
+//! camera's [`CalibrationData`], the [`Conditions`] the camera is operating in, and the temperature
+//! each pixel should see, [`generate_ram`] fills in a copy of the camera's RAM with the pixel
+//! values, $T_{a_{V_{BE}}}$, $T_{a_{PTAT}}$, $V_{DD_{pix}}$, gain, and compensation pixel that
+//! would produce those temperatures. The RAM buffer starts at address 0x0400, so it can be loaded
+//! straight into a mocked camera (like `MockCameraBus::update_frame` from the
+//! `mlx9064x-test-data` crate) to test applications without any hardware.
+//!
+//! ```
+//! use mlx9064x::mlx90640::{Mlx90640, Mlx90640Calibration};
+//! use mlx9064x::simulation::{generate_ram, status_register, Conditions};
+//! use mlx9064x::{AccessPattern, MelexisCamera, Mlx90640Driver, Subpage};
+//! use mlx9064x_test_data::{
+//!     example_mlx90640_at_address, mlx90640_example_data, MLX90640_RAM_LENGTH,
+//! };
+//!
+//! let mut bus = example_mlx90640_at_address(0x33);
+//! let mut camera = Mlx90640Driver::new(bus.clone(), 0x33).unwrap();
+//! let calibration = Mlx90640Calibration::from_data(mlx90640_example_data::EEPROM_DATA)?;
+//! // A warm object in the middle of a room temperature scene
+//! let mut scene = [20f32; Mlx90640::NUM_PIXELS];
+//! scene[12 * Mlx90640::WIDTH + 16] = 37.0;
+//! let mut ram = [0u8; MLX90640_RAM_LENGTH];
+//! let conditions = Conditions::new(25.0);
+//! let mut temperatures = [0f32; Mlx90640::NUM_PIXELS];
+//! for subpage in [Subpage::Zero, Subpage::One] {
+//!     let pattern = AccessPattern::Chess;
+//!     generate_ram(&calibration, &conditions, 1.0, subpage, pattern, &scene, &mut ram)?;
+//!     bus.update_frame(&ram, &status_register(subpage));
+//!     assert!(camera.generate_image_if_ready(&mut temperatures).unwrap());
+//! }
+//! assert!((temperatures[12 * Mlx90640::WIDTH + 16] - 37.0).abs() < 0.25);
+//! # Ok::<(), mlx9064x::LibraryError>(())
+//! ```
+//!
+//! The RAM values are integers, so the temperatures calculated from the generated RAM will not
+//! exactly match the requested temperatures. The ambient temperature and supply voltage are
+//! typically within a hundredth of a degree or volt. A step of one in a pixel value is roughly a
+//! tenth to a quarter of a degree (depending on the pixel's sensitivity and the temperature), so
+//! pixel temperatures are within a few tenths of a degree.
+#![doc = include_str!("katex.html")]
+
+#[cfg_attr(feature = "std", allow(unused_imports))]
+use num_traits::Float;
+
+use crate::calculations::{
+    compensation_pixel_offset, sensitivity_correction_coefficient, t_ar, CommonIrData, RamData,
+    TemperatureRanges, KELVINS_TO_CELSIUS,
+};
+use crate::common::{Address, CalibrationData, MelexisCamera};
+use crate::error::LibraryError;
+use crate::register::{AccessPattern, Subpage};
+
+/// The first address of camera RAM.
+const RAM_START: u16 = 0x0400;
+
+/// The $T_{a_{V_{BE}}}$ value to aim for when generating $T_{a_{PTAT}}$.
+///
+/// Both values are needed to get an ambient temperature, but only their ratio matters. This value
+/// is close to what real cameras report at room temperature.
+const NOMINAL_V_BE: f32 = 19_500f32;
+
+/// The most iterations to use when solving for a pixel's IR measurement.
+const MAX_ITERATIONS: usize = 32;
+
+/// How close (in degrees Celsius) a solved pixel temperature needs to be to the target.
+const TEMPERATURE_TOLERANCE: f32 = 1e-4;
+
+/// The environment a simulated frame is captured in.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Conditions {
+    /// The temperature of the sensor itself ($T_a$), in degrees Celsius.
+    pub ambient_temperature: f32,
+
+    /// The emissivity ($\varepsilon$) of the objects in the scene.
+    ///
+    /// This needs to match the emissivity used when calculating temperatures from the generated
+    /// RAM.
+    pub emissivity: f32,
+
+    /// The reflected temperature ($T_r$), in degrees Celsius.
+    ///
+    /// Like the high-level API, if this is `None` the ambient temperature minus the
+    /// [expected self-heating][MelexisCamera::SELF_HEATING] is used.
+    pub reflected_temperature: Option<f32>,
+
+    /// The pixel supply voltage ($V_{DD}$), in volts.
+    pub supply_voltage: f32,
+}
+
+impl Conditions {
+    /// Create a new set of conditions at the given ambient temperature.
+    ///
+    /// The other values are the defaults for the high-level API: an emissivity of 1, no reflected
+    /// temperature, and the nominal supply voltage of 3.3V.
+    pub fn new(ambient_temperature: f32) -> Self {
+        Self {
+            ambient_temperature,
+            emissivity: 1f32,
+            reflected_temperature: None,
+            supply_voltage: 3.3f32,
+        }
+    }
+}
+
+/// The status register value for a camera that has just measured `subpage`.
+///
+/// The new data flag is set, so a driver polling the camera will see the frame as ready.
+pub fn status_register(subpage: Subpage) -> [u8; 2] {
+    // The subpage is the lowest three bits, the new data flag is bit 3.
+    (0x0008 | subpage as u16).to_be_bytes()
+}
+
+/// Round a calculated value and convert it to a RAM word.
+fn to_word(value: f32) -> Result<i16, LibraryError> {
+    let rounded = value.round();
+    // NaNs fail both comparisons, so they're also caught here.
+    if rounded >= f32::from(i16::MIN) && rounded <= f32::from(i16::MAX) {
+        Ok(rounded as i16)
+    } else {
+        Err(LibraryError::InvalidData(
+            "The simulated value does not fit in a RAM word",
+        ))
+    }
+}
+
+/// Write a single word into the RAM buffer.
+fn write_word(ram: &mut [u8], address: Address, value: i16) -> Result<(), LibraryError> {
+    let offset = u16::from(address)
+        .checked_sub(RAM_START)
+        .map(|word_offset| usize::from(word_offset) * 2)
+        .ok_or(LibraryError::Other("The address is not in camera RAM"))?;
+    ram.get_mut(offset..(offset + 2))
+        .ok_or(LibraryError::Other("The RAM buffer is too small"))?
+        .copy_from_slice(&value.to_be_bytes());
+    Ok(())
+}
+
+/// Find where in RAM a pixel is stored for a subpage.
+fn pixel_address<Cam: MelexisCamera>(
+    subpage: Subpage,
+    access_pattern: AccessPattern,
+    pixel_index: usize,
+) -> Option<Address> {
+    let byte_offset = pixel_index * 2;
+    Cam::pixel_ranges(subpage, access_pattern)
+        .into_iter()
+        .find(|range| {
+            (range.buffer_offset..(range.buffer_offset + range.length)).contains(&byte_offset)
+        })
+        .map(|range| {
+            let word_offset = (byte_offset - range.buffer_offset) / 2;
+            Address::new(u16::from(range.start_address) + word_offset as u16)
+        })
+}
+
+/// Solve for the IR measurement ($V_{IR}$) that gives `temperature`.
+///
+/// The temperature calculation can't be rearranged for $V_{IR}$ (and switching temperature ranges
+/// makes it discontinuous), so this uses the secant method on the forward calculation. The first
+/// guess ignores the sensitivity slope, which is a small correction.
+fn solve_v_ir(ranges: &TemperatureRanges, alpha: f32, t_ar: f32, temperature: f32) -> f32 {
+    let target_k = temperature + KELVINS_TO_CELSIUS;
+    let error = |v_ir: f32| ranges.per_pixel_temperature(v_ir, alpha, t_ar) - temperature;
+    // The derivative of the guess, used to take a first step towards the target.
+    let slope = 4f32 * alpha * target_k.powi(3);
+    let mut previous = alpha * (target_k.powi(4) - t_ar);
+    let mut previous_error = error(previous);
+    let mut current = previous - previous_error * slope;
+    for _ in 0..MAX_ITERATIONS {
+        let current_error = error(current);
+        if current_error.abs() < TEMPERATURE_TOLERANCE || current_error == previous_error {
+            break;
+        }
+        let next =
+            current - current_error * (current - previous) / (current_error - previous_error);
+        previous = current;
+        previous_error = current_error;
+        current = next;
+    }
+    current
+}
+
+/// Generate the RAM contents for a camera measuring a scene.
+///
+/// `temperatures` is the temperature (in degrees Celsius) each pixel should measure, in the same
+/// order as the images generated by the rest of this crate. Only the pixels belonging to `subpage`
+/// are written to `ram`, along with the non-pixel values from [`RamData`] for that subpage. The
+/// other pixels are left untouched, like how a real camera keeps the previous subpage's data in
+/// RAM.
+///
+/// `ram` is a copy of the camera's RAM starting from address 0x0400, with every value stored as a
+/// big-endian word. The returned `RamData` has the non-pixel values written to `ram`.
+///
+/// `resolution_correction` should be calculated the same way as when processing the generated
+/// RAM (see [`MelexisCamera::resolution_correction`]); if the camera is using the ADC resolution it
+/// was calibrated at, the correction is 1.
+///
+/// An error is returned if `temperatures` doesn't have a value for every pixel, if `ram` is too
+/// small, or if a value can't be represented by the camera (for example a temperature far outside
+/// of the camera's range).
+pub fn generate_ram<'a, Clb>(
+    calibration: &'a Clb,
+    conditions: &Conditions,
+    resolution_correction: f32,
+    subpage: Subpage,
+    access_pattern: AccessPattern,
+    temperatures: &[f32],
+    ram: &mut [u8],
+) -> Result<RamData, LibraryError>
+where
+    Clb: CalibrationData<'a>,
+{
+    if temperatures.len() != Clb::Camera::NUM_PIXELS {
+        return Err(LibraryError::InvalidData(
+            "A temperature is needed for every pixel",
+        ));
+    }
+    // Start with the values shared by the entire frame. First, the supply voltage is
+    //   V_DD = (V_DD_pix - V_DD_25) / K_V_DD * resolution_corr + V_DD_0
+    let delta_v = (conditions.supply_voltage - calibration.v_dd_0()) / resolution_correction;
+    let v_dd_pixel =
+        to_word(delta_v * f32::from(calibration.k_v_dd()) + f32::from(calibration.v_dd_25()))?;
+    // The rest of the calculations use the actual values after rounding.
+    let delta_v = crate::calculations::delta_v(calibration, v_dd_pixel);
+    // Then the ambient temperature, working back to V_PTAT_art and then splitting that into the
+    // two RAM values. T_a_PTAT is a much coarser control on the temperature than T_a_V_BE, so it
+    // is picked first and then T_a_V_BE is solved for.
+    let v_ptat_art = ((conditions.ambient_temperature - 25f32) * calibration.k_t_ptat()
+        + calibration.v_ptat_25())
+        * (1f32 + calibration.k_v_ptat() * delta_v);
+    let ptat_scale = 18f32.exp2();
+    let t_a_ptat =
+        to_word(v_ptat_art * NOMINAL_V_BE / (ptat_scale - v_ptat_art * calibration.alpha_ptat()))?;
+    let t_a_v_be =
+        to_word(f32::from(t_a_ptat) * (ptat_scale / v_ptat_art - calibration.alpha_ptat()))?;
+    // Using the calibrated gain makes K_gain (very nearly) 1.
+    let gain = to_word(calibration.gain())?;
+    let mut ram_data = RamData {
+        t_a_v_be,
+        t_a_ptat,
+        v_dd_pixel,
+        gain,
+        compensation_pixel: 0,
+    };
+    let common = CommonIrData::new(
+        resolution_correction,
+        conditions.emissivity,
+        calibration,
+        &ram_data,
+    );
+    // Pick a compensation pixel value that cancels out its offset. The thermal gradient
+    // compensation is then only the rounding error (which is still accounted for below).
+    let compensation_pixel = to_word(
+        (f32::from(calibration.offset_reference_cp(subpage))
+            * (1f32 + calibration.k_ta_cp(subpage) * (common.t_a - 25f32))
+            * (1f32 + calibration.k_v_cp(subpage) * (common.v_dd - 3.3f32))
+            - calibration
+                .access_pattern_compensation_cp(subpage, access_pattern)
+                .unwrap_or_default())
+            / common.gain,
+    )?;
+    ram_data.compensation_pixel = compensation_pixel;
+    let compensation_pixel_offset = compensation_pixel_offset(
+        calibration,
+        &common,
+        compensation_pixel,
+        None,
+        subpage,
+        access_pattern,
+    )
+    .unwrap_or_default();
+    write_word(ram, Clb::Camera::T_A_V_BE, t_a_v_be)?;
+    write_word(ram, Clb::Camera::T_A_PTAT, t_a_ptat)?;
+    write_word(ram, Clb::Camera::V_DD_PIXEL, v_dd_pixel)?;
+    write_word(ram, Clb::Camera::GAIN, gain)?;
+    write_word(
+        ram,
+        Clb::Camera::compensation_pixel(subpage),
+        compensation_pixel,
+    )?;
+    // These are the same steps as in `raw_pixels_to_temperatures`.
+    let alpha_compensation_pixel = calibration
+        .temperature_gradient_coefficient()
+        .map(|tgc| calibration.alpha_cp(subpage) * tgc);
+    let temperature_ranges = TemperatureRanges::new(calibration);
+    let alpha_coefficient = sensitivity_correction_coefficient(calibration, common.t_a);
+    let t_r = conditions
+        .reflected_temperature
+        .unwrap_or(common.t_a - Clb::Camera::SELF_HEATING);
+    let t_ar = t_ar(common.t_a, t_r, conditions.emissivity);
+    let pixels = temperatures
+        .iter()
+        .zip(calibration.offset_reference_pixels(subpage))
+        .zip(calibration.k_v_pixels(subpage))
+        .zip(calibration.k_ta_pixels(subpage))
+        .zip(calibration.alpha_pixels(subpage))
+        .zip(calibration.access_pattern_compensation_pixels(access_pattern))
+        .zip(Clb::Camera::pixels_in_subpage(subpage, access_pattern))
+        .enumerate()
+        .filter(|(_, (_, valid))| *valid);
+    for (
+        index,
+        ((((((temperature, reference_offset), k_v), k_ta), alpha), access_mode_compensation), _),
+    ) in pixels
+    {
+        let compensated_alpha = match alpha_compensation_pixel {
+            Some(alpha_compensation_pixel) => alpha - alpha_compensation_pixel,
+            None => *alpha,
+        } * alpha_coefficient;
+        let v_ir = solve_v_ir(&temperature_ranges, compensated_alpha, t_ar, *temperature);
+        // And now undo `per_pixel_v_ir`
+        let pixel_offset = (v_ir + compensation_pixel_offset) * common.emissivity;
+        let pixel_gain = pixel_offset - access_mode_compensation.copied().unwrap_or_default()
+            + f32::from(*reference_offset)
+                * (1f32 + k_ta * (common.t_a - 25f32))
+                * (1f32 + k_v * (common.v_dd - 3.3f32));
+        let pixel = to_word(pixel_gain / common.gain)?;
+        let address = pixel_address::<Clb::Camera>(subpage, access_pattern, index).ok_or(
+            LibraryError::Other("The pixel is not read for this subpage"),
+        )?;
+        write_word(ram, address, pixel)?;
+    }
+    Ok(ram_data)
+}
+
+#[cfg(test)]
+mod test {
+    use float_cmp::assert_approx_eq;
+
+    use mlx9064x_test_data::*;
+
+    use crate::calculations::{ambient_temperature, delta_v, v_dd, v_ptat_art};
+    use crate::mlx90640::{Mlx90640, Mlx90640Calibration};
+    use crate::mlx90641::{Mlx90641, Mlx90641Calibration};
+    use crate::{
+        AccessPattern, LibraryError, MelexisCamera, Mlx90640Driver, Mlx90641Driver, StatusRegister,
+        Subpage,
+    };
+
+    use super::{generate_ram, status_register, Conditions};
+
+    /// A scene with a temperature gradient across it, from `low` to `high`.
+    fn gradient<const N: usize>(low: f32, high: f32) -> [f32; N] {
+        let mut scene = [0f32; N];
+        let step = (high - low) / (N - 1) as f32;
+        for (index, temperature) in scene.iter_mut().enumerate() {
+            *temperature = low + step * index as f32;
+        }
+        scene
+    }
+
+    fn assert_scene(actual: &[f32], expected: &[f32], tolerance: f32) {
+        for (index, (actual, expected)) in actual.iter().zip(expected).enumerate() {
+            assert!(
+                (actual - expected).abs() < tolerance,
+                "pixel {}: expected {}, got {}",
+                index,
+                expected,
+                actual
+            );
+        }
+    }
+
+    #[test]
+    fn status() {
+        for subpage in [Subpage::Zero, Subpage::One] {
+            let status = StatusRegister::from(&status_register(subpage)[..]);
+            assert!(status.new_data());
+            assert_eq!(status.last_updated_subpage(), subpage);
+        }
+    }
+
+    #[test]
+    fn common_values() {
+        let calibration = Mlx90640Calibration::from_data(&mlx90640_datasheet_eeprom()).unwrap();
+        let scene = [30f32; Mlx90640::NUM_PIXELS];
+        let mut ram = [0u8; MLX90640_RAM_LENGTH];
+        let mut conditions = Conditions::new(41.5);
+        conditions.supply_voltage = 3.25;
+        let ram_data = generate_ram(
+            &calibration,
+            &conditions,
+            1.0,
+            Subpage::Zero,
+            AccessPattern::Chess,
+            &scene,
+            &mut ram,
+        )
+        .unwrap();
+        let delta_v = delta_v(&calibration, ram_data.v_dd_pixel);
+        assert_approx_eq!(f32, v_dd(&calibration, 1.0, delta_v), 3.25, epsilon = 0.01);
+        let v_ptat_art = v_ptat_art(&calibration, ram_data.t_a_ptat, ram_data.t_a_v_be);
+        assert_approx_eq!(
+            f32,
+            ambient_temperature(&calibration, v_ptat_art, delta_v),
+            41.5,
+            epsilon = 0.01
+        );
+        // Check the values were written to the right places
+        let word = |address: u16| {
+            let offset = usize::from(address - 0x0400) * 2;
+            i16::from_be_bytes([ram[offset], ram[offset + 1]])
+        };
+        assert_eq!(word(0x0700), ram_data.t_a_v_be);
+        assert_eq!(word(0x0708), ram_data.compensation_pixel);
+        assert_eq!(word(0x070A), ram_data.gain);
+        assert_eq!(word(0x0720), ram_data.t_a_ptat);
+        assert_eq!(word(0x072A), ram_data.v_dd_pixel);
+    }
+
+    #[test]
+    fn mlx90640_driver() {
+        let address = 0x33;
+        let mut mocked = example_mlx90640_at_address(address);
+        let mut cam = Mlx90640Driver::new(mocked.clone(), address).unwrap();
+        // Simulated pixels don't fail, so nothing should be replaced.
+        cam.set_pixel_replacement(None);
+        cam.override_emissivity(0.95);
+        cam.set_reflected_temperature(Some(22.0));
+        let calibration =
+            Mlx90640Calibration::from_data(mlx90640_example_data::EEPROM_DATA).unwrap();
+        let scene = gradient::<{ Mlx90640::NUM_PIXELS }>(-10.0, 120.0);
+        let mut conditions = Conditions::new(30.0);
+        conditions.emissivity = 0.95;
+        conditions.reflected_temperature = Some(22.0);
+        let mut ram = [0u8; MLX90640_RAM_LENGTH];
+        let mut temperatures = [f32::NAN; Mlx90640::NUM_PIXELS];
+        for subpage in [Subpage::Zero, Subpage::One] {
+            generate_ram(
+                &calibration,
+                &conditions,
+                1.0,
+                subpage,
+                AccessPattern::Chess,
+                &scene,
+                &mut ram,
+            )
+            .unwrap();
+            mocked.update_frame(&ram, &status_register(subpage));
+            assert!(cam.generate_image_if_ready(&mut temperatures).unwrap());
+            assert_approx_eq!(
+                f32,
+                cam.ambient_temperature().unwrap(),
+                30.0,
+                epsilon = 0.01
+            );
+        }
+        assert_scene(&temperatures, &scene, 0.25);
+    }
+
+    #[test]
+    fn mlx90640_interleave() {
+        let calibration =
+            Mlx90640Calibration::from_data(mlx90640_example_data::EEPROM_DATA).unwrap();
+        let scene = gradient::<{ Mlx90640::NUM_PIXELS }>(15.0, 45.0);
+        let mut ram = [0u8; MLX90640_RAM_LENGTH];
+        generate_ram(
+            &calibration,
+            &Conditions::new(25.0),
+            1.0,
+            Subpage::One,
+            AccessPattern::Interleave,
+            &scene,
+            &mut ram,
+        )
+        .unwrap();
+        // Only the odd rows belong to subpage one, the rest of RAM is untouched.
+        let row_length = Mlx90640::WIDTH * 2;
+        for (row, bytes) in ram[..(Mlx90640::NUM_PIXELS * 2)]
+            .chunks_exact(row_length)
+            .enumerate()
+        {
+            assert_eq!(row % 2 == 1, bytes.iter().any(|b| *b != 0), "row {}", row);
+        }
+    }
+
+    #[test]
+    fn mlx90641_driver() {
+        let address = 0x33;
+        let mut mocked = mock_mlx90641_at_address(address);
+        let mut cam = Mlx90641Driver::new(mocked.clone(), address).unwrap();
+        cam.set_pixel_replacement(None);
+        let calibration = Mlx90641Calibration::from_data(&mlx90641_datasheet_eeprom()).unwrap();
+        // Going into the extended temperature ranges at both ends
+        let scene = gradient::<{ Mlx90641::NUM_PIXELS }>(-30.0, 250.0);
+        let mut conditions = Conditions::new(35.0);
+        conditions.emissivity = cam.effective_emissivity();
+        let mut ram = [0u8; MLX90641_RAM_LENGTH];
+        let mut temperatures = [f32::NAN; Mlx90641::NUM_PIXELS];
+        for subpage in [Subpage::Zero, Subpage::One] {
+            generate_ram(
+                &calibration,
+                &conditions,
+                1.0,
+                subpage,
+                AccessPattern::Interleave,
+                &scene,
+                &mut ram,
+            )
+            .unwrap();
+            mocked.update_frame(&ram, &status_register(subpage));
+            assert!(cam.generate_image_if_ready(&mut temperatures).unwrap());
+            assert_scene(&temperatures, &scene, 0.15);
+        }
+    }
+
+    #[test]
+    fn errors() {
+        let calibration = Mlx90640Calibration::from_data(&mlx90640_datasheet_eeprom()).unwrap();
+        let conditions = Conditions::new(25.0);
+        let mut ram = [0u8; MLX90640_RAM_LENGTH];
+        let generate = |scene: &[f32], ram: &mut [u8]| {
+            generate_ram(
+                &calibration,
+                &conditions,
+                1.0,
+                Subpage::Zero,
+                AccessPattern::Chess,
+                scene,
+                ram,
+            )
+        };
+        let too_short = [25f32; Mlx90640::NUM_PIXELS - 1];
+        assert!(matches!(
+            generate(&too_short, &mut ram),
+            Err(LibraryError::InvalidData(_))
+        ));
+        let mut scene = [25f32; Mlx90640::NUM_PIXELS];
+        assert!(matches!(
+            generate(&scene, &mut ram[..100]),
+            Err(LibraryError::Other(_))
+        ));
+        scene[0] = 5000.0;
+        assert!(matches!(
+            generate(&scene, &mut ram),
+            Err(LibraryError::InvalidData(_))
+        ));
+    }
+}
@@ -0,0 +1,121 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright © 2021 Will Ross
+
+//! Subpages read from mocked cameras, for the tests comparing the different ways of calculating
+//! temperatures.
+extern crate std;
+
+use std::vec;
+
+use mlx9064x_test_data::compare_images;
+
+use crate::calculations::{raw_pixels_to_temperatures, RamData};
+use crate::common::{read_ram, FromI2C, MelexisCamera};
+use crate::emissivity::Emissivity;
+use crate::{AccessPattern, CalibrationData, ControlRegister, Subpage};
+
+/// The address of the mocked cameras.
+const ADDRESS: u8 = 0x33;
+
+/// The raw data for one subpage.
+pub(crate) struct TestSubpage {
+    pub(crate) subpage: Subpage,
+    pub(crate) access_pattern: AccessPattern,
+    pub(crate) resolution_correction: f32,
+    pub(crate) pixel_bytes: vec::Vec<u8>,
+    pub(crate) ram: RamData,
+}
+
+impl TestSubpage {
+    /// Read both subpages from a mocked camera.
+    pub(crate) fn read_both<'a, Clb, I2C, const HEIGHT: usize>(
+        calibration: &'a Clb,
+        bus: &mut I2C,
+        access_pattern: AccessPattern,
+    ) -> [Self; 2]
+    where
+        Clb: CalibrationData<'a>,
+        I2C: embedded_hal::i2c::I2c,
+        I2C::Error: core::fmt::Debug,
+    {
+        let control = ControlRegister::from_i2c(bus, ADDRESS).unwrap();
+        let resolution_correction =
+            Clb::Camera::resolution_correction(calibration.resolution(), control.resolution());
+        [Subpage::Zero, Subpage::One].map(|subpage| {
+            let mut pixel_bytes = vec![0u8; Clb::Camera::NUM_PIXELS * 2];
+            let ram = read_ram::<Clb::Camera, _, HEIGHT>(
+                bus,
+                ADDRESS,
+                access_pattern,
+                subpage,
+                &mut pixel_bytes,
+            )
+            .unwrap();
+            Self {
+                subpage,
+                access_pattern,
+                resolution_correction,
+                pixel_bytes,
+                ram,
+            }
+        })
+    }
+
+    /// The pixels updated in this subpage.
+    pub(crate) fn valid_pixels<Cam: MelexisCamera>(&self) -> Cam::PixelsInSubpageIterator {
+        Cam::pixels_in_subpage(self.subpage, self.access_pattern)
+    }
+
+    /// Calculate the temperatures with [`raw_pixels_to_temperatures`], returning the ambient
+    /// temperature.
+    ///
+    /// Pixels not in this subpage are NaN.
+    pub(crate) fn temperatures<'a, 'm, Clb: CalibrationData<'a>>(
+        &self,
+        calibration: &'a Clb,
+        emissivity: impl Into<Emissivity<'m>>,
+        t_r: Option<f32>,
+        destination: &mut [f32],
+    ) -> f32 {
+        destination.fill(f32::NAN);
+        raw_pixels_to_temperatures(
+            calibration,
+            emissivity,
+            t_r,
+            self.resolution_correction,
+            &self.pixel_bytes,
+            self.ram,
+            None,
+            self.subpage,
+            self.access_pattern,
+            &mut self.valid_pixels::<Clb::Camera>().into_iter(),
+            destination,
+        )
+    }
+    /// Process both subpages from a mocked camera with `pipeline` and with
+    /// [`raw_pixels_to_temperatures`], checking that they're within `tolerance` of each other.
+    ///
+    /// `pipeline` is given each subpage and a destination filled with NaN, and returns the ambient
+    /// temperature it calculated, which must be the same.
+    pub(crate) fn compare_pipeline<'a, Clb, I2C, F, const HEIGHT: usize>(
+        calibration: &'a Clb,
+        bus: &mut I2C,
+        access_pattern: AccessPattern,
+        emissivity: f32,
+        tolerance: f32,
+        mut pipeline: F,
+    ) where
+        Clb: CalibrationData<'a>,
+        I2C: embedded_hal::i2c::I2c,
+        I2C::Error: core::fmt::Debug,
+        F: FnMut(&Self, &mut [f32]) -> f32,
+    {
+        for subpage in Self::read_both::<_, _, HEIGHT>(calibration, bus, access_pattern) {
+            let mut expected = vec![f32::NAN; Clb::Camera::NUM_PIXELS];
+            let t_a = subpage.temperatures(calibration, emissivity, None, &mut expected);
+            let mut actual = vec![f32::NAN; Clb::Camera::NUM_PIXELS];
+            assert_eq!(t_a, pipeline(&subpage, &mut actual));
+            compare_images(&expected, &actual, tolerance);
+        }
+    }
+}
@@ -0,0 +1,362 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright © 2021 Will Ross
+
+//! Upscaling thermal images.
+//!
+//! The cameras have a fairly low resolution (32×24 for the MLX90640, 16×12 for the MLX90641), so
+//! images usually need to be scaled up before being shown. [`upscale`] resizes an image (laid out
+//! the same way as in [`image`][crate::image]) into a caller-provided buffer, which can be any
+//! size. [`upscaled_size`] gives the size of the buffer for a scale factor, which doesn't need to
+//! be a whole number.
+//!
+//! ```
+//! use mlx9064x::mlx90641::Mlx90641;
+//! use mlx9064x::upscale::{upscale, upscaled_size, Interpolation};
+//! use mlx9064x::MelexisCamera;
+//!
+//! let temperatures = [21.5f32; Mlx90641::NUM_PIXELS];
+//! let (width, height) = upscaled_size::<Mlx90641>(2.5);
+//! assert_eq!((width, height), (40, 30));
+//! let mut upscaled = vec![0f32; width * height];
+//! // No pixels are flagged in this example
+//! let flagged = bitvec::bitarr![0; Mlx90641::NUM_PIXELS];
+//! upscale::<Mlx90641, _>(
+//!     &temperatures,
+//!     &flagged.as_bitslice(),
+//!     Interpolation::Bicubic,
+//!     width,
+//!     &mut upscaled,
+//! );
+//! assert!(upscaled
+//!     .iter()
+//!     .all(|temperature| (temperature - 21.5).abs() < 0.001));
+//! ```
+
+// Various floating point operations are not implemented in core, so we use libm to provide them as
+// needed.
+#[cfg_attr(feature = "std", allow(unused_imports))]
+use num_traits::Float;
+
+use crate::common::{FlaggedPixels, MelexisCamera};
+
+/// How to calculate the values between the pixels of the original image.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Interpolation {
+    /// Use the value of the closest pixel.
+    ///
+    /// This keeps the hard edges between pixels, and is the fastest.
+    Nearest,
+
+    /// Linear interpolation between the 4 closest pixels.
+    Bilinear,
+
+    /// Cubic interpolation between the 16 closest pixels.
+    ///
+    /// This uses the [Catmull-Rom spline][catmull-rom], which gives sharper images than
+    /// `Bilinear`, but can overshoot slightly around large changes in temperature.
+    ///
+    /// [catmull-rom]: https://en.wikipedia.org/wiki/Cubic_Hermite_spline#Catmull%E2%80%93Rom_spline
+    Bicubic,
+}
+
+/// The width and height of an image upscaled by `factor`, rounded to the nearest pixel.
+pub fn upscaled_size<Cam: MelexisCamera>(factor: f32) -> (usize, usize) {
+    let scale = |length: usize| (length as f32 * factor).round() as usize;
+    (scale(Cam::WIDTH), scale(Cam::HEIGHT))
+}
+
+/// The pixels (and their weights) used to interpolate along one axis.
+fn linear_taps(position: f32) -> [(isize, f32); 2] {
+    let floor = position.floor();
+    let fraction = position - floor;
+    let floor = floor as isize;
+    [(floor, 1f32 - fraction), (floor + 1, fraction)]
+}
+
+/// The Catmull-Rom kernel for a pixel `distance` away from the position being interpolated.
+fn cubic_weight(distance: f32) -> f32 {
+    let distance = distance.abs();
+    if distance < 1f32 {
+        (1.5 * distance - 2.5) * distance * distance + 1f32
+    } else if distance < 2f32 {
+        ((-0.5 * distance + 2.5) * distance - 4f32) * distance + 2f32
+    } else {
+        0f32
+    }
+}
+
+/// The pixels (and their weights) used to interpolate along one axis.
+fn cubic_taps(position: f32) -> [(isize, f32); 4] {
+    let floor = position.floor();
+    let fraction = position - floor;
+    let floor = floor as isize;
+    [
+        (floor - 1, cubic_weight(fraction + 1f32)),
+        (floor, cubic_weight(fraction)),
+        (floor + 1, cubic_weight(1f32 - fraction)),
+        (floor + 2, cubic_weight(2f32 - fraction)),
+    ]
+}
+
+/// Interpolate between the pixels given by `columns` and `rows`.
+///
+/// Pixels that are missing are skipped, and the weights of the remaining pixels are scaled up to
+/// compensate. If `partial` is false, `None` is returned if any of the pixels are missing instead.
+fn interpolate<P, const N: usize>(
+    pixel: P,
+    columns: [(isize, f32); N],
+    rows: [(isize, f32); N],
+    partial: bool,
+) -> Option<f32>
+where
+    P: Fn(isize, isize) -> Option<f32>,
+{
+    let mut sum = 0f32;
+    let mut total_weight = 0f32;
+    for (row, row_weight) in rows {
+        for (column, column_weight) in columns {
+            match pixel(column, row) {
+                Some(value) => {
+                    let weight = row_weight * column_weight;
+                    sum += value * weight;
+                    total_weight += weight;
+                }
+                None if !partial => return None,
+                None => (),
+            }
+        }
+    }
+    if total_weight > f32::EPSILON {
+        Some(sum / total_weight)
+    } else {
+        None
+    }
+}
+
+/// Upscale an image.
+///
+/// `image` is a full image from a camera (either temperatures or $V_{IR}$ data), and the upscaled
+/// image is written to `output`, which is `output_width` pixels wide. The height of the upscaled
+/// image is `output.len() / output_width`, and the scale factors are the ratios between the sizes
+/// of the two images. The centers of the pixels in both images are aligned, so the edges of the
+/// images line up.
+///
+/// Pixels flagged in `flagged` (typically from [`failed_pixels`]) and pixels that are NaN are not
+/// used directly. Instead they're filled in with the mean of the (up to) 8 surrounding pixels, the
+/// same as [`PixelReplacement::NeighbourMean`]. If none of the surrounding pixels can be used
+/// either, the pixel is skipped: [`Interpolation::Bilinear`] uses the remaining pixels,
+/// [`Interpolation::Nearest`] and [`Interpolation::Bicubic`] fall back to bilinear interpolation,
+/// and if there are no usable pixels at all the output is NaN.
+///
+/// [`failed_pixels`]: crate::CalibrationData::failed_pixels
+/// [`PixelReplacement::NeighbourMean`]: crate::image::PixelReplacement::NeighbourMean
+#[doc = include_str!("katex.html")]
+pub fn upscale<Cam, F>(
+    image: &[f32],
+    flagged: &F,
+    interpolation: Interpolation,
+    output_width: usize,
+    output: &mut [f32],
+) where
+    Cam: MelexisCamera,
+    F: FlaggedPixels,
+{
+    if output_width == 0 {
+        return;
+    }
+    let output_height = output.len() / output_width;
+    let scale_x = Cam::WIDTH as f32 / output_width as f32;
+    let scale_y = Cam::HEIGHT as f32 / output_height as f32;
+    // There's usually only a handful of flagged pixels (if any), so searching for them instead of
+    // building a lookup table is fine.
+    let any_flagged = flagged.any();
+    let usable = |column: isize, row: isize| {
+        if !(0..Cam::WIDTH as isize).contains(&column) || !(0..Cam::HEIGHT as isize).contains(&row)
+        {
+            return None;
+        }
+        let index = row as usize * Cam::WIDTH + column as usize;
+        if any_flagged && flagged.iter_flagged().any(|flagged| flagged == index) {
+            None
+        } else {
+            image.get(index).copied().filter(|value| !value.is_nan())
+        }
+    };
+    // Pixels past the edges of the image are clamped to the closest edge pixel, and missing pixels
+    // are filled in with the mean of their neighbours.
+    let pixel = |column: isize, row: isize| {
+        let column = column.clamp(0, Cam::WIDTH as isize - 1);
+        let row = row.clamp(0, Cam::HEIGHT as isize - 1);
+        usable(column, row).or_else(|| {
+            let (sum, count) = (-1isize..=1)
+                .flat_map(|row_delta| {
+                    (-1isize..=1).map(move |column_delta| (row_delta, column_delta))
+                })
+                .filter_map(|(row_delta, column_delta)| {
+                    usable(column + column_delta, row + row_delta)
+                })
+                .fold((0f32, 0usize), |(sum, count), value| {
+                    (sum + value, count + 1)
+                });
+            (count > 0).then(|| sum / count as f32)
+        })
+    };
+    for (index, destination) in output
+        .iter_mut()
+        .take(output_width * output_height)
+        .enumerate()
+    {
+        // The position of the output pixel's center, in the coordinates of the input image.
+        let x = ((index % output_width) as f32 + 0.5) * scale_x - 0.5;
+        let y = ((index / output_width) as f32 + 0.5) * scale_y - 0.5;
+        let bilinear = || interpolate(pixel, linear_taps(x), linear_taps(y), true);
+        let value = match interpolation {
+            Interpolation::Nearest => {
+                pixel(x.round() as isize, y.round() as isize).or_else(bilinear)
+            }
+            Interpolation::Bilinear => bilinear(),
+            Interpolation::Bicubic => {
+                interpolate(pixel, cubic_taps(x), cubic_taps(y), false).or_else(bilinear)
+            }
+        };
+        *destination = value.unwrap_or(f32::NAN);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use bitvec::prelude::*;
+
+    use crate::mlx90640::Mlx90640;
+    use crate::mlx90641::Mlx90641;
+    use crate::MelexisCamera;
+
+    use super::{upscale, upscaled_size, Interpolation};
+
+    const INTERPOLATIONS: [Interpolation; 3] = [
+        Interpolation::Nearest,
+        Interpolation::Bilinear,
+        Interpolation::Bicubic,
+    ];
+
+    /// An image where each pixel is its column
+    fn column_image<Cam: MelexisCamera, const N: usize>() -> [f32; N] {
+        let mut image = [0f32; N];
+        image
+            .iter_mut()
+            .enumerate()
+            .for_each(|(index, pixel)| *pixel = (index % Cam::WIDTH) as f32);
+        image
+    }
+
+    #[test]
+    fn size() {
+        assert_eq!(upscaled_size::<Mlx90640>(1.0), (32, 24));
+        assert_eq!(upscaled_size::<Mlx90640>(1.5), (48, 36));
+        assert_eq!(upscaled_size::<Mlx90641>(4.0), (64, 48));
+        assert_eq!(upscaled_size::<Mlx90641>(2.3), (37, 28));
+    }
+
+    #[test]
+    fn nearest() {
+        let image = column_image::<Mlx90641, { Mlx90641::NUM_PIXELS }>();
+        let flagged = bitarr![0; Mlx90641::NUM_PIXELS];
+        let (width, height) = upscaled_size::<Mlx90641>(2.0);
+        let mut output = [0f32; Mlx90641::NUM_PIXELS * 4];
+        upscale::<Mlx90641, _>(
+            &image,
+            &flagged.as_bitslice(),
+            Interpolation::Nearest,
+            width,
+            &mut output,
+        );
+        for row in 0..height {
+            for column in 0..width {
+                assert_eq!(output[row * width + column], (column / 2) as f32);
+            }
+        }
+    }
+
+    #[test]
+    fn linear_gradient() {
+        // Both bilinear and bicubic interpolation reproduce a linear gradient exactly (away from
+        // the edges, where the pixels are clamped).
+        let image = column_image::<Mlx90640, { Mlx90640::NUM_PIXELS }>();
+        let flagged = bitarr![0; Mlx90640::NUM_PIXELS];
+        let (width, height) = upscaled_size::<Mlx90640>(2.5);
+        let mut output = [0f32; 80 * 60];
+        for interpolation in [Interpolation::Bilinear, Interpolation::Bicubic] {
+            upscale::<Mlx90640, _>(
+                &image,
+                &flagged.as_bitslice(),
+                interpolation,
+                width,
+                &mut output,
+            );
+            for row in 0..height {
+                for column in 5..(width - 5) {
+                    let expected = (column as f32 + 0.5) / 2.5 - 0.5;
+                    let actual = output[row * width + column];
+                    assert!(
+                        (expected - actual).abs() < 1e-4,
+                        "{:?} at ({}, {}) was {}, expected {}",
+                        interpolation,
+                        column,
+                        row,
+                        actual,
+                        expected
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn skip_flagged() {
+        let mut image = [20f32; Mlx90641::NUM_PIXELS];
+        let mut flagged = bitarr![0; Mlx90641::NUM_PIXELS];
+        let index = 5 * Mlx90641::WIDTH + 7;
+        image[index] = 1000.0;
+        flagged.set(index, true);
+        // And a NaN pixel that isn't flagged
+        image[index + 2] = f32::NAN;
+        let (width, height) = upscaled_size::<Mlx90641>(3.0);
+        let mut output = [0f32; Mlx90641::NUM_PIXELS * 9];
+        for interpolation in INTERPOLATIONS {
+            upscale::<Mlx90641, _>(
+                &image,
+                &flagged.as_bitslice(),
+                interpolation,
+                width,
+                &mut output,
+            );
+            assert_eq!(output.len(), width * height);
+            for (index, value) in output.iter().enumerate() {
+                assert!(
+                    (value - 20.0).abs() < 1e-4,
+                    "{:?} at {} was {}",
+                    interpolation,
+                    index,
+                    value
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn no_usable_pixels() {
+        let image = [f32::NAN; Mlx90641::NUM_PIXELS];
+        let flagged = bitarr![0; Mlx90641::NUM_PIXELS];
+        let mut output = [0f32; Mlx90641::NUM_PIXELS * 4];
+        for interpolation in INTERPOLATIONS {
+            upscale::<Mlx90641, _>(
+                &image,
+                &flagged.as_bitslice(),
+                interpolation,
+                32,
+                &mut output,
+            );
+            assert!(output.iter().all(|value| value.is_nan()));
+        }
+    }
+}
@@ -1,5 +1,6 @@
 // SPDX-License-Identifier: Apache-2.0
 // Copyright © 2021 Will Ross
+use core::time::Duration;
 
 /// The word size of the camera in terms of 8-bit bytes.
 pub(crate) const WORD_SIZE: usize = (u16::BITS / u8::BITS) as usize;
@@ -7,6 +8,8 @@ pub(crate) const WORD_SIZE: usize = (u16::BITS / u8::BITS) as usize;
 /// Define addition and subtraction for address enumerations.
 #[doc(hidden)]
 #[macro_export]
+// Only used within this crate, so `crate` is the right crate.
+#[allow(clippy::crate_in_macro_def)]
 macro_rules! address_enum_ops {
     ($typ:ident) => {
         // Add
@@ -155,9 +158,185 @@ pub(crate) fn i16_from_bits(mut bytes: &[u8], num_bits: u8) -> i16 {
     (num << shift_amount) >> shift_amount
 }
 
+/// Convert a duration to whole microseconds, saturating instead of overflowing.
+///
+/// The `embedded-hal` delays take a `u32`, which only covers a bit over an hour in microseconds.
+pub(crate) fn saturating_micros(duration: Duration) -> u32 {
+    u32::try_from(duration.as_micros()).unwrap_or(u32::MAX)
+}
+
+/// The number of polls `poll_interval` apart needed to cover `timeout`.
+///
+/// This is rounded up, so the polls cover at least the entire timeout.
+pub(crate) fn polls_within(timeout: Duration, poll_interval: Duration) -> u32 {
+    let interval = poll_interval.as_micros().max(1);
+    let polls = timeout.as_micros().div_ceil(interval);
+    u32::try_from(polls).unwrap_or(u32::MAX)
+}
+
+/// Serde support for arrays.
+///
+/// Serde only implements `Serialize` and `Deserialize` for arrays up to 32 elements long, so the
+/// per-pixel calibration arrays need some help. The arrays are serialized as tuples, the same way
+/// serde handles the shorter arrays.
+#[cfg(feature = "serde")]
+pub(crate) mod serde_array {
+    use core::fmt;
+    use core::marker::PhantomData;
+
+    use arrayvec::ArrayVec;
+    use serde::de::{self, Deserialize, Deserializer, SeqAccess, Visitor};
+    use serde::ser::{Serialize, SerializeTuple, Serializer};
+
+    pub(crate) fn serialize<S, T, const N: usize>(
+        array: &[T; N],
+        serializer: S,
+    ) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+        T: Serialize,
+    {
+        let mut tuple = serializer.serialize_tuple(N)?;
+        for element in array {
+            tuple.serialize_element(element)?;
+        }
+        tuple.end()
+    }
+
+    pub(crate) fn deserialize<'de, D, T, const N: usize>(
+        deserializer: D,
+    ) -> Result<[T; N], D::Error>
+    where
+        D: Deserializer<'de>,
+        T: Deserialize<'de>,
+    {
+        deserializer.deserialize_tuple(N, ArrayVisitor(PhantomData))
+    }
+
+    struct ArrayVisitor<T, const N: usize>(PhantomData<T>);
+
+    impl<'de, T, const N: usize> Visitor<'de> for ArrayVisitor<T, N>
+    where
+        T: Deserialize<'de>,
+    {
+        type Value = [T; N];
+
+        fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+            write!(formatter, "an array of length {}", N)
+        }
+
+        fn visit_seq<A>(self, mut seq: A) -> Result<Self::Value, A::Error>
+        where
+            A: SeqAccess<'de>,
+        {
+            let mut values = ArrayVec::<T, N>::new();
+            for index in 0..N {
+                let value = seq
+                    .next_element()?
+                    .ok_or_else(|| de::Error::invalid_length(index, &self))?;
+                values.push(value);
+            }
+            // All N elements were pushed above
+            values
+                .into_inner()
+                .map_err(|values| de::Error::invalid_length(values.len(), &self))
+        }
+    }
+
+    /// Serialize a reference to an inner array of a nested array.
+    struct ArrayRef<'r, T, const N: usize>(&'r [T; N]);
+
+    impl<T: Serialize, const N: usize> Serialize for ArrayRef<'_, T, N> {
+        fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+            serialize(self.0, serializer)
+        }
+    }
+
+    /// Deserialize an inner array of a nested array.
+    struct Array<T, const N: usize>([T; N]);
+
+    impl<'de, T: Deserialize<'de>, const N: usize> Deserialize<'de> for Array<T, N> {
+        fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+            deserialize(deserializer).map(Self)
+        }
+    }
+
+    /// The same as the parent module, but for arrays of arrays.
+    pub(crate) mod nested {
+        use serde::de::{Deserialize, Deserializer};
+        use serde::ser::{Serialize, SerializeTuple, Serializer};
+
+        use super::{Array, ArrayRef};
+
+        pub(crate) fn serialize<S, T, const N: usize, const M: usize>(
+            arrays: &[[T; N]; M],
+            serializer: S,
+        ) -> Result<S::Ok, S::Error>
+        where
+            S: Serializer,
+            T: Serialize,
+        {
+            let mut tuple = serializer.serialize_tuple(M)?;
+            for array in arrays {
+                tuple.serialize_element(&ArrayRef(array))?;
+            }
+            tuple.end()
+        }
+
+        pub(crate) fn deserialize<'de, D, T, const N: usize, const M: usize>(
+            deserializer: D,
+        ) -> Result<[[T; N]; M], D::Error>
+        where
+            D: Deserializer<'de>,
+            T: Deserialize<'de>,
+        {
+            let arrays: [Array<T, N>; M] = super::deserialize(deserializer)?;
+            Ok(arrays.map(|array| array.0))
+        }
+    }
+
+    /// Flagged pixel bit arrays, serialized as their underlying words.
+    pub(crate) mod flags {
+        use bitvec::array::BitArray;
+        use serde::de::Deserializer;
+        use serde::ser::Serializer;
+
+        pub(crate) fn serialize<S, const N: usize>(
+            flags: &BitArray<[u32; N]>,
+            serializer: S,
+        ) -> Result<S::Ok, S::Error>
+        where
+            S: Serializer,
+        {
+            super::serialize(&flags.data, serializer)
+        }
+
+        pub(crate) fn deserialize<'de, D, const N: usize>(
+            deserializer: D,
+        ) -> Result<BitArray<[u32; N]>, D::Error>
+        where
+            D: Deserializer<'de>,
+        {
+            super::deserialize(deserializer).map(BitArray::new)
+        }
+    }
+}
+
 #[cfg(test)]
 mod test {
-    use super::Buffer;
+    use core::time::Duration;
+
+    use super::{polls_within, saturating_micros, Buffer};
+
+    #[test]
+    fn poll_counts() {
+        let interval = Duration::from_millis(10);
+        assert_eq!(polls_within(Duration::from_millis(100), interval), 10);
+        assert_eq!(polls_within(Duration::from_millis(101), interval), 11);
+        assert_eq!(polls_within(Duration::ZERO, interval), 0);
+        assert_eq!(saturating_micros(Duration::from_secs(1)), 1_000_000);
+        assert_eq!(saturating_micros(Duration::from_secs(5000)), u32::MAX);
+    }
 
     #[test]
     fn buffer_advance() {